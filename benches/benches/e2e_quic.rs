@@ -71,16 +71,11 @@ async fn build_harness() -> Harness {
     let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let tcp_echo_addr = tcp_listener.local_addr().unwrap();
     tokio::spawn(async move {
-        loop {
-            match tcp_listener.accept().await {
-                Ok((mut s, _)) => {
-                    tokio::spawn(async move {
-                        let (mut r, mut w) = s.split();
-                        let _ = tokio::io::copy(&mut r, &mut w).await;
-                    });
-                }
-                Err(_) => break,
-            }
+        while let Ok((mut s, _)) = tcp_listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = s.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
         }
     });
 
@@ -91,13 +86,8 @@ async fn build_harness() -> Harness {
         let udp_arc = udp_arc.clone();
         tokio::spawn(async move {
             let mut buf = vec![0u8; 65535];
-            loop {
-                match udp_arc.recv_from(&mut buf).await {
-                    Ok((n, peer)) => {
-                        let _ = udp_arc.send_to(&buf[..n], peer).await;
-                    }
-                    Err(_) => break,
-                }
+            while let Ok((n, peer)) = udp_arc.recv_from(&mut buf).await {
+                let _ = udp_arc.send_to(&buf[..n], peer).await;
             }
         });
     }
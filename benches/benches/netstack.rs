@@ -81,9 +81,11 @@ fn bench_udp_send_via_stack(c: &mut Criterion) {
             |b, &size| {
                 b.to_async(&runtime).iter_batched(
                     || {
-                        let mut cfg = NetStackConfig::default();
-                        cfg.number_workers = 1;
-                        cfg.channel_size = 256;
+                        let cfg = NetStackConfig {
+                            number_workers: 1,
+                            channel_size: 256,
+                            ..Default::default()
+                        };
                         let (stack, _tcp, udp) = NetStack::new(cfg);
                         let (sink, source) = stack.split();
                         // Hold sink so the channel stays open; return source + writer.
@@ -127,9 +129,11 @@ fn bench_stack_new(c: &mut Criterion) {
             &workers,
             |b, &workers| {
                 b.to_async(&runtime).iter(|| async move {
-                    let mut cfg = NetStackConfig::default();
-                    cfg.number_workers = workers;
-                    cfg.channel_size = 256;
+                    let cfg = NetStackConfig {
+                        number_workers: workers,
+                        channel_size: 256,
+                        ..Default::default()
+                    };
                     let (_stack, _tcp, _udp) = NetStack::new(cfg);
                 });
             },
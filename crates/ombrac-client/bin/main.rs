@@ -1,7 +1,31 @@
 use std::io;
+use std::path::Path;
+
+use clap::Parser;
+use ombrac_client::config::cli::{Args, Command};
 
 fn main() {
-    let config = match ombrac_client::config::load() {
+    let args = Args::parse();
+    if let Some(Command::Keygen { output, label }) = &args.command {
+        if let Err(e) = keygen(output, label.as_deref()) {
+            eprintln!("failed to generate key: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Pin {
+        server,
+        server_name,
+    }) = &args.command
+    {
+        if let Err(e) = print_pins(server, server_name.as_ref()) {
+            eprintln!("failed to fetch the server certificate: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = match ombrac_client::config::load_from_args(args) {
         Ok(cfg) => cfg,
         Err(error) => {
             eprintln!("failed to load configuration: {}", error);
@@ -24,6 +48,30 @@ fn main() {
     }
 }
 
+/// Writes a new private key to `output`, readable only by its owner, and
+/// prints the matching authorized keys line.
+fn keygen(output: &Path, label: Option<&str>) -> io::Result<()> {
+    let key = ombrac::key::PrivateKey::generate()?;
+    key.save(output)?;
+
+    match label {
+        Some(label) => println!("{} {}", key.public_key(), label),
+        None => println!("{}", key.public_key()),
+    }
+    Ok(())
+}
+
+/// Prints the pins of the certificate chain `server` presents.
+fn print_pins(server: &str, server_name: Option<&String>) -> io::Result<()> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    for pin in rt.block_on(ombrac_client::service::server_pins(server, server_name))? {
+        println!("{pin}");
+    }
+    Ok(())
+}
+
 /// A high-level function to run the client from a command-line context.
 /// It builds the session, waits for a shutdown signal, and then gracefully shuts down.
 pub async fn run_from_cli(config: ombrac_client::config::ServiceConfig) -> io::Result<()> {
@@ -98,6 +98,50 @@ int32_t ombrac_client_service_rebind(void);
  */
 int32_t ombrac_client_service_shutdown(void);
 
+/**
+ * Starts writing the packets through the TUN endpoint to a pcap file.
+ *
+ * Any running capture is replaced. The capture is rotated according to the
+ * `pcap_max_size` and `pcap_max_files` settings of the configuration and
+ * needs no privileges, so it can be used to debug mobile builds.
+ *
+ * # Arguments
+ *
+ * * `path` - A pointer to a null-terminated UTF-8 string with the file to
+ *   write to.
+ * * `filter` - A pointer to a null-terminated UTF-8 string with a capture
+ *   filter such as `"udp and dst port 53"`, or null to use the configured
+ *   `pcap_filter`.
+ *
+ * # Returns
+ *
+ * * `0` on success.
+ * * `-1` if the service is not running, the filter is invalid or the file
+ *   cannot be created.
+ *
+ * # Safety
+ *
+ * The caller must ensure that `path` and `filter` are valid pointers to
+ * null-terminated C strings, or null for `filter`.
+ *
+ * This function is protected against Rust panics crossing the FFI boundary.
+ */
+int32_t ombrac_client_capture_start(const char *path, const char *filter);
+
+/**
+ * Stops the running packet capture, flushing and closing its file.
+ *
+ * It is safe to call even if no capture is running.
+ *
+ * # Returns
+ *
+ * * `0` on completion.
+ * * `-1` if the service is not running.
+ *
+ * This function is protected against Rust panics crossing the FFI boundary.
+ */
+int32_t ombrac_client_capture_stop(void);
+
 /**
  * Returns the version of the ombrac-client library.
  *
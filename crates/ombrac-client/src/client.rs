@@ -8,11 +8,12 @@ use bytes::Bytes;
 use tokio_util::sync::CancellationToken;
 
 use ombrac::metrics::Metrics;
-use ombrac::protocol::{Address, Secret};
+use ombrac::protocol::{Address, Secret, ServerCapabilities};
 use ombrac_transport::{Connection, Initiator};
 
+use crate::config::AuthMode;
 use crate::connection::BufferedStream;
-use crate::connection::ClientConnection;
+use crate::connection::{ClientConnection, Credential};
 #[cfg(feature = "datagram")]
 use crate::connection::{UdpDispatcher, UdpSession};
 
@@ -49,7 +50,28 @@ where
     /// This involves performing a handshake and spawning a background task to
     /// handle incoming UDP datagrams.
     pub async fn new(transport: T, secret: Secret, options: Option<Bytes>) -> io::Result<Self> {
-        let connection = Arc::new(ClientConnection::new(transport, secret, options).await?);
+        Self::with_auth_mode(transport, secret, options, AuthMode::default()).await
+    }
+
+    /// Creates a new `Client` proving knowledge of the secret as `auth_mode`
+    /// says.
+    pub async fn with_auth_mode(
+        transport: T,
+        secret: Secret,
+        options: Option<Bytes>,
+        auth_mode: AuthMode,
+    ) -> io::Result<Self> {
+        Self::with_credential(transport, Credential::Secret(secret, auth_mode), options).await
+    }
+
+    /// Creates a new `Client` authenticating with `credential`.
+    pub async fn with_credential(
+        transport: T,
+        credential: Credential,
+        options: Option<Bytes>,
+    ) -> io::Result<Self> {
+        let connection =
+            Arc::new(ClientConnection::with_credential(transport, credential, options).await?);
 
         #[cfg(feature = "datagram")]
         let session_id_counter = Arc::new(AtomicU64::new(1));
@@ -117,6 +139,20 @@ where
         self.connection.open_bidirectional(dest_addr).await
     }
 
+    /// Resolves a DNS query (in wire format) through the server's resolver.
+    ///
+    /// Returns the wire-format response. No caching is done at this level;
+    /// see `dns::RemoteResolver` for a cached resolver built on top of it.
+    pub async fn dns_query(&self, message: Bytes) -> io::Result<Bytes> {
+        self.connection.dns_query(message).await
+    }
+
+    /// Sends an ICMP echo to `address` from the server, returning once it
+    /// has been answered.
+    pub async fn echo(&self, address: Address, data: Bytes) -> io::Result<()> {
+        self.connection.echo(address, data).await
+    }
+
     /// Rebind the transport to a new socket to ensure a clean state for reconnection.
     pub async fn rebind(&self) -> io::Result<()> {
         self.connection.rebind().await
@@ -126,6 +162,11 @@ where
     pub fn metrics(&self) -> Metrics {
         self.connection.metrics()
     }
+
+    /// Returns the protocol version and features agreed with the server.
+    pub fn capabilities(&self) -> ServerCapabilities {
+        self.connection.capabilities()
+    }
 }
 
 impl<T, C> Drop for Client<T, C>
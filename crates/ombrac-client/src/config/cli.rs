@@ -1,18 +1,29 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use clap::Parser;
 use clap::builder::Styles;
 use clap::builder::styling::{AnsiColor, Style};
+use clap::{Parser, Subcommand};
 
 use ombrac_transport::quic::Congestion;
 
-use crate::config::{EndpointConfig, TlsMode, TransportConfig};
+#[cfg(feature = "endpoint-tun")]
+use crate::config::IcmpEchoMode;
+use crate::config::{AuthMode, EndpointConfig, TlsMode, TransportConfig};
 
 /// Command-line arguments for the ombrac client
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None, styles = styles())]
+#[command(
+    version,
+    about,
+    long_about = None,
+    styles = styles(),
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to the JSON configuration file
     #[clap(long, short = 'c', value_name = "FILE")]
     pub config: Option<PathBuf>,
@@ -23,7 +34,7 @@ pub struct Args {
         short = 'k',
         help_heading = "Required",
         value_name = "STR",
-        required_unless_present = "config"
+        required_unless_present_any = ["config", "auth_key", "auth_token", "client_cert"]
     )]
     pub secret: Option<String>,
 
@@ -70,6 +81,10 @@ pub struct CliEndpointConfig {
     #[clap(long, value_name = "ADDR", help_heading = "Endpoint")]
     pub socks: Option<SocketAddr>,
 
+    #[cfg(feature = "endpoint-dns")]
+    #[clap(flatten)]
+    pub dns: Option<CliDnsConfig>,
+
     #[cfg(feature = "endpoint-tun")]
     #[clap(flatten)]
     pub tun: Option<CliTunConfig>,
@@ -83,12 +98,59 @@ impl CliEndpointConfig {
             http: self.http,
             #[cfg(feature = "endpoint-socks")]
             socks: self.socks,
+            #[cfg(feature = "endpoint-dns")]
+            dns: self.dns.map(|d| d.into_dns_config()),
             #[cfg(feature = "endpoint-tun")]
             tun: self.tun.map(|t| t.into_tun_config()),
         }
     }
 }
 
+/// CLI-specific DNS configuration
+#[cfg(feature = "endpoint-dns")]
+#[derive(Parser, Debug, Clone, Default)]
+pub struct CliDnsConfig {
+    /// The address to bind for the DNS server (UDP and TCP)
+    #[clap(long, help_heading = "Endpoint", value_name = "ADDR")]
+    pub dns_bind: Option<SocketAddr>,
+
+    /// Upstream DNS server reached through the tunnel over TCP.
+    /// Queries are answered by the server's resolver if not set
+    #[clap(long, help_heading = "Endpoint", value_name = "ADDR")]
+    pub dns_upstream: Option<String>,
+
+    /// Answer A queries with fake IPs from this IPv4 pool, in CIDR notation
+    #[clap(long, help_heading = "Endpoint", value_name = "CIDR")]
+    pub dns_fake_ip: Option<String>,
+
+    /// Answer AAAA queries with fake IPs from this IPv6 pool, in CIDR notation
+    #[clap(long, help_heading = "Endpoint", value_name = "CIDR")]
+    pub dns_fake_ipv6: Option<String>,
+
+    /// File where fake IP mappings are kept across restarts
+    #[clap(long, help_heading = "Endpoint", value_name = "FILE")]
+    pub dns_fake_ip_state: Option<PathBuf>,
+
+    /// Maximum number of cached DNS responses [default: 4096]
+    #[clap(long, help_heading = "Endpoint", value_name = "NUM")]
+    pub dns_cache_size: Option<u64>,
+}
+
+#[cfg(feature = "endpoint-dns")]
+impl CliDnsConfig {
+    /// Convert CLI DNS config to internal DnsConfig
+    pub fn into_dns_config(self) -> crate::config::DnsConfig {
+        crate::config::DnsConfig {
+            bind: self.dns_bind,
+            upstream: self.dns_upstream,
+            fake_ip: self.dns_fake_ip,
+            fake_ipv6: self.dns_fake_ipv6,
+            fake_ip_state: self.dns_fake_ip_state,
+            cache_size: self.dns_cache_size,
+        }
+    }
+}
+
 /// CLI-specific TUN configuration
 #[cfg(feature = "endpoint-tun")]
 #[derive(Parser, Debug, Clone, Default)]
@@ -114,6 +176,78 @@ pub struct CliTunConfig {
     #[clap(long, help_heading = "Endpoint", value_name = "CIDR")]
     pub fake_dns: Option<String>,
 
+    /// The IPv6 address pool for the built-in fake DNS server, in CIDR notation
+    #[clap(long, help_heading = "Endpoint", value_name = "CIDR")]
+    pub fake_dns_ipv6: Option<String>,
+
+    /// File where fake DNS mappings are kept across restarts
+    #[clap(long, help_heading = "Endpoint", value_name = "FILE")]
+    pub fake_dns_state: Option<PathBuf>,
+
+    /// Destinations always sent through the tunnel, as CIDRs, IPs or domains
+    #[clap(
+        long,
+        help_heading = "Endpoint",
+        value_name = "RULES",
+        value_delimiter = ','
+    )]
+    pub route_include: Option<Vec<String>>,
+
+    /// Destinations dialed directly from the host, as CIDRs, IPs or domains.
+    /// [default: private, loopback, link-local and reserved ranges]
+    #[clap(
+        long,
+        help_heading = "Endpoint",
+        value_name = "RULES",
+        value_delimiter = ','
+    )]
+    pub route_exclude: Option<Vec<String>>,
+
+    /// Route all traffic into the TUN device, rolled back on shutdown. Linux only [default: false]
+    #[clap(long, help_heading = "Endpoint", value_name = "BOOL")]
+    pub auto_route: Option<bool>,
+
+    /// Nameserver to set as the system resolver while auto route is active
+    #[clap(long, help_heading = "Endpoint", value_name = "IP")]
+    pub auto_route_dns: Option<String>,
+
+    /// Nameservers resolving the domains routed directly, queried from the host
+    #[clap(
+        long,
+        help_heading = "Endpoint",
+        value_name = "ADDRS",
+        value_delimiter = ','
+    )]
+    pub direct_dns: Option<Vec<String>>,
+
+    /// Recover domains of connections to real IPs from TLS SNI, HTTP Host or QUIC Initial [default: false]
+    #[clap(long, help_heading = "Endpoint", value_name = "BOOL")]
+    pub sniff: Option<bool>,
+
+    /// How ping through the TUN device is answered [default: local]
+    #[clap(long, help_heading = "Endpoint", value_name = "MODE")]
+    pub icmp_echo: Option<IcmpEchoMode>,
+
+    /// Delay (in milliseconds) before answering ping in local mode [default: 0]
+    #[clap(long, help_heading = "Endpoint", value_name = "MS")]
+    pub icmp_echo_latency: Option<u64>,
+
+    /// Write the packets through the TUN device to this file in pcap format
+    #[clap(long, help_heading = "Endpoint", value_name = "FILE")]
+    pub pcap: Option<PathBuf>,
+
+    /// Only capture packets matching this tcpdump-like filter, e.g. "udp and dst port 53"
+    #[clap(long, help_heading = "Endpoint", value_name = "FILTER")]
+    pub pcap_filter: Option<String>,
+
+    /// Size (in bytes) after which the capture file is rotated, 0 to never rotate [default: 16777216]
+    #[clap(long, help_heading = "Endpoint", value_name = "BYTES")]
+    pub pcap_max_size: Option<u64>,
+
+    /// Capture files kept, including the one being written [default: 4]
+    #[clap(long, help_heading = "Endpoint", value_name = "N")]
+    pub pcap_max_files: Option<usize>,
+
     /// Disable UDP traffic to port 443
     #[clap(long, help_heading = "Endpoint", value_name = "BOOL")]
     pub disable_udp_443: Option<bool>,
@@ -129,6 +263,20 @@ impl CliTunConfig {
             tun_ipv6: self.tun_ipv6,
             tun_mtu: self.tun_mtu,
             fake_dns: self.fake_dns,
+            fake_dns_ipv6: self.fake_dns_ipv6,
+            fake_dns_state: self.fake_dns_state,
+            route_include: self.route_include,
+            route_exclude: self.route_exclude,
+            auto_route: self.auto_route,
+            auto_route_dns: self.auto_route_dns,
+            direct_dns: self.direct_dns,
+            sniff: self.sniff,
+            icmp_echo: self.icmp_echo,
+            icmp_echo_latency: self.icmp_echo_latency,
+            pcap: self.pcap,
+            pcap_filter: self.pcap_filter,
+            pcap_max_size: self.pcap_max_size,
+            pcap_max_files: self.pcap_max_files,
             disable_udp_443: self.disable_udp_443,
         }
     }
@@ -162,6 +310,45 @@ pub struct CliTransportConfig {
     #[clap(long, help_heading = "Transport", value_name = "FILE")]
     pub client_key: Option<PathBuf>,
 
+    /// Certificate revocation lists (PEM or DER) to check the server certificate against
+    #[clap(
+        long,
+        help_heading = "Transport",
+        value_name = "FILE",
+        value_delimiter = ','
+    )]
+    pub crl: Option<Vec<PathBuf>>,
+
+    /// Accept server certificates none of the revocation lists covers [default: false]
+    #[clap(long, help_heading = "Transport", value_name = "BOOL")]
+    pub crl_allow_unknown: Option<bool>,
+
+    /// SHA-256 pins (sha256/<base64>) of keys the server certificate chain must
+    /// contain, in place of verifying it against the CA
+    #[clap(
+        long = "pin",
+        help_heading = "Transport",
+        value_name = "PIN",
+        value_delimiter = ','
+    )]
+    pub pins: Option<Vec<String>>,
+
+    /// Check the server name against the certificate when pinning [default: true]
+    #[clap(long, help_heading = "Transport", value_name = "BOOL")]
+    pub pin_verify_hostname: Option<bool>,
+
+    /// How to prove knowledge of the secret to the server [default: secret]
+    #[clap(long, value_enum, help_heading = "Transport")]
+    pub auth_mode: Option<AuthMode>,
+
+    /// Path to the Ed25519 private key to authenticate with instead of the secret
+    #[clap(long, help_heading = "Transport", value_name = "FILE")]
+    pub auth_key: Option<PathBuf>,
+
+    /// Access token to authenticate with instead of the secret
+    #[clap(long, help_heading = "Transport", value_name = "STR")]
+    pub auth_token: Option<String>,
+
     /// Enable 0-RTT for faster connection establishment
     #[clap(long, help_heading = "Transport", value_name = "BOOL")]
     pub zero_rtt: Option<bool>,
@@ -215,6 +402,13 @@ impl CliTransportConfig {
             ca_cert: self.ca_cert,
             client_cert: self.client_cert,
             client_key: self.client_key,
+            crl: self.crl,
+            crl_allow_unknown: self.crl_allow_unknown,
+            pins: self.pins,
+            pin_verify_hostname: self.pin_verify_hostname,
+            auth_mode: self.auth_mode,
+            auth_key: self.auth_key,
+            auth_token: self.auth_token,
             zero_rtt: self.zero_rtt,
             alpn_protocols: self.alpn_protocols,
             congestion: self.congestion,
@@ -236,6 +430,38 @@ impl CliLoggingConfig {
     }
 }
 
+/// Subcommands run instead of the client
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate an Ed25519 key for public-key authentication
+    ///
+    /// The private key is written to FILE and the line to add to the
+    /// server's authorized keys file is printed.
+    Keygen {
+        /// Path to write the private key to
+        #[clap(long, short = 'o', value_name = "FILE")]
+        output: PathBuf,
+
+        /// Label of the key in the authorized keys file
+        #[clap(long, value_name = "STR")]
+        label: Option<String>,
+    },
+
+    /// Print the pins of a server's certificate chain, leaf first
+    ///
+    /// The server isn't verified: check the printed pin through a trusted
+    /// channel before pinning it.
+    Pin {
+        /// Address of the server
+        #[clap(long, short = 's', value_name = "ADDR")]
+        server: String,
+
+        /// Name of the server (derived from the address if not provided)
+        #[clap(long, value_name = "STR")]
+        server_name: Option<String>,
+    },
+}
+
 /// Represents CLI arguments as a partial configuration
 #[derive(Debug)]
 pub struct CliConfig {
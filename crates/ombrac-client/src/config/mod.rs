@@ -21,6 +21,10 @@ pub struct EndpointConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub socks: Option<SocketAddr>,
 
+    #[cfg(feature = "endpoint-dns")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsConfig>,
+
     #[cfg(feature = "endpoint-tun")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tun: Option<TunConfig>,
@@ -53,6 +57,35 @@ pub struct TransportConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_key: Option<PathBuf>,
 
+    /// Certificate revocation lists (PEM or DER) to check the server certificate against
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crl: Option<Vec<PathBuf>>,
+
+    /// Accept server certificates none of the revocation lists covers [default: false]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crl_allow_unknown: Option<bool>,
+
+    /// SHA-256 pins (`sha256/<base64>`) of keys the server certificate chain
+    /// must contain, in place of verifying it against the CA
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pins: Option<Vec<String>>,
+
+    /// Check the server name against the certificate when pinning [default: true]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_verify_hostname: Option<bool>,
+
+    /// How to prove knowledge of the secret to the server [default: secret]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_mode: Option<AuthMode>,
+
+    /// Path to the Ed25519 private key to authenticate with instead of the secret
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_key: Option<PathBuf>,
+
+    /// Access token to authenticate with instead of the secret
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+
     /// Enable 0-RTT for faster connection establishment
     #[serde(skip_serializing_if = "Option::is_none")]
     pub zero_rtt: Option<bool>,
@@ -91,6 +124,13 @@ impl Default for TransportConfig {
             ca_cert: None,
             client_cert: None,
             client_key: None,
+            crl: None,
+            crl_allow_unknown: None,
+            pins: None,
+            pin_verify_hostname: None,
+            auth_mode: Some(AuthMode::Secret),
+            auth_key: None,
+            auth_token: None,
             zero_rtt: Some(false),
             alpn_protocols: Some(vec!["h3".into()]),
             congestion: Some(Congestion::Bbr),
@@ -120,6 +160,60 @@ impl Default for LoggingConfig {
     }
 }
 
+#[cfg(feature = "endpoint-dns")]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DnsConfig {
+    /// The address to bind for the DNS server (UDP and TCP)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind: Option<SocketAddr>,
+
+    /// Upstream DNS server reached through the tunnel over TCP (e.g., 1.1.1.1:53).
+    /// Queries are answered by the server's resolver if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+
+    /// Answer A queries with fake IPs from this IPv4 pool, in CIDR notation.
+    /// The SOCKS and HTTP endpoints map them back to domain names.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fake_ip: Option<String>,
+
+    /// Answer AAAA queries with fake IPs from this IPv6 pool, in CIDR notation.
+    /// Only used together with `fake_ip`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fake_ipv6: Option<String>,
+
+    /// File where fake IP mappings are kept across restarts.
+    /// Only used together with `fake_ip`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fake_ip_state: Option<PathBuf>,
+
+    /// Maximum number of cached DNS responses [default: 4096]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<u64>,
+}
+
+#[cfg(feature = "endpoint-dns")]
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            bind: None,
+            upstream: None,
+            fake_ip: None,
+            fake_ipv6: None,
+            fake_ip_state: None,
+            cache_size: Some(crate::dns::DEFAULT_CACHE_CAPACITY),
+        }
+    }
+}
+
+#[cfg(feature = "endpoint-dns")]
+impl DnsConfig {
+    /// Get cache size with default
+    pub fn cache_size(&self) -> u64 {
+        self.cache_size.unwrap_or(crate::dns::DEFAULT_CACHE_CAPACITY)
+    }
+}
+
 #[cfg(feature = "endpoint-tun")]
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TunConfig {
@@ -144,6 +238,75 @@ pub struct TunConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fake_dns: Option<String>,
 
+    /// The IPv6 address pool for the built-in fake DNS server, in CIDR notation (e.g., fdfe:dcba:9876::/64).
+    /// AAAA queries are resolved by the server if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fake_dns_ipv6: Option<String>,
+
+    /// File where fake DNS mappings are kept across restarts.
+    /// Mappings are held in memory only if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fake_dns_state: Option<PathBuf>,
+
+    /// Destinations that are always sent through the tunnel, even if they match `route_exclude`.
+    /// Each entry is a CIDR, an IP address or a domain (matching its subdomains too)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_include: Option<Vec<String>>,
+
+    /// Destinations that are dialed directly from the host instead of through the tunnel.
+    /// Each entry is a CIDR, an IP address or a domain (matching its subdomains too).
+    /// [default: private, loopback, link-local and reserved ranges]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_exclude: Option<Vec<String>>,
+
+    /// Route all traffic into the TUN device and keep the server on its current route.
+    /// Changes are rolled back on shutdown. Linux only [default: false]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_route: Option<bool>,
+
+    /// Nameserver to set as the system resolver while `auto_route` is active,
+    /// e.g. an address inside the TUN subnet. The resolver is left alone if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_route_dns: Option<String>,
+
+    /// Nameservers resolving the domains routed directly, queried over UDP from the host
+    /// (e.g. 1.1.1.1 or 1.1.1.1:53). [default: the system's nameservers with `auto_route`,
+    /// otherwise the system resolver]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direct_dns: Option<Vec<String>>,
+
+    /// Recover the domain of connections made to a real IP from the TLS SNI,
+    /// the HTTP Host header or the SNI of a QUIC Initial [default: false]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sniff: Option<bool>,
+
+    /// How ping through the TUN device is answered: `off`, `local` (answered by the client)
+    /// or `relay` (sent by the server, answered only if the destination replies) [default: local]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_echo: Option<IcmpEchoMode>,
+
+    /// Delay (in milliseconds) before answering ping in `local` mode [default: 0]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icmp_echo_latency: Option<u64>,
+
+    /// Write the packets through the TUN device to this file in pcap format.
+    /// Capture can also be started and stopped at runtime; off if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap: Option<PathBuf>,
+
+    /// Only capture packets matching this filter, in a tcpdump-like syntax
+    /// over `host`, `net`, `port` and protocols (e.g. `udp and dst port 53`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap_filter: Option<String>,
+
+    /// Size (in bytes) after which the capture file is rotated, 0 to never rotate [default: 16777216]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap_max_size: Option<u64>,
+
+    /// Capture files kept, including the one being written [default: 4]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap_max_files: Option<usize>,
+
     /// Disable UDP traffic to port 443
     #[serde(skip_serializing_if = "Option::is_none")]
     pub disable_udp_443: Option<bool>,
@@ -158,6 +321,20 @@ impl Default for TunConfig {
             tun_ipv6: None,
             tun_mtu: Some(1500),
             fake_dns: Some("198.18.0.0/16".to_string()),
+            fake_dns_ipv6: None,
+            fake_dns_state: None,
+            route_include: None,
+            route_exclude: None,
+            auto_route: Some(false),
+            auto_route_dns: None,
+            direct_dns: None,
+            sniff: Some(false),
+            icmp_echo: Some(IcmpEchoMode::Local),
+            icmp_echo_latency: Some(0),
+            pcap: None,
+            pcap_filter: None,
+            pcap_max_size: Some(16 * 1024 * 1024),
+            pcap_max_files: Some(4),
             disable_udp_443: Some(false),
         }
     }
@@ -172,6 +349,31 @@ pub enum TlsMode {
     Insecure,
 }
 
+/// How the client proves knowledge of the secret.
+///
+/// `secret` sends the hash of the secret in the hello, which every server
+/// understands. `challenge` never sends it: the client answers a nonce from
+/// the server with a MAC bound to the TLS session, so nothing observed on
+/// one connection can be replayed on another. It requires a server with
+/// challenge-response support.
+#[derive(ValueEnum, Clone, Debug, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMode {
+    #[default]
+    Secret,
+    Challenge,
+}
+
+#[cfg(feature = "endpoint-tun")]
+#[derive(ValueEnum, Clone, Debug, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum IcmpEchoMode {
+    Off,
+    #[default]
+    Local,
+    Relay,
+}
+
 /// Final service configuration with all defaults applied
 #[derive(Debug, Clone)]
 pub struct ServiceConfig {
@@ -257,10 +459,20 @@ impl ConfigBuilder {
     }
 
     /// Build the final ServiceConfig, validating required fields
+    ///
+    /// The secret may be omitted when the client authenticates with a key,
+    /// a token or a client certificate.
     pub fn build(self) -> Result<ServiceConfig, String> {
-        let secret = self
-            .secret
-            .ok_or_else(|| "missing required field: secret".to_string())?;
+        let secret = match self.secret {
+            Some(secret) => secret,
+            None if self.transport.auth_key.is_some()
+                || self.transport.auth_token.is_some()
+                || self.transport.client_cert.is_some() =>
+            {
+                String::new()
+            }
+            None => return Err("missing required field: secret".to_string()),
+        };
         let server = self
             .server
             .ok_or_else(|| "missing required field: server".to_string())?;
@@ -282,11 +494,30 @@ impl ConfigBuilder {
             http: _override_config.http.or(_base.http),
             #[cfg(feature = "endpoint-socks")]
             socks: _override_config.socks.or(_base.socks),
+            #[cfg(feature = "endpoint-dns")]
+            dns: Self::merge_dns(_base.dns, _override_config.dns),
             #[cfg(feature = "endpoint-tun")]
             tun: Self::merge_tun(_base.tun, _override_config.tun),
         }
     }
 
+    #[cfg(feature = "endpoint-dns")]
+    fn merge_dns(base: Option<DnsConfig>, override_config: Option<DnsConfig>) -> Option<DnsConfig> {
+        match (base, override_config) {
+            (None, None) => None,
+            (Some(base), None) => Some(base),
+            (None, Some(override_config)) => Some(override_config),
+            (Some(base), Some(override_config)) => Some(DnsConfig {
+                bind: override_config.bind.or(base.bind),
+                upstream: override_config.upstream.or(base.upstream),
+                fake_ip: override_config.fake_ip.or(base.fake_ip),
+                fake_ipv6: override_config.fake_ipv6.or(base.fake_ipv6),
+                fake_ip_state: override_config.fake_ip_state.or(base.fake_ip_state),
+                cache_size: override_config.cache_size.or(base.cache_size),
+            }),
+        }
+    }
+
     #[cfg(feature = "endpoint-tun")]
     fn merge_tun(base: Option<TunConfig>, override_config: Option<TunConfig>) -> Option<TunConfig> {
         match (base, override_config) {
@@ -299,6 +530,20 @@ impl ConfigBuilder {
                 tun_ipv6: override_config.tun_ipv6.or(base.tun_ipv6),
                 tun_mtu: override_config.tun_mtu.or(base.tun_mtu),
                 fake_dns: override_config.fake_dns.or(base.fake_dns),
+                fake_dns_ipv6: override_config.fake_dns_ipv6.or(base.fake_dns_ipv6),
+                fake_dns_state: override_config.fake_dns_state.or(base.fake_dns_state),
+                route_include: override_config.route_include.or(base.route_include),
+                route_exclude: override_config.route_exclude.or(base.route_exclude),
+                auto_route: override_config.auto_route.or(base.auto_route),
+                auto_route_dns: override_config.auto_route_dns.or(base.auto_route_dns),
+                direct_dns: override_config.direct_dns.or(base.direct_dns),
+                sniff: override_config.sniff.or(base.sniff),
+                icmp_echo: override_config.icmp_echo.or(base.icmp_echo),
+                icmp_echo_latency: override_config.icmp_echo_latency.or(base.icmp_echo_latency),
+                pcap: override_config.pcap.or(base.pcap),
+                pcap_filter: override_config.pcap_filter.or(base.pcap_filter),
+                pcap_max_size: override_config.pcap_max_size.or(base.pcap_max_size),
+                pcap_max_files: override_config.pcap_max_files.or(base.pcap_max_files),
                 disable_udp_443: override_config.disable_udp_443.or(base.disable_udp_443),
             }),
         }
@@ -312,6 +557,15 @@ impl ConfigBuilder {
             ca_cert: override_config.ca_cert.or(base.ca_cert),
             client_cert: override_config.client_cert.or(base.client_cert),
             client_key: override_config.client_key.or(base.client_key),
+            crl: override_config.crl.or(base.crl),
+            crl_allow_unknown: override_config.crl_allow_unknown.or(base.crl_allow_unknown),
+            pins: override_config.pins.or(base.pins),
+            pin_verify_hostname: override_config
+                .pin_verify_hostname
+                .or(base.pin_verify_hostname),
+            auth_mode: override_config.auth_mode.or(base.auth_mode),
+            auth_key: override_config.auth_key.or(base.auth_key),
+            auth_token: override_config.auth_token.or(base.auth_token),
             zero_rtt: override_config.zero_rtt.or(base.zero_rtt),
             alpn_protocols: override_config.alpn_protocols.or(base.alpn_protocols),
             congestion: override_config.congestion.or(base.congestion),
@@ -349,7 +603,15 @@ impl Default for ConfigBuilder {
 #[cfg(feature = "binary")]
 pub fn load() -> Result<ServiceConfig, Box<dyn std::error::Error>> {
     use clap::Parser;
-    let cli_args = cli::Args::parse();
+    load_from_args(cli::Args::parse())
+}
+
+/// Loads configuration from already parsed command-line arguments.
+///
+/// Like [`load`], for callers that need to look at the arguments first,
+/// e.g. to run a subcommand.
+#[cfg(feature = "binary")]
+pub fn load_from_args(cli_args: cli::Args) -> Result<ServiceConfig, Box<dyn std::error::Error>> {
     let mut builder = ConfigBuilder::new();
 
     // Load JSON config if specified
@@ -437,6 +699,7 @@ mod tests {
         assert_eq!(cfg.transport.keep_alive, Some(8000));
         assert_eq!(cfg.transport.max_streams, Some(100));
         assert_eq!(cfg.transport.zero_rtt, Some(false));
+        assert_eq!(cfg.transport.auth_mode, Some(AuthMode::Secret));
     }
 
     #[test]
@@ -446,6 +709,41 @@ mod tests {
         assert!(err.to_string().contains("secret"));
     }
 
+    #[test]
+    fn load_from_json_without_secret_accepts_key_token_or_certificate() {
+        let json = r#"{
+            "server": "example.com:443",
+            "transport": { "auth_token": "claims.signature" }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.secret, "");
+        assert_eq!(
+            cfg.transport.auth_token.as_deref(),
+            Some("claims.signature")
+        );
+
+        let json = r#"{
+            "server": "example.com:443",
+            "transport": { "auth_key": "/etc/ombrac/client.pem" }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(
+            cfg.transport.auth_key,
+            Some(PathBuf::from("/etc/ombrac/client.pem"))
+        );
+
+        let json = r#"{
+            "server": "example.com:443",
+            "transport": {
+                "tls_mode": "m-tls",
+                "client_cert": "/etc/ombrac/client.crt",
+                "client_key": "/etc/ombrac/client.key"
+            }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.secret, "");
+    }
+
     #[test]
     fn load_from_json_missing_server_fails() {
         let json = r#"{ "secret": "s" }"#;
@@ -466,14 +764,24 @@ mod tests {
             "server": "1.2.3.4:443",
             "transport": {
                 "tls_mode": "insecure",
+                "crl": ["ca.crl"],
+                "crl_allow_unknown": true,
+                "pins": ["sha256/AAAA"],
+                "pin_verify_hostname": false,
                 "idle_timeout": 60000,
                 "keep_alive": 4000,
                 "max_streams": 200,
-                "zero_rtt": true
+                "zero_rtt": true,
+                "auth_mode": "challenge"
             }
         }"#;
         let cfg = load_from_json(json).unwrap();
         assert_eq!(cfg.transport.tls_mode, Some(TlsMode::Insecure));
+        assert_eq!(cfg.transport.auth_mode, Some(AuthMode::Challenge));
+        assert_eq!(cfg.transport.crl, Some(vec![PathBuf::from("ca.crl")]));
+        assert_eq!(cfg.transport.crl_allow_unknown, Some(true));
+        assert_eq!(cfg.transport.pins, Some(vec!["sha256/AAAA".to_string()]));
+        assert_eq!(cfg.transport.pin_verify_hostname, Some(false));
         assert_eq!(cfg.transport.idle_timeout, Some(60000));
         assert_eq!(cfg.transport.keep_alive, Some(4000));
         assert_eq!(cfg.transport.max_streams, Some(200));
@@ -609,4 +917,44 @@ mod tests {
             "127.0.0.1:1080"
         );
     }
+
+    #[cfg(feature = "endpoint-dns")]
+    #[test]
+    fn endpoint_dns_merges_field_by_field() {
+        let json = r#"{
+            "secret": "k",
+            "server": "s:1",
+            "endpoint": {
+                "dns": { "bind": "127.0.0.1:5353", "upstream": "1.1.1.1:53" }
+            }
+        }"#;
+        let cli = cli::CliConfig {
+            secret: None,
+            server: None,
+            auth_option: None,
+            endpoint: EndpointConfig {
+                dns: Some(DnsConfig {
+                    fake_ip: Some("198.18.0.0/15".into()),
+                    cache_size: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            transport: TransportConfig::default(),
+            #[cfg(feature = "tracing")]
+            logging: LoggingConfig::default(),
+        };
+
+        let cfg = ConfigBuilder::new()
+            .merge_json(json::JsonConfig::from_json_str(json).unwrap())
+            .merge_cli(cli)
+            .build()
+            .unwrap();
+
+        let dns = cfg.endpoint.dns.unwrap();
+        assert_eq!(dns.bind.unwrap().to_string(), "127.0.0.1:5353");
+        assert_eq!(dns.upstream.as_deref(), Some("1.1.1.1:53"));
+        assert_eq!(dns.fake_ip.as_deref(), Some("198.18.0.0/15"));
+        assert_eq!(dns.cache_size, None);
+    }
 }
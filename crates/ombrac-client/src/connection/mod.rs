@@ -17,14 +17,18 @@ use tokio::time::Instant;
 use tokio_util::codec::Framed;
 
 use ombrac::codec::{ClientMessage, ServerMessage, length_codec};
+use ombrac::key::PrivateKey;
 use ombrac::metrics::Metrics;
 use ombrac::protocol::{
-    self, Address, ClientConnect, ClientHello, ConnectErrorKind, PROTOCOL_VERSION, Secret,
-    ServerAuthResponse, ServerConnectResponse,
+    self, Address, ClientAuthProof, ClientCapabilities, ClientConnect, ClientDnsQuery, ClientEcho,
+    ClientHello, ClientKeyProof, ClientToken, Features, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    Secret, ServerAuthResponse, ServerCapabilities, ServerConnectResponse, ServerEchoResponse,
 };
 use ombrac_macros::{error, warn};
 use ombrac_transport::{Connection, Initiator};
 
+use crate::config::AuthMode;
+
 pub use stream::BufferedStream;
 
 #[cfg(feature = "datagram")]
@@ -34,6 +38,15 @@ pub use datagram::{UdpDispatcher, UdpSession};
 /// Timeout for the initial authentication with the server [default: 10 seconds]
 const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Optional features this client advertises to the server.
+#[cfg(feature = "datagram")]
+const CLIENT_FEATURES: Features = Features::from_bits(
+    Features::DATAGRAM.bits() | Features::DNS_QUERY.bits() | Features::ECHO.bits(),
+);
+#[cfg(not(feature = "datagram"))]
+const CLIENT_FEATURES: Features =
+    Features::from_bits(Features::DNS_QUERY.bits() | Features::ECHO.bits());
+
 // --- Reconnection Strategy ---
 /// Initial backoff duration for reconnection attempts [default: 1 second]
 const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
@@ -63,6 +76,17 @@ fn next_backoff(current: Duration) -> Duration {
     Duration::from_secs_f64(capped)
 }
 
+/// What the client authenticates with.
+#[derive(Debug, Clone)]
+pub enum Credential {
+    /// The shared secret, proven as the auth mode says.
+    Secret(Secret, AuthMode),
+    /// A key listed in the server's authorized keys.
+    Key(Arc<PrivateKey>),
+    /// An access token minted by the server's operator.
+    Token(String),
+}
+
 struct ReconnectState {
     last_attempt: Option<Instant>,
     backoff: Duration,
@@ -88,9 +112,10 @@ where
 {
     transport: T,
     connection: ArcSwap<C>,
+    capabilities: ArcSwap<ServerCapabilities>,
     connection_id: AtomicU64,
     reconnect_lock: Mutex<ReconnectState>,
-    secret: Secret,
+    credential: Credential,
     options: Bytes,
     metrics: Metrics,
 }
@@ -104,25 +129,47 @@ where
     ///
     /// This involves performing authentication with the server.
     pub async fn new(transport: T, secret: Secret, options: Option<Bytes>) -> io::Result<Self> {
+        Self::with_auth_mode(transport, secret, options, AuthMode::default()).await
+    }
+
+    /// Creates a new `ClientConnection` proving knowledge of the secret as
+    /// `auth_mode` says.
+    pub async fn with_auth_mode(
+        transport: T,
+        secret: Secret,
+        options: Option<Bytes>,
+        auth_mode: AuthMode,
+    ) -> io::Result<Self> {
+        Self::with_credential(transport, Credential::Secret(secret, auth_mode), options).await
+    }
+
+    /// Creates a new `ClientConnection` authenticating with `credential`.
+    pub async fn with_credential(
+        transport: T,
+        credential: Credential,
+        options: Option<Bytes>,
+    ) -> io::Result<Self> {
         let options = options.unwrap_or_default();
-        let connection = match authenticate(&transport, secret, options.clone()).await {
-            Ok(conn) => conn,
-            Err(err) => {
-                error!(
-                    error = %err,
-                    error_kind = ?err.kind(),
-                    "failed to initialize connection"
-                );
-                return Err(err);
-            }
-        };
+        let (connection, capabilities) =
+            match authenticate(&transport, &credential, options.clone()).await {
+                Ok(authenticated) => authenticated,
+                Err(err) => {
+                    error!(
+                        error = %err,
+                        error_kind = ?err.kind(),
+                        "failed to initialize connection"
+                    );
+                    return Err(err);
+                }
+            };
 
         Ok(Self {
             transport,
             connection: ArcSwap::new(Arc::new(connection)),
+            capabilities: ArcSwap::new(Arc::new(capabilities)),
             connection_id: AtomicU64::new(0),
             reconnect_lock: Mutex::new(ReconnectState::default()),
-            secret,
+            credential,
             options,
             metrics: Metrics::new(),
         })
@@ -133,6 +180,24 @@ where
         self.metrics.clone()
     }
 
+    /// Returns the protocol version and features agreed with the server for
+    /// the current connection.
+    pub fn capabilities(&self) -> ServerCapabilities {
+        **self.capabilities.load()
+    }
+
+    /// Fails with `Unsupported` unless the server agreed to `feature`.
+    fn require(&self, feature: Features, name: &str) -> io::Result<()> {
+        if self.capabilities().features.contains(feature) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("server does not support {name}"),
+            ))
+        }
+    }
+
     /// Opens a new bidirectional stream for TCP-like communication.
     ///
     /// This method negotiates a new stream with the server, which will then
@@ -180,7 +245,6 @@ where
         let message: ServerMessage = protocol::decode(&payload)?;
         let response = match message {
             ServerMessage::ConnectResponse(response) => response,
-            #[allow(unreachable_patterns)]
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
@@ -230,15 +294,97 @@ where
                     .counters()
                     .streams_failed
                     .fetch_add(1, Ordering::Relaxed);
-                let error_kind = match kind {
-                    ConnectErrorKind::ConnectionRefused => io::ErrorKind::ConnectionRefused,
-                    ConnectErrorKind::NetworkUnreachable => io::ErrorKind::NetworkUnreachable,
-                    ConnectErrorKind::HostUnreachable => io::ErrorKind::HostUnreachable,
-                    ConnectErrorKind::TimedOut => io::ErrorKind::TimedOut,
-                    ConnectErrorKind::Other => io::ErrorKind::Other,
-                };
-                Err(io::Error::new(error_kind, message))
+                Err(io::Error::new(kind.to_io_error_kind(), message))
+            }
+        }
+    }
+
+    /// Resolves a DNS query through the server's resolver.
+    ///
+    /// The query is sent in wire format on a new stream and the server's
+    /// wire-format response is returned unchanged; resolution failures are
+    /// reported in its response code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream cannot be opened or the server closes
+    /// it without answering (e.g. because the query is malformed).
+    pub async fn dns_query(&self, message: Bytes) -> io::Result<Bytes> {
+        self.require(Features::DNS_QUERY, "dns queries")?;
+        let mut stream = self
+            .with_retry(|conn| async move { conn.open_bidirectional().await })
+            .await?;
+        let mut framed = Framed::new(&mut stream, length_codec());
+
+        let query_message = ClientMessage::DnsQuery(ClientDnsQuery { message });
+        framed.send(protocol::encode(&query_message)?).await?;
+
+        let payload = match framed.next().await {
+            Some(Ok(payload)) => payload,
+            Some(Err(e)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to read dns response: {}", e),
+                ));
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream closed before receiving dns response",
+                ));
             }
+        };
+
+        match protocol::decode(&payload)? {
+            ServerMessage::DnsResponse(response) => Ok(response.message),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected dns response message",
+            )),
+        }
+    }
+
+    /// Sends an ICMP echo to `address` from the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream cannot be opened, or the error the
+    /// server reported if the destination did not answer.
+    pub async fn echo(&self, address: Address, data: Bytes) -> io::Result<()> {
+        self.require(Features::ECHO, "icmp echo")?;
+        let mut stream = self
+            .with_retry(|conn| async move { conn.open_bidirectional().await })
+            .await?;
+        let mut framed = Framed::new(&mut stream, length_codec());
+
+        let echo_message = ClientMessage::Echo(ClientEcho { address, data });
+        framed.send(protocol::encode(&echo_message)?).await?;
+
+        let payload = match framed.next().await {
+            Some(Ok(payload)) => payload,
+            Some(Err(e)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to read echo response: {}", e),
+                ));
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream closed before receiving echo response",
+                ));
+            }
+        };
+
+        match protocol::decode(&payload)? {
+            ServerMessage::EchoResponse(ServerEchoResponse::Ok) => Ok(()),
+            ServerMessage::EchoResponse(ServerEchoResponse::Err { kind, message }) => {
+                Err(io::Error::new(kind.to_io_error_kind(), message))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected echo response message",
+            )),
         }
     }
 
@@ -341,11 +487,12 @@ where
             return Err(e);
         }
 
-        match authenticate(&self.transport, self.secret, self.options.clone()).await {
-            Ok(new_connection) => {
+        match authenticate(&self.transport, &self.credential, self.options.clone()).await {
+            Ok((new_connection, capabilities)) => {
                 state.backoff = INITIAL_RECONNECT_BACKOFF;
                 state.last_attempt = None;
 
+                self.capabilities.store(Arc::new(capabilities));
                 self.connection.store(Arc::new(new_connection));
                 self.connection_id.fetch_add(1, Ordering::Release);
                 self.metrics
@@ -369,19 +516,73 @@ where
 }
 
 /// Performs the initial authentication with the server.
-async fn authenticate<T, C>(transport: &T, secret: Secret, options: Bytes) -> io::Result<C>
+///
+/// The client advertises its capabilities and returns what the server agreed
+/// to. Servers predating capability negotiation answer with a plain `Ok`,
+/// which stands for [`ServerCapabilities::LEGACY`].
+///
+/// With [`AuthMode::Challenge`] the secret never leaves the client: the hello
+/// carries a zeroed one, and the server's nonce is answered with a MAC bound
+/// to this connection's TLS session. A [`Credential::Key`] answers it with a
+/// signature over the same data instead, and a [`Credential::Token`] with
+/// the token.
+async fn authenticate<T, C>(
+    transport: &T,
+    credential: &Credential,
+    options: Bytes,
+) -> io::Result<(C, ServerCapabilities)>
 where
     T: Initiator<Connection = C>,
     C: Connection,
 {
     let do_auth = async {
         let connection = transport.connect().await?;
-        let mut stream = connection.open_bidirectional().await?;
 
+        let (auth_feature, secret) = match credential {
+            Credential::Secret(secret, AuthMode::Secret) => (None, *secret),
+            Credential::Secret(_, AuthMode::Challenge) => {
+                (Some(Features::CHALLENGE_AUTH), [0u8; 32])
+            }
+            Credential::Key(_) => (Some(Features::PUBLIC_KEY_AUTH), [0u8; 32]),
+            Credential::Token(_) => (Some(Features::TOKEN_AUTH), [0u8; 32]),
+        };
+        let method = match credential {
+            Credential::Secret(..) => "challenge-response",
+            Credential::Key(_) => "public-key",
+            Credential::Token(_) => "token",
+        };
+
+        // The challenge is bound to the TLS session, so a transport that
+        // can't export keying material is given up on before anything is sent
+        let mut keying_material = [0u8; 32];
+        if auth_feature.is_some()
+            && let Err(err) = connection.export_keying_material(
+                &mut keying_material,
+                protocol::CHALLENGE_EXPORTER_LABEL,
+                &[],
+            )
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("transport does not support {method} authentication: {err}"),
+            ));
+        }
+
+        let mut stream = connection.open_bidirectional().await?;
+        let capabilities = ClientCapabilities {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            features: match auth_feature {
+                Some(feature) => CLIENT_FEATURES | feature,
+                None => CLIENT_FEATURES,
+            },
+            auth: options,
+        };
         let hello_message = ClientMessage::Hello(ClientHello {
-            version: PROTOCOL_VERSION,
+            // Servers predating negotiation only accept the version they speak
+            version: MIN_PROTOCOL_VERSION,
             secret,
-            options,
+            options: capabilities.to_options()?,
         });
 
         let encoded_bytes = protocol::encode(&hello_message)?;
@@ -389,23 +590,47 @@ where
 
         framed.send(encoded_bytes).await?;
 
-        match framed.next().await {
-            Some(Ok(payload)) => {
-                let response: ServerAuthResponse = protocol::decode(&payload)?;
-                match response {
-                    ServerAuthResponse::Ok => {
-                        stream.shutdown().await?;
-                        Ok(connection)
-                    }
-                    ServerAuthResponse::Err => Err(io::Error::other("authentication failed")),
+        let mut response = read_auth_response(&mut framed).await?;
+        if auth_feature.is_some() {
+            let challenge = match response {
+                ServerAuthResponse::Challenge(challenge) => challenge,
+                ServerAuthResponse::Rejected(rejection) => return Err(rejection.into()),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("server does not support {method} authentication"),
+                    ));
                 }
-            }
-            Some(Err(e)) => Err(e),
-            None => Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "connection closed by server during authentication",
-            )),
+            };
+            let proof = match credential {
+                Credential::Secret(secret, _) => ClientMessage::AuthProof(ClientAuthProof {
+                    mac: protocol::challenge_mac(secret, &challenge.nonce, &keying_material),
+                }),
+                Credential::Key(key) => {
+                    let message = protocol::key_proof_message(&challenge.nonce, &keying_material);
+                    ClientMessage::KeyProof(ClientKeyProof {
+                        public_key: key.public_key().to_bytes(),
+                        signature: key.sign(&message).into(),
+                    })
+                }
+                Credential::Token(token) => ClientMessage::Token(ClientToken {
+                    token: Bytes::copy_from_slice(token.as_bytes()),
+                }),
+            };
+            framed.send(protocol::encode(&proof)?).await?;
+            response = read_auth_response(&mut framed).await?;
         }
+
+        let capabilities = match response {
+            ServerAuthResponse::Accepted(capabilities) => capabilities,
+            ServerAuthResponse::Ok => ServerCapabilities::LEGACY,
+            ServerAuthResponse::Rejected(rejection) => return Err(rejection.into()),
+            ServerAuthResponse::Err | ServerAuthResponse::Challenge(_) => {
+                return Err(io::Error::other("authentication failed"));
+            }
+        };
+        stream.shutdown().await?;
+        Ok((connection, capabilities))
     };
 
     match tokio::time::timeout(AUTH_TIMEOUT, do_auth).await {
@@ -420,6 +645,23 @@ where
     }
 }
 
+/// Reads the server's next answer during authentication.
+async fn read_auth_response<S>(
+    framed: &mut Framed<S, tokio_util::codec::LengthDelimitedCodec>,
+) -> io::Result<ServerAuthResponse>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    match framed.next().await {
+        Some(Ok(payload)) => protocol::decode(&payload),
+        Some(Err(e)) => Err(e),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by server during authentication",
+        )),
+    }
+}
+
 // --- Error Handling ---
 /// Error context for connection-related operations
 struct ErrorContext {
@@ -0,0 +1,609 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam_queue::SegQueue;
+use hickory_proto::op::{Message, MessageType, Query, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use ipnet::{Ipv4Net, Ipv6Net};
+use moka::future::Cache;
+use moka::notification::ListenerFuture;
+use serde::{Deserialize, Serialize};
+
+use ombrac::protocol::Address;
+use ombrac_macros::{debug, info, warn};
+
+const DNS_RESPONSE_TTL: u32 = 5;
+const CACHE_TTL: Duration = Duration::from_secs(DNS_RESPONSE_TTL as u64 + (7 * 24 * 60 * 60));
+
+/// Upper bound for the number of addresses handed out from an IPv6 range,
+/// which is usually far larger than the mapping table should grow.
+const MAX_IPV6_HOSTS: u32 = 1 << 20;
+
+/// First allocation index; the lowest addresses of a range are left for the gateway.
+const FIRST_HOST_INDEX: u32 = 2;
+
+/// How often mappings are written to the state file [default: 60 seconds]
+const STATE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An address family the fake pool can allocate from.
+trait PoolAddr: Copy + Eq + Hash + fmt::Display + Send + Sync + 'static {
+    /// Returns the `index`-th address after `network`.
+    fn host(network: Self, index: u32) -> Self;
+
+    /// Returns the index of `addr` relative to `network`, the inverse of [`PoolAddr::host`].
+    fn index(network: Self, addr: Self) -> Option<u32>;
+}
+
+impl PoolAddr for Ipv4Addr {
+    fn host(network: Self, index: u32) -> Self {
+        Ipv4Addr::from(u32::from(network) + 1 + index)
+    }
+
+    fn index(network: Self, addr: Self) -> Option<u32> {
+        u32::from(addr)
+            .checked_sub(u32::from(network))?
+            .checked_sub(1)
+    }
+}
+
+impl PoolAddr for Ipv6Addr {
+    fn host(network: Self, index: u32) -> Self {
+        Ipv6Addr::from(u128::from(network) + 1 + index as u128)
+    }
+
+    fn index(network: Self, addr: Self) -> Option<u32> {
+        let offset = u128::from(addr).checked_sub(u128::from(network))?;
+        u32::try_from(offset.checked_sub(1)?).ok()
+    }
+}
+
+/// On-disk snapshot of a [`Pool`].
+#[derive(Serialize, Deserialize)]
+struct PoolState<A> {
+    cursor: u32,
+    mappings: Vec<(String, A)>,
+}
+
+/// On-disk snapshot of a [`FakeDns`], written to its state file.
+#[derive(Serialize, Deserialize)]
+struct State {
+    /// Unix time the snapshot was taken, in seconds.
+    saved_at: u64,
+    v4: PoolState<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    v6: Option<PoolState<Ipv6Addr>>,
+}
+
+/// Domain to fake address mappings for a single address family.
+#[derive(Clone)]
+struct Pool<A: PoolAddr> {
+    network: A,
+    domain_to_ip: Cache<Name, A>,
+    ip_to_domain: Cache<A, Name>,
+    recycled_ips: Arc<SegQueue<A>>,
+    cursor: Arc<AtomicU32>,
+    max_hosts: u32,
+}
+
+impl<A: PoolAddr> Pool<A> {
+    fn new(network: A, max_hosts: u32) -> Self {
+        let recycled_ips = Arc::new(SegQueue::new());
+        let cursor = Arc::new(AtomicU32::new(FIRST_HOST_INDEX));
+
+        let domain_to_ip: Cache<Name, A> = Cache::builder()
+            .max_capacity(max_hosts as u64)
+            .time_to_idle(CACHE_TTL)
+            .build();
+
+        let recycled_ips_ref = recycled_ips.clone();
+        let domain_cache_ref = domain_to_ip.clone();
+
+        let listener = move |k: Arc<A>, v: Name, cause| -> ListenerFuture {
+            let q = recycled_ips_ref.clone();
+            let d_cache = domain_cache_ref.clone();
+            Box::pin(async move {
+                d_cache.invalidate(&v).await;
+                q.push(*k);
+                debug!(ip = %k, domain = %v, reason = ?cause, "fakedns ip recycled");
+            })
+        };
+
+        let ip_to_domain = Cache::builder()
+            .max_capacity(max_hosts as u64)
+            .time_to_idle(CACHE_TTL)
+            .async_eviction_listener(listener)
+            .build();
+
+        Self {
+            network,
+            domain_to_ip,
+            ip_to_domain,
+            recycled_ips,
+            cursor,
+            max_hosts,
+        }
+    }
+
+    fn allocate_unique_ip(&self) -> Option<A> {
+        if let Some(ip) = self.recycled_ips.pop() {
+            return Some(ip);
+        }
+
+        loop {
+            let current = self.cursor.load(Ordering::Relaxed);
+            if current >= self.max_hosts {
+                break;
+            }
+
+            if self
+                .cursor
+                .compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(A::host(self.network, current));
+            }
+        }
+
+        warn!("fakedns ip pool is exhausted! no recycled or new ips available");
+        None
+    }
+
+    async fn lookup_or_allocate(&self, domain: &Name) -> Option<A> {
+        if let Some(ip) = self.domain_to_ip.get(domain).await {
+            return Some(ip);
+        }
+
+        let new_ip = self.allocate_unique_ip()?;
+        self.ip_to_domain.insert(new_ip, domain.clone()).await;
+        self.domain_to_ip.insert(domain.clone(), new_ip).await;
+        debug!(domain = %domain, fake_ip = %new_ip, "fakedns mapped new domain");
+        Some(new_ip)
+    }
+
+    async fn domain(&self, ip: &A) -> Option<Name> {
+        self.ip_to_domain.get(ip).await
+    }
+
+    fn snapshot(&self) -> PoolState<A> {
+        PoolState {
+            cursor: self.cursor.load(Ordering::SeqCst),
+            mappings: self
+                .ip_to_domain
+                .iter()
+                .map(|(ip, domain)| (domain.to_ascii(), *ip))
+                .collect(),
+        }
+    }
+
+    /// Restores mappings from `state`.
+    ///
+    /// Mappings that fall outside the range are dropped, and addresses below
+    /// the restored cursor that are no longer mapped become recyclable.
+    async fn restore(&self, state: PoolState<A>) {
+        let mut cursor = state.cursor.max(FIRST_HOST_INDEX);
+        let mut mapped = HashSet::new();
+
+        for (domain, ip) in state.mappings {
+            let Some(index) = A::index(self.network, ip)
+                .filter(|index| (FIRST_HOST_INDEX..self.max_hosts).contains(index))
+            else {
+                continue;
+            };
+            let Ok(domain) = Name::from_ascii(&domain) else {
+                continue;
+            };
+            if self.domain_to_ip.contains_key(&domain) || !mapped.insert(index) {
+                continue;
+            }
+
+            self.ip_to_domain.insert(ip, domain.clone()).await;
+            self.domain_to_ip.insert(domain, ip).await;
+            cursor = cursor.max(index + 1);
+        }
+
+        let cursor = cursor.min(self.max_hosts);
+        for index in FIRST_HOST_INDEX..cursor {
+            if !mapped.contains(&index) {
+                self.recycled_ips.push(A::host(self.network, index));
+            }
+        }
+        self.cursor.store(cursor, Ordering::SeqCst);
+    }
+}
+
+/// Hands out fake addresses for domain names and maps them back.
+///
+/// A queries are answered from an IPv4 range. AAAA queries are answered from
+/// an IPv6 range if one is configured with [`FakeDns::with_ipv6`].
+///
+/// Mappings live in memory and are lost on restart unless a state file is
+/// set with [`FakeDns::with_state_file`].
+#[derive(Clone)]
+pub struct FakeDns {
+    ip_net: Ipv4Net,
+    ipv6_net: Option<Ipv6Net>,
+    v4: Pool<Ipv4Addr>,
+    v6: Option<Pool<Ipv6Addr>>,
+    state_file: Option<PathBuf>,
+}
+
+impl FakeDns {
+    pub fn new(ip_net: Ipv4Net) -> Self {
+        let max_hosts = ip_net.hosts().count() as u32;
+
+        Self {
+            ip_net,
+            ipv6_net: None,
+            v4: Pool::new(ip_net.network(), max_hosts),
+            v6: None,
+            state_file: None,
+        }
+    }
+
+    /// Answers AAAA queries with fake addresses from `ipv6_net`.
+    pub fn with_ipv6(mut self, ipv6_net: Ipv6Net) -> Self {
+        let host_bits = 128 - u32::from(ipv6_net.prefix_len());
+        let max_hosts = match 1u128.checked_shl(host_bits) {
+            Some(size) => size.saturating_sub(2).min(MAX_IPV6_HOSTS as u128) as u32,
+            None => MAX_IPV6_HOSTS,
+        };
+
+        self.ipv6_net = Some(ipv6_net);
+        self.v6 = Some(Pool::new(ipv6_net.network(), max_hosts));
+        self
+    }
+
+    /// Keeps mappings in `path` so they survive restarts.
+    ///
+    /// The file is read by [`FakeDns::load_state`] and written by
+    /// [`FakeDns::persist`].
+    pub fn with_state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// Restores mappings from the state file, if one is set and exists.
+    ///
+    /// Should be called before the first query is answered. A snapshot older
+    /// than the mapping idle timeout is discarded, as all of its mappings
+    /// would have expired had the client kept running.
+    pub async fn load_state(&self) -> io::Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let state: State = serde_json::from_slice(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if unix_time().saturating_sub(state.saved_at) > CACHE_TTL.as_secs() {
+            info!(path = %path.display(), "fakedns state file is stale, ignoring");
+            return Ok(());
+        }
+
+        self.v4.restore(state.v4).await;
+        if let (Some(pool), Some(state)) = (&self.v6, state.v6) {
+            pool.restore(state).await;
+        }
+
+        info!(
+            path = %path.display(),
+            mappings = self.v4.ip_to_domain.entry_count()
+                + self.v6.as_ref().map_or(0, |v6| v6.ip_to_domain.entry_count()),
+            "fakedns state restored"
+        );
+        Ok(())
+    }
+
+    /// Writes the current mappings to the state file, if one is set.
+    pub async fn save_state(&self) -> io::Result<()> {
+        let Some(path) = self.state_file.clone() else {
+            return Ok(());
+        };
+
+        let state = State {
+            saved_at: unix_time(),
+            v4: self.v4.snapshot(),
+            v6: self.v6.as_ref().map(Pool::snapshot),
+        };
+        let data = serde_json::to_vec(&state).map_err(io::Error::other)?;
+
+        tokio::task::spawn_blocking(move || write_atomic(&path, &data))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// Flushes the state file periodically and once more when `shutdown`
+    /// resolves. Returns immediately if no state file is set.
+    pub async fn persist(&self, shutdown: impl Future<Output = ()>) {
+        if self.state_file.is_none() {
+            return;
+        }
+
+        tokio::pin!(shutdown);
+        let mut interval = tokio::time::interval(STATE_FLUSH_INTERVAL);
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = interval.tick() => {
+                    if let Err(_err) = self.save_state().await {
+                        warn!("failed to save fakedns state: {_err}");
+                    }
+                }
+            }
+        }
+
+        if let Err(_err) = self.save_state().await {
+            warn!("failed to save fakedns state: {_err}");
+        }
+    }
+
+    /// Returns whether `question` is answered with a fake address.
+    ///
+    /// Other questions should be resolved for real, e.g. through the server.
+    pub fn handles(&self, question: &Query) -> bool {
+        if question.query_class() != DNSClass::IN {
+            return false;
+        }
+
+        match question.query_type() {
+            RecordType::A => true,
+            RecordType::AAAA => self.v6.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether `ip` falls inside one of the fake ranges.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.ip_net.contains(ip),
+            IpAddr::V6(ip) => self.ipv6_net.is_some_and(|net| net.contains(ip)),
+        }
+    }
+
+    /// Answers a query with a fake address from the pool.
+    ///
+    /// Questions that [`FakeDns::handles`] rejects are answered `Refused`.
+    pub async fn handle_dns_query(&self, query: &Message) -> Option<Message> {
+        let question = query.queries.first()?;
+        let domain_name = question.name();
+
+        if !self.handles(question) {
+            let mut response = query.clone();
+            response.metadata.message_type = MessageType::Response;
+            response.metadata.response_code = ResponseCode::Refused;
+            return Some(response);
+        }
+
+        let rdata = match (question.query_type(), &self.v6) {
+            (RecordType::AAAA, Some(v6)) => {
+                RData::AAAA(AAAA(v6.lookup_or_allocate(domain_name).await?))
+            }
+            _ => RData::A(A(self.v4.lookup_or_allocate(domain_name).await?)),
+        };
+
+        let mut response = query.clone();
+        response.metadata.message_type = MessageType::Response;
+        response.metadata.response_code = ResponseCode::NoError;
+        response.add_answer(Record::from_rdata(
+            domain_name.clone(),
+            DNS_RESPONSE_TTL,
+            rdata,
+        ));
+
+        Some(response)
+    }
+
+    pub async fn get_domain_by_ip(&self, ip: &IpAddr) -> Option<Name> {
+        match ip {
+            IpAddr::V4(ip) => self.v4.domain(ip).await,
+            IpAddr::V6(ip) => self.v6.as_ref()?.domain(ip).await,
+        }
+    }
+
+    /// Maps a destination using a fake address back to its domain name.
+    ///
+    /// Addresses outside the fake ranges are returned unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address is in a fake range but no longer
+    /// mapped, e.g. because the client cached the answer longer than the
+    /// mapping.
+    pub async fn restore(&self, address: Address) -> io::Result<Address> {
+        let addr = match &address {
+            Address::SocketV4(addr) => std::net::SocketAddr::V4(*addr),
+            Address::SocketV6(addr) => std::net::SocketAddr::V6(*addr),
+            Address::Domain(..) => return Ok(address),
+        };
+        if let Some(domain) = self.get_domain_by_ip(&addr.ip()).await {
+            return Ok(Address::from((domain.to_utf8(), addr.port())));
+        }
+        if self.contains(&addr.ip()) {
+            return Err(io::Error::other(format!("dns cache miss: {}", addr)));
+        }
+        Ok(address)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Replaces `path` with `data` without leaving a partially written file behind.
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddrV4, SocketAddrV6};
+
+    use hickory_proto::op::OpCode;
+
+    use super::*;
+
+    fn fake_dns() -> FakeDns {
+        FakeDns::new("198.18.0.0/16".parse().unwrap())
+            .with_ipv6("fdfe:dcba:9876::/64".parse().unwrap())
+    }
+
+    fn query(name: &str, record_type: RecordType) -> Message {
+        let mut message = Message::new(7, MessageType::Query, OpCode::Query);
+        message.add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        message
+    }
+
+    #[tokio::test]
+    async fn aaaa_answered_from_ipv6_range_and_restored() {
+        let fake_dns = fake_dns();
+        let response = fake_dns
+            .handle_dns_query(&query("example.com.", RecordType::AAAA))
+            .await
+            .unwrap();
+
+        let RData::AAAA(AAAA(ip)) = &response.answers[0].data else {
+            panic!("expected an AAAA answer");
+        };
+        assert!(fake_dns.contains(&IpAddr::V6(*ip)));
+
+        let restored = fake_dns
+            .restore(Address::SocketV6(SocketAddrV6::new(*ip, 443, 0, 0)))
+            .await
+            .unwrap();
+        assert_eq!(restored, Address::from(("example.com.".to_string(), 443)));
+    }
+
+    #[tokio::test]
+    async fn same_domain_keeps_its_address_per_family() {
+        let fake_dns = fake_dns();
+        let answer = |name: &'static str, record_type| {
+            let fake_dns = &fake_dns;
+            async move {
+                let response = fake_dns
+                    .handle_dns_query(&query(name, record_type))
+                    .await
+                    .unwrap();
+                response.answers[0].data.clone()
+            }
+        };
+
+        let first_v4 = answer("example.com.", RecordType::A).await;
+        let first_v6 = answer("example.com.", RecordType::AAAA).await;
+        // Allocating from one pool doesn't move the other
+        answer("other.example.", RecordType::AAAA).await;
+        let second_v4 = answer("example.com.", RecordType::A).await;
+        answer("other.example.", RecordType::A).await;
+        let second_v6 = answer("example.com.", RecordType::AAAA).await;
+
+        assert!(matches!(first_v4, RData::A(_)));
+        assert!(matches!(first_v6, RData::AAAA(_)));
+        assert_eq!(first_v4, second_v4);
+        assert_eq!(first_v6, second_v6);
+    }
+
+    #[test]
+    fn aaaa_is_not_handled_without_ipv6_range() {
+        let fake_dns = FakeDns::new("198.18.0.0/16".parse().unwrap());
+        let name = Name::from_ascii("example.com.").unwrap();
+
+        assert!(fake_dns.handles(&Query::query(name.clone(), RecordType::A)));
+        assert!(!fake_dns.handles(&Query::query(name, RecordType::AAAA)));
+    }
+
+    #[tokio::test]
+    async fn unmapped_address_in_range_is_a_cache_miss() {
+        let fake_dns = fake_dns();
+        let inside = Address::SocketV4(SocketAddrV4::new(Ipv4Addr::new(198, 18, 3, 4), 80));
+        let outside = Address::SocketV4(SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 80));
+
+        assert!(fake_dns.restore(inside).await.is_err());
+        assert_eq!(fake_dns.restore(outside.clone()).await.unwrap(), outside);
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ombrac-fakedns-{name}-{}.json", std::process::id()))
+    }
+
+    async fn fake_ip(fake_dns: &FakeDns, name: &str) -> Ipv4Addr {
+        let response = fake_dns
+            .handle_dns_query(&query(name, RecordType::A))
+            .await
+            .unwrap();
+        match &response.answers[0].data {
+            RData::A(A(ip)) => *ip,
+            _ => panic!("expected an A answer"),
+        }
+    }
+
+    #[tokio::test]
+    async fn state_file_restores_mappings_and_cursor() {
+        let path = state_path("restore");
+        let before = fake_dns().with_state_file(&path);
+        let first = fake_ip(&before, "one.example.").await;
+        let second = fake_ip(&before, "two.example.").await;
+        before.save_state().await.unwrap();
+
+        let after = fake_dns().with_state_file(&path);
+        after.load_state().await.unwrap();
+
+        assert_eq!(fake_ip(&after, "one.example.").await, first);
+        assert_eq!(
+            after
+                .get_domain_by_ip(&IpAddr::V4(second))
+                .await
+                .unwrap()
+                .to_ascii(),
+            "two.example."
+        );
+        let third = fake_ip(&after, "three.example.").await;
+        assert!(third != first && third != second);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn stale_state_file_is_ignored() {
+        let path = state_path("stale");
+        let state = State {
+            saved_at: unix_time() - CACHE_TTL.as_secs() - 1,
+            v4: PoolState {
+                cursor: 3,
+                mappings: vec![("old.example.".to_string(), Ipv4Addr::new(198, 18, 0, 3))],
+            },
+            v6: None,
+        };
+        std::fs::write(&path, serde_json::to_vec(&state).unwrap()).unwrap();
+
+        let fake_dns = fake_dns().with_state_file(&path);
+        fake_dns.load_state().await.unwrap();
+
+        assert!(
+            fake_dns
+                .get_domain_by_ip(&IpAddr::V4(Ipv4Addr::new(198, 18, 0, 3)))
+                .await
+                .is_none()
+        );
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,401 @@
+mod fake;
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use hickory_proto::op::{Message, OpCode, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, RecordType};
+use moka::Expiry;
+use moka::future::Cache;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use ombrac::protocol::Address;
+use ombrac_macros::debug;
+use ombrac_transport::{Connection, Initiator};
+
+use crate::client::Client;
+
+pub use fake::FakeDns;
+
+/// Timeout for a single query forwarded to the server [default: 5 seconds]
+const DNS_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of cached responses [default: 4096]
+pub(crate) const DEFAULT_CACHE_CAPACITY: u64 = 4096;
+
+/// Upper bound for how long a response is cached, in seconds [default: 1 hour]
+const MAX_CACHE_TTL: u32 = 60 * 60;
+
+/// How long a negative response without an SOA record is cached, in seconds [default: 60]
+const NEGATIVE_CACHE_TTL: u32 = 60;
+
+/// Resolves DNS queries through the server, caching the responses.
+///
+/// Queries are answered by the server's resolver, so names resolve as seen
+/// from the exit's network. Positive answers are cached for the smallest TTL
+/// in the answer section and negative answers for the SOA negative TTL (RFC
+/// 2308), both capped at one hour. Cached answers are served with their TTLs
+/// reduced by the time spent in the cache.
+///
+/// With [`RemoteResolver::with_upstream`], queries are instead sent over
+/// DNS-over-TCP to a fixed upstream server, reached through a tunnel stream.
+pub struct RemoteResolver<T, C>
+where
+    T: Initiator<Connection = C>,
+    C: Connection,
+{
+    client: Arc<Client<T, C>>,
+    cache: Cache<CacheKey, CachedResponse>,
+    upstream: Option<Address>,
+}
+
+impl<T, C> RemoteResolver<T, C>
+where
+    T: Initiator<Connection = C>,
+    C: Connection,
+{
+    /// Creates a resolver with the default cache capacity.
+    pub fn new(client: Arc<Client<T, C>>) -> Self {
+        Self::with_capacity(client, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Creates a resolver caching at most `capacity` responses.
+    pub fn with_capacity(client: Arc<Client<T, C>>, capacity: u64) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(capacity)
+            .expire_after(ResponseExpiry)
+            .build();
+        Self {
+            client,
+            cache,
+            upstream: None,
+        }
+    }
+
+    /// Sends queries to `upstream` through the tunnel instead of to the
+    /// server's resolver.
+    pub fn with_upstream(mut self, upstream: Address) -> Self {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    /// Resolves `query`, answering from the cache when possible.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query cannot be forwarded, the server does not
+    /// answer within the timeout, or the response is malformed.
+    pub async fn resolve(&self, query: &Message) -> io::Result<Message> {
+        let key = CacheKey::from_query(query);
+        if let Some(key) = &key
+            && let Some(cached) = self.cache.get(key).await
+        {
+            return Ok(cached.response_for(query));
+        }
+
+        let request = query.to_vec().map_err(io::Error::other)?;
+        let response = tokio::time::timeout(DNS_QUERY_TIMEOUT, self.forward(request.into()))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "dns query timed out"))??;
+        let response = Message::from_vec(&response).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed dns response: {e}"),
+            )
+        })?;
+
+        if let Some(key) = key
+            && let Some(ttl) = cacheable_ttl(&response)
+        {
+            let cached = CachedResponse {
+                message: Arc::new(response.clone()),
+                ttl,
+                cached_at: Instant::now(),
+            };
+            self.cache.insert(key, cached).await;
+        }
+
+        Ok(response)
+    }
+
+    /// Sends a wire-format query to the configured upstream.
+    async fn forward(&self, request: Bytes) -> io::Result<Bytes> {
+        let Some(upstream) = &self.upstream else {
+            return self.client.dns_query(request).await;
+        };
+
+        // DNS over TCP (RFC 1035 section 4.2.2): each message is prefixed
+        // with its length as a 16-bit big-endian integer.
+        let length = u16::try_from(request.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "dns query too large"))?;
+        let mut stream = self.client.open_bidirectional(upstream.clone()).await?;
+        stream.write_all(&length.to_be_bytes()).await?;
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let length = stream.read_u16().await?;
+        let mut response = vec![0u8; length as usize];
+        stream.read_exact(&mut response).await?;
+        let _ = stream.shutdown().await;
+        Ok(response.into())
+    }
+}
+
+/// Answers a query from a local DNS listener.
+///
+/// Questions `fake_dns` handles get a fake address; everything else is
+/// resolved through `resolver`. Resolution errors are answered `SERVFAIL` so
+/// the application does not wait for its own timeout. Returns `None` for
+/// queries without a question.
+pub(crate) async fn handle_query<T, C>(
+    query: &Message,
+    fake_dns: Option<&FakeDns>,
+    resolver: &RemoteResolver<T, C>,
+) -> Option<Message>
+where
+    T: Initiator<Connection = C>,
+    C: Connection,
+{
+    let question = query.queries.first()?;
+    if let Some(fake_dns) = fake_dns
+        && fake_dns.handles(question)
+    {
+        return fake_dns.handle_dns_query(query).await;
+    }
+
+    match resolver.resolve(query).await {
+        Ok(response) => Some(response),
+        Err(_err) => {
+            debug!(
+                domain = %question.name(),
+                query_type = %question.query_type(),
+                error = %_err,
+                "remote dns query failed"
+            );
+            let mut response = query.clone().into_response();
+            response.metadata.response_code = ResponseCode::ServFail;
+            Some(response)
+        }
+    }
+}
+
+/// Serializes a response to be sent over UDP.
+///
+/// Responses larger than the query's advertised UDP payload size (512 bytes
+/// without EDNS) are truncated so the client retries over TCP.
+pub(crate) fn encode_udp_response(query: &Message, response: &Message) -> io::Result<Vec<u8>> {
+    let bytes = response.to_vec().map_err(io::Error::other)?;
+    if bytes.len() <= query.max_payload() as usize {
+        return Ok(bytes);
+    }
+    response.truncate().to_vec().map_err(io::Error::other)
+}
+
+/// Identifies a cached response by its (case-insensitive) question.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    name: Name,
+    query_type: RecordType,
+    query_class: DNSClass,
+}
+
+impl CacheKey {
+    /// Returns the key for a standard query with exactly one question.
+    fn from_query(message: &Message) -> Option<Self> {
+        match message.queries.as_slice() {
+            [question] if message.metadata.op_code == OpCode::Query => Some(Self {
+                name: question.name().to_lowercase(),
+                query_type: question.query_type(),
+                query_class: question.query_class(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CachedResponse {
+    message: Arc<Message>,
+    ttl: u32,
+    cached_at: Instant,
+}
+
+impl CachedResponse {
+    /// Builds the response to `query` from the cached message.
+    ///
+    /// The ID, question and RD flag are taken from `query` and record TTLs
+    /// are reduced by the time the response has spent in the cache.
+    fn response_for(&self, query: &Message) -> Message {
+        let elapsed = self.cached_at.elapsed().as_secs().min(u32::MAX as u64) as u32;
+        let mut response = (*self.message).clone();
+        response.metadata.id = query.metadata.id;
+        response.metadata.recursion_desired = query.metadata.recursion_desired;
+        response.queries = query.queries.clone();
+        for record in response
+            .answers
+            .iter_mut()
+            .chain(response.authorities.iter_mut())
+            .chain(response.additionals.iter_mut())
+        {
+            record.decrement_ttl(elapsed);
+        }
+        response
+    }
+}
+
+/// Expires each cached response after its own TTL.
+struct ResponseExpiry;
+
+impl Expiry<CacheKey, CachedResponse> for ResponseExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &CacheKey,
+        value: &CachedResponse,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(Duration::from_secs(value.ttl as u64))
+    }
+}
+
+/// Returns how long `response` may be cached, or `None` if it must not be.
+///
+/// Only complete `NOERROR` and `NXDOMAIN` responses are cached.
+fn cacheable_ttl(response: &Message) -> Option<u32> {
+    if response.metadata.truncation {
+        return None;
+    }
+
+    let ttl = match response.metadata.response_code {
+        ResponseCode::NoError if !response.answers.is_empty() => {
+            response.answers.iter().map(|record| record.ttl).min()?
+        }
+        ResponseCode::NoError | ResponseCode::NXDomain => response
+            .authorities
+            .iter()
+            .find_map(|record| match &record.data {
+                RData::SOA(soa) => Some(record.ttl.min(soa.minimum)),
+                _ => None,
+            })
+            .unwrap_or(NEGATIVE_CACHE_TTL),
+        _ => return None,
+    };
+
+    match ttl.min(MAX_CACHE_TTL) {
+        0 => None,
+        ttl => Some(ttl),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use hickory_proto::op::{MessageType, Query};
+    use hickory_proto::rr::Record;
+    use hickory_proto::rr::rdata::{A, SOA};
+
+    use super::*;
+
+    fn query(id: u16, name: &str, record_type: RecordType) -> Message {
+        let mut message = Message::new(id, MessageType::Query, OpCode::Query);
+        message.add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        message
+    }
+
+    fn answer(ttl: u32) -> Record {
+        Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            ttl,
+            RData::A(A(Ipv4Addr::new(192, 0, 2, 1))),
+        )
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> Record {
+        let name = Name::from_ascii("example.com.").unwrap();
+        let data = SOA::new(name.clone(), name.clone(), 1, 3600, 600, 86400, minimum);
+        Record::from_rdata(name, ttl, RData::SOA(data))
+    }
+
+    #[test]
+    fn cache_key_ignores_name_case() {
+        let lower = CacheKey::from_query(&query(1, "example.com.", RecordType::AAAA));
+        let upper = CacheKey::from_query(&query(2, "EXAMPLE.com.", RecordType::AAAA));
+        assert!(lower.is_some());
+        assert_eq!(lower, upper);
+
+        let other_type = CacheKey::from_query(&query(1, "example.com.", RecordType::MX));
+        assert_ne!(lower, other_type);
+    }
+
+    #[test]
+    fn cache_key_requires_single_question() {
+        let mut message = query(1, "example.com.", RecordType::A);
+        message.add_query(Query::query(
+            Name::from_ascii("example.org.").unwrap(),
+            RecordType::A,
+        ));
+        assert!(CacheKey::from_query(&message).is_none());
+        assert!(
+            CacheKey::from_query(&Message::new(1, MessageType::Query, OpCode::Query)).is_none()
+        );
+    }
+
+    #[test]
+    fn cacheable_ttl_uses_smallest_answer_ttl() {
+        let mut response = query(1, "example.com.", RecordType::A).into_response();
+        response.add_answer(answer(300));
+        response.add_answer(answer(120));
+        assert_eq!(cacheable_ttl(&response), Some(120));
+
+        response.answers = vec![answer(7 * 24 * 60 * 60)];
+        assert_eq!(cacheable_ttl(&response), Some(MAX_CACHE_TTL));
+    }
+
+    #[test]
+    fn cacheable_ttl_negative_answers_use_soa() {
+        let mut response = query(1, "missing.example.com.", RecordType::A).into_response();
+        response.metadata.response_code = ResponseCode::NXDomain;
+        assert_eq!(cacheable_ttl(&response), Some(NEGATIVE_CACHE_TTL));
+
+        response.add_authority(soa(900, 30));
+        assert_eq!(cacheable_ttl(&response), Some(30));
+    }
+
+    #[test]
+    fn cacheable_ttl_skips_failures_and_truncation() {
+        let mut response = query(1, "example.com.", RecordType::A).into_response();
+        response.metadata.response_code = ResponseCode::ServFail;
+        assert_eq!(cacheable_ttl(&response), None);
+
+        response.metadata.response_code = ResponseCode::NoError;
+        response.add_answer(answer(60));
+        response.metadata.truncation = true;
+        assert_eq!(cacheable_ttl(&response), None);
+
+        response.metadata.truncation = false;
+        response.answers[0].ttl = 0;
+        assert_eq!(cacheable_ttl(&response), None);
+    }
+
+    #[test]
+    fn cached_response_takes_id_and_question_from_query() {
+        let mut message = query(1, "example.com.", RecordType::A).into_response();
+        message.add_answer(answer(60));
+        let cached = CachedResponse {
+            message: Arc::new(message),
+            ttl: 60,
+            cached_at: Instant::now() - Duration::from_secs(10),
+        };
+
+        let mut request = query(0xbeef, "Example.COM.", RecordType::A);
+        request.metadata.recursion_desired = true;
+        let response = cached.response_for(&request);
+
+        assert_eq!(response.metadata.id, 0xbeef);
+        assert!(response.metadata.recursion_desired);
+        assert_eq!(response.queries, request.queries);
+        assert!(response.answers[0].ttl <= 50);
+    }
+}
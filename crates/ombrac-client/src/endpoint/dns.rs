@@ -0,0 +1,167 @@
+//! Local DNS endpoint.
+//!
+//! Serves DNS over UDP and TCP on a local address so a machine's resolver
+//! can be pointed at ombrac. Queries are answered from the
+//! [`RemoteResolver`] cache or forwarded through the tunnel, and can
+//! optionally be answered with fake addresses from a shared [`FakeDns`] that
+//! the other endpoints map back to domain names.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hickory_proto::op::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use ombrac_macros::{debug, error, warn};
+use ombrac_transport::quic::Connection as QuicConnection;
+use ombrac_transport::quic::client::Client as QuicClient;
+
+use crate::dns::{self, FakeDns, RemoteResolver};
+
+/// Largest DNS message accepted over UDP (EDNS payloads rarely exceed 4096).
+const MAX_UDP_MESSAGE_SIZE: usize = 4096;
+
+/// How long an idle DNS-over-TCP connection is kept open [default: 10 seconds]
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the rest of a query may take once its length has arrived.
+const TCP_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// DNS server bound to a [`RemoteResolver`].
+pub struct Server {
+    resolver: RemoteResolver<QuicClient, QuicConnection>,
+    fake_dns: Option<Arc<FakeDns>>,
+}
+
+impl Server {
+    pub fn new(resolver: RemoteResolver<QuicClient, QuicConnection>) -> Self {
+        Self {
+            resolver,
+            fake_dns: None,
+        }
+    }
+
+    /// Answers A queries with fake addresses from `fake_dns`.
+    pub fn with_fake_dns(mut self, fake_dns: Arc<FakeDns>) -> Self {
+        self.fake_dns = Some(fake_dns);
+        self
+    }
+
+    /// Serves queries on `udp` and `tcp` until `shutdown` resolves.
+    pub async fn run(
+        self,
+        udp: UdpSocket,
+        tcp: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> io::Result<()> {
+        tokio::pin!(shutdown);
+
+        let server = Arc::new(self);
+        let udp = Arc::new(udp);
+        let mut buf = vec![0u8; MAX_UDP_MESSAGE_SIZE];
+
+        loop {
+            tokio::select! {
+                biased;
+
+                _ = &mut shutdown => return Ok(()),
+
+                result = udp.recv_from(&mut buf) => {
+                    let (len, peer) = match result {
+                        Ok(pair) => pair,
+                        Err(_err) => {
+                            debug!("dns: udp receive error: {_err}");
+                            continue;
+                        }
+                    };
+
+                    let query = buf[..len].to_vec();
+                    let server = server.clone();
+                    let udp = udp.clone();
+                    tokio::spawn(async move {
+                        server.handle_udp(&udp, &query, peer).await;
+                    });
+                }
+
+                result = tcp.accept() => {
+                    let (stream, peer) = match result {
+                        Ok(pair) => pair,
+                        Err(_err) => {
+                            error!("dns: failed to accept connection: {_err}");
+                            continue;
+                        }
+                    };
+
+                    let server = server.clone();
+                    tokio::spawn(async move {
+                        if let Err(_err) = server.handle_tcp(stream).await {
+                            debug!("dns: tcp connection {peer} error: {_err}");
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    async fn answer(&self, query: &Message) -> Option<Message> {
+        dns::handle_query(query, self.fake_dns.as_deref(), &self.resolver).await
+    }
+
+    async fn handle_udp(&self, udp: &UdpSocket, query: &[u8], peer: SocketAddr) {
+        let query = match Message::from_vec(query) {
+            Ok(query) => query,
+            Err(_err) => {
+                warn!("dns: failed to parse query from {peer}: {_err}");
+                return;
+            }
+        };
+
+        let Some(response) = self.answer(&query).await else {
+            return;
+        };
+
+        match dns::encode_udp_response(&query, &response) {
+            Ok(bytes) => {
+                if let Err(_err) = udp.send_to(&bytes, peer).await {
+                    error!("dns: failed to send response to {peer}: {_err}");
+                }
+            }
+            Err(_err) => error!("dns: failed to serialize response: {_err}"),
+        }
+    }
+
+    /// Serves length-prefixed queries (RFC 1035 section 4.2.2) until the
+    /// peer closes the connection or stays idle for too long.
+    async fn handle_tcp(&self, mut stream: TcpStream) -> io::Result<()> {
+        loop {
+            let len = match tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_u16()).await {
+                Ok(Ok(len)) => len,
+                Ok(Err(err)) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Ok(Err(err)) => return Err(err),
+                Err(_) => return Ok(()),
+            };
+
+            let mut query = vec![0u8; len as usize];
+            tokio::time::timeout(TCP_READ_TIMEOUT, stream.read_exact(&mut query))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "query read timed out"))??;
+            let query = Message::from_vec(&query).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("malformed query: {e}"))
+            })?;
+
+            let Some(response) = self.answer(&query).await else {
+                continue;
+            };
+
+            let bytes = response.to_vec().map_err(io::Error::other)?;
+            let len = u16::try_from(bytes.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "response too large"))?;
+            stream.write_all(&len.to_be_bytes()).await?;
+            stream.write_all(&bytes).await?;
+        }
+    }
+}
@@ -16,18 +16,34 @@ use ombrac_transport::quic::client::Client as QuicClient;
 
 use crate::client::Client;
 use crate::connection::BufferedStream;
+#[cfg(feature = "endpoint-dns")]
+use crate::dns::FakeDns;
 
 type HttpResult = Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>;
 type HyperClientBuilder = hyper::client::conn::http1::Builder;
 type HyperServerBuilder = hyper::server::conn::http1::Builder;
 
+#[derive(Clone)]
 pub struct Server {
     client: Arc<Client<QuicClient, QuicConnection>>,
+    #[cfg(feature = "endpoint-dns")]
+    fake_dns: Option<Arc<FakeDns>>,
 }
 
 impl Server {
     pub fn new(client: Arc<Client<QuicClient, QuicConnection>>) -> Self {
-        Self { client }
+        Self {
+            client,
+            #[cfg(feature = "endpoint-dns")]
+            fake_dns: None,
+        }
+    }
+
+    /// Maps fake addresses handed out by `fake_dns` back to domain names.
+    #[cfg(feature = "endpoint-dns")]
+    pub fn with_fake_dns(mut self, fake_dns: Arc<FakeDns>) -> Self {
+        self.fake_dns = Some(fake_dns);
+        self
     }
 
     pub async fn accept_loop(
@@ -52,11 +68,11 @@ impl Server {
                         }
                     };
 
-                    let client = self.client.clone();
+                    let server = self.clone();
                     tokio::spawn(async move {
                         let io = TokioIo::new(stream);
                         let service = hyper::service::service_fn(move |req| {
-                            Self::proxy_handler(req, client.clone(), remote_addr)
+                            server.clone().proxy_handler(req, remote_addr)
                         });
 
                         if let Err(e) = HyperServerBuilder::new()
@@ -79,8 +95,8 @@ impl Server {
     }
 
     async fn proxy_handler(
+        self,
         req: Request<hyper::body::Incoming>,
-        client: Arc<Client<QuicClient, QuicConnection>>,
         remote_addr: SocketAddr,
     ) -> HttpResult {
         let target_addr = match Self::extract_target_address(&req) {
@@ -88,7 +104,15 @@ impl Server {
             Err(response) => return Ok(*response),
         };
 
-        let outbound_conn = match client.open_bidirectional(target_addr.clone()).await {
+        let target_addr = match self.restore(target_addr).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!(error = %e, "failed to restore target address");
+                return Ok(Self::create_error_response(StatusCode::BAD_GATEWAY));
+            }
+        };
+
+        let outbound_conn = match self.client.open_bidirectional(target_addr.clone()).await {
             Ok(conn) => conn,
             Err(e) => {
                 error!(
@@ -186,6 +210,15 @@ impl Server {
         Ok(resp.map(|b| b.boxed()))
     }
 
+    async fn restore(&self, address: Address) -> io::Result<Address> {
+        #[cfg(feature = "endpoint-dns")]
+        if let Some(fake_dns) = &self.fake_dns {
+            return fake_dns.restore(address).await;
+        }
+
+        Ok(address)
+    }
+
     fn extract_target_address(
         req: &Request<hyper::body::Incoming>,
     ) -> Result<Address, Box<Response<BoxBody<Bytes, hyper::Error>>>> {
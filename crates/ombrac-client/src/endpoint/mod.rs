@@ -1,3 +1,5 @@
+#[cfg(feature = "endpoint-dns")]
+pub mod dns;
 #[cfg(feature = "endpoint-http")]
 pub mod http;
 #[cfg(feature = "endpoint-socks")]
@@ -18,6 +18,8 @@ use ombrac_transport::quic::Connection as QuicConnection;
 use ombrac_transport::quic::client::Client as QuicClient;
 
 use crate::client::Client;
+#[cfg(feature = "endpoint-dns")]
+use crate::dns::FakeDns;
 
 use protocol::{Address, Reply, Request, VERSION, encode_reply};
 
@@ -31,13 +33,27 @@ use {
 };
 
 /// SOCKS5 server bound to a [`Client`].
+#[derive(Clone)]
 pub struct Server {
     client: Arc<Client<QuicClient, QuicConnection>>,
+    #[cfg(feature = "endpoint-dns")]
+    fake_dns: Option<Arc<FakeDns>>,
 }
 
 impl Server {
     pub fn new(client: Arc<Client<QuicClient, QuicConnection>>) -> Self {
-        Self { client }
+        Self {
+            client,
+            #[cfg(feature = "endpoint-dns")]
+            fake_dns: None,
+        }
+    }
+
+    /// Maps fake addresses handed out by `fake_dns` back to domain names.
+    #[cfg(feature = "endpoint-dns")]
+    pub fn with_fake_dns(mut self, fake_dns: Arc<FakeDns>) -> Self {
+        self.fake_dns = Some(fake_dns);
+        self
     }
 
     /// Accepts connections until `shutdown` resolves.
@@ -64,9 +80,9 @@ impl Server {
                     };
 
                     let _ = stream.set_nodelay(true);
-                    let client = self.client.clone();
+                    let server = self.clone();
                     tokio::spawn(async move {
-                        if let Err(_err) = handle_connection(client, stream, peer).await {
+                        if let Err(_err) = handle_connection(server, stream, peer).await {
                             warn!("socks: connection {peer} error: {_err}");
                         }
                     });
@@ -74,12 +90,22 @@ impl Server {
             }
         }
     }
+
+    /// Resolves the address a `CONNECT` should be tunnelled to.
+    async fn target(&self, address: Address) -> io::Result<ombrac::protocol::Address> {
+        #[cfg(feature = "endpoint-dns")]
+        if let Some(fake_dns) = &self.fake_dns {
+            return fake_dns.restore(address.into()).await;
+        }
+
+        Ok(address.into())
+    }
 }
 
 /// Runs the full SOCKS5 exchange for a single accepted connection: method
 /// negotiation, request parsing, then command dispatch.
 async fn handle_connection(
-    client: Arc<Client<QuicClient, QuicConnection>>,
+    server: Server,
     mut stream: TcpStream,
     peer: SocketAddr,
 ) -> io::Result<()> {
@@ -87,9 +113,9 @@ async fn handle_connection(
 
     let request = Request::read(&mut stream).await?;
     match request {
-        Request::Connect(address) => handle_connect(&client, &mut stream, address, peer).await,
+        Request::Connect(address) => handle_connect(&server, &mut stream, address, peer).await,
         #[cfg(feature = "datagram")]
-        Request::Associate(_) => handle_associate(&client, &mut stream).await,
+        Request::Associate(_) => handle_associate(&server.client, &mut stream).await,
         #[cfg(not(feature = "datagram"))]
         Request::Associate(_) => {
             reply_failure(&mut stream, Reply::CommandNotSupported).await?;
@@ -152,15 +178,24 @@ async fn reply_failure(stream: &mut TcpStream, reply: Reply) -> io::Result<()> {
 /// Handles `CONNECT`: opens a tunnel stream to `address`, replies, then relays
 /// bytes bidirectionally.
 async fn handle_connect(
-    client: &Arc<Client<QuicClient, QuicConnection>>,
+    server: &Server,
     stream: &mut TcpStream,
     address: Address,
     peer: SocketAddr,
 ) -> io::Result<()> {
     let dst = address.to_string();
 
+    let target = match server.target(address).await {
+        Ok(target) => target,
+        Err(err) => {
+            error!(dst_addr = %dst, error = %err, "tcp connect failed");
+            reply_failure(stream, Reply::HostUnreachable).await?;
+            return Err(err);
+        }
+    };
+
     // Connect first so the reply code reflects the real outcome (RFC 1928).
-    let mut upstream = match client.open_bidirectional(address.into()).await {
+    let mut upstream = match server.client.open_bidirectional(target).await {
         Ok(upstream) => upstream,
         Err(err) => {
             error!(dst_addr = %dst, error = %err, "tcp connect failed");
@@ -0,0 +1,504 @@
+//! Automatic route and resolver setup for the TUN device on Linux.
+//!
+//! [`AutoRoute::install`] points the default route at the TUN device using
+//! split `/1` routes, which win over the existing default route without
+//! replacing it. The ombrac server itself is pinned to its current gateway
+//! so the tunnel's own traffic never loops back into the device, and
+//! [`AutoRoute::interface`] names the interface the default route used, for
+//! direct connections to be bound to. Optionally the system resolver is
+//! pointed at an address inside the tunnel.
+//!
+//! Changes are made with iproute2's `ip` (and `resolvectl` where
+//! systemd-resolved is running). Every change is journaled to a state file
+//! before it is applied and rolled back when the [`AutoRoute`] is dropped.
+//! If the client dies without dropping it, the next [`AutoRoute::install`]
+//! rolls back the leftover journal first.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use ombrac_macros::{info, warn};
+
+/// Routes that together cover the IPv4 address space.
+const SPLIT_ROUTES_V4: [&str; 2] = ["0.0.0.0/1", "128.0.0.0/1"];
+
+/// Routes that together cover the IPv6 address space.
+const SPLIT_ROUTES_V6: [&str; 2] = ["::/1", "8000::/1"];
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Present when systemd-resolved manages the system resolver.
+const RESOLVED_RUNTIME_DIR: &str = "/run/systemd/resolve";
+
+/// Lists the nameservers systemd-resolved forwards to.
+const RESOLVED_UPSTREAM_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// Default location of the rollback journal.
+pub const DEFAULT_STATE_FILE: &str = "/run/ombrac-auto-route.json";
+
+#[derive(Debug, Clone)]
+pub struct AutoRouteConfig {
+    /// Name of the TUN device.
+    pub device: String,
+    /// Route IPv4 traffic into the device.
+    pub ipv4: bool,
+    /// Route IPv6 traffic into the device.
+    pub ipv6: bool,
+    /// Addresses kept on their current route, i.e. the ombrac server.
+    pub bypass: Vec<IpAddr>,
+    /// Nameserver to configure as the system resolver.
+    pub dns: Option<IpAddr>,
+    /// Where the rollback journal is kept.
+    pub state_file: PathBuf,
+}
+
+/// A single change to the system, recorded so it can be rolled back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+enum Change {
+    Route {
+        destination: IpNet,
+        via: Option<IpAddr>,
+        device: String,
+    },
+    ResolvedLink {
+        device: String,
+        dns: IpAddr,
+    },
+    ResolvConf {
+        dns: IpAddr,
+        original: String,
+        /// Target of `/etc/resolv.conf` if it was a symlink, e.g. one
+        /// managed by systemd-resolved or NetworkManager.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        link: Option<PathBuf>,
+    },
+}
+
+impl Change {
+    fn apply(&self) -> io::Result<()> {
+        match self {
+            Self::Route {
+                destination,
+                via,
+                device,
+            } => {
+                let destination = destination.to_string();
+                let via = via.map(|via| via.to_string());
+                let mut args = vec!["route", "add", destination.as_str()];
+                if let Some(via) = &via {
+                    args.extend(["via", via.as_str()]);
+                }
+                args.extend(["dev", device.as_str()]);
+                run("ip", &args).map(drop)
+            }
+            Self::ResolvedLink { device, dns } => {
+                run("resolvectl", &["dns", device, &dns.to_string()])?;
+                run("resolvectl", &["domain", device, "~."]).map(drop)
+            }
+            Self::ResolvConf { dns, link, .. } => {
+                // Writing through the symlink would clobber the file it points to
+                if link.is_some() {
+                    std::fs::remove_file(RESOLV_CONF)?;
+                }
+                std::fs::write(RESOLV_CONF, format!("nameserver {dns}\n"))
+            }
+        }
+    }
+
+    fn revert(&self) -> io::Result<()> {
+        match self {
+            Self::Route {
+                destination,
+                device,
+                ..
+            } => run(
+                "ip",
+                &["route", "del", &destination.to_string(), "dev", device],
+            )
+            .map(drop),
+            Self::ResolvedLink { device, .. } => run("resolvectl", &["revert", device]).map(drop),
+            Self::ResolvConf {
+                link: Some(target), ..
+            } => {
+                if let Err(err) = std::fs::remove_file(RESOLV_CONF)
+                    && err.kind() != io::ErrorKind::NotFound
+                {
+                    return Err(err);
+                }
+                std::os::unix::fs::symlink(target, RESOLV_CONF)
+            }
+            Self::ResolvConf { original, .. } => std::fs::write(RESOLV_CONF, original),
+        }
+    }
+}
+
+/// System routing and resolver changes, rolled back on drop.
+#[derive(Debug)]
+pub struct AutoRoute {
+    changes: Vec<Change>,
+    state_file: PathBuf,
+    interface: Option<String>,
+}
+
+impl AutoRoute {
+    /// Installs routes (and optionally the resolver) for `config.device`.
+    ///
+    /// Leftovers of a previous run that did not shut down cleanly are rolled
+    /// back first. If any step fails, the steps already taken are undone.
+    pub fn install(config: &AutoRouteConfig) -> io::Result<Self> {
+        Self::recover(&config.state_file)?;
+
+        let mut auto_route = Self {
+            changes: Vec::new(),
+            state_file: config.state_file.clone(),
+            interface: default_interface(config.ipv4),
+        };
+
+        // Pin the server first so the tunnel never routes its own traffic.
+        for address in &config.bypass {
+            if let Some(change) = bypass_route(*address, &config.device)? {
+                auto_route.apply(change)?;
+            }
+        }
+
+        for destination in split_routes(config.ipv4, config.ipv6) {
+            auto_route.apply(Change::Route {
+                destination,
+                via: None,
+                device: config.device.clone(),
+            })?;
+        }
+
+        if let Some(dns) = config.dns {
+            auto_route.apply(resolver_change(&config.device, dns)?)?;
+        }
+
+        info!(
+            device = %config.device,
+            changes = auto_route.changes.len(),
+            "auto route installed"
+        );
+        Ok(auto_route)
+    }
+
+    /// Rolls back the changes journaled in `state_file`, if any.
+    pub fn recover(state_file: &Path) -> io::Result<()> {
+        let data = match std::fs::read(state_file) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let changes: Vec<Change> = serde_json::from_slice(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        warn!(
+            path = %state_file.display(),
+            changes = changes.len(),
+            "rolling back auto route changes left by a previous run"
+        );
+        drop(Self {
+            changes,
+            state_file: state_file.to_path_buf(),
+            interface: None,
+        });
+        Ok(())
+    }
+
+    /// Returns the interface of the default route before it was pointed at
+    /// the device. Sockets bound to it bypass the device.
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.as_deref()
+    }
+
+    /// Journals `change`, then applies it.
+    fn apply(&mut self, change: Change) -> io::Result<()> {
+        self.changes.push(change);
+        self.save()?;
+
+        let change = self.changes.last().expect("change was just pushed");
+        if let Err(err) = change.apply() {
+            self.changes.pop();
+            self.save()?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let data = serde_json::to_vec(&self.changes).map_err(io::Error::other)?;
+        let tmp = self.state_file.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, &self.state_file)
+    }
+}
+
+impl Drop for AutoRoute {
+    fn drop(&mut self) {
+        for change in self.changes.drain(..).rev() {
+            // Routes on the TUN device disappear with it; nothing to undo then.
+            if let Err(_err) = change.revert() {
+                warn!("failed to roll back {change:?}: {_err}");
+            }
+        }
+
+        if let Err(err) = std::fs::remove_file(&self.state_file)
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            warn!(
+                path = %self.state_file.display(),
+                "failed to remove auto route state file: {err}"
+            );
+        }
+    }
+}
+
+fn split_routes(ipv4: bool, ipv6: bool) -> Vec<IpNet> {
+    let v4 = SPLIT_ROUTES_V4.iter().filter(|_| ipv4);
+    let v6 = SPLIT_ROUTES_V6.iter().filter(|_| ipv6);
+    v4.chain(v6)
+        .map(|route| route.parse().expect("split route is invalid"))
+        .collect()
+}
+
+/// Returns the device of the current IPv4 (or IPv6) default route.
+fn default_interface(ipv4: bool) -> Option<String> {
+    let family = if ipv4 { "-4" } else { "-6" };
+    let output = run("ip", &[family, "route", "show", "default"]).ok()?;
+    parse_route_get(&output).map(|(_, device)| device)
+}
+
+/// Builds a host route keeping `address` on the route it uses now.
+///
+/// Returns `None` if the address is local or already routed into `device`.
+fn bypass_route(address: IpAddr, device: &str) -> io::Result<Option<Change>> {
+    let output = run("ip", &["route", "get", &address.to_string()])?;
+    let Some((via, route_device)) = parse_route_get(&output) else {
+        return Ok(None);
+    };
+    if route_device == device || route_device == "lo" {
+        return Ok(None);
+    }
+
+    Ok(Some(Change::Route {
+        destination: IpNet::from(address),
+        via,
+        device: route_device,
+    }))
+}
+
+/// Extracts the gateway and device from `ip route get` (or `ip route show`)
+/// output, e.g. `203.0.113.7 via 10.0.0.1 dev eth0 src 10.0.0.2 uid 0`.
+fn parse_route_get(output: &str) -> Option<(Option<IpAddr>, String)> {
+    let line = output.lines().next()?;
+    let mut tokens = line.split_whitespace();
+    let mut via = None;
+    let mut device = None;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "via" => via = tokens.next().and_then(|value| value.parse().ok()),
+            "dev" => device = tokens.next().map(str::to_string),
+            _ => {}
+        }
+    }
+
+    Some((via, device?))
+}
+
+/// Returns the nameservers the system resolver forwards to.
+///
+/// Must be read before [`AutoRoute::install`] points the resolver at the
+/// device. Loopback stubs such as systemd-resolved's are skipped in favour
+/// of the upstreams they forward to.
+pub fn system_nameservers() -> Vec<SocketAddr> {
+    [RESOLVED_UPSTREAM_CONF, RESOLV_CONF]
+        .into_iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .map(|contents| parse_nameservers(&contents))
+        .find(|nameservers| !nameservers.is_empty())
+        .unwrap_or_default()
+}
+
+/// Extracts the non-loopback `nameserver` entries of a `resolv.conf`.
+fn parse_nameservers(contents: &str) -> Vec<SocketAddr> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            if tokens.next()? != "nameserver" {
+                return None;
+            }
+            let ip: IpAddr = tokens.next()?.parse().ok()?;
+            (!ip.is_loopback()).then_some(SocketAddr::new(ip, 53))
+        })
+        .collect()
+}
+
+fn resolver_change(device: &str, dns: IpAddr) -> io::Result<Change> {
+    if Path::new(RESOLVED_RUNTIME_DIR).is_dir() {
+        return Ok(Change::ResolvedLink {
+            device: device.to_string(),
+            dns,
+        });
+    }
+
+    Ok(Change::ResolvConf {
+        dns,
+        original: std::fs::read_to_string(RESOLV_CONF)?,
+        link: std::fs::read_link(RESOLV_CONF).ok(),
+    })
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`{program} {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_routes_cover_requested_families() {
+        let routes = split_routes(true, false);
+        assert_eq!(routes.len(), 2);
+        assert!(routes.iter().all(|route| route.addr().is_ipv4()));
+        assert_eq!(split_routes(true, true).len(), 4);
+        assert!(split_routes(false, false).is_empty());
+    }
+
+    #[test]
+    fn parse_route_get_output() {
+        let output = "203.0.113.7 via 10.0.0.1 dev eth0 src 10.0.0.2 uid 0 \n    cache \n";
+        assert_eq!(
+            parse_route_get(output),
+            Some((Some("10.0.0.1".parse().unwrap()), "eth0".to_string()))
+        );
+
+        let output = "10.0.0.9 dev eth0 src 10.0.0.2 uid 0 \n    cache \n";
+        assert_eq!(parse_route_get(output), Some((None, "eth0".to_string())));
+
+        let output = "default via 10.0.0.1 dev wlan0 proto dhcp metric 600 \n";
+        assert_eq!(
+            parse_route_get(output),
+            Some((Some("10.0.0.1".parse().unwrap()), "wlan0".to_string()))
+        );
+
+        assert_eq!(parse_route_get("unreachable"), None);
+    }
+
+    #[test]
+    fn parse_nameservers_skips_loopback_stubs() {
+        let contents = "# managed\nnameserver 127.0.0.53\noptions edns0\nnameserver 10.0.0.1\nnameserver 2001:db8::53\n";
+        assert_eq!(
+            parse_nameservers(contents),
+            vec![
+                "10.0.0.1:53".parse().unwrap(),
+                "[2001:db8::53]:53".parse().unwrap()
+            ]
+        );
+        assert!(parse_nameservers("nameserver 127.0.0.53\n").is_empty());
+    }
+
+    #[test]
+    fn journal_roundtrip() {
+        let changes = vec![
+            Change::Route {
+                destination: "203.0.113.7/32".parse().unwrap(),
+                via: Some("10.0.0.1".parse().unwrap()),
+                device: "eth0".to_string(),
+            },
+            Change::ResolvConf {
+                dns: "198.18.0.2".parse().unwrap(),
+                original: "nameserver 1.1.1.1\n".to_string(),
+                link: None,
+            },
+            Change::ResolvConf {
+                dns: "198.18.0.2".parse().unwrap(),
+                original: "nameserver 127.0.0.53\n".to_string(),
+                link: Some("../run/systemd/resolve/stub-resolv.conf".into()),
+            },
+        ];
+
+        let data = serde_json::to_vec(&changes).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<Change>>(&data).unwrap(),
+            changes
+        );
+    }
+
+    fn routes() -> String {
+        run("ip", &["route", "show"]).unwrap()
+    }
+
+    /// Needs CAP_NET_ADMIN; run it in a throwaway network namespace:
+    /// `unshare -rn cargo test -p ombrac-client --features full auto_route -- --ignored`
+    #[test]
+    #[ignore = "modifies routing tables, run inside a network namespace"]
+    fn install_and_roll_back_in_network_namespace() {
+        run("ip", &["link", "set", "lo", "up"]).unwrap();
+        run(
+            "ip",
+            &["link", "add", "up0", "type", "veth", "peer", "name", "up1"],
+        )
+        .unwrap();
+        run("ip", &["addr", "add", "10.9.0.1/24", "dev", "up0"]).unwrap();
+        run("ip", &["link", "set", "up0", "up"]).unwrap();
+        run("ip", &["link", "set", "up1", "up"]).unwrap();
+        run("ip", &["route", "add", "default", "via", "10.9.0.2"]).unwrap();
+        run("ip", &["tuntap", "add", "mode", "tun", "name", "tun0"]).unwrap();
+        run("ip", &["addr", "add", "198.19.0.1/24", "dev", "tun0"]).unwrap();
+        run("ip", &["link", "set", "tun0", "up"]).unwrap();
+
+        let state_file =
+            std::env::temp_dir().join(format!("ombrac-auto-route-{}.json", std::process::id()));
+        let config = AutoRouteConfig {
+            device: "tun0".to_string(),
+            ipv4: true,
+            ipv6: false,
+            bypass: vec!["203.0.113.7".parse().unwrap()],
+            dns: None,
+            state_file: state_file.clone(),
+        };
+
+        let auto_route = AutoRoute::install(&config).unwrap();
+        assert_eq!(auto_route.interface(), Some("up0"));
+        let installed = routes();
+        assert!(installed.contains("0.0.0.0/1 dev tun0"), "{installed}");
+        assert!(installed.contains("128.0.0.0/1 dev tun0"), "{installed}");
+        assert!(
+            installed.contains("203.0.113.7 via 10.9.0.2 dev up0"),
+            "{installed}"
+        );
+        assert!(state_file.exists());
+
+        drop(auto_route);
+        let restored = routes();
+        assert!(!restored.contains("0.0.0.0/1"), "{restored}");
+        assert!(!restored.contains("128.0.0.0/1"), "{restored}");
+        assert!(!restored.contains("203.0.113.7"), "{restored}");
+        assert!(!state_file.exists());
+
+        // A run that died without cleaning up is rolled back on the next start.
+        std::mem::forget(AutoRoute::install(&config).unwrap());
+        assert!(routes().contains("0.0.0.0/1 dev tun0"));
+        AutoRoute::recover(&state_file).unwrap();
+        let recovered = routes();
+        assert!(!recovered.contains("0.0.0.0/1"), "{recovered}");
+        assert!(!recovered.contains("203.0.113.7"), "{recovered}");
+    }
+}
@@ -0,0 +1,222 @@
+//! Resolution of the domains the TUN endpoint dials directly.
+//!
+//! Once the device carries the default route, the system resolver is either
+//! answered by the fake DNS or reached through the tunnel, and neither gives
+//! an address the host can dial. Domains routed directly are looked up with
+//! plain DNS over UDP instead, sent to real nameservers from the interface
+//! direct connections are bound to.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use tokio::net::UdpSocket;
+
+use ombrac::protocol::Address;
+
+/// How long a nameserver is waited for before the next one is tried.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves the domains of direct connections, bypassing the tunnel.
+///
+/// Nameservers are tried in order, asking for an IPv4 address first. Without
+/// nameservers, domains are resolved by the system resolver.
+#[derive(Debug, Clone, Default)]
+pub struct DirectResolver {
+    nameservers: Vec<SocketAddr>,
+    interface: Option<String>,
+}
+
+impl DirectResolver {
+    pub fn new(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            interface: None,
+        }
+    }
+
+    /// Sends queries from `interface` (Linux only).
+    pub fn with_interface(mut self, interface: Option<String>) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Returns the socket address to dial for `address`, resolving domains.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no nameserver knows an address for the domain.
+    pub async fn resolve(&self, address: &Address) -> io::Result<SocketAddr> {
+        let Address::Domain(domain, port) = address else {
+            return address.to_socket_addr().await;
+        };
+        if self.nameservers.is_empty() {
+            return address.to_socket_addr().await;
+        }
+
+        let domain = std::str::from_utf8(domain).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "domain name contains invalid utf-8 characters",
+            )
+        })?;
+        let name =
+            Name::from_utf8(domain).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mut last_err = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("domain name '{domain}' could not be resolved"),
+        );
+        for nameserver in &self.nameservers {
+            for record_type in [RecordType::A, RecordType::AAAA] {
+                match self.query(*nameserver, &name, record_type).await {
+                    Ok(Some(ip)) => return Ok(SocketAddr::new(ip, *port)),
+                    Ok(None) => {}
+                    Err(err) => {
+                        last_err = err;
+                        break;
+                    }
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Asks `nameserver` for an address of `name`, returning `None` if it
+    /// has none of `record_type`.
+    async fn query(
+        &self,
+        nameserver: SocketAddr,
+        name: &Name,
+        record_type: RecordType,
+    ) -> io::Result<Option<IpAddr>> {
+        let id = rand::random();
+        let mut query = Message::new(id, MessageType::Query, OpCode::Query);
+        query.metadata.recursion_desired = true;
+        query.add_query(Query::query(name.clone(), record_type));
+        let request = query.to_vec().map_err(io::Error::other)?;
+
+        let bind_addr: SocketAddr = if nameserver.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.interface {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        socket.connect(nameserver).await?;
+        socket.send(&request).await?;
+
+        let mut buf = vec![0u8; u16::MAX as usize];
+        let response = tokio::time::timeout(QUERY_TIMEOUT, async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                // Datagrams that don't answer this query are ignored.
+                if let Ok(response) = Message::from_vec(&buf[..len])
+                    && response.metadata.id == id
+                {
+                    return Ok::<_, io::Error>(response);
+                }
+            }
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "dns query timed out"))??;
+
+        match response.metadata.response_code {
+            ResponseCode::NoError | ResponseCode::NXDomain => {}
+            code => {
+                return Err(io::Error::other(format!(
+                    "nameserver {nameserver} answered {code}"
+                )));
+            }
+        }
+
+        Ok(response
+            .answers
+            .iter()
+            .find_map(|record| match &record.data {
+                RData::A(a) => Some(IpAddr::V4(a.0)),
+                RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_proto::rr::Record;
+    use hickory_proto::rr::rdata::A;
+
+    use super::*;
+    use crate::dns::FakeDns;
+    use crate::endpoint::tun::{Route, RouteRules, Rule};
+
+    /// Answers every A query with `ip`, like a real upstream would.
+    async fn spawn_nameserver(ip: Ipv4Addr) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let query = Message::from_vec(&buf[..len]).unwrap();
+                let question = query.queries[0].clone();
+                let mut response = query.into_response();
+                if question.query_type() == RecordType::A {
+                    response.add_answer(Record::from_rdata(
+                        question.name().clone(),
+                        60,
+                        RData::A(A(ip)),
+                    ));
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+        addr
+    }
+
+    /// Returns the address the fake DNS hands out for `name`.
+    async fn fake_address(fake_dns: &FakeDns, name: &str) -> IpAddr {
+        let mut query = Message::new(7, MessageType::Query, OpCode::Query);
+        query.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        let response = fake_dns.handle_dns_query(&query).await.unwrap();
+        match &response.answers[0].data {
+            RData::A(A(ip)) => IpAddr::V4(*ip),
+            data => panic!("unexpected answer: {data:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn direct_domain_behind_fake_dns_resolves_to_its_real_address() {
+        let nameserver = spawn_nameserver(Ipv4Addr::new(192, 0, 2, 7)).await;
+        let fake_dns = FakeDns::new("198.18.0.0/16".parse().unwrap());
+        let rules = RouteRules::new(Vec::new(), vec!["direct.example".parse::<Rule>().unwrap()]);
+
+        // The application's lookup was answered by the fake DNS, so the
+        // connection arrives at a fake address.
+        let fake_ip = fake_address(&fake_dns, "www.direct.example.").await;
+        let domain = fake_dns.get_domain_by_ip(&fake_ip).await.unwrap();
+        let target = Address::from((domain.to_utf8(), 443));
+        assert_eq!(rules.route(&target), Route::Direct);
+
+        let resolved = DirectResolver::new(vec![nameserver])
+            .resolve(&target)
+            .await
+            .unwrap();
+        assert_eq!(resolved, SocketAddr::from(([192, 0, 2, 7], 443)));
+        assert!(!fake_dns.contains(&resolved.ip()));
+    }
+
+    #[tokio::test]
+    async fn ip_addresses_are_not_resolved() {
+        let resolver = DirectResolver::new(vec![SocketAddr::from(([192, 0, 2, 1], 53))]);
+        let target = Address::from(SocketAddr::from(([192, 0, 2, 9], 80)));
+        assert_eq!(
+            resolver.resolve(&target).await.unwrap(),
+            SocketAddr::from(([192, 0, 2, 9], 80))
+        );
+    }
+}
@@ -0,0 +1,839 @@
+#[cfg(target_os = "linux")]
+pub mod auto_route;
+mod direct_dns;
+mod rules;
+mod sniff;
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use dashmap::{DashMap, Entry};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use hickory_proto::op::Message;
+use ipnet::{Ipv4Net, Ipv6Net};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Semaphore, mpsc};
+use tokio_util::sync::CancellationToken;
+use tun_rs::async_framed::{BytesCodec, DeviceFramed};
+
+use ombrac::protocol::Address;
+use ombrac_macros::{debug, error, info, warn};
+use ombrac_netstack::{
+    icmp::{self, EchoRequest, IcmpTunnel},
+    stack::{NetStack, NetStackConfig, Packet, StackSplitSink, StackSplitStream},
+    tcp::{TcpConnection, TcpStream},
+    udp::{SplitWrite, UdpPacket, UdpTunnel},
+};
+use ombrac_transport::quic::Connection as QuicConnection;
+use ombrac_transport::quic::client::Client as QuicClient;
+
+pub use ombrac_netstack::pcap::{PcapFilter, PcapOptions, PcapTap};
+pub use tun_rs::AsyncDevice;
+
+pub use crate::client::Client;
+pub use crate::dns::FakeDns;
+use crate::dns::{self, RemoteResolver};
+
+pub use direct_dns::DirectResolver;
+pub use rules::{Route, RouteRules, Rule};
+use sniff::{QuicSniffer, Sniff};
+
+/// Echo requests relayed through the server at once; further ones are
+/// dropped, like a host rate limiting ICMP would.
+const MAX_RELAYED_ECHO: usize = 64;
+
+/// How long a relayed echo request holds its slot, in case the server never
+/// answers; the server gives up on the destination well before.
+const ECHO_RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How echo requests (ping) through the TUN device are answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcmpEcho {
+    /// Echo requests are dropped.
+    Off,
+    /// Answered by the client after the given delay, whether the destination
+    /// is reachable or not.
+    Local(Duration),
+    /// Sent by the server and answered only if the destination replies.
+    /// Destinations routed directly are answered locally.
+    Relay,
+}
+
+#[derive(Debug, Clone)]
+pub struct TunConfig {
+    pub fakedns_cidr: Ipv4Net,
+    pub fakedns_ipv6_cidr: Option<Ipv6Net>,
+    pub fakedns_state_file: Option<PathBuf>,
+    pub rules: RouteRules,
+    /// Interface direct connections are bound to (Linux only), so they
+    /// don't loop back into the device once it carries the default route.
+    pub direct_interface: Option<String>,
+    /// Nameservers domains routed directly are resolved with, queried from
+    /// `direct_interface`. The system resolver is used if empty.
+    pub direct_dns: Vec<SocketAddr>,
+    pub sniff: bool,
+    pub icmp_echo: IcmpEcho,
+    /// Records the packets through the device while a capture is running.
+    pub pcap: PcapTap,
+    pub udp_idle_timeout: Duration,
+    pub udp_session_channel_capacity: usize,
+    pub disable_udp_443: bool,
+}
+
+impl Default for TunConfig {
+    fn default() -> Self {
+        Self {
+            fakedns_cidr: "198.18.0.0/16"
+                .parse()
+                .expect("default fake dns cidr is invalid"),
+            fakedns_ipv6_cidr: None,
+            fakedns_state_file: None,
+            rules: RouteRules::default(),
+            direct_interface: None,
+            direct_dns: Vec::new(),
+            sniff: false,
+            icmp_echo: IcmpEcho::Local(Duration::ZERO),
+            pcap: PcapTap::new(),
+            udp_idle_timeout: Duration::from_secs(60),
+            udp_session_channel_capacity: 2048,
+            disable_udp_443: false,
+        }
+    }
+}
+
+pub struct Tun {
+    config: Arc<TunConfig>,
+    client: Arc<Client<QuicClient, QuicConnection>>,
+    fakedns: Arc<FakeDns>,
+    resolver: Arc<RemoteResolver<QuicClient, QuicConnection>>,
+    direct_resolver: Arc<DirectResolver>,
+    quic_sniffers: Arc<DashMap<SocketAddr, QuicSniffer>>,
+}
+
+impl Clone for Tun {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            client: self.client.clone(),
+            fakedns: self.fakedns.clone(),
+            resolver: self.resolver.clone(),
+            direct_resolver: self.direct_resolver.clone(),
+            quic_sniffers: self.quic_sniffers.clone(),
+        }
+    }
+}
+
+impl Tun {
+    pub fn new(config: Arc<TunConfig>, client: Arc<Client<QuicClient, QuicConnection>>) -> Self {
+        let mut fakedns = FakeDns::new(config.fakedns_cidr);
+        if let Some(ipv6_cidr) = config.fakedns_ipv6_cidr {
+            fakedns = fakedns.with_ipv6(ipv6_cidr);
+        }
+        if let Some(path) = &config.fakedns_state_file {
+            fakedns = fakedns.with_state_file(path);
+        }
+
+        Self {
+            fakedns: Arc::new(fakedns),
+            resolver: Arc::new(RemoteResolver::new(client.clone())),
+            direct_resolver: Arc::new(
+                DirectResolver::new(config.direct_dns.clone())
+                    .with_interface(config.direct_interface.clone()),
+            ),
+            quic_sniffers: Arc::new(DashMap::new()),
+            config,
+            client,
+        }
+    }
+
+    pub async fn accept_loop(
+        &self,
+        device: AsyncDevice,
+        shutdown_signal: impl Future<Output = ()>,
+    ) -> std::io::Result<()> {
+        let framed = DeviceFramed::new(device, BytesCodec::new());
+        let (tun_sink, tun_stream) = framed.split::<bytes::Bytes>();
+
+        let icmp_echo = match self.config.icmp_echo {
+            IcmpEcho::Off => icmp::IcmpEcho::Disabled,
+            IcmpEcho::Local(latency) => icmp::IcmpEcho::Local(latency),
+            IcmpEcho::Relay => icmp::IcmpEcho::Forward,
+        };
+        let (mut stack, tcp_listener, udp_socket) = NetStack::new(NetStackConfig {
+            icmp_echo,
+            ..Default::default()
+        });
+        let icmp_tunnel = stack.icmp_tunnel();
+        let (stack_sink, stack_stream) = stack.with_pcap(self.config.pcap.clone()).split();
+
+        if let Err(_err) = self.fakedns.load_state().await {
+            warn!("failed to load fakedns state: {_err}");
+        }
+
+        let shutdown_token = CancellationToken::new();
+
+        let mut processing_tasks = vec![
+            tokio::spawn({
+                let fakedns = self.fakedns.clone();
+                let token = shutdown_token.clone();
+                async move { fakedns.persist(token.cancelled()).await }
+            }),
+            tokio::spawn(Self::forward_packets_from_stack_to_tun(
+                stack_stream,
+                tun_sink,
+                shutdown_token.clone(),
+            )),
+            tokio::spawn(Self::forward_packets_from_tun_to_stack(
+                tun_stream,
+                stack_sink,
+                shutdown_token.clone(),
+            )),
+            tokio::spawn(
+                self.clone()
+                    .accept_tcp_connections(tcp_listener, shutdown_token.clone()),
+            ),
+            tokio::spawn(
+                self.clone()
+                    .process_incoming_udp_packets(udp_socket, shutdown_token.clone()),
+            ),
+        ];
+        if let Some(icmp_tunnel) = icmp_tunnel {
+            processing_tasks.push(tokio::spawn(
+                self.clone()
+                    .process_echo_requests(icmp_tunnel, shutdown_token.clone()),
+            ));
+        }
+
+        shutdown_signal.await;
+        shutdown_token.cancel();
+
+        for task in processing_tasks {
+            if let Err(err) = task.await {
+                error!(error = ?err, "processing task panicked or failed");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn forward_packets_from_stack_to_tun(
+        mut stack_stream: StackSplitStream,
+        mut tun_sink: SplitSink<DeviceFramed<BytesCodec>, Bytes>,
+        token: CancellationToken,
+    ) {
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                packet_result = stack_stream.next() => {
+                    match packet_result {
+                        Some(Ok(packet)) => {
+                            if let Err(err) = tun_sink.send(packet.into_bytes()).await {
+                                error!("failed to send packet to tun device: {}, stopping forwarding", err);
+                                break;
+                            }
+                        }
+                        Some(Err(err)) => {
+                            error!("netstack read error: {}, stopping forwarding", err);
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+        debug!("stack-to-tun forwarding finished");
+    }
+
+    async fn forward_packets_from_tun_to_stack(
+        mut tun_stream: SplitStream<DeviceFramed<BytesCodec>>,
+        mut stack_sink: StackSplitSink,
+        token: CancellationToken,
+    ) {
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                packet_result = tun_stream.next() => {
+                    match packet_result {
+                        Some(Ok(packet)) => {
+                            if let Err(err) = stack_sink.send(Packet::new(packet)).await {
+                                error!("failed to send packet to stack: {}, stopping forwarding", err);
+                                break;
+                            }
+                        }
+                        Some(Err(err)) => {
+                            error!("tun device read error: {}, stopping forwarding", err);
+                            break;
+                        }
+                        None => {
+                            info!("tun device stream closed, stopping tun-to-stack forwarding");
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        debug!("tun-to-stack forwarding finished");
+    }
+
+    async fn accept_tcp_connections(
+        self,
+        mut tcp_listener: TcpConnection,
+        token: CancellationToken,
+    ) {
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+                stream_opt = tcp_listener.next() => {
+                    let stream = match stream_opt {
+                        Some(s) => s,
+                        None => break,
+                    };
+
+                    let tun_instance = self.clone();
+                    tokio::spawn(async move {
+                        let remote_addr = stream.remote_addr();
+                        if let Err(err) = tun_instance.relay_tcp_stream(stream).await {
+                            error!(
+                                src_addr = %remote_addr,
+                                error = %err,
+                                "tcp connect"
+                            );
+                        }
+                    });
+                }
+            }
+        }
+        debug!("tcp connection acceptor finished");
+    }
+
+    async fn relay_tcp_stream(&self, mut stream: TcpStream) -> io::Result<()> {
+        let local_addr = stream.local_addr();
+        let fake_remote_addr = stream.remote_addr();
+
+        let mut target_addr =
+            if let Some(domain) = self.fakedns.get_domain_by_ip(&fake_remote_addr.ip()).await {
+                Address::from((domain.to_utf8(), fake_remote_addr.port()))
+            } else {
+                if self.fakedns.contains(&fake_remote_addr.ip()) {
+                    return Err(io::Error::other(format!(
+                        "dns cache miss: {} -> {}",
+                        local_addr, fake_remote_addr
+                    )));
+                }
+                Address::from(fake_remote_addr)
+            };
+
+        // Bytes read while sniffing, forwarded before relaying the rest.
+        let mut initial = Vec::new();
+        let mut route = self.config.rules.route(&target_addr);
+        // Only connections the address sends through the tunnel are sniffed,
+        // so excluded ranges never reach it by the domain they carry. Domain
+        // rules still apply to the sniffed domain.
+        if route == Route::Tunnel
+            && self.config.sniff
+            && !matches!(target_addr, Address::Domain(..))
+            && sniff::sniffs_tcp_port(fake_remote_addr.port())
+            && let Some(domain) = sniff::sniff_tcp(&mut stream, &mut initial).await
+        {
+            target_addr = Address::from((domain, fake_remote_addr.port()));
+            route = self.config.rules.route(&target_addr);
+        }
+
+        let result = match route {
+            Route::Tunnel => {
+                let mut remote_stream =
+                    self.client.open_bidirectional(target_addr.clone()).await?;
+                remote_stream.write_all(&initial).await?;
+                ombrac_transport::io::copy_bidirectional(&mut stream, &mut remote_stream).await
+            }
+            Route::Direct => {
+                let target = self.direct_resolver.resolve(&target_addr).await?;
+                let mut remote_stream = self.connect_direct(target).await?;
+                remote_stream.write_all(&initial).await?;
+                ombrac_transport::io::copy_bidirectional(&mut stream, &mut remote_stream).await
+            }
+            Route::Drop => {
+                return Err(io::Error::other(format!(
+                    "dropping non-unicast destination: {} -> {}",
+                    local_addr, target_addr
+                )));
+            }
+        };
+
+        match result {
+            Ok(stats) => {
+                info!(
+                    src_addr = %local_addr,
+                    fake_addr = %fake_remote_addr,
+                    dst_addr = %target_addr,
+                    route = %route,
+                    send = initial.len() as u64 + stats.a_to_b_bytes,
+                    recv = stats.b_to_a_bytes,
+                    "tcp connect"
+                );
+            }
+            Err((err, stats)) => {
+                error!(
+                    src_addr = %local_addr,
+                    fake_addr = %fake_remote_addr,
+                    dst_addr = %target_addr,
+                    route = %route,
+                    send = initial.len() as u64 + stats.a_to_b_bytes,
+                    recv = stats.b_to_a_bytes,
+                    error = %err,
+                    "tcp connect"
+                );
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_incoming_udp_packets(self, udp_socket: UdpTunnel, token: CancellationToken) {
+        let (mut reader, writer) = udp_socket.split();
+        let active_sessions = Arc::new(DashMap::new());
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+
+                packet_opt = reader.recv() => {
+                    let packet = match packet_opt {
+                        Some(p) => p,
+                        None => break,
+                    };
+
+                    let src_addr = packet.src_addr;
+                    let dst_addr = packet.dst_addr;
+
+                    if dst_addr.port() == 53 {
+                        tokio::spawn(self.clone().handle_dns_query_packet(packet, writer.clone()));
+                        continue;
+                    }
+
+                    // Skip UDP packets to port 443 if disabled
+                    if self.config.disable_udp_443 && dst_addr.port() == 443 {
+                        info!(
+                            src_addr = %src_addr,
+                            dst_addr = %dst_addr,
+                            "dropping udp packet to port 443"
+                        );
+                        continue;
+                    }
+
+                    if let Err(err) = self.handle_udp_data_packet(packet, &active_sessions, writer.clone()).await {
+                        error!(
+                            src_addr = %src_addr,
+                            dst_addr = %dst_addr,
+                            error = %err,
+                            "udp error"
+                        );
+                    };
+                }
+            }
+        }
+        debug!("udp packet processing finished");
+    }
+
+    /// Relays echo requests the stack forwards; only used with [`IcmpEcho::Relay`].
+    async fn process_echo_requests(self, icmp_tunnel: IcmpTunnel, token: CancellationToken) {
+        let (mut reader, writer) = icmp_tunnel.split();
+        let relays = Arc::new(Semaphore::new(MAX_RELAYED_ECHO));
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => break,
+
+                request = reader.recv() => {
+                    let Some(request) = request else { break };
+                    let Ok(permit) = relays.clone().try_acquire_owned() else {
+                        debug!(dst_addr = %request.dst_addr, "dropping echo request, too many in flight");
+                        continue;
+                    };
+                    let relay = self.clone().relay_echo_request(request, writer.clone());
+                    tokio::spawn(async move {
+                        let _ = tokio::time::timeout(ECHO_RELAY_TIMEOUT, relay).await;
+                        drop(permit);
+                    });
+                }
+            }
+        }
+        debug!("icmp echo processing finished");
+    }
+
+    /// Pings the destination from the server and answers once it replies.
+    async fn relay_echo_request(self, request: EchoRequest, mut writer: icmp::SplitWrite) {
+        let target_addr =
+            if let Some(domain) = self.fakedns.get_domain_by_ip(&request.dst_addr).await {
+                Address::from((domain.to_utf8(), 0))
+            } else {
+                if self.fakedns.contains(&request.dst_addr) {
+                    debug!(dst_addr = %request.dst_addr, "dropping echo request on dns cache miss");
+                    return;
+                }
+                Address::from(SocketAddr::new(request.dst_addr, 0))
+            };
+
+        let result = match self.config.rules.route(&target_addr) {
+            Route::Tunnel => {
+                self.client
+                    .echo(target_addr.clone(), request.data.clone())
+                    .await
+            }
+            Route::Direct => Ok(()),
+            Route::Drop => return,
+        };
+
+        match result {
+            Ok(()) => {
+                if let Err(_err) = writer.reply(&request).await {
+                    error!(error = %_err, "failed to send echo reply to tun stack");
+                }
+            }
+            Err(_err) => {
+                debug!(
+                    src_addr = %request.src_addr,
+                    dst_addr = %target_addr,
+                    seq = request.seq_no,
+                    error = %_err,
+                    "echo request failed"
+                );
+            }
+        }
+    }
+
+    /// Answers a DNS query sent to port 53.
+    ///
+    /// Questions the fake DNS handles get a fake address; everything else
+    /// (AAAA, MX, TXT, SRV, HTTPS, ...) is resolved by the server.
+    async fn handle_dns_query_packet(self, packet: UdpPacket, mut writer: SplitWrite) {
+        let query_data: Bytes = packet.data.into_bytes();
+        let query = match Message::from_vec(&query_data) {
+            Ok(query) => query,
+            Err(err) => {
+                warn!(error = %err, "failed to parse dns query");
+                return;
+            }
+        };
+
+        let response = dns::handle_query(&query, Some(&self.fakedns), &self.resolver).await;
+
+        if let Some(response_message) = response {
+            match dns::encode_udp_response(&query, &response_message) {
+                Ok(response_bytes) => {
+                    let response_packet = UdpPacket {
+                        data: Packet::new(response_bytes),
+                        src_addr: packet.dst_addr,
+                        dst_addr: packet.src_addr,
+                    };
+                    if let Err(err) = writer.send(response_packet).await {
+                        error!(error = %err, "failed to send dns response to tun stack");
+                    }
+                }
+                Err(err) => {
+                    error!(error = %err, "failed to serialize dns response");
+                }
+            }
+        }
+    }
+
+    async fn handle_udp_data_packet(
+        &self,
+        packet: UdpPacket,
+        active_sessions: &Arc<DashMap<SocketAddr, mpsc::Sender<(Bytes, Address)>>>,
+        writer: SplitWrite,
+    ) -> io::Result<()> {
+        let local_addr = packet.src_addr;
+        let fake_remote_addr = packet.dst_addr;
+        let packet_data = packet.data.into_bytes();
+
+        let mut target_addr =
+            if let Some(domain) = self.fakedns.get_domain_by_ip(&fake_remote_addr.ip()).await {
+                Address::from((domain.to_utf8(), fake_remote_addr.port()))
+            } else {
+                if self.fakedns.contains(&fake_remote_addr.ip()) {
+                    return Err(io::Error::other(format!(
+                        "dns cache miss: {} -> {}",
+                        local_addr, fake_remote_addr
+                    )));
+                }
+                Address::from(fake_remote_addr)
+            };
+
+        if self.config.rules.route(&target_addr) == Route::Drop {
+            return Err(io::Error::other(format!(
+                "dropping non-unicast destination: {} -> {}",
+                local_addr, target_addr
+            )));
+        }
+
+        let mut packets = vec![packet_data];
+        if self.config.sniff
+            && fake_remote_addr.port() == 443
+            && !matches!(target_addr, Address::Domain(..))
+            && !active_sessions.contains_key(&local_addr)
+            && self.config.rules.route(&target_addr) == Route::Tunnel
+        {
+            let Some((domain, held)) = self.sniff_quic(local_addr, packets.remove(0)) else {
+                return Ok(());
+            };
+            if let Some(domain) = domain {
+                target_addr = Address::from((domain, fake_remote_addr.port()));
+            }
+            packets = held;
+        }
+
+        match active_sessions.entry(local_addr) {
+            Entry::Occupied(entry) => {
+                for packet_data in packets {
+                    if entry
+                        .get()
+                        .send((packet_data, target_addr.clone()))
+                        .await
+                        .is_err()
+                    {
+                        entry.remove();
+                        break;
+                    }
+                }
+            }
+            Entry::Vacant(entry) => {
+                let (sender, receiver) = mpsc::channel(self.config.udp_session_channel_capacity);
+                for packet_data in packets {
+                    if sender
+                        .send((packet_data, target_addr.clone()))
+                        .await
+                        .is_err()
+                    {
+                        return Ok(());
+                    }
+                }
+
+                entry.insert(sender);
+
+                #[cfg(feature = "datagram")]
+                tokio::spawn(self.clone().relay_udp_flow(
+                    receiver,
+                    writer,
+                    active_sessions.clone(),
+                    local_addr,
+                    fake_remote_addr,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Holds back the first packets of a UDP/443 flow until the SNI of its
+    /// QUIC Initial is known.
+    ///
+    /// Returns `None` while the packet is held, otherwise the sniffed domain
+    /// and every packet of the flow held so far.
+    fn sniff_quic(
+        &self,
+        local_addr: SocketAddr,
+        packet: Bytes,
+    ) -> Option<(Option<String>, Vec<Bytes>)> {
+        // Flows that went quiet mid-sniff are dropped with their packets.
+        if !self.quic_sniffers.contains_key(&local_addr) {
+            self.quic_sniffers
+                .retain(|_, sniffer| !sniffer.is_expired());
+        }
+
+        let result = self
+            .quic_sniffers
+            .entry(local_addr)
+            .or_insert_with(QuicSniffer::new)
+            .push(packet);
+
+        let domain = match result {
+            Sniff::Incomplete => return None,
+            Sniff::Found(domain) => Some(domain),
+            Sniff::Unknown => None,
+        };
+        let (_, sniffer) = self.quic_sniffers.remove(&local_addr)?;
+        Some((domain, sniffer.into_packets()))
+    }
+
+    #[cfg(feature = "datagram")]
+    async fn relay_udp_flow(
+        self,
+        mut receiver: mpsc::Receiver<(Bytes, Address)>,
+        mut writer: SplitWrite,
+        active_sessions: Arc<DashMap<SocketAddr, mpsc::Sender<(Bytes, Address)>>>,
+        local_addr: SocketAddr,
+        fake_remote_addr: SocketAddr,
+    ) {
+        let mut udp_session = self.client.open_associate();
+        // Bound on the first packet to an excluded destination.
+        let mut direct_socket: Option<tokio::net::UdpSocket> = None;
+        let mut direct_buf = vec![0u8; u16::MAX as usize];
+
+        let idle_timeout = tokio::time::sleep(self.config.udp_idle_timeout);
+        tokio::pin!(idle_timeout);
+
+        let mut total_send_bytes = 0u64;
+        let mut total_recv_bytes = 0u64;
+        let mut target_addr: Option<Address> = None;
+
+        loop {
+            tokio::select! {
+                Some((packet_data, addr)) = receiver.recv() => {
+                    if target_addr.is_none() {
+                        target_addr = Some(addr.clone());
+                    }
+                    total_send_bytes += packet_data.len() as u64;
+                    let result = match self.config.rules.route(&addr) {
+                        Route::Tunnel => udp_session.send_to(packet_data, addr.clone()).await,
+                        Route::Direct => self.send_direct(&mut direct_socket, &packet_data, &addr).await,
+                        Route::Drop => Ok(()),
+                    };
+                    if let Err(err) = result {
+                        error!(
+                            local_addr = %local_addr,
+                            target = %addr,
+                            error = %err,
+                            "udp send failed"
+                        );
+                    }
+                    idle_timeout.as_mut().reset(tokio::time::Instant::now() + self.config.udp_idle_timeout);
+                }
+
+                Some((packet_data, _source_addr)) = udp_session.recv_from() => {
+                    total_recv_bytes += packet_data.len() as u64;
+                    let response_packet = UdpPacket {
+                        data: Packet::new(packet_data),
+                        src_addr: fake_remote_addr,
+                        dst_addr: local_addr,
+                    };
+
+                    if writer.send(response_packet).await.is_err() {
+                        error!(
+                            local_addr = %local_addr,
+                            "udp send to tun stack failed, terminating flow"
+                        );
+                        break;
+                    }
+                    idle_timeout.as_mut().reset(tokio::time::Instant::now() + self.config.udp_idle_timeout);
+                }
+
+                Ok((len, _source_addr)) = Self::recv_direct(direct_socket.as_ref(), &mut direct_buf) => {
+                    total_recv_bytes += len as u64;
+                    let response_packet = UdpPacket {
+                        data: Packet::new(Bytes::copy_from_slice(&direct_buf[..len])),
+                        src_addr: fake_remote_addr,
+                        dst_addr: local_addr,
+                    };
+
+                    if writer.send(response_packet).await.is_err() {
+                        error!(
+                            local_addr = %local_addr,
+                            "udp send to tun stack failed, terminating flow"
+                        );
+                        break;
+                    }
+                    idle_timeout.as_mut().reset(tokio::time::Instant::now() + self.config.udp_idle_timeout);
+                }
+
+                _ = &mut idle_timeout => {
+                    debug!(
+                        local_addr = %local_addr,
+                        fake_remote_addr = %fake_remote_addr,
+                        idle_timeout_secs = self.config.udp_idle_timeout.as_secs(),
+                        "udp session timeout"
+                    );
+                    break;
+                }
+            }
+        }
+
+        active_sessions.remove(&local_addr);
+        if let Some(target) = target_addr {
+            info!(
+                src_addr = %local_addr,
+                fake_addr = %fake_remote_addr,
+                dst_addr = %target,
+                send = total_send_bytes,
+                recv = total_recv_bytes,
+                "udp session"
+            );
+        } else {
+            info!(
+                src_addr = %local_addr,
+                fake_addr = %fake_remote_addr,
+                send = total_send_bytes,
+                recv = total_recv_bytes,
+                "udp session"
+            );
+        }
+    }
+
+    /// Connects to an excluded destination from the host.
+    async fn connect_direct(&self, target: SocketAddr) -> io::Result<tokio::net::TcpStream> {
+        let socket = if target.is_ipv4() {
+            tokio::net::TcpSocket::new_v4()?
+        } else {
+            tokio::net::TcpSocket::new_v6()?
+        };
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.config.direct_interface {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        socket.connect(target).await
+    }
+
+    /// Sends a datagram to an excluded destination from the host, binding
+    /// `socket` on first use.
+    #[cfg(feature = "datagram")]
+    async fn send_direct(
+        &self,
+        socket: &mut Option<tokio::net::UdpSocket>,
+        data: &[u8],
+        target: &Address,
+    ) -> io::Result<()> {
+        let target = self.direct_resolver.resolve(target).await?;
+        let socket = match socket {
+            Some(socket) => socket,
+            None => {
+                let bind_addr: SocketAddr = if target.is_ipv4() {
+                    (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                let direct = tokio::net::UdpSocket::bind(bind_addr).await?;
+                #[cfg(target_os = "linux")]
+                if let Some(interface) = &self.config.direct_interface {
+                    direct.bind_device(Some(interface.as_bytes()))?;
+                }
+                socket.insert(direct)
+            }
+        };
+        socket.send_to(data, target).await?;
+        Ok(())
+    }
+
+    /// Receives from the direct socket, or never completes if none is bound.
+    #[cfg(feature = "datagram")]
+    async fn recv_direct(
+        socket: Option<&tokio::net::UdpSocket>,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+        match socket {
+            Some(socket) => socket.recv_from(buf).await,
+            None => std::future::pending().await,
+        }
+    }
+}
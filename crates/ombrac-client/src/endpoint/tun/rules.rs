@@ -0,0 +1,249 @@
+//! Routing rules for the TUN endpoint.
+//!
+//! Every destination captured by the TUN device is either sent through the
+//! tunnel or dialed directly from the host. A destination goes direct if it
+//! matches an exclude rule and no include rule, so include rules can carve
+//! holes into the excluded ranges (e.g. `100.64.0.0/10` for Tailscale).
+//! Unspecified, multicast and broadcast destinations can't be dialed either
+//! way, so they are dropped whatever the rules say.
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use ipnet::IpNet;
+
+use ombrac::protocol::Address;
+
+/// Destinations that are dialed directly unless configured otherwise:
+/// private, loopback, link-local and reserved ranges.
+const DEFAULT_EXCLUDE: &[&str] = &[
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "240.0.0.0/8",
+    "::1/128",
+    "100::/16",
+    "2001:db8::/32",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// How a destination is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// Through the ombrac tunnel.
+    Tunnel,
+    /// Directly from the host, bypassing the tunnel.
+    Direct,
+    /// Nowhere, the destination isn't a unicast address.
+    Drop,
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tunnel => f.write_str("tunnel"),
+            Self::Direct => f.write_str("direct"),
+            Self::Drop => f.write_str("drop"),
+        }
+    }
+}
+
+/// A single include or exclude entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Matches IP destinations inside the network.
+    Cidr(IpNet),
+    /// Matches the domain and all of its subdomains.
+    Domain(String),
+}
+
+impl Rule {
+    fn matches(&self, address: &Address) -> bool {
+        match (self, address) {
+            (Self::Cidr(net), Address::SocketV4(addr)) => net.contains(&IpAddr::V4(*addr.ip())),
+            (Self::Cidr(net), Address::SocketV6(addr)) => net.contains(&IpAddr::V6(*addr.ip())),
+            (Self::Domain(suffix), Address::Domain(domain, _)) => {
+                let domain = domain.strip_suffix(b".").unwrap_or(domain);
+                let Some(start) = domain.len().checked_sub(suffix.len()) else {
+                    return false;
+                };
+                domain[start..].eq_ignore_ascii_case(suffix.as_bytes())
+                    && (start == 0 || domain[start - 1] == b'.')
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = io::Error;
+
+    /// Parses a CIDR (`10.0.0.0/8`), a bare IP address or a domain suffix
+    /// (`example.com`, `*.example.com` and `.example.com` are equivalent).
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Ok(net) = value.parse::<IpNet>() {
+            return Ok(Self::Cidr(net.trunc()));
+        }
+        if let Ok(ip) = value.parse::<IpAddr>() {
+            return Ok(Self::Cidr(IpNet::from(ip)));
+        }
+
+        let domain = value
+            .trim_start_matches("*.")
+            .trim_start_matches('.')
+            .trim_end_matches('.');
+        if domain.is_empty()
+            || !domain
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid route rule '{value}': expected a CIDR, IP address or domain"),
+            ));
+        }
+
+        Ok(Self::Domain(domain.to_ascii_lowercase()))
+    }
+}
+
+/// Include and exclude lists deciding the [`Route`] of each destination.
+#[derive(Debug, Clone)]
+pub struct RouteRules {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+}
+
+impl RouteRules {
+    pub fn new(include: Vec<Rule>, exclude: Vec<Rule>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// Returns the default exclude list.
+    pub fn default_exclude() -> Vec<Rule> {
+        DEFAULT_EXCLUDE
+            .iter()
+            .map(|value| value.parse().expect("default route rule is invalid"))
+            .collect()
+    }
+
+    pub fn route(&self, address: &Address) -> Route {
+        if is_non_unicast(address) {
+            return Route::Drop;
+        }
+
+        let excluded = self.exclude.iter().any(|rule| rule.matches(address))
+            && !self.include.iter().any(|rule| rule.matches(address));
+
+        if excluded {
+            Route::Direct
+        } else {
+            Route::Tunnel
+        }
+    }
+}
+
+/// Whether `address` is unspecified (or in `0.0.0.0/8`), multicast or the
+/// broadcast address.
+fn is_non_unicast(address: &Address) -> bool {
+    match address {
+        Address::SocketV4(addr) => {
+            let ip = addr.ip();
+            ip.octets()[0] == 0 || ip.is_multicast() || ip.is_broadcast()
+        }
+        Address::SocketV6(addr) => addr.ip().is_unspecified() || addr.ip().is_multicast(),
+        Address::Domain(..) => false,
+    }
+}
+
+impl Default for RouteRules {
+    fn default() -> Self {
+        Self::new(Vec::new(), Self::default_exclude())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(rules: &RouteRules, address: &str) -> Route {
+        rules.route(&Address::try_from(address).unwrap())
+    }
+
+    fn rules(values: &[&str]) -> Vec<Rule> {
+        values.iter().map(|v| v.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn default_rules_keep_private_ranges_off_the_tunnel() {
+        let rules = RouteRules::default();
+
+        assert_eq!(route(&rules, "192.168.1.1:80"), Route::Direct);
+        assert_eq!(route(&rules, "100.100.100.100:53"), Route::Direct);
+        assert_eq!(route(&rules, "[fe80::1]:80"), Route::Direct);
+        assert_eq!(route(&rules, "1.1.1.1:443"), Route::Tunnel);
+        assert_eq!(route(&rules, "[2606:4700::1111]:443"), Route::Tunnel);
+        assert_eq!(route(&rules, "example.com:443"), Route::Tunnel);
+    }
+
+    #[test]
+    fn non_unicast_destinations_are_dropped() {
+        let rules = RouteRules::new(rules(&["0.0.0.0/0", "::/0"]), Vec::new());
+
+        assert_eq!(route(&rules, "0.0.0.0:80"), Route::Drop);
+        assert_eq!(route(&rules, "0.1.2.3:80"), Route::Drop);
+        assert_eq!(route(&rules, "224.0.0.251:5353"), Route::Drop);
+        assert_eq!(route(&rules, "255.255.255.255:67"), Route::Drop);
+        assert_eq!(route(&rules, "[::]:80"), Route::Drop);
+        assert_eq!(route(&rules, "[ff02::fb]:5353"), Route::Drop);
+        assert_eq!(route(&rules, "192.168.1.255:137"), Route::Tunnel);
+    }
+
+    #[test]
+    fn include_overrides_exclude() {
+        let rules = RouteRules::new(rules(&["100.64.0.0/10"]), RouteRules::default_exclude());
+
+        assert_eq!(route(&rules, "100.100.100.100:53"), Route::Tunnel);
+        assert_eq!(route(&rules, "10.0.0.1:22"), Route::Direct);
+    }
+
+    #[test]
+    fn domain_rules_match_subdomains_only_on_label_boundary() {
+        let rules = RouteRules::new(Vec::new(), rules(&["*.Example.com"]));
+
+        assert_eq!(route(&rules, "example.com:443"), Route::Direct);
+        assert_eq!(route(&rules, "www.example.com.:443"), Route::Direct);
+        assert_eq!(route(&rules, "badexample.com:443"), Route::Tunnel);
+        assert_eq!(route(&rules, "93.184.216.34:443"), Route::Tunnel);
+    }
+
+    #[test]
+    fn rule_parsing() {
+        assert_eq!(
+            "10.1.2.3/8".parse::<Rule>().unwrap(),
+            Rule::Cidr("10.0.0.0/8".parse().unwrap())
+        );
+        assert_eq!(
+            "::1".parse::<Rule>().unwrap(),
+            Rule::Cidr("::1/128".parse().unwrap())
+        );
+        assert_eq!(
+            ".example.com".parse::<Rule>().unwrap(),
+            Rule::Domain("example.com".to_string())
+        );
+        assert!("not a rule".parse::<Rule>().is_err());
+        assert!("".parse::<Rule>().is_err());
+    }
+}
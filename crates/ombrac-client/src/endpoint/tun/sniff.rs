@@ -0,0 +1,621 @@
+//! Protocol sniffing for the TUN endpoint.
+//!
+//! Connections that reach the TUN device with a real IP (hard-coded
+//! addresses, DoH, a fake DNS miss) carry no domain name. When sniffing is
+//! enabled, the first bytes a client sends are inspected for the TLS
+//! ClientHello SNI, the HTTP `Host` header, or the SNI inside a QUIC Initial
+//! packet, so the destination can be sent to the server as a domain and
+//! resolved from its vantage point.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use rustls::crypto::aws_lc_rs::cipher_suite::TLS13_AES_128_GCM_SHA256;
+use rustls::quic::{Keys, Version};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long to wait for a TCP client to send its first bytes [default: 300 ms]
+const TCP_SNIFF_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// TCP ports of HTTP and TLS, where the client speaks first. Connections to
+/// other ports aren't sniffed, so a server speaking first (SSH, SMTP, ...)
+/// isn't held up waiting for a client that waits for it.
+const TCP_SNIFF_PORTS: &[u16] = &[80, 443, 8080, 8443];
+
+/// Upper bound for the bytes buffered while sniffing, enough for a ClientHello
+/// carrying post-quantum key shares.
+const MAX_SNIFF_LENGTH: usize = 16 * 1024;
+
+/// Upper bound for the QUIC packets held back while sniffing.
+const MAX_QUIC_SNIFF_PACKETS: usize = 4;
+
+/// How long QUIC packets are held back waiting for the rest of a ClientHello.
+const QUIC_SNIFF_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// QUIC version 2 (RFC 9369).
+const QUIC_VERSION_2: u32 = 0x6b33_43cf;
+
+/// Outcome of inspecting the data seen so far.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sniff {
+    /// The destination domain.
+    Found(String),
+    /// The data could still turn out to carry a domain.
+    Incomplete,
+    /// The data carries no domain.
+    Unknown,
+}
+
+/// Whether TCP connections to `port` are sniffed.
+pub fn sniffs_tcp_port(port: u16) -> bool {
+    TCP_SNIFF_PORTS.contains(&port)
+}
+
+/// Reads the first bytes of a TCP stream into `buf` and sniffs them.
+///
+/// Reading stops once the outcome is known, after [`TCP_SNIFF_TIMEOUT`], or
+/// when the peer closes. The bytes read must be forwarded before relaying.
+pub async fn sniff_tcp<R: AsyncRead + Unpin>(stream: &mut R, buf: &mut Vec<u8>) -> Option<String> {
+    let deadline = tokio::time::Instant::now() + TCP_SNIFF_TIMEOUT;
+    let mut chunk = [0u8; 4096];
+
+    while buf.len() < MAX_SNIFF_LENGTH {
+        let n = match tokio::time::timeout_at(deadline, stream.read(&mut chunk)).await {
+            Ok(Ok(0)) | Ok(Err(_)) | Err(_) => break,
+            Ok(Ok(n)) => n,
+        };
+        buf.extend_from_slice(&chunk[..n]);
+
+        match tcp(buf) {
+            Sniff::Found(domain) => return Some(domain),
+            Sniff::Unknown => return None,
+            Sniff::Incomplete => {}
+        }
+    }
+
+    None
+}
+
+/// Sniffs the first bytes of a TCP stream for TLS or HTTP.
+pub fn tcp(data: &[u8]) -> Sniff {
+    match data.first() {
+        None => Sniff::Incomplete,
+        Some(0x16) => tls(data),
+        Some(_) => http(data),
+    }
+}
+
+/// Extracts the SNI from TLS handshake records.
+fn tls(data: &[u8]) -> Sniff {
+    let mut handshake = Vec::new();
+    let mut rest = data;
+
+    // A ClientHello may be fragmented over several records.
+    while rest.len() >= 5 {
+        if rest[0] != 0x16 || rest[1] != 0x03 {
+            if handshake.is_empty() {
+                return Sniff::Unknown;
+            }
+            break;
+        }
+
+        let length = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        let available = length.min(rest.len() - 5);
+        handshake.extend_from_slice(&rest[5..5 + available]);
+        if available < length {
+            break;
+        }
+        rest = &rest[5 + length..];
+    }
+
+    client_hello(&handshake)
+}
+
+/// Extracts the SNI from a TLS handshake message.
+fn client_hello(handshake: &[u8]) -> Sniff {
+    let mut reader = Reader(handshake);
+    let (Some(kind), Some(length)) = (reader.u8(), reader.u24()) else {
+        return Sniff::Incomplete;
+    };
+    if kind != 1 {
+        return Sniff::Unknown;
+    }
+    let Some(body) = reader.bytes(length as usize) else {
+        return Sniff::Incomplete;
+    };
+
+    match server_name(body) {
+        Some(domain) => Sniff::Found(domain),
+        None => Sniff::Unknown,
+    }
+}
+
+/// Finds the `server_name` extension (RFC 6066) in a ClientHello body.
+fn server_name(body: &[u8]) -> Option<String> {
+    const SERVER_NAME: u16 = 0;
+    const HOST_NAME: u8 = 0;
+
+    let mut reader = Reader(body);
+    reader.bytes(2 + 32)?; // legacy_version, random
+    let session_id = reader.u8()?;
+    reader.bytes(session_id as usize)?;
+    let cipher_suites = reader.u16()?;
+    reader.bytes(cipher_suites as usize)?;
+    let compression_methods = reader.u8()?;
+    reader.bytes(compression_methods as usize)?;
+    let extensions = reader.u16()?;
+    let mut extensions = Reader(reader.bytes(extensions as usize)?);
+
+    while let (Some(kind), Some(length)) = (extensions.u16(), extensions.u16()) {
+        let data = extensions.bytes(length as usize)?;
+        if kind != SERVER_NAME {
+            continue;
+        }
+
+        let mut data = Reader(data);
+        let list = data.u16()?;
+        let mut list = Reader(data.bytes(list as usize)?);
+        while let Some(name_type) = list.u8() {
+            let length = list.u16()?;
+            let name = list.bytes(length as usize)?;
+            if name_type == HOST_NAME {
+                return domain(name);
+            }
+        }
+    }
+
+    None
+}
+
+/// Extracts the host from an HTTP/1 request head.
+fn http(data: &[u8]) -> Sniff {
+    const METHODS: [&[u8]; 9] = [
+        b"GET ",
+        b"POST ",
+        b"HEAD ",
+        b"PUT ",
+        b"DELETE ",
+        b"OPTIONS ",
+        b"PATCH ",
+        b"CONNECT ",
+        b"TRACE ",
+    ];
+
+    let is_request = METHODS.iter().any(|method| {
+        let n = method.len().min(data.len());
+        data[..n] == method[..n]
+    });
+    if !is_request {
+        return Sniff::Unknown;
+    }
+
+    let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Sniff::Incomplete;
+    };
+
+    for line in data[..end].split(|&b| b == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
+            continue;
+        };
+        if !line[..colon].eq_ignore_ascii_case(b"host") {
+            continue;
+        }
+
+        let value = line[colon + 1..].trim_ascii();
+        let host = match value.iter().rposition(|&b| b == b':') {
+            Some(colon) if value[colon + 1..].iter().all(u8::is_ascii_digit) => &value[..colon],
+            _ => value,
+        };
+        return domain(host).map_or(Sniff::Unknown, Sniff::Found);
+    }
+
+    Sniff::Unknown
+}
+
+/// Returns `name` if it is a plausible domain rather than an IP literal.
+fn domain(name: &[u8]) -> Option<String> {
+    let name = std::str::from_utf8(name).ok()?;
+    let valid = !name.is_empty()
+        && name.len() <= 253
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_');
+    if !valid || name.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    Some(name.to_ascii_lowercase())
+}
+
+/// Collects the ClientHello from QUIC Initial packets of a single flow.
+///
+/// The ClientHello can span several Initial packets, with CRYPTO frames in
+/// any order, so packets are held back until the SNI is found or the
+/// sniffer gives up. The held packets must then be forwarded in order.
+pub struct QuicSniffer {
+    crypto: BTreeMap<u64, Vec<u8>>,
+    buffered: usize,
+    packets: Vec<Bytes>,
+    started: Instant,
+}
+
+impl QuicSniffer {
+    pub fn new() -> Self {
+        Self {
+            crypto: BTreeMap::new(),
+            buffered: 0,
+            packets: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Feeds the next datagram of the flow.
+    ///
+    /// Returns [`Sniff::Incomplete`] while more packets are worth waiting for.
+    pub fn push(&mut self, datagram: Bytes) -> Sniff {
+        let mut rest: &[u8] = &datagram;
+        while let Some((payload, consumed)) = decrypt_initial(rest) {
+            self.collect_crypto(&payload);
+            rest = &rest[consumed..];
+        }
+        self.packets.push(datagram);
+
+        if self.crypto.is_empty() {
+            return Sniff::Unknown;
+        }
+
+        match client_hello(&self.contiguous()) {
+            Sniff::Incomplete
+                if self.packets.len() >= MAX_QUIC_SNIFF_PACKETS || self.is_expired() =>
+            {
+                Sniff::Unknown
+            }
+            sniff => sniff,
+        }
+    }
+
+    /// Whether the sniffer has waited long enough for more packets.
+    pub fn is_expired(&self) -> bool {
+        self.started.elapsed() >= QUIC_SNIFF_TIMEOUT
+    }
+
+    /// Returns the packets held back so far, in arrival order.
+    pub fn into_packets(self) -> Vec<Bytes> {
+        self.packets
+    }
+
+    fn collect_crypto(&mut self, payload: &[u8]) {
+        let mut reader = Reader(payload);
+        // Stops at the first frame that cannot appear before the ClientHello.
+        let _ = (|| -> Option<()> {
+            loop {
+                match reader.varint()? {
+                    0x00 | 0x01 => {} // PADDING, PING
+                    kind @ (0x02 | 0x03) => {
+                        // ACK: largest, delay, range count, first range, ranges
+                        reader.varint()?;
+                        reader.varint()?;
+                        let ranges = reader.varint()?;
+                        reader.varint()?;
+                        for _ in 0..ranges {
+                            reader.varint()?;
+                            reader.varint()?;
+                        }
+                        if kind == 0x03 {
+                            for _ in 0..3 {
+                                reader.varint()?;
+                            }
+                        }
+                    }
+                    0x06 => {
+                        let offset = reader.varint()?;
+                        let length = reader.varint()?;
+                        let data = reader.bytes(length as usize)?;
+                        if self.buffered + data.len() <= MAX_SNIFF_LENGTH {
+                            self.buffered += data.len();
+                            self.crypto.insert(offset, data.to_vec());
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+        })();
+    }
+
+    /// Returns the CRYPTO stream data received without gaps from offset 0.
+    fn contiguous(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (&offset, fragment) in &self.crypto {
+            let offset = offset as usize;
+            if offset > data.len() {
+                break;
+            }
+            let end = offset + fragment.len();
+            if end > data.len() {
+                data.extend_from_slice(&fragment[data.len() - offset..]);
+            }
+        }
+        data
+    }
+}
+
+/// Decrypts the client Initial packet at the start of `packet` (RFC 9001
+/// section 5), returning its payload and the packet length.
+fn decrypt_initial(packet: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let first = *packet.first()?;
+    if first & 0xc0 != 0xc0 {
+        return None;
+    }
+
+    let mut reader = Reader(packet.get(1..)?);
+    let version = match reader.u32()? {
+        1 if (first >> 4) & 0x03 == 0 => Version::V1,
+        QUIC_VERSION_2 if (first >> 4) & 0x03 == 1 => Version::V2,
+        _ => return None,
+    };
+
+    let dcid_length = reader.u8()?;
+    if dcid_length > 20 {
+        return None;
+    }
+    let dcid = reader.bytes(dcid_length as usize)?;
+    let scid_length = reader.u8()?;
+    reader.bytes(scid_length as usize)?;
+    let token_length = reader.varint()?;
+    reader.bytes(token_length as usize)?;
+    let length = reader.varint()? as usize;
+
+    let pn_offset = packet.len() - reader.0.len();
+    let end = pn_offset.checked_add(length)?;
+    if end > packet.len() {
+        return None;
+    }
+
+    let suite = TLS13_AES_128_GCM_SHA256.tls13()?;
+    let keys = Keys::initial(version, suite, suite.quic?, dcid, rustls::Side::Server);
+
+    let sample_offset = pn_offset + 4;
+    let sample = packet.get(sample_offset..sample_offset + keys.remote.header.sample_len())?;
+    if sample_offset + sample.len() > end {
+        return None;
+    }
+
+    let mut packet = packet[..end].to_vec();
+    let sample = sample.to_vec();
+    let (header, rest) = packet.split_at_mut(pn_offset);
+    keys.remote
+        .header
+        .decrypt_in_place(&sample, &mut header[0], &mut rest[..4])
+        .ok()?;
+
+    let pn_length = (packet[0] & 0x03) as usize + 1;
+    let packet_number = packet[pn_offset..pn_offset + pn_length]
+        .iter()
+        .fold(0u64, |pn, &b| (pn << 8) | b as u64);
+
+    let (header, payload) = packet.split_at_mut(pn_offset + pn_length);
+    let payload = keys
+        .remote
+        .packet
+        .decrypt_in_place(packet_number, header, payload)
+        .ok()?;
+
+    Some((payload.to_vec(), end))
+}
+
+/// Cursor over a byte slice; every read fails once the slice is exhausted.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<u32> {
+        self.bytes(3)
+            .map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// QUIC variable-length integer (RFC 9000 section 16).
+    fn varint(&mut self) -> Option<u64> {
+        let first = *self.0.first()?;
+        let bytes = self.bytes(1 << (first >> 6))?;
+        Some(
+            bytes[1..]
+                .iter()
+                .fold((first & 0x3f) as u64, |value, &b| (value << 8) | b as u64),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::pki_types::ServerName;
+    use rustls::quic::{self, Connection};
+    use rustls::{ClientConfig, RootCertStore};
+
+    use super::*;
+
+    fn client_config() -> Arc<ClientConfig> {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        Arc::new(config)
+    }
+
+    fn tls_client_hello(name: &str) -> Vec<u8> {
+        let name = ServerName::try_from(name.to_string()).unwrap();
+        let mut conn = rustls::ClientConnection::new(client_config(), name).unwrap();
+        let mut out = Vec::new();
+        conn.write_tls(&mut out).unwrap();
+        out
+    }
+
+    fn quic_client_hello(name: &str) -> Vec<u8> {
+        let name = ServerName::try_from(name.to_string()).unwrap();
+        let conn = quic::ClientConnection::new(client_config(), Version::V1, name, vec![]).unwrap();
+        let mut conn = Connection::Client(conn);
+        let mut out = Vec::new();
+        conn.write_hs(&mut out);
+        out
+    }
+
+    /// Builds a client Initial carrying `crypto` at `offset`, padded to 1200 bytes.
+    fn initial_packet(dcid: &[u8], packet_number: u32, offset: u64, crypto: &[u8]) -> Vec<u8> {
+        let suite = TLS13_AES_128_GCM_SHA256.tls13().unwrap();
+        let keys = Keys::initial(
+            Version::V1,
+            suite,
+            suite.quic.unwrap(),
+            dcid,
+            rustls::Side::Client,
+        );
+
+        let mut payload = vec![0x06];
+        payload.extend_from_slice(&(0x8000_0000u32 | offset as u32).to_be_bytes());
+        payload.extend_from_slice(&(0x4000u16 | crypto.len() as u16).to_be_bytes());
+        payload.extend_from_slice(crypto);
+        payload.resize(1200 - 64, 0);
+
+        let tag_length = 16;
+        let length = 4 + payload.len() + tag_length;
+        let mut packet = vec![0xc3];
+        packet.extend_from_slice(&1u32.to_be_bytes());
+        packet.push(dcid.len() as u8);
+        packet.extend_from_slice(dcid);
+        packet.push(0); // scid
+        packet.push(0); // token
+        packet.extend_from_slice(&(0x4000u16 | length as u16).to_be_bytes());
+        let pn_offset = packet.len();
+        packet.extend_from_slice(&packet_number.to_be_bytes());
+
+        let tag = keys
+            .local
+            .packet
+            .encrypt_in_place(packet_number as u64, &packet, &mut payload)
+            .unwrap();
+        packet.extend_from_slice(&payload);
+        packet.extend_from_slice(tag.as_ref());
+
+        let sample = packet[pn_offset + 4..pn_offset + 20].to_vec();
+        let (header, rest) = packet.split_at_mut(pn_offset);
+        keys.local
+            .header
+            .encrypt_in_place(&sample, &mut header[0], &mut rest[..4])
+            .unwrap();
+        packet
+    }
+
+    #[test]
+    fn tls_sni_is_found() {
+        let hello = tls_client_hello("Example.COM");
+        assert_eq!(tcp(&hello), Sniff::Found("example.com".to_string()));
+        assert_eq!(tcp(&hello[..hello.len() / 2]), Sniff::Incomplete);
+    }
+
+    #[test]
+    fn http_host_is_found() {
+        let request = b"GET / HTTP/1.1\r\nUser-Agent: test\r\nhost: example.com:8080\r\n\r\n";
+        assert_eq!(tcp(request), Sniff::Found("example.com".to_string()));
+        assert_eq!(tcp(&request[..20]), Sniff::Incomplete);
+        assert_eq!(tcp(b"GE"), Sniff::Incomplete);
+
+        let request = b"GET / HTTP/1.1\r\nHost: 192.0.2.1\r\n\r\n";
+        assert_eq!(tcp(request), Sniff::Unknown);
+    }
+
+    #[test]
+    fn only_client_first_ports_are_sniffed() {
+        assert!(sniffs_tcp_port(443));
+        assert!(sniffs_tcp_port(80));
+        assert!(!sniffs_tcp_port(22));
+        assert!(!sniffs_tcp_port(25));
+    }
+
+    #[test]
+    fn other_protocols_are_unknown() {
+        assert_eq!(tcp(b"SSH-2.0-OpenSSH_9.6\r\n"), Sniff::Unknown);
+        assert_eq!(tcp(&[0x16, 0x01, 0x00, 0x00, 0x10]), Sniff::Unknown);
+        assert_eq!(tcp(&[]), Sniff::Incomplete);
+    }
+
+    #[tokio::test]
+    async fn sniff_tcp_keeps_the_bytes_it_read() {
+        let hello = tls_client_hello("example.com");
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        tokio::io::AsyncWriteExt::write_all(&mut client, &hello)
+            .await
+            .unwrap();
+
+        let mut buf = Vec::new();
+        let domain = sniff_tcp(&mut server, &mut buf).await;
+        assert_eq!(domain.as_deref(), Some("example.com"));
+        assert_eq!(buf, hello);
+    }
+
+    #[test]
+    fn quic_sni_is_found_in_initial() {
+        let hello = quic_client_hello("example.com");
+        let mut sniffer = QuicSniffer::new();
+
+        let packet = initial_packet(&[7; 8], 0, 0, &hello);
+        assert_eq!(
+            sniffer.push(packet.into()),
+            Sniff::Found("example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn quic_client_hello_split_across_packets_out_of_order() {
+        let hello = quic_client_hello("example.com");
+        let (head, tail) = hello.split_at(hello.len() / 2);
+        let mut sniffer = QuicSniffer::new();
+
+        let second = initial_packet(&[7; 8], 1, head.len() as u64, tail);
+        assert_eq!(sniffer.push(second.clone().into()), Sniff::Incomplete);
+        let first = initial_packet(&[7; 8], 0, 0, head);
+        assert_eq!(
+            sniffer.push(first.clone().into()),
+            Sniff::Found("example.com".to_string())
+        );
+        assert_eq!(
+            sniffer.into_packets(),
+            vec![Bytes::from(second), Bytes::from(first)]
+        );
+    }
+
+    #[test]
+    fn non_quic_datagrams_are_unknown() {
+        let mut sniffer = QuicSniffer::new();
+        assert_eq!(
+            sniffer.push(Bytes::from_static(b"not quic")),
+            Sniff::Unknown
+        );
+    }
+}
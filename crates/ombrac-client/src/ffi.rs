@@ -248,6 +248,102 @@ pub extern "C" fn ombrac_client_service_shutdown() -> i32 {
     }
 }
 
+/// Starts writing the packets through the TUN endpoint to a pcap file.
+///
+/// Any running capture is replaced. The capture is rotated according to the
+/// `pcap_max_size` and `pcap_max_files` settings of the configuration and
+/// needs no privileges, so it can be used to debug mobile builds.
+///
+/// # Arguments
+///
+/// * `path` - A pointer to a null-terminated UTF-8 string with the file to
+///   write to.
+/// * `filter` - A pointer to a null-terminated UTF-8 string with a capture
+///   filter such as `"udp and dst port 53"`, or null to use the configured
+///   `pcap_filter`.
+///
+/// # Returns
+///
+/// * `0` on success.
+/// * `-1` if the service is not running, the filter is invalid or the file
+///   cannot be created.
+///
+/// # Safety
+///
+/// The caller must ensure that `path` and `filter` are valid pointers to
+/// null-terminated C strings, or null for `filter`.
+///
+/// This function is protected against Rust panics crossing the FFI boundary.
+#[cfg(feature = "endpoint-tun")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ombrac_client_capture_start(
+    path: *const c_char,
+    filter: *const c_char,
+) -> i32 {
+    let result = std::panic::catch_unwind(|| {
+        let path = unsafe { c_str_to_string(path) };
+        if path.is_empty() {
+            error!("Capture path must not be empty");
+            return -1;
+        }
+        let filter = (!filter.is_null()).then(|| unsafe { c_str_to_string(filter) });
+
+        let handle_guard = SERVICE_HANDLE.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(service) = handle_guard.as_ref().and_then(|h| h.service.as_ref()) else {
+            error!("Service is not running");
+            return -1;
+        };
+        match service.start_capture(path, filter.as_deref()) {
+            Ok(()) => 0,
+            Err(e) => {
+                error!("Failed to start capture: {}", e);
+                -1
+            }
+        }
+    });
+
+    match result {
+        Ok(ret) => ret,
+        Err(_) => {
+            error!("Panic occurred in ombrac_client_capture_start");
+            -1
+        }
+    }
+}
+
+/// Stops the running packet capture, flushing and closing its file.
+///
+/// It is safe to call even if no capture is running.
+///
+/// # Returns
+///
+/// * `0` on completion.
+/// * `-1` if the service is not running.
+///
+/// This function is protected against Rust panics crossing the FFI boundary.
+#[cfg(feature = "endpoint-tun")]
+#[unsafe(no_mangle)]
+pub extern "C" fn ombrac_client_capture_stop() -> i32 {
+    let result = std::panic::catch_unwind(|| {
+        let handle_guard = SERVICE_HANDLE.lock().unwrap_or_else(|e| e.into_inner());
+        match handle_guard.as_ref().and_then(|h| h.service.as_ref()) {
+            Some(service) => {
+                service.stop_capture();
+                0
+            }
+            None => -1,
+        }
+    });
+
+    match result {
+        Ok(ret) => ret,
+        Err(_) => {
+            error!("Panic occurred in ombrac_client_capture_stop");
+            -1
+        }
+    }
+}
+
 /// Returns the version of the ombrac-client library.
 ///
 /// The returned string is a null-terminated UTF-8 string. The memory for this
@@ -1,10 +1,13 @@
 pub mod client;
 pub mod config;
 pub mod connection;
+#[cfg(any(feature = "endpoint-dns", feature = "endpoint-tun"))]
+pub mod dns;
 #[cfg(any(
     feature = "endpoint-default",
     feature = "endpoint-socks",
     feature = "endpoint-http",
+    feature = "endpoint-dns",
     feature = "endpoint-tun"
 ))]
 pub mod endpoint;
@@ -1,19 +1,23 @@
 use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
 
+use ombrac::key::PrivateKey;
 use ombrac_macros::{error, info, warn};
 use ombrac_transport::quic::Connection as QuicConnection;
 use ombrac_transport::quic::TransportConfig as QuicTransportConfig;
 use ombrac_transport::quic::client::Client as QuicClient;
 use ombrac_transport::quic::client::Config as QuicConfig;
+use ombrac_transport::quic::client::Pin;
 use ombrac_transport::quic::error::Error as QuicError;
 
 use crate::client::Client;
 use crate::config::{ServiceConfig, TlsMode};
+use crate::connection::Credential;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -36,6 +40,7 @@ pub enum Error {
     feature = "endpoint-default",
     feature = "endpoint-socks",
     feature = "endpoint-http",
+    feature = "endpoint-dns",
     feature = "endpoint-tun"
 ))]
 macro_rules! require_config {
@@ -81,6 +86,10 @@ pub struct OmbracClient {
     client: Arc<Client<QuicClient, QuicConnection>>,
     handles: Vec<JoinHandle<()>>,
     shutdown_tx: broadcast::Sender<()>,
+    #[cfg(feature = "endpoint-tun")]
+    pcap: crate::endpoint::tun::PcapTap,
+    #[cfg(feature = "endpoint-tun")]
+    pcap_options: crate::endpoint::tun::PcapOptions,
 }
 
 impl OmbracClient {
@@ -106,11 +115,19 @@ impl OmbracClient {
 
         info!("binding udp socket to {}", transport.local_addr()?);
 
-        let secret = *blake3::hash(config.secret.as_bytes()).as_bytes();
+        let transport_cfg = &config.transport;
+        let credential = match (&transport_cfg.auth_key, &transport_cfg.auth_token) {
+            (Some(path), _) => Credential::Key(Arc::new(PrivateKey::load(path)?)),
+            (None, Some(token)) => Credential::Token(token.clone()),
+            (None, None) => Credential::Secret(
+                *blake3::hash(config.secret.as_bytes()).as_bytes(),
+                transport_cfg.auth_mode.unwrap_or_default(),
+            ),
+        };
         let client = Arc::new(
-            Client::new(
+            Client::with_credential(
                 transport,
-                secret,
+                credential,
                 config.auth_option.clone().map(Into::into),
             )
             .await
@@ -120,6 +137,23 @@ impl OmbracClient {
         let mut _handles = Vec::new();
         let (shutdown_tx, _) = broadcast::channel(1);
 
+        // Fake addresses handed out by the DNS endpoint are shared with the
+        // HTTP and SOCKS endpoints so they can be mapped back to domains.
+        #[cfg(feature = "endpoint-dns")]
+        let fake_dns = Self::fake_dns_from_config(&config).await?;
+        #[cfg(feature = "endpoint-dns")]
+        if let Some(fake_dns) = &fake_dns {
+            let fake_dns = fake_dns.clone();
+            let mut shutdown_rx = shutdown_tx.subscribe();
+            _handles.push(tokio::spawn(async move {
+                fake_dns
+                    .persist(async {
+                        let _ = shutdown_rx.recv().await;
+                    })
+                    .await
+            }));
+        }
+
         // Start HTTP endpoint if configured
         #[cfg(feature = "endpoint-http")]
         if config.endpoint.http.is_some() {
@@ -128,6 +162,8 @@ impl OmbracClient {
                 Self::endpoint_http_accept_loop(
                     config.clone(),
                     client.clone(),
+                    #[cfg(feature = "endpoint-dns")]
+                    fake_dns.clone(),
                     shutdown_tx.subscribe(),
                 ),
             ));
@@ -141,11 +177,32 @@ impl OmbracClient {
                 Self::endpoint_socks_accept_loop(
                     config.clone(),
                     client.clone(),
+                    #[cfg(feature = "endpoint-dns")]
+                    fake_dns.clone(),
                     shutdown_tx.subscribe(),
                 ),
             ));
         }
 
+        // Start DNS endpoint if configured
+        #[cfg(feature = "endpoint-dns")]
+        if let Some(dns_config) = &config.endpoint.dns
+            && dns_config.bind.is_some()
+        {
+            _handles.push(Self::spawn_endpoint(
+                "DNS",
+                Self::endpoint_dns_accept_loop(
+                    config.clone(),
+                    client.clone(),
+                    fake_dns.clone(),
+                    shutdown_tx.subscribe(),
+                ),
+            ));
+        }
+
+        #[cfg(feature = "endpoint-tun")]
+        let (pcap, pcap_options) = Self::pcap_from_config(&config)?;
+
         // Start TUN endpoint if configured
         #[cfg(feature = "endpoint-tun")]
         if let Some(tun_config) = &config.endpoint.tun
@@ -158,6 +215,7 @@ impl OmbracClient {
                 Self::endpoint_tun_accept_loop(
                     config.clone(),
                     client.clone(),
+                    pcap.clone(),
                     shutdown_tx.subscribe(),
                 ),
             ));
@@ -174,6 +232,10 @@ impl OmbracClient {
             client,
             handles: _handles,
             shutdown_tx,
+            #[cfg(feature = "endpoint-tun")]
+            pcap,
+            #[cfg(feature = "endpoint-tun")]
+            pcap_options,
         })
     }
 
@@ -191,6 +253,38 @@ impl OmbracClient {
         self.client.metrics()
     }
 
+    /// Starts capturing the packets through the TUN endpoint to `path`,
+    /// replacing any running capture.
+    ///
+    /// `filter` overrides the configured `pcap_filter`; rotation follows the
+    /// configured `pcap_max_size` and `pcap_max_files`.
+    #[cfg(feature = "endpoint-tun")]
+    pub fn start_capture(
+        &self,
+        path: impl Into<std::path::PathBuf>,
+        filter: Option<&str>,
+    ) -> Result<()> {
+        let mut options = self.pcap_options.clone();
+        options.path = path.into();
+        if let Some(filter) = filter {
+            options.filter = filter
+                .parse()
+                .map_err(|e: io::Error| Error::Config(e.to_string()))?;
+        }
+        self.pcap.start(options)?;
+        info!("started packet capture");
+        Ok(())
+    }
+
+    /// Stops the packet capture started from the config or [`Self::start_capture`].
+    #[cfg(feature = "endpoint-tun")]
+    pub fn stop_capture(&self) {
+        if self.pcap.is_active() {
+            self.pcap.stop();
+            info!("stopped packet capture");
+        }
+    }
+
     /// Gracefully shuts down the client.
     ///
     /// This method will:
@@ -225,12 +319,16 @@ impl OmbracClient {
                 error!("task failed to shut down cleanly: {:?}", _err);
             }
         }
+
+        #[cfg(feature = "endpoint-tun")]
+        self.pcap.stop();
     }
 
     #[cfg(any(
         feature = "endpoint-default",
         feature = "endpoint-socks",
         feature = "endpoint-http",
+        feature = "endpoint-dns",
         feature = "endpoint-tun"
     ))]
     fn spawn_endpoint(
@@ -248,6 +346,7 @@ impl OmbracClient {
     async fn endpoint_http_accept_loop(
         config: Arc<ServiceConfig>,
         ombrac: Arc<Client<QuicClient, QuicConnection>>,
+        #[cfg(feature = "endpoint-dns")] fake_dns: Option<Arc<crate::dns::FakeDns>>,
         mut shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<()> {
         use crate::endpoint::http::Server as HttpServer;
@@ -256,7 +355,14 @@ impl OmbracClient {
 
         info!("starting http/https endpoint, listening on {bind_addr}");
 
-        HttpServer::new(ombrac)
+        #[allow(unused_mut)]
+        let mut server = HttpServer::new(ombrac);
+        #[cfg(feature = "endpoint-dns")]
+        if let Some(fake_dns) = fake_dns {
+            server = server.with_fake_dns(fake_dns);
+        }
+
+        server
             .accept_loop(socket, async {
                 let _ = shutdown_rx.recv().await;
             })
@@ -268,6 +374,7 @@ impl OmbracClient {
     async fn endpoint_socks_accept_loop(
         config: Arc<ServiceConfig>,
         ombrac: Arc<Client<QuicClient, QuicConnection>>,
+        #[cfg(feature = "endpoint-dns")] fake_dns: Option<Arc<crate::dns::FakeDns>>,
         mut shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<()> {
         use crate::endpoint::socks::Server as SocksServer;
@@ -277,7 +384,14 @@ impl OmbracClient {
 
         info!("starting socks endpoint, listening on {bind_addr}");
 
-        SocksServer::new(ombrac)
+        #[allow(unused_mut)]
+        let mut server = SocksServer::new(ombrac);
+        #[cfg(feature = "endpoint-dns")]
+        if let Some(fake_dns) = fake_dns {
+            server = server.with_fake_dns(fake_dns);
+        }
+
+        server
             .run(socket, async {
                 let _ = shutdown_rx.recv().await;
             })
@@ -285,14 +399,134 @@ impl OmbracClient {
             .map_err(|e| Error::Endpoint(format!("socks server failed to run: {}", e)))
     }
 
+    #[cfg(feature = "endpoint-dns")]
+    async fn fake_dns_from_config(config: &ServiceConfig) -> Result<Option<Arc<crate::dns::FakeDns>>> {
+        use std::str::FromStr;
+
+        let Some(dns_config) = &config.endpoint.dns else {
+            return Ok(None);
+        };
+        if dns_config.bind.is_none() {
+            return Ok(None);
+        }
+        let Some(cidr) = &dns_config.fake_ip else {
+            return Ok(None);
+        };
+
+        let ip_net = ipnet::Ipv4Net::from_str(cidr)
+            .map_err(|e| Error::Config(format!("failed to parse fake ip cidr '{cidr}': {e}")))?;
+        let mut fake_dns = crate::dns::FakeDns::new(ip_net);
+
+        if let Some(cidr) = &dns_config.fake_ipv6 {
+            let ip_net = ipnet::Ipv6Net::from_str(cidr).map_err(|e| {
+                Error::Config(format!("failed to parse fake ipv6 cidr '{cidr}': {e}"))
+            })?;
+            fake_dns = fake_dns.with_ipv6(ip_net);
+        }
+
+        if let Some(path) = &dns_config.fake_ip_state {
+            fake_dns = fake_dns.with_state_file(path);
+            if let Err(_err) = fake_dns.load_state().await {
+                warn!("failed to load fake ip state from {}: {_err}", path.display());
+            }
+        }
+
+        Ok(Some(Arc::new(fake_dns)))
+    }
+
+    #[cfg(feature = "endpoint-dns")]
+    async fn endpoint_dns_accept_loop(
+        config: Arc<ServiceConfig>,
+        ombrac: Arc<Client<QuicClient, QuicConnection>>,
+        fake_dns: Option<Arc<crate::dns::FakeDns>>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<()> {
+        use crate::dns::RemoteResolver;
+        use crate::endpoint::dns::Server as DnsServer;
+
+        let config = require_config!(config.endpoint.dns.as_ref(), "endpoint.dns")?;
+        let bind_addr = require_config!(config.bind, "endpoint.dns.bind")?;
+
+        let mut resolver = RemoteResolver::with_capacity(ombrac, config.cache_size());
+        if let Some(upstream) = &config.upstream {
+            let upstream = ombrac::protocol::Address::try_from(upstream.as_str()).map_err(|e| {
+                Error::Config(format!("invalid dns upstream address '{upstream}': {e}"))
+            })?;
+            resolver = resolver.with_upstream(upstream);
+        }
+
+        let udp = tokio::net::UdpSocket::bind(bind_addr).await?;
+        let tcp = tokio::net::TcpListener::bind(bind_addr).await?;
+
+        info!("starting dns endpoint, listening on {bind_addr}");
+
+        let mut server = DnsServer::new(resolver);
+        if let Some(fake_dns) = fake_dns {
+            server = server.with_fake_dns(fake_dns);
+        }
+
+        server
+            .run(udp, tcp, async {
+                let _ = shutdown_rx.recv().await;
+            })
+            .await
+            .map_err(|e| Error::Endpoint(format!("dns server failed to run: {}", e)))
+    }
+
+    /// Returns the capture tap shared with the TUN endpoint and the options
+    /// captures start with, starting the capture if `tun.pcap` is set.
+    #[cfg(feature = "endpoint-tun")]
+    fn pcap_from_config(
+        config: &ServiceConfig,
+    ) -> Result<(
+        crate::endpoint::tun::PcapTap,
+        crate::endpoint::tun::PcapOptions,
+    )> {
+        use crate::endpoint::tun::{PcapOptions, PcapTap};
+
+        let tap = PcapTap::new();
+        let mut options = PcapOptions::new("");
+        let Some(tun_config) = &config.endpoint.tun else {
+            return Ok((tap, options));
+        };
+
+        if let Some(value) = &tun_config.pcap_filter {
+            options.filter = value
+                .parse()
+                .map_err(|e: io::Error| Error::Config(e.to_string()))?;
+        }
+        if let Some(value) = tun_config.pcap_max_size {
+            options.max_size = value;
+        }
+        if let Some(value) = tun_config.pcap_max_files {
+            options.max_files = value;
+        }
+        if let Some(path) = &tun_config.pcap {
+            let mut options = options.clone();
+            options.path = path.clone();
+            tap.start(options).map_err(|e| {
+                Error::Config(format!(
+                    "failed to open pcap file '{}': {e}",
+                    path.display()
+                ))
+            })?;
+            info!("capturing tun packets to {}", path.display());
+        }
+
+        Ok((tap, options))
+    }
+
     #[cfg(feature = "endpoint-tun")]
     async fn endpoint_tun_accept_loop(
         config: Arc<ServiceConfig>,
         ombrac: Arc<Client<QuicClient, QuicConnection>>,
+        pcap: crate::endpoint::tun::PcapTap,
         mut shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<()> {
-        use crate::endpoint::tun::{AsyncDevice, Tun, TunConfig};
+        use crate::config::IcmpEchoMode;
+        use crate::endpoint::tun::{AsyncDevice, IcmpEcho, RouteRules, Rule, Tun, TunConfig};
 
+        let server = config.server.clone();
         let config = require_config!(config.endpoint.tun.as_ref(), "endpoint.tun")?;
 
         let device = match config.tun_fd {
@@ -360,10 +594,74 @@ impl OmbracClient {
                 Error::Config(format!("failed to parse fake_dns cidr '{value}': {e}"))
             })?;
         };
+        tun_config.fakedns_state_file = config.fake_dns_state.clone();
+        if config.route_include.is_some() || config.route_exclude.is_some() {
+            let parse = |values: &[String]| {
+                values
+                    .iter()
+                    .map(|value| value.parse::<Rule>())
+                    .collect::<io::Result<Vec<_>>>()
+                    .map_err(|e| Error::Config(e.to_string()))
+            };
+            let include = parse(config.route_include.as_deref().unwrap_or_default())?;
+            let exclude = match &config.route_exclude {
+                Some(values) => parse(values)?,
+                None => RouteRules::default_exclude(),
+            };
+            tun_config.rules = RouteRules::new(include, exclude);
+        }
+        if let Some(value) = &config.fake_dns_ipv6 {
+            tun_config.fakedns_ipv6_cidr = Some(value.parse().map_err(|e| {
+                Error::Config(format!("failed to parse fake_dns_ipv6 cidr '{value}': {e}"))
+            })?);
+        };
+        if let Some(value) = config.sniff {
+            tun_config.sniff = value;
+        };
+        tun_config.icmp_echo = match config.icmp_echo.unwrap_or_default() {
+            IcmpEchoMode::Off => IcmpEcho::Off,
+            IcmpEchoMode::Local => IcmpEcho::Local(Duration::from_millis(
+                config.icmp_echo_latency.unwrap_or_default(),
+            )),
+            IcmpEchoMode::Relay => IcmpEcho::Relay,
+        };
+        tun_config.pcap = pcap;
         if let Some(value) = config.disable_udp_443 {
             tun_config.disable_udp_443 = value;
         };
 
+        tun_config.direct_dns = match &config.direct_dns {
+            Some(values) => values
+                .iter()
+                .map(|value| {
+                    let nameserver = match value.parse::<std::net::IpAddr>() {
+                        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+                        Err(_) => value.parse::<SocketAddr>(),
+                    };
+                    nameserver.map_err(|e| {
+                        Error::Config(format!("failed to parse direct_dns '{value}': {e}"))
+                    })
+                })
+                .collect::<Result<_>>()?,
+            // Read before the auto route points the system resolver at the device.
+            #[cfg(target_os = "linux")]
+            None if config.auto_route.unwrap_or(false) => {
+                crate::endpoint::tun::auto_route::system_nameservers()
+            }
+            None => Vec::new(),
+        };
+
+        // Kept alive until the endpoint stops; dropping it rolls the routes back.
+        let _auto_route = if config.auto_route.unwrap_or(false) {
+            Some(Self::install_auto_route(config, &device, &server).await?)
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        if let Some(auto_route) = &_auto_route {
+            tun_config.direct_interface = auto_route.interface().map(str::to_string);
+        }
+
         let tun = Tun::new(tun_config.into(), ombrac);
         let shutdown_signal = async {
             let _ = shutdown_rx.recv().await;
@@ -373,13 +671,64 @@ impl OmbracClient {
             .await
             .map_err(|e| Error::Endpoint(format!("tun device runtime error: {}", e)))
     }
-}
 
-async fn quic_client_from_config(config: &ServiceConfig) -> io::Result<QuicClient> {
-    let server = &config.server;
-    let transport_cfg = &config.transport;
+    #[cfg(all(feature = "endpoint-tun", target_os = "linux"))]
+    async fn install_auto_route(
+        config: &crate::config::TunConfig,
+        device: &tun_rs::AsyncDevice,
+        server: &str,
+    ) -> Result<crate::endpoint::tun::auto_route::AutoRoute> {
+        use crate::endpoint::tun::auto_route::{AutoRoute, AutoRouteConfig, DEFAULT_STATE_FILE};
+
+        let dns = match &config.auto_route_dns {
+            Some(value) => Some(value.parse().map_err(|e| {
+                Error::Config(format!("failed to parse auto_route_dns '{value}': {e}"))
+            })?),
+            None => None,
+        };
+        let addresses = device.addresses()?;
+        let bypass = tokio::net::lookup_host(server)
+            .await?
+            .map(|addr| addr.ip())
+            .collect();
+
+        let auto_route_config = AutoRouteConfig {
+            device: device.name()?,
+            ipv4: addresses.iter().any(|addr| addr.is_ipv4()),
+            // Link-local addresses alone don't make the device routable for IPv6.
+            ipv6: addresses.iter().any(|addr| match addr {
+                std::net::IpAddr::V6(v6) => !v6.is_unicast_link_local(),
+                std::net::IpAddr::V4(_) => false,
+            }),
+            bypass,
+            dns,
+            state_file: DEFAULT_STATE_FILE.into(),
+        };
+
+        tokio::task::spawn_blocking(move || AutoRoute::install(&auto_route_config))
+            .await
+            .map_err(io::Error::other)?
+            .map_err(|e| Error::Endpoint(format!("failed to set up auto route: {e}")))
+    }
 
-    let server_name = match &transport_cfg.server_name {
+    #[cfg(all(feature = "endpoint-tun", not(target_os = "linux")))]
+    async fn install_auto_route(
+        _config: &crate::config::TunConfig,
+        _device: &tun_rs::AsyncDevice,
+        _server: &str,
+    ) -> Result<()> {
+        Err(Error::Config(
+            "'auto_route' is only supported on Linux.".to_string(),
+        ))
+    }
+}
+
+/// Resolves `server` and derives the TLS server name from it, unless given.
+async fn resolve_server(
+    server: &str,
+    server_name: Option<&String>,
+) -> io::Result<(SocketAddr, String)> {
+    let server_name = match server_name {
         Some(value) => value.clone(),
         None => {
             let pos = server.rfind(':').ok_or_else(|| {
@@ -400,6 +749,28 @@ async fn quic_client_from_config(config: &ServiceConfig) -> io::Result<QuicClien
         )
     })?;
 
+    Ok((server_addr, server_name))
+}
+
+/// Connects to `server` without verifying it and returns the pins of the
+/// certificate chain it presents, leaf first.
+pub async fn server_pins(server: &str, server_name: Option<&String>) -> io::Result<Vec<Pin>> {
+    let (server_addr, server_name) = resolve_server(server, server_name).await?;
+    let mut quic_config = QuicConfig::new(server_addr, server_name);
+    quic_config.alpn_protocols = vec!["h3".into()];
+
+    let chain = ombrac_transport::quic::client::fetch_certificate_chain(quic_config).await?;
+    Ok(chain
+        .iter()
+        .map(Pin::of_certificate)
+        .collect::<std::result::Result<_, _>>()?)
+}
+
+async fn quic_client_from_config(config: &ServiceConfig) -> io::Result<QuicClient> {
+    let transport_cfg = &config.transport;
+    let (server_addr, server_name) =
+        resolve_server(&config.server, transport_cfg.server_name.as_ref()).await?;
+
     let mut quic_config = QuicConfig::new(server_addr, server_name);
 
     quic_config.enable_zero_rtt = transport_cfg.zero_rtt.unwrap_or(false);
@@ -407,6 +778,29 @@ async fn quic_client_from_config(config: &ServiceConfig) -> io::Result<QuicClien
         quic_config.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
     }
 
+    quic_config.crl_paths = transport_cfg.crl.clone().unwrap_or_default();
+    quic_config.allow_unknown_revocation_status = transport_cfg.crl_allow_unknown.unwrap_or(false);
+
+    if let Some(pins) = &transport_cfg.pins {
+        if transport_cfg.tls_mode == Some(TlsMode::Insecure) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pins can't be used in insecure mode, use tls instead",
+            ));
+        }
+        if !quic_config.crl_paths.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pins can't be combined with crl, which only applies to certificates verified against a ca",
+            ));
+        }
+        quic_config.pinned_keys = pins
+            .iter()
+            .map(|pin| pin.parse())
+            .collect::<io::Result<_>>()?;
+        quic_config.verify_pinned_hostname = transport_cfg.pin_verify_hostname.unwrap_or(true);
+    }
+
     match transport_cfg.tls_mode.unwrap_or(TlsMode::Tls) {
         TlsMode::Tls => {
             if let Some(ca) = &transport_cfg.ca_cert {
@@ -0,0 +1,608 @@
+//! IP-level reassembly of fragmented IPv4 and IPv6 packets.
+//!
+//! Packets are reassembled before they are dispatched to the TCP, UDP or
+//! ICMP handlers, which all expect complete datagrams. Reassembly is bounded
+//! in the number of datagrams, the number of fragments per datagram and the
+//! total bytes held, and incomplete datagrams are dropped after a timeout.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use smoltcp::wire::{Ipv4Packet, Ipv6Packet};
+
+use crate::stack::IpPacket;
+use crate::trace;
+
+/// Default timeout for fragment reassembly [10 seconds]
+const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default maximum number of datagrams being reassembled at once [256]
+const DEFAULT_MAX_CONCURRENT_REASSEMBLIES: usize = 256;
+
+/// Default maximum number of bytes held across all datagrams [4 MiB]
+const DEFAULT_MAX_BUFFERED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Maximum allowed number of fragments per datagram to prevent memory exhaustion.
+const MAX_FRAGMENT_COUNT: usize = 64;
+
+/// Largest payload an IP datagram can carry.
+const MAX_PAYLOAD_LEN: usize = 65535;
+
+const IPV6_HEADER_LEN: usize = 40;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+
+/// Cache key type: (source, destination, protocol, identification)
+///
+/// For IPv6 the protocol is always that of the fragment header, since the
+/// identification alone distinguishes datagrams.
+type Key = (IpAddr, IpAddr, u8, u32);
+
+/// Outcome of feeding a packet to the [`Reassembler`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Reassembly {
+    /// The packet is not a fragment and can be dispatched as is.
+    Unfragmented,
+    /// The packet was a fragment of a datagram that is not complete yet, or
+    /// was dropped.
+    Pending,
+    /// The packet completed a datagram, returned as a single unfragmented packet.
+    Complete(Bytes),
+}
+
+/// A fragment of a datagram, with its offset in the original payload.
+struct Fragment<'a> {
+    key: Key,
+    offset: usize,
+    more: bool,
+    /// Headers that precede the fragmented payload, already rewritten to
+    /// describe an unfragmented packet. Only kept from the first fragment.
+    header: Option<BytesMut>,
+    data: &'a [u8],
+}
+
+/// Buffer for reassembling one fragmented datagram.
+struct ReassemblyBuffer {
+    /// Received fragments as (offset, data), sorted by offset
+    fragments: Vec<(usize, Bytes)>,
+    /// Headers of the rebuilt packet, once the first fragment arrived
+    header: Option<BytesMut>,
+    /// Payload length, once the last fragment arrived
+    total_len: Option<usize>,
+    /// Bytes held by this buffer
+    buffered: usize,
+    started: Instant,
+}
+
+impl ReassemblyBuffer {
+    fn new(now: Instant) -> Self {
+        Self {
+            fragments: Vec::new(),
+            header: None,
+            total_len: None,
+            buffered: 0,
+            started: now,
+        }
+    }
+
+    /// Adds a fragment to the buffer.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the fragment is inconsistent with those received before, in
+    /// which case the whole datagram should be dropped. Exact duplicates are
+    /// accepted and ignored.
+    fn add_fragment(&mut self, fragment: Fragment<'_>) -> bool {
+        let end = fragment.offset + fragment.data.len();
+        if end > MAX_PAYLOAD_LEN || self.total_len.is_some_and(|total| end > total) {
+            return false;
+        }
+        if !fragment.more {
+            if self.total_len.is_some_and(|total| total != end)
+                || self
+                    .fragments
+                    .last()
+                    .is_some_and(|(offset, data)| offset + data.len() > end)
+            {
+                return false;
+            }
+            self.total_len = Some(end);
+        }
+
+        let index = self
+            .fragments
+            .partition_point(|(offset, _)| *offset < fragment.offset);
+        if let Some((offset, data)) = self.fragments.get(index)
+            && *offset == fragment.offset
+        {
+            // Retransmitted fragment; anything else at the same offset is an overlap.
+            return data.as_ref() == fragment.data;
+        }
+        // Overlapping fragments are never legitimate (RFC 5722) and are a
+        // known way to evade inspection, so they invalidate the datagram.
+        let overlaps_previous = index > 0 && {
+            let (offset, data) = &self.fragments[index - 1];
+            offset + data.len() > fragment.offset
+        };
+        let overlaps_next = self
+            .fragments
+            .get(index)
+            .is_some_and(|(offset, _)| *offset < end);
+        if overlaps_previous || overlaps_next || self.fragments.len() >= MAX_FRAGMENT_COUNT {
+            return false;
+        }
+
+        if let Some(header) = fragment.header {
+            self.buffered += header.len();
+            self.header = Some(header);
+        }
+        self.buffered += fragment.data.len();
+        self.fragments.insert(
+            index,
+            (fragment.offset, Bytes::copy_from_slice(fragment.data)),
+        );
+        true
+    }
+
+    /// Checks if the fragments cover the whole payload and the headers are known.
+    fn is_complete(&self) -> bool {
+        let Some(total_len) = self.total_len else {
+            return false;
+        };
+        self.header.is_some()
+            && self
+                .fragments
+                .iter()
+                .try_fold(0, |expected, (offset, data)| {
+                    (*offset == expected).then_some(offset + data.len())
+                })
+                == Some(total_len)
+    }
+
+    /// Assembles the fragments into a complete packet and takes ownership.
+    fn assemble_and_take(&mut self) -> Option<Bytes> {
+        if !self.is_complete() {
+            return None;
+        }
+
+        let mut packet = self.header.take()?;
+        packet.reserve(self.total_len?);
+        for (_, data) in self.fragments.drain(..) {
+            packet.extend_from_slice(&data);
+        }
+        finish(packet)
+    }
+}
+
+/// Updates the length fields (and the IPv4 checksum) of a rebuilt packet.
+fn finish(mut packet: BytesMut) -> Option<Bytes> {
+    if packet[0] >> 4 == 4 {
+        let len = u16::try_from(packet.len()).ok()?;
+        let mut ipv4 = Ipv4Packet::new_unchecked(&mut packet[..]);
+        ipv4.set_total_len(len);
+        ipv4.fill_checksum();
+    } else {
+        let len = u16::try_from(packet.len() - IPV6_HEADER_LEN).ok()?;
+        Ipv6Packet::new_unchecked(&mut packet[..]).set_payload_len(len);
+    }
+    Some(packet.freeze())
+}
+
+/// Reassembles fragmented IPv4 and IPv6 packets into complete datagrams.
+pub(crate) struct Reassembler {
+    buffers: HashMap<Key, ReassemblyBuffer>,
+    max_concurrent_reassemblies: usize,
+    max_buffered_bytes: usize,
+    reassembly_timeout: Duration,
+    buffered: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_CONCURRENT_REASSEMBLIES,
+            DEFAULT_MAX_BUFFERED_BYTES,
+            DEFAULT_REASSEMBLY_TIMEOUT,
+        )
+    }
+}
+
+impl Reassembler {
+    pub(crate) fn new(
+        max_concurrent_reassemblies: usize,
+        max_buffered_bytes: usize,
+        reassembly_timeout: Duration,
+    ) -> Self {
+        Self {
+            buffers: HashMap::new(),
+            max_concurrent_reassemblies,
+            max_buffered_bytes,
+            reassembly_timeout,
+            buffered: 0,
+        }
+    }
+
+    pub(crate) fn process(&mut self, packet: &IpPacket<&[u8]>) -> Reassembly {
+        self.process_at(packet, Instant::now())
+    }
+
+    fn process_at(&mut self, packet: &IpPacket<&[u8]>, now: Instant) -> Reassembly {
+        let fragment = match packet {
+            IpPacket::Ipv4(packet) => ipv4_fragment(packet),
+            IpPacket::Ipv6(packet) => ipv6_fragment(packet),
+        };
+        let fragment = match fragment {
+            Ok(Some(fragment)) => fragment,
+            Ok(None) => return Reassembly::Unfragmented,
+            Err(_reason) => {
+                trace!("dropping ip fragment: {}", _reason);
+                return Reassembly::Pending;
+            }
+        };
+
+        // A fragment carrying the whole payload (an IPv6 atomic fragment)
+        // needs no buffering.
+        if !fragment.more
+            && let Some(mut packet) = fragment.header
+        {
+            packet.extend_from_slice(fragment.data);
+            return finish(packet).map_or(Reassembly::Pending, Reassembly::Complete);
+        }
+
+        self.evict_expired(now);
+        let key = fragment.key;
+        let size = fragment.data.len() + fragment.header.as_ref().map_or(0, |h| h.len());
+        if self.buffered + size > self.max_buffered_bytes
+            || (!self.buffers.contains_key(&key)
+                && self.buffers.len() >= self.max_concurrent_reassemblies)
+        {
+            trace!("dropping ip fragment: reassembly limits reached");
+            return Reassembly::Pending;
+        }
+
+        let buffer = self
+            .buffers
+            .entry(key)
+            .or_insert_with(|| ReassemblyBuffer::new(now));
+        let before = buffer.buffered;
+        if !buffer.add_fragment(fragment) {
+            trace!("dropping fragmented datagram: inconsistent fragments");
+            self.remove(&key);
+            return Reassembly::Pending;
+        }
+        self.buffered += buffer.buffered - before;
+
+        if !buffer.is_complete() {
+            return Reassembly::Pending;
+        }
+        let packet = buffer.assemble_and_take();
+        self.remove(&key);
+        packet.map_or(Reassembly::Pending, Reassembly::Complete)
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(buffer) = self.buffers.remove(key) {
+            self.buffered -= buffer.buffered;
+        }
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let timeout = self.reassembly_timeout;
+        let mut released = 0;
+        self.buffers.retain(|_, buffer| {
+            let alive = now.duration_since(buffer.started) < timeout;
+            if !alive {
+                released += buffer.buffered;
+            }
+            alive
+        });
+        self.buffered -= released;
+    }
+}
+
+fn ipv4_fragment<'a>(packet: &Ipv4Packet<&'a [u8]>) -> Result<Option<Fragment<'a>>, &'static str> {
+    let offset = packet.frag_offset() as usize;
+    let more = packet.more_frags();
+    if offset == 0 && !more {
+        return Ok(None);
+    }
+
+    let data = packet.payload();
+    if more && (data.is_empty() || !data.len().is_multiple_of(8)) {
+        return Err("non-final fragment is not a multiple of 8 bytes");
+    }
+
+    let header = (offset == 0).then(|| {
+        let raw: &'a [u8] = packet.clone().into_inner();
+        let mut header = BytesMut::from(&raw[..packet.header_len() as usize]);
+        let mut ipv4 = Ipv4Packet::new_unchecked(&mut header[..]);
+        ipv4.set_more_frags(false);
+        ipv4.set_frag_offset(0);
+        header
+    });
+
+    Ok(Some(Fragment {
+        key: (
+            IpAddr::from(packet.src_addr()),
+            IpAddr::from(packet.dst_addr()),
+            packet.next_header().into(),
+            packet.ident() as u32,
+        ),
+        offset,
+        more,
+        header,
+        data,
+    }))
+}
+
+fn ipv6_fragment<'a>(packet: &Ipv6Packet<&'a [u8]>) -> Result<Option<Fragment<'a>>, &'static str> {
+    let payload = packet.payload();
+
+    // Walk the extension headers that may precede the fragment header,
+    // remembering where the "next header" field pointing to it lives.
+    let mut next_header = u8::from(packet.next_header());
+    let mut next_header_field = 6;
+    let mut pos = 0;
+    while matches!(
+        next_header,
+        IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS
+    ) {
+        let Some(&[next, len]) = payload.get(pos..pos + 2) else {
+            return Err("truncated extension header");
+        };
+        next_header = next;
+        next_header_field = IPV6_HEADER_LEN + pos;
+        pos += (len as usize + 1) * 8;
+        if pos > payload.len() {
+            return Err("truncated extension header");
+        }
+    }
+    if next_header != IPV6_FRAGMENT {
+        return Ok(None);
+    }
+
+    let Some(fragment_header) = payload.get(pos..pos + 8) else {
+        return Err("truncated fragment header");
+    };
+    let offset = (u16::from_be_bytes([fragment_header[2], fragment_header[3]]) & 0xfff8) as usize;
+    let more = fragment_header[3] & 1 != 0;
+    let ident = u32::from_be_bytes(fragment_header[4..8].try_into().unwrap_or_default());
+    let data = &payload[pos + 8..];
+    if more && (data.is_empty() || !data.len().is_multiple_of(8)) {
+        return Err("non-final fragment is not a multiple of 8 bytes");
+    }
+
+    let header = (offset == 0).then(|| {
+        let raw: &'a [u8] = packet.clone().into_inner();
+        let mut header = BytesMut::from(&raw[..IPV6_HEADER_LEN + pos]);
+        header[next_header_field] = fragment_header[0];
+        header
+    });
+
+    Ok(Some(Fragment {
+        key: (
+            IpAddr::from(packet.src_addr()),
+            IpAddr::from(packet.dst_addr()),
+            IPV6_FRAGMENT,
+            ident,
+        ),
+        offset,
+        more,
+        header,
+        data,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use etherparse::PacketBuilder;
+
+    use super::*;
+
+    fn udp_v4(payload: &[u8]) -> Vec<u8> {
+        let builder = PacketBuilder::ipv4([10, 0, 0, 1], [8, 8, 8, 8], 64).udp(5353, 53);
+        let mut buf = Vec::with_capacity(builder.size(payload.len()));
+        builder.write(&mut buf, payload).unwrap();
+        buf
+    }
+
+    fn udp_v6(payload: &[u8]) -> Vec<u8> {
+        let src = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        let dst = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        let builder = PacketBuilder::ipv6(src, dst, 64).udp(5353, 53);
+        let mut buf = Vec::with_capacity(builder.size(payload.len()));
+        builder.write(&mut buf, payload).unwrap();
+        buf
+    }
+
+    /// Splits an IPv4 packet into fragments carrying at most `size` bytes.
+    fn fragment_v4(packet: &[u8], size: usize) -> Vec<Vec<u8>> {
+        let header = &packet[..20];
+        let payload = &packet[20..];
+        payload
+            .chunks(size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut fragment = [header, chunk].concat();
+                let mut ipv4 = Ipv4Packet::new_unchecked(&mut fragment[..]);
+                ipv4.set_ident(0x1234);
+                ipv4.set_dont_frag(false);
+                ipv4.set_more_frags((i + 1) * size < payload.len());
+                ipv4.set_frag_offset((i * size) as u16);
+                ipv4.set_total_len((20 + chunk.len()) as u16);
+                ipv4.fill_checksum();
+                fragment
+            })
+            .collect()
+    }
+
+    /// Splits an IPv6 packet into fragments carrying at most `size` bytes.
+    fn fragment_v6(packet: &[u8], size: usize) -> Vec<Vec<u8>> {
+        let header = &packet[..IPV6_HEADER_LEN];
+        let payload = &packet[IPV6_HEADER_LEN..];
+        payload
+            .chunks(size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let more = (i + 1) * size < payload.len();
+                let offset = (i * size) as u16 | more as u16;
+                let mut fragment = header.to_vec();
+                fragment[6] = IPV6_FRAGMENT;
+                fragment.extend_from_slice(&[header[6], 0]);
+                fragment.extend_from_slice(&offset.to_be_bytes());
+                fragment.extend_from_slice(&0xdead_beefu32.to_be_bytes());
+                fragment.extend_from_slice(chunk);
+                let len = (fragment.len() - IPV6_HEADER_LEN) as u16;
+                Ipv6Packet::new_unchecked(&mut fragment[..]).set_payload_len(len);
+                fragment
+            })
+            .collect()
+    }
+
+    fn feed(reassembler: &mut Reassembler, packet: &[u8], now: Instant) -> Reassembly {
+        reassembler.process_at(&IpPacket::new_checked(packet).unwrap(), now)
+    }
+
+    #[test]
+    fn unfragmented_packets_pass_through() {
+        let mut reassembler = Reassembler::default();
+        let now = Instant::now();
+        assert_eq!(
+            feed(&mut reassembler, &udp_v4(b"dns"), now),
+            Reassembly::Unfragmented
+        );
+        assert_eq!(
+            feed(&mut reassembler, &udp_v6(b"dns"), now),
+            Reassembly::Unfragmented
+        );
+    }
+
+    #[test]
+    fn reassembles_ipv4_out_of_order() {
+        let packet = udp_v4(&[7u8; 3000]);
+        let mut fragments = fragment_v4(&packet, 1480);
+        fragments.reverse();
+
+        let mut reassembler = Reassembler::default();
+        let now = Instant::now();
+        assert_eq!(
+            feed(&mut reassembler, &fragments[0], now),
+            Reassembly::Pending
+        );
+        assert_eq!(
+            feed(&mut reassembler, &fragments[1], now),
+            Reassembly::Pending
+        );
+        // A retransmitted fragment is ignored.
+        assert_eq!(
+            feed(&mut reassembler, &fragments[1], now),
+            Reassembly::Pending
+        );
+        let Reassembly::Complete(reassembled) = feed(&mut reassembler, &fragments[2], now) else {
+            panic!("datagram was not reassembled");
+        };
+
+        let ipv4 = Ipv4Packet::new_checked(&reassembled[..]).unwrap();
+        assert!(ipv4.verify_checksum());
+        assert!(!ipv4.more_frags());
+        assert_eq!(ipv4.frag_offset(), 0);
+        assert_eq!(ipv4.payload(), &packet[20..]);
+        assert_eq!(reassembler.buffered, 0);
+        assert!(reassembler.buffers.is_empty());
+    }
+
+    #[test]
+    fn reassembles_ipv6() {
+        let packet = udp_v6(&[9u8; 2000]);
+        let fragments = fragment_v6(&packet, 1232);
+
+        let mut reassembler = Reassembler::default();
+        let now = Instant::now();
+        assert_eq!(
+            feed(&mut reassembler, &fragments[0], now),
+            Reassembly::Pending
+        );
+        let Reassembly::Complete(reassembled) = feed(&mut reassembler, &fragments[1], now) else {
+            panic!("datagram was not reassembled");
+        };
+        assert_eq!(reassembled.as_ref(), packet.as_slice());
+    }
+
+    #[test]
+    fn ipv6_atomic_fragment_is_unwrapped() {
+        let packet = udp_v6(b"dns");
+        let fragments = fragment_v6(&packet, 1232);
+        assert_eq!(fragments.len(), 1);
+
+        let mut reassembler = Reassembler::default();
+        let reassembled = feed(&mut reassembler, &fragments[0], Instant::now());
+        assert_eq!(reassembled, Reassembly::Complete(Bytes::from(packet)));
+    }
+
+    #[test]
+    fn drops_overlapping_fragments() {
+        let packet = udp_v4(&[1u8; 100]);
+        let fragments = fragment_v4(&packet, 48);
+        let mut overlapping = fragments[1].clone();
+        overlapping[20] ^= 0xff;
+
+        let mut reassembler = Reassembler::default();
+        let now = Instant::now();
+        assert_eq!(
+            feed(&mut reassembler, &fragments[1], now),
+            Reassembly::Pending
+        );
+        assert_eq!(
+            feed(&mut reassembler, &overlapping, now),
+            Reassembly::Pending
+        );
+        assert!(reassembler.buffers.is_empty());
+        assert_eq!(reassembler.buffered, 0);
+    }
+
+    #[test]
+    fn incomplete_datagrams_expire() {
+        let fragments = fragment_v4(&udp_v4(&[1u8; 100]), 48);
+        let mut reassembler = Reassembler::new(8, 1024, Duration::from_secs(10));
+        let now = Instant::now();
+        assert_eq!(
+            feed(&mut reassembler, &fragments[0], now),
+            Reassembly::Pending
+        );
+
+        let later = now + Duration::from_secs(11);
+        assert_eq!(
+            feed(&mut reassembler, &fragments[1], later),
+            Reassembly::Pending
+        );
+        assert_eq!(
+            feed(&mut reassembler, &fragments[2], later),
+            Reassembly::Pending
+        );
+        assert_eq!(reassembler.buffers.len(), 1);
+    }
+
+    #[test]
+    fn respects_limits() {
+        let fragments = fragment_v4(&udp_v4(&[1u8; 100]), 48);
+        let other = fragment_v6(&udp_v6(&[1u8; 100]), 48);
+        let now = Instant::now();
+
+        let mut reassembler = Reassembler::new(1, 1024, Duration::from_secs(10));
+        feed(&mut reassembler, &fragments[0], now);
+        feed(&mut reassembler, &other[0], now);
+        assert_eq!(reassembler.buffers.len(), 1);
+
+        let mut reassembler = Reassembler::new(8, 64, Duration::from_secs(10));
+        feed(&mut reassembler, &fragments[0], now);
+        assert_eq!(
+            feed(&mut reassembler, &fragments[1], now),
+            Reassembly::Pending
+        );
+        assert!(reassembler.buffered <= 64);
+    }
+}
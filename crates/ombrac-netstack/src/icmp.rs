@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use etherparse::PacketBuilder;
+use smoltcp::wire::{Icmpv4Message, Icmpv4Packet, Icmpv6Message, Icmpv6Packet, IpProtocol};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::buffer::BufferPool;
+use crate::stack::{IpPacket, NetStackConfig, Packet};
+use crate::{debug, trace};
+
+/// Echo requests waiting for their local answer; further ones are dropped.
+const MAX_PENDING_LOCAL_ECHO: usize = 1024;
+
+/// How the stack handles ICMP and ICMPv6 echo requests.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum IcmpEcho {
+    /// Echo requests are dropped.
+    #[default]
+    Disabled,
+    /// The stack answers every echo request itself after the given delay.
+    Local(Duration),
+    /// Echo requests are handed to the application through [`IcmpTunnel`].
+    Forward,
+}
+
+/// An echo request read from the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EchoRequest {
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    pub ident: u16,
+    pub seq_no: u16,
+    pub data: Bytes,
+}
+
+impl EchoRequest {
+    /// Parses an echo request, returning `None` for any other packet.
+    pub(crate) fn parse(packet: &[u8]) -> Option<Self> {
+        let ip_packet = IpPacket::new_checked(packet).ok()?;
+        let (ident, seq_no, data) = match ip_packet.protocol() {
+            IpProtocol::Icmp => {
+                let icmp = Icmpv4Packet::new_checked(ip_packet.payload()).ok()?;
+                if icmp.msg_type() != Icmpv4Message::EchoRequest {
+                    return None;
+                }
+                (icmp.echo_ident(), icmp.echo_seq_no(), icmp.data())
+            }
+            IpProtocol::Icmpv6 => {
+                let icmp = Icmpv6Packet::new_checked(ip_packet.payload()).ok()?;
+                if icmp.msg_type() != Icmpv6Message::EchoRequest {
+                    return None;
+                }
+                (icmp.echo_ident(), icmp.echo_seq_no(), icmp.payload())
+            }
+            _ => return None,
+        };
+
+        Some(EchoRequest {
+            src_addr: ip_packet.src_addr(),
+            dst_addr: ip_packet.dst_addr(),
+            ident,
+            seq_no,
+            data: Bytes::copy_from_slice(data),
+        })
+    }
+}
+
+pub struct IcmpTunnel {
+    inbound: mpsc::Receiver<Packet>,
+    outbound: mpsc::Sender<Packet>,
+    buffer_pool: Arc<BufferPool>,
+    config: Arc<NetStackConfig>,
+}
+
+impl IcmpTunnel {
+    pub fn new(
+        config: Arc<NetStackConfig>,
+        inbound: mpsc::Receiver<Packet>,
+        outbound: mpsc::Sender<Packet>,
+        buffer_pool: Arc<BufferPool>,
+    ) -> Self {
+        Self {
+            inbound,
+            outbound,
+            buffer_pool,
+            config,
+        }
+    }
+
+    pub fn split(self) -> (SplitRead, SplitWrite) {
+        let read = SplitRead { recv: self.inbound };
+        let write = SplitWrite {
+            config: self.config,
+            send: self.outbound,
+            buffer_pool: self.buffer_pool,
+        };
+        (read, write)
+    }
+
+    /// Answers every echo request after `latency`, until the stack is dropped.
+    ///
+    /// Requests wait in a single queue, as they all wait as long.
+    pub(crate) async fn answer_locally(self, latency: Duration) {
+        let (mut reader, mut writer) = self.split();
+        let mut pending = VecDeque::new();
+        loop {
+            let next = pending.front().map(|(deadline, _)| *deadline);
+            tokio::select! {
+                request = reader.recv() => {
+                    let Some(request) = request else { break };
+                    if pending.len() < MAX_PENDING_LOCAL_ECHO {
+                        pending.push_back((Instant::now() + latency, request));
+                    } else {
+                        trace!("dropping icmp echo request, too many pending");
+                    }
+                }
+                _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    let (_, request) = pending.pop_front().expect("a request is pending");
+                    if let Err(_e) = writer.reply(&request).await {
+                        debug!("failed to send icmp echo reply: {_e}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct SplitRead {
+    recv: mpsc::Receiver<Packet>,
+}
+
+impl SplitRead {
+    /// Returns the next echo request, or `None` once the stack is dropped.
+    pub async fn recv(&mut self) -> Option<EchoRequest> {
+        loop {
+            let packet = self.recv.recv().await?;
+            if let Some(request) = EchoRequest::parse(packet.data()) {
+                return Some(request);
+            }
+            trace!("ignoring icmp packet that is not an echo request");
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SplitWrite {
+    config: Arc<NetStackConfig>,
+    send: mpsc::Sender<Packet>,
+    buffer_pool: Arc<BufferPool>,
+}
+
+impl SplitWrite {
+    /// Sends the echo reply for `request` back to its sender.
+    pub async fn reply(&mut self, request: &EchoRequest) -> Result<(), std::io::Error> {
+        let ttl = self.config.ip_ttl;
+        let (ident, seq_no, data) = (request.ident, request.seq_no, request.data.as_ref());
+        let mut buffer = match (request.dst_addr, request.src_addr) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let builder = PacketBuilder::ipv4(src.octets(), dst.octets(), ttl)
+                    .icmpv4_echo_reply(ident, seq_no);
+                let mut buffer = self.buffer_pool.get(builder.size(data.len()));
+                builder
+                    .write(&mut buffer, data)
+                    .map_err(std::io::Error::other)?;
+                buffer
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                let builder = PacketBuilder::ipv6(src.octets(), dst.octets(), ttl)
+                    .icmpv6_echo_reply(ident, seq_no);
+                let mut buffer = self.buffer_pool.get(builder.size(data.len()));
+                builder
+                    .write(&mut buffer, data)
+                    .map_err(std::io::Error::other)?;
+                buffer
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "echo request mixes IPv4 and IPv6 addresses",
+                ));
+            }
+        };
+        let final_bytes = buffer.split().freeze();
+
+        match self.send.send(Packet::new(final_bytes)).await {
+            Ok(()) => Ok(()),
+            Err(err) => Err(std::io::Error::other(format!("send error: {err}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_request_v4() -> Vec<u8> {
+        let builder =
+            PacketBuilder::ipv4([10, 0, 0, 1], [1, 1, 1, 1], 64).icmpv4_echo_request(7, 3);
+        let mut buf = Vec::with_capacity(builder.size(4));
+        builder.write(&mut buf, b"ping").unwrap();
+        buf
+    }
+
+    fn echo_request_v6() -> Vec<u8> {
+        let src = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        let dst = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        let builder = PacketBuilder::ipv6(src, dst, 64).icmpv6_echo_request(9, 1);
+        let mut buf = Vec::with_capacity(builder.size(4));
+        builder.write(&mut buf, b"pong").unwrap();
+        buf
+    }
+
+    fn tunnel() -> (mpsc::Sender<Packet>, IcmpTunnel, mpsc::Receiver<Packet>) {
+        let (inbound_tx, inbound_rx) = mpsc::channel(4);
+        let (outbound_tx, outbound_rx) = mpsc::channel(4);
+        let tunnel = IcmpTunnel::new(
+            Arc::new(NetStackConfig::default()),
+            inbound_rx,
+            outbound_tx,
+            Arc::new(BufferPool::new(8, 1500)),
+        );
+        (inbound_tx, tunnel, outbound_rx)
+    }
+
+    #[test]
+    fn parses_echo_requests() {
+        let request = EchoRequest::parse(&echo_request_v4()).unwrap();
+        assert_eq!(request.src_addr.to_string(), "10.0.0.1");
+        assert_eq!(request.dst_addr.to_string(), "1.1.1.1");
+        assert_eq!((request.ident, request.seq_no), (7, 3));
+        assert_eq!(request.data.as_ref(), b"ping");
+
+        let request = EchoRequest::parse(&echo_request_v6()).unwrap();
+        assert_eq!(request.dst_addr.to_string(), "2001:db8::1");
+        assert_eq!((request.ident, request.seq_no), (9, 1));
+        assert_eq!(request.data.as_ref(), b"pong");
+    }
+
+    #[test]
+    fn ignores_other_icmp_messages() {
+        let builder = PacketBuilder::ipv4([1, 1, 1, 1], [10, 0, 0, 1], 64).icmpv4_echo_reply(7, 3);
+        let mut buf = Vec::new();
+        builder.write(&mut buf, b"ping").unwrap();
+        assert!(EchoRequest::parse(&buf).is_none());
+    }
+
+    #[tokio::test]
+    async fn reply_mirrors_the_request() {
+        let (_inbound, tunnel, mut outbound) = tunnel();
+        let (_reader, mut writer) = tunnel.split();
+
+        let request = EchoRequest::parse(&echo_request_v6()).unwrap();
+        writer.reply(&request).await.unwrap();
+
+        let reply = outbound.recv().await.unwrap();
+        let ip = IpPacket::new_checked(reply.data()).unwrap();
+        assert_eq!(ip.src_addr(), request.dst_addr);
+        assert_eq!(ip.dst_addr(), request.src_addr);
+        let icmp = Icmpv6Packet::new_checked(ip.payload()).unwrap();
+        assert_eq!(icmp.msg_type(), Icmpv6Message::EchoReply);
+        assert_eq!((icmp.echo_ident(), icmp.echo_seq_no()), (9, 1));
+        assert_eq!(icmp.payload(), b"pong");
+    }
+
+    #[tokio::test]
+    async fn local_answers_wait_for_the_latency() {
+        let (inbound, tunnel, mut outbound) = tunnel();
+        tokio::spawn(tunnel.answer_locally(Duration::from_millis(50)));
+
+        let started = std::time::Instant::now();
+        inbound.send(Packet::new(echo_request_v4())).await.unwrap();
+
+        let reply = outbound.recv().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        let ip = IpPacket::new_checked(reply.data()).unwrap();
+        let icmp = Icmpv4Packet::new_checked(ip.payload()).unwrap();
+        assert_eq!(icmp.msg_type(), Icmpv4Message::EchoReply);
+        assert_eq!(icmp.data(), b"ping");
+    }
+
+    #[tokio::test]
+    async fn local_answers_keep_the_request_order() {
+        let (inbound, tunnel, mut outbound) = tunnel();
+        tokio::spawn(tunnel.answer_locally(Duration::from_millis(20)));
+
+        for _ in 0..3 {
+            inbound.send(Packet::new(echo_request_v4())).await.unwrap();
+            inbound.send(Packet::new(echo_request_v6())).await.unwrap();
+        }
+        for _ in 0..3 {
+            let reply = outbound.recv().await.unwrap();
+            assert_eq!(
+                IpPacket::new_checked(reply.data()).unwrap().protocol(),
+                IpProtocol::Icmp
+            );
+            let reply = outbound.recv().await.unwrap();
+            assert_eq!(
+                IpPacket::new_checked(reply.data()).unwrap().protocol(),
+                IpProtocol::Icmpv6
+            );
+        }
+    }
+}
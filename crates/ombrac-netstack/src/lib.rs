@@ -1,7 +1,10 @@
 mod buffer;
 mod device;
+mod fragment;
 mod macros;
 
+pub mod icmp;
+pub mod pcap;
 pub mod stack;
 pub mod tcp;
 pub mod udp;
@@ -0,0 +1,686 @@
+//! Packet capture for the stack's data path.
+//!
+//! A [`PcapTap`] records the raw IP packets entering the stack through
+//! [`StackSplitSink`](crate::stack::StackSplitSink) and leaving it through
+//! [`StackSplitStream`](crate::stack::StackSplitStream) in the classic pcap
+//! format (`LINKTYPE_RAW`), readable by Wireshark and tcpdump. Packets the
+//! stack drops (unsupported protocols, malformed headers) are recorded too,
+//! since they are captured before being dispatched.
+//!
+//! Capturing needs no privileges and can be started and stopped while the
+//! stack runs. Packets are written by a background thread; if it falls
+//! behind, packets are skipped rather than slowing down the data path.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use smoltcp::wire::IpProtocol;
+
+use crate::stack::IpPacket;
+use crate::{error, warn};
+
+/// pcap link type for raw IPv4/IPv6 packets without a link-layer header.
+const LINKTYPE_RAW: u32 = 101;
+
+/// Packets waiting to be written before new ones are skipped.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Options for a capture started with [`PcapTap::start`].
+#[derive(Clone, Debug)]
+pub struct PcapOptions {
+    /// File the capture is written to.
+    pub path: PathBuf,
+    /// Only packets matching the filter are recorded.
+    pub filter: PcapFilter,
+    /// Bytes of each packet that are recorded.
+    pub snaplen: u32,
+    /// Size in bytes after which the file is rotated; never rotated if 0.
+    pub max_size: u64,
+    /// Files kept, including the one being written; older ones are deleted.
+    pub max_files: usize,
+}
+
+impl PcapOptions {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            filter: PcapFilter::default(),
+            snaplen: 65535,
+            max_size: 16 * 1024 * 1024,
+            max_files: 4,
+        }
+    }
+}
+
+/// Handle to start, stop and feed a packet capture; clones share the capture.
+#[derive(Clone, Default)]
+pub struct PcapTap {
+    inner: Arc<TapInner>,
+}
+
+#[derive(Default)]
+struct TapInner {
+    active: AtomicBool,
+    capture: RwLock<Option<Capture>>,
+    skipped: AtomicU64,
+}
+
+struct Capture {
+    filter: PcapFilter,
+    snaplen: u32,
+    sender: SyncSender<Record>,
+}
+
+struct Record {
+    timestamp: SystemTime,
+    data: Bytes,
+    snaplen: u32,
+}
+
+impl fmt::Debug for PcapTap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PcapTap")
+            .field("active", &self.is_active())
+            .finish()
+    }
+}
+
+impl PcapTap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts writing packets to `options.path`, replacing any running capture.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created.
+    pub fn start(&self, options: PcapOptions) -> io::Result<()> {
+        let writer = RotatingWriter::create(&options)?;
+        let (sender, receiver) = mpsc::sync_channel::<Record>(QUEUE_CAPACITY);
+
+        std::thread::Builder::new()
+            .name("ombrac-pcap".to_string())
+            .spawn(move || {
+                let mut writer = writer;
+                while let Ok(record) = receiver.recv() {
+                    // Flush once the queue is drained, so the file stays readable.
+                    let written = std::iter::once(record)
+                        .chain(receiver.try_iter())
+                        .try_for_each(|record| writer.write(&record))
+                        .and_then(|()| writer.flush());
+                    if let Err(_e) = written {
+                        error!("failed to write packet capture: {}", _e);
+                        return;
+                    }
+                }
+            })?;
+
+        let capture = Capture {
+            filter: options.filter,
+            snaplen: options.snaplen,
+            sender,
+        };
+        *self
+            .inner
+            .capture
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some(capture);
+        self.inner.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Stops the running capture; the file is flushed and closed.
+    pub fn stop(&self) {
+        self.inner.active.store(false, Ordering::Release);
+        self.inner
+            .capture
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.inner.active.load(Ordering::Acquire)
+    }
+
+    /// Number of packets skipped because the writer fell behind.
+    pub fn skipped(&self) -> u64 {
+        self.inner.skipped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, data: &Bytes) {
+        if !self.is_active() {
+            return;
+        }
+
+        let capture = self.inner.capture.read().unwrap_or_else(|e| e.into_inner());
+        let Some(capture) = capture.as_ref() else {
+            return;
+        };
+        if !capture.filter.matches(data) {
+            return;
+        }
+
+        let record = Record {
+            timestamp: SystemTime::now(),
+            data: data.clone(),
+            snaplen: capture.snaplen,
+        };
+        match capture.sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.inner.skipped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("packet capture writer stopped, disabling capture");
+                self.inner.active.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+/// Writes pcap records, rotating the file once it grows past `max_size`.
+struct RotatingWriter {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    snaplen: u32,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingWriter {
+    fn create(options: &PcapOptions) -> io::Result<Self> {
+        let (file, size) = Self::open(&options.path, options.snaplen)?;
+        Ok(Self {
+            path: options.path.clone(),
+            max_size: options.max_size,
+            max_files: options.max_files.max(1),
+            snaplen: options.snaplen,
+            file,
+            size,
+        })
+    }
+
+    fn open(path: &Path, snaplen: u32) -> io::Result<(BufWriter<File>, u64)> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&0xa1b2_c3d4u32.to_ne_bytes())?;
+        file.write_all(&2u16.to_ne_bytes())?;
+        file.write_all(&4u16.to_ne_bytes())?;
+        file.write_all(&0i32.to_ne_bytes())?; // thiszone
+        file.write_all(&0u32.to_ne_bytes())?; // sigfigs
+        file.write_all(&snaplen.to_ne_bytes())?;
+        file.write_all(&LINKTYPE_RAW.to_ne_bytes())?;
+        Ok((file, 24))
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let captured = record.data.len().min(record.snaplen as usize);
+        let length = 16 + captured as u64;
+        if self.max_size > 0 && self.size + length > self.max_size && self.size > 24 {
+            self.rotate()?;
+        }
+
+        let elapsed = record
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.file
+            .write_all(&(elapsed.as_secs() as u32).to_ne_bytes())?;
+        self.file
+            .write_all(&elapsed.subsec_micros().to_ne_bytes())?;
+        self.file.write_all(&(captured as u32).to_ne_bytes())?;
+        self.file
+            .write_all(&(record.data.len() as u32).to_ne_bytes())?;
+        self.file.write_all(&record.data[..captured])?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Moves `path` to `path.1`, `path.1` to `path.2` and so on, deleting the
+    /// oldest file, then starts a new file at `path`.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let rotated = |index: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{index}"));
+            PathBuf::from(name)
+        };
+        if self.max_files > 1 {
+            let _ = fs::remove_file(rotated(self.max_files - 1));
+            for index in (1..self.max_files - 1).rev() {
+                let _ = fs::rename(rotated(index), rotated(index + 1));
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        let (file, size) = Self::open(&self.path, self.snaplen)?;
+        self.file = file;
+        self.size = size;
+        Ok(())
+    }
+}
+
+/// A filter on addresses, ports and protocols in a subset of the tcpdump
+/// (BPF) syntax.
+///
+/// Primitives are `host <ip>`, `net <cidr>` and `port <n>`, optionally
+/// prefixed by `src` or `dst`, and the protocols `ip`, `ip6`, `tcp`, `udp`,
+/// `icmp` and `icmp6`. They combine with `and` (`&&`), `or` (`||`), `not`
+/// (`!`) and parentheses, e.g. `udp and dst port 443 and not net 10.0.0.0/8`.
+/// An empty filter matches every packet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PcapFilter {
+    expr: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Host(Side, IpAddr),
+    Net(Side, IpAddr, u8),
+    Port(Side, u16),
+    Ipv4,
+    Ipv6,
+    Protocol(IpProtocol),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Src,
+    Dst,
+    Either,
+}
+
+/// The fields of a packet a filter looks at.
+struct Fields {
+    ipv6: bool,
+    protocol: IpProtocol,
+    src: IpAddr,
+    dst: IpAddr,
+    ports: Option<(u16, u16)>,
+}
+
+impl PcapFilter {
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        let Some(expr) = &self.expr else {
+            return true;
+        };
+        let Ok(ip_packet) = IpPacket::new_checked(packet) else {
+            return false;
+        };
+
+        let protocol = ip_packet.protocol();
+        let payload = ip_packet.payload();
+        let ports = match protocol {
+            IpProtocol::Tcp | IpProtocol::Udp if payload.len() >= 4 => Some((
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+            )),
+            _ => None,
+        };
+        let fields = Fields {
+            ipv6: matches!(ip_packet, IpPacket::Ipv6(_)),
+            protocol,
+            src: ip_packet.src_addr(),
+            dst: ip_packet.dst_addr(),
+            ports,
+        };
+
+        expr.eval(&fields)
+    }
+}
+
+impl Expr {
+    fn eval(&self, fields: &Fields) -> bool {
+        let side = |side: Side, test: &dyn Fn(IpAddr) -> bool| match side {
+            Side::Src => test(fields.src),
+            Side::Dst => test(fields.dst),
+            Side::Either => test(fields.src) || test(fields.dst),
+        };
+
+        match self {
+            Expr::Or(a, b) => a.eval(fields) || b.eval(fields),
+            Expr::And(a, b) => a.eval(fields) && b.eval(fields),
+            Expr::Not(a) => !a.eval(fields),
+            Expr::Host(s, host) => side(*s, &|addr| addr == *host),
+            Expr::Net(s, net, prefix) => side(*s, &|addr| in_network(addr, *net, *prefix)),
+            Expr::Port(s, port) => match (s, fields.ports) {
+                (_, None) => false,
+                (Side::Src, Some((src, _))) => src == *port,
+                (Side::Dst, Some((_, dst))) => dst == *port,
+                (Side::Either, Some((src, dst))) => src == *port || dst == *port,
+            },
+            Expr::Ipv4 => !fields.ipv6,
+            Expr::Ipv6 => fields.ipv6,
+            Expr::Protocol(protocol) => fields.protocol == *protocol,
+        }
+    }
+}
+
+fn in_network(addr: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (addr, net) {
+        (IpAddr::V4(addr), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+impl FromStr for PcapFilter {
+    type Err = io::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let spaced = value
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .replace("&&", " and ")
+            .replace("||", " or ")
+            .replace('!', " not ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or().map_err(|message| invalid(value, &message))?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(value, &format!("unexpected '{token}'")));
+        }
+        Ok(Self { expr: Some(expr) })
+    }
+}
+
+fn invalid(filter: &str, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid capture filter '{filter}': {message}"),
+    )
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self.peek().ok_or("unexpected end of filter")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.eat("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err("missing ')'".to_string());
+            }
+            return Ok(expr);
+        }
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Expr, String> {
+        let side = if self.eat("src") {
+            Side::Src
+        } else if self.eat("dst") {
+            Side::Dst
+        } else {
+            Side::Either
+        };
+
+        let token = self.next()?;
+        let expr = match token {
+            "host" => {
+                let value = self.next()?;
+                let host = value
+                    .parse()
+                    .map_err(|_| format!("'{value}' is not an IP address"))?;
+                Expr::Host(side, host)
+            }
+            "net" => {
+                let value = self.next()?;
+                let (net, prefix) = value
+                    .split_once('/')
+                    .ok_or_else(|| format!("'{value}' is not a CIDR"))?;
+                let net: IpAddr = net
+                    .parse()
+                    .map_err(|_| format!("'{value}' is not a CIDR"))?;
+                let max = if net.is_ipv4() { 32 } else { 128 };
+                let prefix = prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|prefix| *prefix <= max)
+                    .ok_or_else(|| format!("'{value}' is not a CIDR"))?;
+                Expr::Net(side, net, prefix)
+            }
+            "port" => {
+                let value = self.next()?;
+                let port = value
+                    .parse()
+                    .map_err(|_| format!("'{value}' is not a port"))?;
+                Expr::Port(side, port)
+            }
+            "ip" | "ip6" | "tcp" | "udp" | "icmp" | "icmp6" if side != Side::Either => {
+                return Err(format!("'{token}' cannot follow src or dst"));
+            }
+            "ip" => Expr::Ipv4,
+            "ip6" => Expr::Ipv6,
+            "tcp" => Expr::Protocol(IpProtocol::Tcp),
+            "udp" => Expr::Protocol(IpProtocol::Udp),
+            "icmp" => Expr::Protocol(IpProtocol::Icmp),
+            "icmp6" => Expr::Protocol(IpProtocol::Icmpv6),
+            _ => return Err(format!("unknown primitive '{token}'")),
+        };
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use etherparse::PacketBuilder;
+
+    use super::*;
+
+    fn udp(src: [u8; 4], dst: [u8; 4], src_port: u16, dst_port: u16) -> Bytes {
+        let builder = PacketBuilder::ipv4(src, dst, 64).udp(src_port, dst_port);
+        let mut buf = Vec::with_capacity(builder.size(4));
+        builder.write(&mut buf, b"data").unwrap();
+        buf.into()
+    }
+
+    fn tcp(src: [u8; 4], dst: [u8; 4], src_port: u16, dst_port: u16) -> Bytes {
+        let builder = PacketBuilder::ipv4(src, dst, 64).tcp(src_port, dst_port, 0, 1024);
+        let mut buf = Vec::with_capacity(builder.size(0));
+        builder.write(&mut buf, &[]).unwrap();
+        buf.into()
+    }
+
+    fn filter(value: &str) -> PcapFilter {
+        value.parse().unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ombrac-pcap-{name}-{}.pcap", std::process::id()))
+    }
+
+    #[test]
+    fn filter_matches_addresses_ports_and_protocols() {
+        let packet = udp([10, 0, 0, 1], [1, 1, 1, 1], 5000, 443);
+
+        assert!(filter("").matches(&packet));
+        assert!(filter("udp and dst port 443").matches(&packet));
+        assert!(filter("host 1.1.1.1").matches(&packet));
+        assert!(filter("src net 10.0.0.0/8").matches(&packet));
+        assert!(filter("tcp || (ip && !port 53)").matches(&packet));
+        assert!(!filter("dst host 10.0.0.1").matches(&packet));
+        assert!(!filter("src port 443").matches(&packet));
+        assert!(!filter("tcp").matches(&packet));
+        assert!(!filter("ip6").matches(&packet));
+        assert!(!filter("port 443").matches(b"not an ip packet"));
+    }
+
+    #[test]
+    fn filter_rejects_invalid_expressions() {
+        for value in [
+            "port",
+            "port http",
+            "host example.com",
+            "net 10.0.0.0/33",
+            "src tcp",
+            "(udp",
+            "udp )",
+            "udp or",
+            "frobnicate",
+        ] {
+            assert!(value.parse::<PcapFilter>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn writer_produces_pcap_records() {
+        let path = temp_path("records");
+        let mut options = PcapOptions::new(&path);
+        options.snaplen = 30;
+        let mut writer = RotatingWriter::create(&options).unwrap();
+
+        let packet = udp([10, 0, 0, 1], [1, 1, 1, 1], 5000, 443);
+        let record = Record {
+            timestamp: UNIX_EPOCH + std::time::Duration::from_micros(1_500_000),
+            data: packet.clone(),
+            snaplen: options.snaplen,
+        };
+        writer.write(&record).unwrap();
+        writer.flush().unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let u32_at =
+            |offset: usize| u32::from_ne_bytes(contents[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), 0xa1b2_c3d4);
+        assert_eq!(u32_at(16), 30);
+        assert_eq!(u32_at(20), LINKTYPE_RAW);
+        assert_eq!((u32_at(24), u32_at(28)), (1, 500_000));
+        assert_eq!((u32_at(32), u32_at(36)), (30, packet.len() as u32));
+        assert_eq!(&contents[40..], &packet[..30]);
+    }
+
+    #[test]
+    fn writer_rotates_and_keeps_max_files() {
+        let path = temp_path("rotate");
+        let rotated = |index: usize| PathBuf::from(format!("{}.{index}", path.display()));
+        let mut options = PcapOptions::new(&path);
+        options.max_size = 24 + 2 * (16 + 32);
+        options.max_files = 3;
+        let mut writer = RotatingWriter::create(&options).unwrap();
+
+        let record = Record {
+            timestamp: SystemTime::now(),
+            data: udp([10, 0, 0, 1], [1, 1, 1, 1], 5000, 443),
+            snaplen: options.snaplen,
+        };
+        for _ in 0..7 {
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 24 + 16 + 32);
+        assert_eq!(fs::metadata(rotated(1)).unwrap().len(), options.max_size);
+        assert_eq!(fs::metadata(rotated(2)).unwrap().len(), options.max_size);
+        assert!(!rotated(3).exists());
+        for file in [path.clone(), rotated(1), rotated(2)] {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn tap_records_matching_packets_until_stopped() {
+        let path = temp_path("tap");
+        let tap = PcapTap::new();
+        tap.record(&udp([10, 0, 0, 1], [1, 1, 1, 1], 5000, 443));
+        assert!(!tap.is_active());
+
+        let mut options = PcapOptions::new(&path);
+        options.filter = filter("tcp");
+        tap.start(options).unwrap();
+        assert!(tap.is_active());
+
+        let packet = tcp([10, 0, 0, 1], [1, 1, 1, 1], 5000, 443);
+        tap.record(&udp([10, 0, 0, 1], [1, 1, 1, 1], 5000, 443));
+        tap.record(&packet);
+        tap.stop();
+        assert!(!tap.is_active());
+
+        // The writer thread finishes once the capture is dropped.
+        let expected = 24 + 16 + packet.len() as u64;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while fs::metadata(&path).map(|m| m.len()).unwrap_or(0) < expected {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "capture was not flushed"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -8,6 +8,9 @@ use bytes::Bytes;
 use smoltcp::wire::{IpProtocol, IpVersion, Ipv4Address, Ipv4Packet, Ipv6Address, Ipv6Packet};
 use tokio::sync::mpsc;
 
+use crate::fragment::{Reassembler, Reassembly};
+use crate::icmp::{IcmpEcho, IcmpTunnel};
+use crate::pcap::PcapTap;
 use crate::tcp::TcpConnection;
 use crate::{buffer::BufferPool, udp::UdpTunnel};
 use crate::{debug, error};
@@ -83,6 +86,8 @@ pub struct NetStackConfig {
     pub tcp_timeout: Duration,
     pub packet_batch_size: usize,
     pub ip_ttl: u8,
+
+    pub icmp_echo: IcmpEcho,
 }
 
 impl Default for NetStackConfig {
@@ -103,6 +108,7 @@ impl Default for NetStackConfig {
             tcp_keep_alive: Duration::from_secs(28),
             packet_batch_size: 32,
             ip_ttl: 64,
+            icmp_echo: IcmpEcho::Disabled,
         }
     }
 }
@@ -110,7 +116,10 @@ impl Default for NetStackConfig {
 pub struct NetStack {
     udp_inbound: mpsc::Sender<Packet>,
     tcp_inbound: mpsc::Sender<Packet>,
+    icmp_inbound: Option<mpsc::Sender<Packet>>,
+    icmp_tunnel: Option<IcmpTunnel>,
     packet_outbound: mpsc::Receiver<Packet>,
+    pcap: Option<PcapTap>,
 }
 
 pub struct Packet {
@@ -141,20 +150,44 @@ where
 }
 
 impl NetStack {
+    /// Creates the stack and the handles for its TCP and UDP traffic.
+    ///
+    /// With [`IcmpEcho::Forward`] echo requests are handed to the
+    /// [`IcmpTunnel`] taken with [`NetStack::icmp_tunnel`]; with
+    /// [`IcmpEcho::Local`] the stack answers them itself.
     pub fn new(config: NetStackConfig) -> (Self, TcpConnection, UdpTunnel) {
         let (packet_sender, packet_receiver) = mpsc::channel::<Packet>(config.channel_size);
         let (udp_inbound_app, udp_outbound_stack) = mpsc::channel::<Packet>(config.channel_size);
         let (tcp_inbound_app, tcp_outbound_stack) = mpsc::channel::<Packet>(config.channel_size);
+        let (icmp_inbound_app, icmp_outbound_stack) = mpsc::channel::<Packet>(config.channel_size);
         let buffer_pool = Arc::new(BufferPool::new(
             config.buffer_pool_size,
             config.buffer_pool_default_buffer_size,
         ));
 
+        let icmp_tunnel = IcmpTunnel::new(
+            Arc::new(config.clone()),
+            icmp_outbound_stack,
+            packet_sender.clone(),
+            buffer_pool.clone(),
+        );
+        let (icmp_inbound, icmp_tunnel) = match config.icmp_echo {
+            IcmpEcho::Disabled => (None, None),
+            IcmpEcho::Forward => (Some(icmp_inbound_app), Some(icmp_tunnel)),
+            IcmpEcho::Local(latency) => {
+                tokio::spawn(icmp_tunnel.answer_locally(latency));
+                (Some(icmp_inbound_app), None)
+            }
+        };
+
         (
             NetStack {
                 udp_inbound: udp_inbound_app,
                 tcp_inbound: tcp_inbound_app,
+                icmp_inbound,
+                icmp_tunnel,
                 packet_outbound: packet_receiver,
+                pcap: None,
             },
             TcpConnection::new(
                 config.clone(),
@@ -171,18 +204,53 @@ impl NetStack {
         )
     }
 
+    /// Takes the tunnel echo requests are handed to, if `config.icmp_echo`
+    /// is [`IcmpEcho::Forward`] and it wasn't taken yet. Echo requests are
+    /// dropped if it is never taken.
+    pub fn icmp_tunnel(&mut self) -> Option<IcmpTunnel> {
+        self.icmp_tunnel.take()
+    }
+
+    /// Records the packets passing through the split sink and stream to
+    /// `tap` whenever it is capturing.
+    pub fn with_pcap(mut self, tap: PcapTap) -> Self {
+        self.pcap = Some(tap);
+        self
+    }
+
     pub fn split(self) -> (StackSplitSink, StackSplitStream) {
-        (
-            StackSplitSink::new(self.udp_inbound, self.tcp_inbound),
-            StackSplitStream::new(self.packet_outbound),
-        )
+        let mut sink = StackSplitSink::new(self.udp_inbound, self.tcp_inbound);
+        let mut stream = StackSplitStream::new(self.packet_outbound);
+        // A forwarding stack whose tunnel nobody took has no one to hand
+        // echo requests to
+        if let Some(icmp_inbound) = self.icmp_inbound
+            && self.icmp_tunnel.is_none()
+        {
+            sink = sink.with_icmp(icmp_inbound);
+        }
+        if let Some(tap) = self.pcap {
+            sink = sink.with_pcap(tap.clone());
+            stream = stream.with_pcap(tap);
+        }
+        (sink, stream)
     }
 }
 
+/// Where the sink delivers a packet.
+#[derive(Clone, Copy, Debug)]
+enum Inbound {
+    Tcp,
+    Udp,
+    IcmpEcho,
+}
+
 pub struct StackSplitSink {
     udp_inbound: mpsc::Sender<Packet>,
     tcp_inbound: mpsc::Sender<Packet>,
-    packet_container: Option<(Packet, IpProtocol)>,
+    icmp_inbound: Option<mpsc::Sender<Packet>>,
+    packet_container: Option<(Packet, Inbound)>,
+    reassembler: Reassembler,
+    pcap: Option<PcapTap>,
 }
 
 impl StackSplitSink {
@@ -190,9 +258,26 @@ impl StackSplitSink {
         Self {
             udp_inbound,
             tcp_inbound,
+            icmp_inbound: None,
             packet_container: None,
+            reassembler: Reassembler::default(),
+            pcap: None,
         }
     }
+
+    /// Delivers ICMP and ICMPv6 echo requests to `icmp_inbound` instead of
+    /// the TCP stack.
+    pub fn with_icmp(mut self, icmp_inbound: mpsc::Sender<Packet>) -> Self {
+        self.icmp_inbound = Some(icmp_inbound);
+        self
+    }
+
+    /// Records every packet written to the sink, including those the stack
+    /// drops, to `tap`.
+    pub fn with_pcap(mut self, tap: PcapTap) -> Self {
+        self.pcap = Some(tap);
+        self
+    }
 }
 
 impl futures::Sink<Packet> for StackSplitSink {
@@ -217,18 +302,40 @@ impl futures::Sink<Packet> for StackSplitSink {
             return Ok(());
         }
 
+        if let Some(tap) = &self.pcap {
+            tap.record(&item.data);
+        }
+
+        // Fragments are held back until their datagram is complete.
+        let reassembly = {
+            let packet = IpPacket::new_checked(item.data())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.reassembler.process(&packet)
+        };
+        let item = match reassembly {
+            Reassembly::Unfragmented => item,
+            Reassembly::Pending => return Ok(()),
+            Reassembly::Complete(data) => Packet::new(data),
+        };
+
         let packet = IpPacket::new_checked(item.data())
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
         let protocol = packet.protocol();
-        if matches!(
-            protocol,
-            IpProtocol::Tcp | IpProtocol::Udp | IpProtocol::Icmp | IpProtocol::Icmpv6
-        ) {
-            self.packet_container.replace((item, protocol));
-        } else {
-            error!("IP packet ignored protocol: {protocol:?}");
-        }
+        let inbound = match protocol {
+            IpProtocol::Udp => Inbound::Udp,
+            IpProtocol::Icmp | IpProtocol::Icmpv6
+                if self.icmp_inbound.is_some() && is_echo_request(&packet) =>
+            {
+                Inbound::IcmpEcho
+            }
+            IpProtocol::Tcp | IpProtocol::Icmp | IpProtocol::Icmpv6 => Inbound::Tcp,
+            _ => {
+                error!("IP packet ignored protocol: {protocol:?}");
+                return Ok(());
+            }
+        };
+        self.packet_container.replace((item, inbound));
 
         Ok(())
     }
@@ -243,12 +350,12 @@ impl futures::Sink<Packet> for StackSplitSink {
         };
 
         let sender = match proto {
-            IpProtocol::Udp => self.udp_inbound.clone(),
-            IpProtocol::Tcp | IpProtocol::Icmp | IpProtocol::Icmpv6 => self.tcp_inbound.clone(),
-            _ => {
-                error!("Unsupported protocol for packet: {proto:?}");
-                return Poll::Ready(Ok(()));
-            }
+            Inbound::Udp => self.udp_inbound.clone(),
+            Inbound::Tcp => self.tcp_inbound.clone(),
+            Inbound::IcmpEcho => match &self.icmp_inbound {
+                Some(sender) => sender.clone(),
+                None => return Poll::Ready(Ok(())),
+            },
         };
         let mut fut = Box::pin(sender.reserve());
 
@@ -277,13 +384,35 @@ impl futures::Sink<Packet> for StackSplitSink {
     }
 }
 
+/// Whether `packet` is an ICMP or ICMPv6 echo request.
+fn is_echo_request(packet: &IpPacket<&[u8]>) -> bool {
+    const ICMPV4_ECHO_REQUEST: u8 = 8;
+    const ICMPV6_ECHO_REQUEST: u8 = 128;
+
+    match (packet, packet.payload().first()) {
+        (IpPacket::Ipv4(_), Some(&kind)) => kind == ICMPV4_ECHO_REQUEST,
+        (IpPacket::Ipv6(_), Some(&kind)) => kind == ICMPV6_ECHO_REQUEST,
+        _ => false,
+    }
+}
+
 pub struct StackSplitStream {
     packet_outbound: mpsc::Receiver<Packet>,
+    pcap: Option<PcapTap>,
 }
 
 impl StackSplitStream {
     pub fn new(packet_outbound: mpsc::Receiver<Packet>) -> Self {
-        Self { packet_outbound }
+        Self {
+            packet_outbound,
+            pcap: None,
+        }
+    }
+
+    /// Records every packet read from the stream to `tap`.
+    pub fn with_pcap(mut self, tap: PcapTap) -> Self {
+        self.pcap = Some(tap);
+        self
     }
 }
 
@@ -295,7 +424,12 @@ impl futures::Stream for StackSplitStream {
         cx: &mut Context<'_>,
     ) -> Poll<Option<Self::Item>> {
         match self.packet_outbound.poll_recv(cx) {
-            Poll::Ready(Some(packet)) => Poll::Ready(Some(Ok(packet))),
+            Poll::Ready(Some(packet)) => {
+                if let Some(tap) = &self.pcap {
+                    tap.record(&packet.data);
+                }
+                Poll::Ready(Some(Ok(packet)))
+            }
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
@@ -411,6 +545,53 @@ mod tests {
         assert!(!got.data().is_empty());
     }
 
+    #[tokio::test]
+    async fn split_sink_reassembles_fragmented_udp() {
+        let (udp_tx, mut udp_rx) = mpsc::channel::<Packet>(8);
+        let (tcp_tx, _tcp_rx) = mpsc::channel::<Packet>(8);
+        let mut sink = StackSplitSink::new(udp_tx, tcp_tx);
+
+        let raw = build_ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], 1000, 53, &[0x42; 2000]);
+        let (header, payload) = raw.split_at(20);
+        for (i, chunk) in payload.chunks(1480).enumerate() {
+            let mut fragment = [header, chunk].concat();
+            let mut ipv4 = Ipv4Packet::new_unchecked(&mut fragment[..]);
+            ipv4.set_more_frags((i + 1) * 1480 < payload.len());
+            ipv4.set_frag_offset((i * 1480) as u16);
+            ipv4.set_total_len((20 + chunk.len()) as u16);
+            ipv4.fill_checksum();
+            sink.send(Packet::new(fragment)).await.unwrap();
+        }
+
+        let got = udp_rx
+            .recv()
+            .await
+            .expect("reassembled packet should arrive");
+        assert_eq!(&got.data()[20..], payload);
+        assert!(udp_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn split_sink_routes_echo_requests_to_icmp_channel() {
+        let (udp_tx, _udp_rx) = mpsc::channel::<Packet>(8);
+        let (tcp_tx, mut tcp_rx) = mpsc::channel::<Packet>(8);
+        let (icmp_tx, mut icmp_rx) = mpsc::channel::<Packet>(8);
+        let mut sink = StackSplitSink::new(udp_tx, tcp_tx).with_icmp(icmp_tx);
+
+        let builder = PacketBuilder::ipv4([10, 0, 0, 1], [1, 1, 1, 1], 64).icmpv4_echo_request(1, 1);
+        let mut raw = Vec::with_capacity(builder.size(0));
+        builder.write(&mut raw, &[]).unwrap();
+        sink.send(Packet::new(Bytes::from(raw))).await.unwrap();
+        assert!(icmp_rx.try_recv().is_ok());
+
+        let builder = PacketBuilder::ipv4([10, 0, 0, 1], [1, 1, 1, 1], 64).icmpv4_echo_reply(1, 1);
+        let mut raw = Vec::with_capacity(builder.size(0));
+        builder.write(&mut raw, &[]).unwrap();
+        sink.send(Packet::new(Bytes::from(raw))).await.unwrap();
+        assert!(icmp_rx.try_recv().is_err());
+        assert!(tcp_rx.try_recv().is_ok());
+    }
+
     #[tokio::test]
     async fn split_sink_ignores_empty_packet() {
         let (udp_tx, mut udp_rx) = mpsc::channel::<Packet>(8);
@@ -436,9 +617,11 @@ mod tests {
 
     #[tokio::test]
     async fn netstack_new_returns_consistent_handles() {
-        let mut cfg = NetStackConfig::default();
-        cfg.number_workers = 1;
-        cfg.channel_size = 64;
+        let cfg = NetStackConfig {
+            number_workers: 1,
+            channel_size: 64,
+            ..Default::default()
+        };
 
         let (_stack, _tcp, _udp) = NetStack::new(cfg);
         // Construction should succeed; we drop everything to clean up workers.
@@ -1,7 +1,21 @@
 use std::io;
 
+use clap::Parser;
+use ombrac::key::PrivateKey;
+use ombrac::token::{self, Claims};
+use ombrac_server::config::cli::{Args, Command};
+
 fn main() {
-    let config = match ombrac_server::config::load() {
+    let args = Args::parse();
+    if let Some(command) = args.command {
+        if let Err(e) = run_command(command) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = match ombrac_server::config::load_from_args(args) {
         Ok(cfg) => cfg,
         Err(error) => {
             eprintln!("failed to load configuration: {}", error);
@@ -24,6 +38,36 @@ fn main() {
     }
 }
 
+/// Runs a subcommand, printing its result.
+fn run_command(command: Command) -> io::Result<()> {
+    match command {
+        Command::Keygen { output } => {
+            let key = PrivateKey::generate()?;
+            key.save(&output)?;
+            println!("{}", key.public_key());
+        }
+        Command::MintToken {
+            key,
+            identity,
+            ttl,
+            id,
+            bandwidth,
+            allow,
+        } => {
+            let key = PrivateKey::load(&key)?;
+            let claims = Claims {
+                id: id.unwrap_or_else(|| format!("{:016x}", rand::random::<u64>())),
+                identity,
+                expires_at: token::now().saturating_add(ttl),
+                bandwidth,
+                allow,
+            };
+            println!("{}", claims.sign(&key)?);
+        }
+    }
+    Ok(())
+}
+
 /// A high-level function to run the server from a command-line context.
 /// It builds the service, waits for a shutdown signal, and then gracefully shuts down.
 pub async fn run_from_cli(config: ombrac_server::config::ServiceConfig) -> io::Result<()> {
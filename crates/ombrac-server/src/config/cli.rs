@@ -1,9 +1,9 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use clap::Parser;
 use clap::builder::Styles;
 use clap::builder::styling::{AnsiColor, Style};
+use clap::{Parser, Subcommand};
 
 use ombrac_transport::quic::Congestion;
 
@@ -11,8 +11,17 @@ use crate::config::{TlsMode, TransportConfig};
 
 /// Command-line arguments for the ombrac server
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None, styles = styles())]
+#[command(
+    version,
+    about,
+    long_about = None,
+    styles = styles(),
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to the JSON configuration file
     #[clap(long, short = 'c', value_name = "FILE")]
     pub config: Option<PathBuf>,
@@ -45,6 +54,46 @@ pub struct Args {
     pub logging: CliLoggingConfig,
 }
 
+/// Subcommands run instead of the server
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate an Ed25519 key to sign access tokens with
+    ///
+    /// The private key is written to FILE, for `connection.token_key`.
+    Keygen {
+        /// Path to write the private key to
+        #[clap(long, short = 'o', value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// Mint an access token signed with the token key
+    MintToken {
+        /// Path to the token key
+        #[clap(long, value_name = "FILE")]
+        key: PathBuf,
+
+        /// Who the token is issued to
+        #[clap(long, value_name = "STR")]
+        identity: String,
+
+        /// Validity of the token in seconds
+        #[clap(long, value_name = "SECS")]
+        ttl: u64,
+
+        /// ID of the token for revocation lists [default: random]
+        #[clap(long, value_name = "STR")]
+        id: Option<String>,
+
+        /// Bandwidth granted to the holder in bytes per second, in each direction
+        #[clap(long, value_name = "NUM")]
+        bandwidth: Option<u64>,
+
+        /// Addresses, networks and domains the holder may reach
+        #[clap(long, value_name = "LIST", value_delimiter = ',')]
+        allow: Option<Vec<String>>,
+    },
+}
+
 /// CLI-specific transport configuration
 #[derive(Parser, Debug, Clone)]
 pub struct CliTransportConfig {
@@ -65,6 +114,24 @@ pub struct CliTransportConfig {
     #[clap(long, help_heading = "Transport", value_name = "FILE")]
     pub tls_key: Option<PathBuf>,
 
+    /// Certificate revocation lists (PEM or DER) to check client certificates
+    /// against in mTLS mode, reloaded when they change
+    #[clap(
+        long,
+        help_heading = "Transport",
+        value_name = "FILE",
+        value_delimiter = ','
+    )]
+    pub crl: Option<Vec<PathBuf>>,
+
+    /// Accept client certificates none of the revocation lists covers [default: false]
+    #[clap(long, help_heading = "Transport", value_name = "BOOL")]
+    pub crl_allow_unknown: Option<bool>,
+
+    /// How often the revocation lists are checked for changes (in seconds) [default: 30]
+    #[clap(long, help_heading = "Transport", value_name = "TIME")]
+    pub crl_reload_secs: Option<u64>,
+
     /// Enable 0-RTT for faster connection establishment
     #[clap(long, help_heading = "Transport", value_name = "BOOL")]
     pub zero_rtt: Option<bool>,
@@ -97,6 +164,18 @@ pub struct CliTransportConfig {
     /// Maximum number of bidirectional streams that can be open simultaneously [default: 1000]
     #[clap(long, help_heading = "Transport", value_name = "NUM")]
     pub max_streams: Option<u64>,
+
+    /// Handshakes per second above which new clients must first prove their address with a stateless retry; 0 disables retries [default: 1000]
+    #[clap(long, help_heading = "Transport", value_name = "NUM")]
+    pub handshake_retry_threshold: Option<u32>,
+
+    /// Maximum number of handshakes in progress; 0 disables the limit [default: 4096]
+    #[clap(long, help_heading = "Transport", value_name = "NUM")]
+    pub max_handshakes: Option<usize>,
+
+    /// Maximum number of handshakes in progress from a single IP address; 0 disables the limit [default: 64]
+    #[clap(long, help_heading = "Transport", value_name = "NUM")]
+    pub max_handshakes_per_ip: Option<usize>,
 }
 
 /// CLI-specific logging configuration
@@ -116,6 +195,9 @@ impl CliTransportConfig {
             ca_cert: self.ca_cert,
             tls_cert: self.tls_cert,
             tls_key: self.tls_key,
+            crl: self.crl,
+            crl_allow_unknown: self.crl_allow_unknown,
+            crl_reload_secs: self.crl_reload_secs,
             zero_rtt: self.zero_rtt,
             alpn_protocols: self.alpn_protocols,
             congestion: self.congestion,
@@ -123,6 +205,9 @@ impl CliTransportConfig {
             idle_timeout: self.idle_timeout,
             keep_alive: self.keep_alive,
             max_streams: self.max_streams,
+            handshake_retry_threshold: self.handshake_retry_threshold,
+            max_handshakes: self.max_handshakes,
+            max_handshakes_per_ip: self.max_handshakes_per_ip,
         }
     }
 }
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "tracing")]
 use crate::config::LoggingConfig;
-use crate::config::{ConnectionConfig, TransportConfig};
+use crate::config::{ConnectionConfig, DnsConfig, OutboundConfig, TransportConfig};
 
 /// JSON configuration file structure
 #[derive(Deserialize, Serialize, Debug, Default)]
@@ -22,6 +22,12 @@ pub struct JsonConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub connection: Option<ConnectionConfig>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<OutboundConfig>,
+
     #[cfg(feature = "tracing")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logging: Option<LoggingConfig>,
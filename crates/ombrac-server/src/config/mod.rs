@@ -1,4 +1,5 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::PathBuf;
 
 use clap::ValueEnum;
@@ -25,6 +26,19 @@ pub struct TransportConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tls_key: Option<PathBuf>,
 
+    /// Certificate revocation lists (PEM or DER) client certificates are
+    /// checked against in mTLS mode, reloaded when they change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crl: Option<Vec<PathBuf>>,
+
+    /// Accept client certificates none of the revocation lists covers [default: false]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crl_allow_unknown: Option<bool>,
+
+    /// How often the revocation lists are checked for changes, in seconds [default: 30]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crl_reload_secs: Option<u64>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub zero_rtt: Option<bool>,
 
@@ -45,6 +59,18 @@ pub struct TransportConfig {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_streams: Option<u64>,
+
+    /// Handshakes per second above which new clients must first prove their address with a stateless retry; 0 disables retries [default: 1000]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_retry_threshold: Option<u32>,
+
+    /// Maximum number of handshakes in progress; 0 disables the limit [default: 4096]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_handshakes: Option<usize>,
+
+    /// Maximum number of handshakes in progress from a single IP address; 0 disables the limit [default: 64]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_handshakes_per_ip: Option<usize>,
 }
 
 impl TransportConfig {
@@ -53,6 +79,21 @@ impl TransportConfig {
         self.tls_mode.unwrap_or_default()
     }
 
+    /// Get certificate revocation lists with default
+    pub fn crl(&self) -> Vec<PathBuf> {
+        self.crl.clone().unwrap_or_default()
+    }
+
+    /// Get crl_allow_unknown with default
+    pub fn crl_allow_unknown(&self) -> bool {
+        self.crl_allow_unknown.unwrap_or(false)
+    }
+
+    /// Get CRL reload interval with default (in seconds)
+    pub fn crl_reload_secs(&self) -> u64 {
+        self.crl_reload_secs.unwrap_or(30)
+    }
+
     /// Get zero_rtt with default
     pub fn zero_rtt(&self) -> bool {
         self.zero_rtt.unwrap_or(false)
@@ -84,6 +125,21 @@ impl TransportConfig {
     pub fn max_streams(&self) -> u64 {
         self.max_streams.unwrap_or(1000)
     }
+
+    /// Get handshake retry threshold with default (per second)
+    pub fn handshake_retry_threshold(&self) -> u32 {
+        self.handshake_retry_threshold.unwrap_or(1000)
+    }
+
+    /// Get max handshakes in progress with default
+    pub fn max_handshakes(&self) -> usize {
+        self.max_handshakes.unwrap_or(4096)
+    }
+
+    /// Get max handshakes in progress per IP address with default
+    pub fn max_handshakes_per_ip(&self) -> usize {
+        self.max_handshakes_per_ip.unwrap_or(64)
+    }
 }
 
 impl Default for TransportConfig {
@@ -93,6 +149,9 @@ impl Default for TransportConfig {
             ca_cert: None,
             tls_cert: None,
             tls_key: None,
+            crl: None,
+            crl_allow_unknown: None,
+            crl_reload_secs: None,
             zero_rtt: Some(false),
             alpn_protocols: Some(vec!["h3".into()]),
             congestion: Some(Congestion::Bbr),
@@ -100,6 +159,9 @@ impl Default for TransportConfig {
             idle_timeout: Some(30000),
             keep_alive: Some(8000),
             max_streams: Some(1000),
+            handshake_retry_threshold: Some(1000),
+            max_handshakes: Some(4096),
+            max_handshakes_per_ip: Some(64),
         }
     }
 }
@@ -123,6 +185,62 @@ pub struct ConnectionConfig {
     /// Maximum concurrent datagram handlers per client connection [default: 4096]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_concurrent_datagrams: Option<usize>,
+
+    /// Address family preference when resolving and dialing upstream destinations [default: prefer-v6]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<IpFamily>,
+
+    /// Which ways of authenticating clients may use [default: secret]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_mode: Option<AuthMode>,
+
+    /// Path to the authorized keys file of clients using public-key authentication
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorized_keys: Option<PathBuf>,
+
+    /// Path to the Ed25519 key access tokens are signed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_key: Option<PathBuf>,
+
+    /// Path to a file listing the IDs of revoked tokens, one per line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_tokens: Option<PathBuf>,
+
+    /// External service deciding on clients sending a secret, `http://HOST[:PORT][/PATH]` or `unix:PATH`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_hook: Option<String>,
+
+    /// Timeout of a request to the auth hook (in milliseconds) [default: 3000]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_hook_timeout: Option<u64>,
+
+    /// How long decisions of the auth hook are cached, in seconds; 0 disables caching [default: 60]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_hook_cache_secs: Option<u64>,
+
+    /// Whether clients are accepted while the auth hook is unreachable [default: false]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_hook_fail_open: Option<bool>,
+
+    /// Authentication failures within the window after which an address is banned; 0 disables banning [default: 0]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_ban_failures: Option<u32>,
+
+    /// Window authentication failures are counted in, in seconds [default: 60]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_ban_window_secs: Option<u64>,
+
+    /// Duration of a first ban, in seconds; each further ban lasts twice as long [default: 60]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_ban_secs: Option<u64>,
+
+    /// Maximum duration of a ban, in seconds [default: 86400]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_ban_max_secs: Option<u64>,
+
+    /// Addresses and networks never banned, e.g. `10.0.0.0/8`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_ban_allowlist: Option<Vec<String>>,
 }
 
 impl ConnectionConfig {
@@ -145,6 +263,61 @@ impl ConnectionConfig {
     pub fn max_concurrent_datagrams(&self) -> usize {
         self.max_concurrent_datagrams.unwrap_or(4096)
     }
+
+    /// Get upstream address family preference with default
+    pub fn ip_family(&self) -> IpFamily {
+        self.ip_family.unwrap_or_default()
+    }
+
+    /// Get client authentication mode with default
+    pub fn auth_mode(&self) -> AuthMode {
+        self.auth_mode.unwrap_or_default()
+    }
+
+    /// Whether clients are checked against the shared secret, which the
+    /// auth hook does instead of it in the secret mode
+    pub fn requires_secret(&self) -> bool {
+        match self.auth_mode() {
+            AuthMode::Secret => self.auth_hook.is_none(),
+            AuthMode::Challenge => true,
+            AuthMode::PublicKey | AuthMode::Token | AuthMode::Certificate => false,
+        }
+    }
+
+    /// Get auth hook timeout with default (in milliseconds)
+    pub fn auth_hook_timeout(&self) -> u64 {
+        self.auth_hook_timeout.unwrap_or(3000)
+    }
+
+    /// Get auth hook cache duration with default (in seconds)
+    pub fn auth_hook_cache_secs(&self) -> u64 {
+        self.auth_hook_cache_secs.unwrap_or(60)
+    }
+
+    /// Get auth hook failure policy with default
+    pub fn auth_hook_fail_open(&self) -> bool {
+        self.auth_hook_fail_open.unwrap_or(false)
+    }
+
+    /// Get failures before a ban with default
+    pub fn auth_ban_failures(&self) -> u32 {
+        self.auth_ban_failures.unwrap_or(0)
+    }
+
+    /// Get ban failure window with default (in seconds)
+    pub fn auth_ban_window_secs(&self) -> u64 {
+        self.auth_ban_window_secs.unwrap_or(60)
+    }
+
+    /// Get first ban duration with default (in seconds)
+    pub fn auth_ban_secs(&self) -> u64 {
+        self.auth_ban_secs.unwrap_or(60)
+    }
+
+    /// Get maximum ban duration with default (in seconds)
+    pub fn auth_ban_max_secs(&self) -> u64 {
+        self.auth_ban_max_secs.unwrap_or(86400)
+    }
 }
 
 impl Default for ConnectionConfig {
@@ -154,10 +327,186 @@ impl Default for ConnectionConfig {
             auth_timeout_secs: Some(10),
             max_concurrent_streams: Some(4096),
             max_concurrent_datagrams: Some(4096),
+            ip_family: Some(IpFamily::PreferV6),
+            auth_mode: Some(AuthMode::Secret),
+            authorized_keys: None,
+            token_key: None,
+            revoked_tokens: None,
+            auth_hook: None,
+            auth_hook_timeout: Some(3000),
+            auth_hook_cache_secs: Some(60),
+            auth_hook_fail_open: Some(false),
+            auth_ban_failures: Some(0),
+            auth_ban_window_secs: Some(60),
+            auth_ban_secs: Some(60),
+            auth_ban_max_secs: Some(86400),
+            auth_ban_allowlist: None,
         }
     }
 }
 
+/// Upstream DNS resolver configuration, shared by stream and datagram tunnels
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct DnsConfig {
+    /// Upstream nameservers; the system resolver configuration is used when empty.
+    /// Accepts `IP[:PORT]` (UDP and TCP), `udp://IP[:PORT]`, `tcp://IP[:PORT]`,
+    /// `tls://IP[:PORT][#NAME]` and `https://IP[:PORT][/PATH][#NAME]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nameservers: Option<Vec<String>>,
+
+    /// Static host entries answered without any upstream lookup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<HashMap<String, Vec<IpAddr>>>,
+
+    /// Maximum number of cached DNS responses [default: 8192]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<u64>,
+
+    /// Lower bound in seconds for the TTL of cached positive responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_ttl_secs: Option<u64>,
+
+    /// Upper bound in seconds for the TTL of cached positive responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ttl_secs: Option<u64>,
+
+    /// Upper bound in seconds for the TTL of cached negative (NXDOMAIN / no data) responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_ttl_secs: Option<u64>,
+
+    /// Timeout for a single query to a nameserver (in milliseconds) [default: 5000]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Number of attempts per lookup before giving up [default: 2]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<usize>,
+}
+
+impl DnsConfig {
+    /// Get configured nameservers (empty means system configuration)
+    pub fn nameservers(&self) -> &[String] {
+        self.nameservers.as_deref().unwrap_or_default()
+    }
+
+    /// Get cache size with default
+    pub fn cache_size(&self) -> u64 {
+        self.cache_size.unwrap_or(8192)
+    }
+
+    /// Get per-query timeout with default (in milliseconds)
+    pub fn timeout_ms(&self) -> u64 {
+        self.timeout_ms.unwrap_or(5000)
+    }
+
+    /// Get lookup attempts with default
+    pub fn attempts(&self) -> usize {
+        self.attempts.unwrap_or(2)
+    }
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            nameservers: None,
+            hosts: None,
+            cache_size: Some(8192),
+            min_ttl_secs: None,
+            max_ttl_secs: None,
+            negative_ttl_secs: None,
+            timeout_ms: Some(5000),
+            attempts: Some(2),
+        }
+    }
+}
+
+/// Egress configuration deciding how upstream destinations are reached
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct OutboundConfig {
+    /// How destinations are reached [default: direct]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<OutboundMode>,
+
+    /// Address of the next hop ombrac server or of the proxy, e.g. `exit.example.com:443`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+
+    /// Username for the proxy; authentication is skipped if omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+
+    /// Password for the proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+
+    /// Secret used to authenticate with the next hop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+
+    /// TLS server name of the next hop, derived from `server` if omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+
+    /// TLS mode towards the next hop [default: tls]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_mode: Option<TlsMode>,
+
+    /// CA certificate used to verify the next hop; the system roots are used if omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Client certificate presented to the next hop in mTLS mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+
+    /// Client private key presented to the next hop in mTLS mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+
+    /// Source address of IPv4 connections dialed directly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_ipv4: Option<Ipv4Addr>,
+
+    /// Source address of IPv6 connections dialed directly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_ipv6: Option<Ipv6Addr>,
+
+    /// Interface direct connections are bound to, e.g. `eth1` (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+
+    /// Firewall mark of direct connections, for policy routing (Linux only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mark: Option<u32>,
+
+    /// IPv6 prefix the source of direct connections is picked from, e.g. `2001:db8:1::/48`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6_prefix: Option<String>,
+
+    /// Whether a source is picked from `ipv6_prefix` per connection or per user [default: connection]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6_prefix_scope: Option<Ipv6PrefixScope>,
+}
+
+impl OutboundConfig {
+    /// Get outbound mode with default
+    pub fn mode(&self) -> OutboundMode {
+        self.mode.unwrap_or_default()
+    }
+
+    /// Get TLS mode towards the next hop with default
+    pub fn tls_mode(&self) -> TlsMode {
+        self.tls_mode.unwrap_or_default()
+    }
+
+    /// Get IPv6 prefix scope with default
+    pub fn ipv6_prefix_scope(&self) -> Ipv6PrefixScope {
+        self.ipv6_prefix_scope.unwrap_or_default()
+    }
+}
+
 /// Logging configuration
 #[cfg(feature = "tracing")]
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -194,13 +543,86 @@ pub enum TlsMode {
     Insecure,
 }
 
+/// Which address families are used when connecting to upstream destinations.
+///
+/// The `*-only` variants discard resolved addresses of the other family; the
+/// `prefer-*` variants keep both and only decide which family is tried first.
+#[derive(ValueEnum, Clone, Debug, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpFamily {
+    Ipv4Only,
+    Ipv6Only,
+    PreferV4,
+    #[default]
+    PreferV6,
+}
+
+/// How clients authenticate.
+///
+/// With `secret` clients may still send the hash of the secret in their
+/// hello, as every client predating challenge-response does; `challenge`
+/// refuses them, so a leaked hash is useless. Both also accept clients
+/// holding an authorized key or a valid access token.
+///
+/// The other modes only accept their own credential: `public-key` an
+/// authorized key, `token` an access token, and `certificate` the
+/// certificate clients present in mTLS mode, naming them after it.
+#[derive(ValueEnum, Clone, Debug, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMode {
+    #[default]
+    Secret,
+    Challenge,
+    PublicKey,
+    Token,
+    Certificate,
+}
+
+/// How the server reaches upstream destinations.
+///
+/// With `ombrac` the server authenticates with another ombrac server as a
+/// regular client and relays streams and UDP sessions through it, so
+/// servers can be chained. Clients are still authenticated by the entry
+/// server; the next hop only sees the entry server's secret.
+///
+/// `socks5` and `http-connect` dial through a plain upstream proxy instead.
+/// Only `socks5` can relay UDP, through `UDP ASSOCIATE`.
+#[derive(ValueEnum, Clone, Debug, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutboundMode {
+    #[default]
+    Direct,
+    Ombrac,
+    Socks5,
+    HttpConnect,
+}
+
+/// How often the source of direct connections is picked from the IPv6 prefix.
+///
+/// `user` derives it from who the client authenticated as: the label of its
+/// key, the identity of its token or certificate, or the identity returned
+/// by the auth hook. Each such user keeps the same egress address across
+/// connections. Clients holding the shared secret, and those the hook
+/// accepts without an identity, can't be told apart and share one address.
+#[derive(ValueEnum, Clone, Debug, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ipv6PrefixScope {
+    #[default]
+    Connection,
+    User,
+}
+
 /// Final service configuration with all defaults applied
 #[derive(Debug, Clone)]
 pub struct ServiceConfig {
+    /// Shared secret of clients, empty if they only authenticate by key,
+    /// token or certificate
     pub secret: String,
     pub listen: SocketAddr,
     pub transport: TransportConfig,
     pub connection: ConnectionConfig,
+    pub dns: DnsConfig,
+    pub outbound: OutboundConfig,
     #[cfg(feature = "tracing")]
     pub logging: LoggingConfig,
 }
@@ -212,6 +634,8 @@ pub struct ConfigBuilder {
     listen: Option<SocketAddr>,
     transport: TransportConfig,
     connection: ConnectionConfig,
+    dns: DnsConfig,
+    outbound: OutboundConfig,
     #[cfg(feature = "tracing")]
     logging: LoggingConfig,
 }
@@ -224,6 +648,8 @@ impl ConfigBuilder {
             listen: None,
             transport: TransportConfig::default(),
             connection: ConnectionConfig::default(),
+            dns: DnsConfig::default(),
+            outbound: OutboundConfig::default(),
             #[cfg(feature = "tracing")]
             logging: LoggingConfig::default(),
         }
@@ -243,6 +669,12 @@ impl ConfigBuilder {
         if let Some(conn) = json_config.connection {
             self.connection = Self::merge_connection(self.connection, conn);
         }
+        if let Some(dns) = json_config.dns {
+            self.dns = Self::merge_dns(self.dns, dns);
+        }
+        if let Some(outbound) = json_config.outbound {
+            self.outbound = Self::merge_outbound(self.outbound, outbound);
+        }
         #[cfg(feature = "tracing")]
         {
             if let Some(logging) = json_config.logging {
@@ -270,9 +702,11 @@ impl ConfigBuilder {
 
     /// Build the final ServiceConfig, validating required fields
     pub fn build(self) -> Result<ServiceConfig, String> {
-        let secret = self
-            .secret
-            .ok_or_else(|| "missing required field: secret".to_string())?;
+        let secret = match self.secret {
+            Some(secret) => secret,
+            None if !self.connection.requires_secret() => String::new(),
+            None => return Err("missing required field: secret".to_string()),
+        };
         let listen = self
             .listen
             .ok_or_else(|| "missing required field: listen".to_string())?;
@@ -282,6 +716,8 @@ impl ConfigBuilder {
             listen,
             transport: self.transport,
             connection: self.connection,
+            dns: self.dns,
+            outbound: self.outbound,
             #[cfg(feature = "tracing")]
             logging: self.logging,
         })
@@ -293,6 +729,9 @@ impl ConfigBuilder {
             ca_cert: override_config.ca_cert.or(base.ca_cert),
             tls_cert: override_config.tls_cert.or(base.tls_cert),
             tls_key: override_config.tls_key.or(base.tls_key),
+            crl: override_config.crl.or(base.crl),
+            crl_allow_unknown: override_config.crl_allow_unknown.or(base.crl_allow_unknown),
+            crl_reload_secs: override_config.crl_reload_secs.or(base.crl_reload_secs),
             zero_rtt: override_config.zero_rtt.or(base.zero_rtt),
             alpn_protocols: override_config.alpn_protocols.or(base.alpn_protocols),
             congestion: override_config.congestion.or(base.congestion),
@@ -300,6 +739,13 @@ impl ConfigBuilder {
             idle_timeout: override_config.idle_timeout.or(base.idle_timeout),
             keep_alive: override_config.keep_alive.or(base.keep_alive),
             max_streams: override_config.max_streams.or(base.max_streams),
+            handshake_retry_threshold: override_config
+                .handshake_retry_threshold
+                .or(base.handshake_retry_threshold),
+            max_handshakes: override_config.max_handshakes.or(base.max_handshakes),
+            max_handshakes_per_ip: override_config
+                .max_handshakes_per_ip
+                .or(base.max_handshakes_per_ip),
         }
     }
 
@@ -316,6 +762,62 @@ impl ConfigBuilder {
             max_concurrent_datagrams: override_config
                 .max_concurrent_datagrams
                 .or(base.max_concurrent_datagrams),
+            ip_family: override_config.ip_family.or(base.ip_family),
+            auth_mode: override_config.auth_mode.or(base.auth_mode),
+            authorized_keys: override_config.authorized_keys.or(base.authorized_keys),
+            token_key: override_config.token_key.or(base.token_key),
+            revoked_tokens: override_config.revoked_tokens.or(base.revoked_tokens),
+            auth_hook: override_config.auth_hook.or(base.auth_hook),
+            auth_hook_timeout: override_config.auth_hook_timeout.or(base.auth_hook_timeout),
+            auth_hook_cache_secs: override_config
+                .auth_hook_cache_secs
+                .or(base.auth_hook_cache_secs),
+            auth_hook_fail_open: override_config
+                .auth_hook_fail_open
+                .or(base.auth_hook_fail_open),
+            auth_ban_failures: override_config.auth_ban_failures.or(base.auth_ban_failures),
+            auth_ban_window_secs: override_config
+                .auth_ban_window_secs
+                .or(base.auth_ban_window_secs),
+            auth_ban_secs: override_config.auth_ban_secs.or(base.auth_ban_secs),
+            auth_ban_max_secs: override_config.auth_ban_max_secs.or(base.auth_ban_max_secs),
+            auth_ban_allowlist: override_config
+                .auth_ban_allowlist
+                .or(base.auth_ban_allowlist),
+        }
+    }
+
+    fn merge_dns(base: DnsConfig, override_config: DnsConfig) -> DnsConfig {
+        DnsConfig {
+            nameservers: override_config.nameservers.or(base.nameservers),
+            hosts: override_config.hosts.or(base.hosts),
+            cache_size: override_config.cache_size.or(base.cache_size),
+            min_ttl_secs: override_config.min_ttl_secs.or(base.min_ttl_secs),
+            max_ttl_secs: override_config.max_ttl_secs.or(base.max_ttl_secs),
+            negative_ttl_secs: override_config.negative_ttl_secs.or(base.negative_ttl_secs),
+            timeout_ms: override_config.timeout_ms.or(base.timeout_ms),
+            attempts: override_config.attempts.or(base.attempts),
+        }
+    }
+
+    fn merge_outbound(base: OutboundConfig, override_config: OutboundConfig) -> OutboundConfig {
+        OutboundConfig {
+            mode: override_config.mode.or(base.mode),
+            server: override_config.server.or(base.server),
+            username: override_config.username.or(base.username),
+            password: override_config.password.or(base.password),
+            secret: override_config.secret.or(base.secret),
+            server_name: override_config.server_name.or(base.server_name),
+            tls_mode: override_config.tls_mode.or(base.tls_mode),
+            ca_cert: override_config.ca_cert.or(base.ca_cert),
+            client_cert: override_config.client_cert.or(base.client_cert),
+            client_key: override_config.client_key.or(base.client_key),
+            bind_ipv4: override_config.bind_ipv4.or(base.bind_ipv4),
+            bind_ipv6: override_config.bind_ipv6.or(base.bind_ipv6),
+            interface: override_config.interface.or(base.interface),
+            mark: override_config.mark.or(base.mark),
+            ipv6_prefix: override_config.ipv6_prefix.or(base.ipv6_prefix),
+            ipv6_prefix_scope: override_config.ipv6_prefix_scope.or(base.ipv6_prefix_scope),
         }
     }
 
@@ -346,7 +848,15 @@ impl Default for ConfigBuilder {
 #[cfg(feature = "binary")]
 pub fn load() -> Result<ServiceConfig, Box<dyn std::error::Error>> {
     use clap::Parser;
-    let cli_args = cli::Args::parse();
+    load_from_args(cli::Args::parse())
+}
+
+/// Loads configuration from already parsed command-line arguments.
+///
+/// Like [`load`], for callers that need to look at the arguments first,
+/// e.g. to run a subcommand.
+#[cfg(feature = "binary")]
+pub fn load_from_args(cli_args: cli::Args) -> Result<ServiceConfig, Box<dyn std::error::Error>> {
     let mut builder = ConfigBuilder::new();
 
     // Load JSON config if specified
@@ -437,6 +947,29 @@ mod tests {
         assert!(err.to_string().contains("secret"));
     }
 
+    #[test]
+    fn load_from_json_without_secret_in_strict_modes() {
+        for auth_mode in ["public-key", "token", "certificate"] {
+            let json = format!(
+                r#"{{ "listen": "0.0.0.0:443", "connection": {{ "auth_mode": "{auth_mode}" }} }}"#
+            );
+            let cfg = load_from_json(&json).unwrap();
+            assert_eq!(cfg.secret, "");
+        }
+
+        let json =
+            r#"{ "listen": "0.0.0.0:443", "connection": { "auth_hook": "unix:/run/auth.sock" } }"#;
+        assert_eq!(load_from_json(json).unwrap().secret, "");
+
+        let json = r#"{ "listen": "0.0.0.0:443", "connection": { "auth_mode": "challenge" } }"#;
+        assert!(
+            load_from_json(json)
+                .unwrap_err()
+                .to_string()
+                .contains("secret")
+        );
+    }
+
     #[test]
     fn load_from_json_missing_listen_fails() {
         let json = r#"{ "secret": "k" }"#;
@@ -458,14 +991,27 @@ mod tests {
             "listen": "127.0.0.1:443",
             "transport": {
                 "tls_mode": "m-tls",
+                "crl": ["ca.crl", "intermediate.crl"],
+                "crl_allow_unknown": true,
                 "idle_timeout": 12345,
-                "max_streams": 999
+                "max_streams": 999,
+                "handshake_retry_threshold": 0,
+                "max_handshakes_per_ip": 8
             }
         }"#;
         let cfg = load_from_json(json).unwrap();
         assert_eq!(cfg.transport.tls_mode, Some(TlsMode::MTls));
+        assert_eq!(
+            cfg.transport.crl(),
+            vec![PathBuf::from("ca.crl"), PathBuf::from("intermediate.crl")]
+        );
+        assert!(cfg.transport.crl_allow_unknown());
+        assert_eq!(cfg.transport.crl_reload_secs(), 30);
         assert_eq!(cfg.transport.idle_timeout, Some(12345));
         assert_eq!(cfg.transport.max_streams, Some(999));
+        assert_eq!(cfg.transport.handshake_retry_threshold(), 0);
+        assert_eq!(cfg.transport.max_handshakes(), 4096);
+        assert_eq!(cfg.transport.max_handshakes_per_ip(), 8);
     }
 
     #[test]
@@ -477,7 +1023,17 @@ mod tests {
                 "max_connections": 500,
                 "auth_timeout_secs": 5,
                 "max_concurrent_streams": 100,
-                "max_concurrent_datagrams": 200
+                "max_concurrent_datagrams": 200,
+                "auth_mode": "public-key",
+                "authorized_keys": "/etc/ombrac/authorized_keys",
+                "token_key": "/etc/ombrac/token.pem",
+                "revoked_tokens": "/etc/ombrac/revoked_tokens",
+                "auth_hook": "unix:/run/ombrac-auth.sock",
+                "auth_hook_cache_secs": 0,
+                "auth_hook_fail_open": true,
+                "auth_ban_failures": 5,
+                "auth_ban_secs": 300,
+                "auth_ban_allowlist": ["10.0.0.0/8", "2001:db8::1"]
             }
         }"#;
         let cfg = load_from_json(json).unwrap();
@@ -485,6 +1041,33 @@ mod tests {
         assert_eq!(cfg.connection.auth_timeout_secs, Some(5));
         assert_eq!(cfg.connection.max_concurrent_streams, Some(100));
         assert_eq!(cfg.connection.max_concurrent_datagrams, Some(200));
+        assert_eq!(cfg.connection.auth_mode(), AuthMode::PublicKey);
+        assert_eq!(
+            cfg.connection.authorized_keys,
+            Some(PathBuf::from("/etc/ombrac/authorized_keys"))
+        );
+        assert_eq!(
+            cfg.connection.token_key,
+            Some(PathBuf::from("/etc/ombrac/token.pem"))
+        );
+        assert_eq!(
+            cfg.connection.revoked_tokens,
+            Some(PathBuf::from("/etc/ombrac/revoked_tokens"))
+        );
+        assert_eq!(
+            cfg.connection.auth_hook.as_deref(),
+            Some("unix:/run/ombrac-auth.sock")
+        );
+        assert_eq!(cfg.connection.auth_hook_timeout(), 3000);
+        assert_eq!(cfg.connection.auth_hook_cache_secs(), 0);
+        assert!(cfg.connection.auth_hook_fail_open());
+        assert_eq!(cfg.connection.auth_ban_failures(), 5);
+        assert_eq!(cfg.connection.auth_ban_window_secs(), 60);
+        assert_eq!(cfg.connection.auth_ban_secs(), 300);
+        assert_eq!(
+            cfg.connection.auth_ban_allowlist,
+            Some(vec!["10.0.0.0/8".to_string(), "2001:db8::1".to_string()])
+        );
     }
 
     #[test]
@@ -498,6 +1081,8 @@ mod tests {
                 ..Default::default()
             }),
             connection: None,
+            dns: None,
+            outbound: None,
             #[cfg(feature = "tracing")]
             logging: None,
         };
@@ -533,6 +1118,9 @@ mod tests {
             ca_cert: None,
             tls_cert: None,
             tls_key: None,
+            crl: None,
+            crl_allow_unknown: None,
+            crl_reload_secs: None,
             zero_rtt: None,
             alpn_protocols: None,
             congestion: None,
@@ -540,12 +1128,20 @@ mod tests {
             idle_timeout: None,
             keep_alive: None,
             max_streams: None,
+            handshake_retry_threshold: None,
+            max_handshakes: None,
+            max_handshakes_per_ip: None,
         };
         assert_eq!(cfg.tls_mode(), TlsMode::Tls);
+        assert!(cfg.crl().is_empty());
+        assert!(!cfg.crl_allow_unknown());
         assert!(!cfg.zero_rtt());
         assert_eq!(cfg.idle_timeout(), 30000);
         assert_eq!(cfg.keep_alive(), 8000);
         assert_eq!(cfg.max_streams(), 1000);
+        assert_eq!(cfg.handshake_retry_threshold(), 1000);
+        assert_eq!(cfg.max_handshakes(), 4096);
+        assert_eq!(cfg.max_handshakes_per_ip(), 64);
         assert_eq!(cfg.alpn_protocols(), vec![b"h3".to_vec()]);
     }
 
@@ -556,11 +1152,170 @@ mod tests {
             auth_timeout_secs: None,
             max_concurrent_streams: None,
             max_concurrent_datagrams: None,
+            ip_family: None,
+            auth_mode: None,
+            authorized_keys: None,
+            token_key: None,
+            revoked_tokens: None,
+            auth_hook: None,
+            auth_hook_timeout: None,
+            auth_hook_cache_secs: None,
+            auth_hook_fail_open: None,
+            auth_ban_failures: None,
+            auth_ban_window_secs: None,
+            auth_ban_secs: None,
+            auth_ban_max_secs: None,
+            auth_ban_allowlist: None,
         };
         assert_eq!(cfg.max_connections(), 10000);
         assert_eq!(cfg.auth_timeout_secs(), 10);
         assert_eq!(cfg.max_concurrent_streams(), 4096);
         assert_eq!(cfg.max_concurrent_datagrams(), 4096);
+        assert_eq!(cfg.ip_family(), IpFamily::PreferV6);
+        assert_eq!(cfg.auth_mode(), AuthMode::Secret);
+        assert_eq!(cfg.auth_hook_timeout(), 3000);
+        assert_eq!(cfg.auth_hook_cache_secs(), 60);
+        assert!(!cfg.auth_hook_fail_open());
+        assert_eq!(cfg.auth_ban_failures(), 0);
+        assert_eq!(cfg.auth_ban_window_secs(), 60);
+        assert_eq!(cfg.auth_ban_secs(), 60);
+        assert_eq!(cfg.auth_ban_max_secs(), 86400);
+    }
+
+    #[test]
+    fn ip_family_kebab_case_serialization() {
+        let json = r#"{
+            "secret": "k",
+            "listen": "127.0.0.1:443",
+            "connection": { "ip_family": "ipv4-only" }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.connection.ip_family(), IpFamily::Ipv4Only);
+        assert_eq!(
+            serde_json::to_string(&IpFamily::PreferV4).unwrap(),
+            "\"prefer-v4\""
+        );
+        assert_eq!(
+            serde_json::to_string(&IpFamily::Ipv6Only).unwrap(),
+            "\"ipv6-only\""
+        );
+    }
+
+    #[test]
+    fn load_from_json_dns_section() {
+        let json = r#"{
+            "secret": "k",
+            "listen": "127.0.0.1:443",
+            "dns": {
+                "nameservers": ["1.1.1.1", "tls://1.1.1.1#cloudflare-dns.com"],
+                "hosts": { "internal.example": ["10.0.0.1", "fd00::1"] },
+                "negative_ttl_secs": 30,
+                "timeout_ms": 2000
+            }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.dns.nameservers().len(), 2);
+        assert_eq!(cfg.dns.hosts.as_ref().unwrap()["internal.example"].len(), 2);
+        assert_eq!(cfg.dns.negative_ttl_secs, Some(30));
+        assert_eq!(cfg.dns.timeout_ms(), 2000);
+        // Unset fields keep their defaults after merging
+        assert_eq!(cfg.dns.cache_size(), 8192);
+        assert_eq!(cfg.dns.attempts(), 2);
+    }
+
+    #[test]
+    fn dns_config_accessors_apply_defaults_on_none() {
+        let cfg = DnsConfig {
+            nameservers: None,
+            hosts: None,
+            cache_size: None,
+            min_ttl_secs: None,
+            max_ttl_secs: None,
+            negative_ttl_secs: None,
+            timeout_ms: None,
+            attempts: None,
+        };
+        assert!(cfg.nameservers().is_empty());
+        assert_eq!(cfg.cache_size(), 8192);
+        assert_eq!(cfg.timeout_ms(), 5000);
+        assert_eq!(cfg.attempts(), 2);
+    }
+
+    #[test]
+    fn load_from_json_outbound_section() {
+        let json = r#"{
+            "secret": "k",
+            "listen": "127.0.0.1:443",
+            "outbound": {
+                "mode": "ombrac",
+                "server": "exit.example.com:443",
+                "secret": "next-hop",
+                "tls_mode": "insecure"
+            }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.outbound.mode(), OutboundMode::Ombrac);
+        assert_eq!(cfg.outbound.server.as_deref(), Some("exit.example.com:443"));
+        assert_eq!(cfg.outbound.secret.as_deref(), Some("next-hop"));
+        assert_eq!(cfg.outbound.tls_mode(), TlsMode::Insecure);
+        assert!(cfg.outbound.server_name.is_none());
+
+        let cfg = load_from_json(r#"{ "secret": "k", "listen": "127.0.0.1:443" }"#).unwrap();
+        assert_eq!(cfg.outbound.mode(), OutboundMode::Direct);
+    }
+
+    #[test]
+    fn load_from_json_outbound_socket_options() {
+        let json = r#"{
+            "secret": "k",
+            "listen": "127.0.0.1:443",
+            "outbound": {
+                "bind_ipv4": "203.0.113.7",
+                "interface": "eth1",
+                "mark": 100,
+                "ipv6_prefix": "2001:db8:1::/48",
+                "ipv6_prefix_scope": "user"
+            }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.outbound.mode(), OutboundMode::Direct);
+        assert_eq!(cfg.outbound.bind_ipv4, Some(Ipv4Addr::new(203, 0, 113, 7)));
+        assert!(cfg.outbound.bind_ipv6.is_none());
+        assert_eq!(cfg.outbound.interface.as_deref(), Some("eth1"));
+        assert_eq!(cfg.outbound.mark, Some(100));
+        assert_eq!(cfg.outbound.ipv6_prefix.as_deref(), Some("2001:db8:1::/48"));
+        assert_eq!(cfg.outbound.ipv6_prefix_scope(), Ipv6PrefixScope::User);
+
+        let cfg = load_from_json(r#"{ "secret": "k", "listen": "127.0.0.1:443" }"#).unwrap();
+        assert_eq!(
+            cfg.outbound.ipv6_prefix_scope(),
+            Ipv6PrefixScope::Connection
+        );
+    }
+
+    #[test]
+    fn load_from_json_outbound_proxy() {
+        let json = r#"{
+            "secret": "k",
+            "listen": "127.0.0.1:443",
+            "outbound": {
+                "mode": "http-connect",
+                "server": "proxy.internal:3128",
+                "username": "egress",
+                "password": "hunter2"
+            }
+        }"#;
+        let cfg = load_from_json(json).unwrap();
+        assert_eq!(cfg.outbound.mode(), OutboundMode::HttpConnect);
+        assert_eq!(cfg.outbound.server.as_deref(), Some("proxy.internal:3128"));
+        assert_eq!(cfg.outbound.username.as_deref(), Some("egress"));
+        assert_eq!(cfg.outbound.password.as_deref(), Some("hunter2"));
+
+        let cfg = load_from_json(
+            r#"{ "secret": "k", "listen": "127.0.0.1:443", "outbound": { "mode": "socks5" } }"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.outbound.mode(), OutboundMode::Socks5);
     }
 
     #[test]
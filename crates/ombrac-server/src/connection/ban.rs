@@ -0,0 +1,361 @@
+//! Bans source addresses that keep failing to authenticate.
+//!
+//! Failures are counted per network: the address itself for IPv4 and its
+//! /64 for IPv6, since a single host usually holds a whole /64. A network
+//! reaching the failure limit within the window is banned, and each further
+//! ban lasts twice as long as the previous one, up to a maximum. A network
+//! that stays out of trouble for the maximum ban duration starts over.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+use ombrac::metrics::Metrics;
+use ombrac_macros::warn;
+
+/// Upper bound for the number of networks tracked at once, so that failures
+/// from many addresses can't grow the list without bound.
+const MAX_TRACKED_NETWORKS: usize = 64 * 1024;
+
+/// How often networks that no longer matter are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A network currently refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub network: IpNet,
+    /// Time left until the ban is lifted.
+    pub remaining: Duration,
+    /// Number of times the network was banned in a row, this ban included.
+    pub strikes: u32,
+}
+
+#[derive(Debug)]
+struct Entry {
+    failures: u32,
+    window_start: Instant,
+    banned_until: Option<Instant>,
+    strikes: u32,
+}
+
+impl Entry {
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+}
+
+#[derive(Debug)]
+struct Entries {
+    networks: HashMap<IpNet, Entry>,
+    last_prune: Instant,
+}
+
+/// Tracks authentication failures and the networks banned for them.
+#[derive(Debug)]
+pub struct BanList {
+    max_failures: u32,
+    window: Duration,
+    ban_duration: Duration,
+    max_ban_duration: Duration,
+    allowlist: Vec<IpNet>,
+    metrics: Metrics,
+    entries: Mutex<Entries>,
+}
+
+impl BanList {
+    /// Creates a list banning a network for 60 s after 10 failures within
+    /// 60 s, for at most a day on repeated bans. Bans are counted in
+    /// `metrics`.
+    pub fn new(metrics: Metrics) -> Self {
+        Self {
+            max_failures: 10,
+            window: Duration::from_secs(60),
+            ban_duration: Duration::from_secs(60),
+            max_ban_duration: Duration::from_secs(24 * 60 * 60),
+            allowlist: Vec::new(),
+            metrics,
+            entries: Mutex::new(Entries {
+                networks: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    /// Sets how many failures within the window get a network banned. Zero
+    /// disables banning.
+    pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Sets the window failures are counted in.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets how long a first ban lasts and how long repeated bans may last.
+    pub fn with_ban_duration(mut self, ban_duration: Duration, max_ban_duration: Duration) -> Self {
+        self.ban_duration = ban_duration;
+        self.max_ban_duration = max_ban_duration.max(ban_duration);
+        self
+    }
+
+    /// Sets the networks never banned.
+    pub fn with_allowlist(mut self, allowlist: Vec<IpNet>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Returns the network failures of `ip` are counted against.
+    pub fn network(ip: IpAddr) -> IpNet {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => IpNet::V4(Ipv4Net::from(ip)),
+            IpAddr::V6(ip) => IpNet::V6(
+                Ipv6Net::new(ip, 64)
+                    .expect("64 is a valid IPv6 prefix length")
+                    .trunc(),
+            ),
+        }
+    }
+
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.allowlist.iter().any(|network| network.contains(&ip))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns whether `ip` is currently banned.
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        if self.is_allowed(ip) {
+            return false;
+        }
+        let now = Instant::now();
+        self.lock()
+            .networks
+            .get(&Self::network(ip))
+            .is_some_and(|entry| entry.is_banned(now))
+    }
+
+    /// Returns whether a new connection from `ip` may go ahead, counting it
+    /// in the metrics if it is refused.
+    pub fn admit(&self, ip: IpAddr) -> bool {
+        if self.is_banned(ip) {
+            self.metrics
+                .counters()
+                .connections_banned
+                .fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Records an authentication failure from `ip` and returns whether its
+    /// network is banned.
+    pub fn record_failure(&self, ip: IpAddr) -> bool {
+        if self.max_failures == 0 || self.is_allowed(ip) {
+            return false;
+        }
+        let now = Instant::now();
+        let network = Self::network(ip);
+
+        let mut entries = self.lock();
+        if now.duration_since(entries.last_prune) >= PRUNE_INTERVAL {
+            self.prune(&mut entries, now);
+        }
+        if entries.networks.len() >= MAX_TRACKED_NETWORKS
+            && !entries.networks.contains_key(&network)
+        {
+            return false;
+        }
+
+        let entry = entries.networks.entry(network).or_insert(Entry {
+            failures: 0,
+            window_start: now,
+            banned_until: None,
+            strikes: 0,
+        });
+        // Connections accepted before the ban may still fail
+        if entry.is_banned(now) {
+            return true;
+        }
+        if entry
+            .banned_until
+            .is_some_and(|until| now.duration_since(until) >= self.max_ban_duration)
+        {
+            entry.strikes = 0;
+            entry.banned_until = None;
+        }
+        if now.duration_since(entry.window_start) >= self.window {
+            entry.failures = 0;
+            entry.window_start = now;
+        }
+
+        entry.failures += 1;
+        if entry.failures < self.max_failures {
+            return false;
+        }
+
+        let duration = self
+            .ban_duration
+            .saturating_mul(1 << entry.strikes.min(31))
+            .min(self.max_ban_duration);
+        entry.failures = 0;
+        entry.strikes += 1;
+        entry.banned_until = Some(now + duration);
+        self.metrics
+            .counters()
+            .auth_bans
+            .fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "banned {} for {:?} after {} authentication failures",
+            network, duration, self.max_failures
+        );
+        true
+    }
+
+    /// Forgets networks that are neither banned, nor failing within the
+    /// window, nor within the maximum ban duration of their last ban.
+    fn prune(&self, entries: &mut Entries, now: Instant) {
+        entries.last_prune = now;
+        entries.networks.retain(|_, entry| {
+            let recent_ban = entry
+                .banned_until
+                .is_some_and(|until| until + self.max_ban_duration > now);
+            recent_ban || now.duration_since(entry.window_start) < self.window
+        });
+    }
+
+    /// Returns the networks currently banned.
+    pub fn bans(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        let mut bans: Vec<Ban> = self
+            .lock()
+            .networks
+            .iter()
+            .filter_map(|(network, entry)| {
+                let until = entry.banned_until.filter(|&until| until > now)?;
+                Some(Ban {
+                    network: *network,
+                    remaining: until - now,
+                    strikes: entry.strikes,
+                })
+            })
+            .collect();
+        bans.sort_by_key(|ban| ban.network);
+        bans
+    }
+
+    /// Lifts the ban on the network of `ip` and forgets its failures.
+    /// Returns whether it was banned.
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        self.lock()
+            .networks
+            .remove(&Self::network(ip))
+            .is_some_and(|entry| entry.is_banned(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn failures_within_the_window_ban_the_network() {
+        let metrics = Metrics::new();
+        let bans = BanList::new(metrics.clone()).with_max_failures(3);
+
+        assert!(!bans.record_failure(ip("203.0.113.7")));
+        assert!(!bans.record_failure(ip("203.0.113.7")));
+        assert!(!bans.record_failure(ip("203.0.113.8")));
+        assert!(bans.admit(ip("203.0.113.7")));
+        assert!(bans.record_failure(ip("203.0.113.7")));
+
+        assert!(!bans.admit(ip("203.0.113.7")));
+        assert!(!bans.admit(ip("::ffff:203.0.113.7")));
+        assert!(bans.admit(ip("203.0.113.8")));
+        assert_eq!(metrics.snapshot().auth_bans, 1);
+        assert_eq!(metrics.snapshot().connections_banned, 2);
+
+        let listed = bans.bans();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].network, "203.0.113.7/32".parse().unwrap());
+        assert_eq!(listed[0].strikes, 1);
+        assert!(listed[0].remaining <= Duration::from_secs(60));
+
+        assert!(bans.unban(ip("203.0.113.7")));
+        assert!(bans.admit(ip("203.0.113.7")));
+        assert!(!bans.unban(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn ipv6_failures_count_against_the_64() {
+        let bans = BanList::new(Metrics::new()).with_max_failures(2);
+
+        bans.record_failure(ip("2001:db8:0:1::1"));
+        assert!(bans.record_failure(ip("2001:db8:0:1:ffff::2")));
+
+        assert!(bans.is_banned(ip("2001:db8:0:1::3")));
+        assert!(!bans.is_banned(ip("2001:db8:0:2::1")));
+        assert_eq!(bans.bans()[0].network, "2001:db8:0:1::/64".parse().unwrap());
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let bans = BanList::new(Metrics::new())
+            .with_max_failures(2)
+            .with_window(Duration::from_millis(20));
+
+        bans.record_failure(ip("203.0.113.7"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!bans.record_failure(ip("203.0.113.7")));
+        assert!(bans.record_failure(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn repeated_bans_escalate_up_to_the_maximum() {
+        let bans = BanList::new(Metrics::new())
+            .with_max_failures(1)
+            .with_ban_duration(Duration::from_millis(20), Duration::from_millis(70));
+
+        let mut durations = Vec::new();
+        for _ in 0..4 {
+            assert!(bans.record_failure(ip("203.0.113.7")));
+            let ban = bans.bans().remove(0);
+            durations.push(ban.remaining);
+            std::thread::sleep(ban.remaining + Duration::from_millis(5));
+            assert!(!bans.is_banned(ip("203.0.113.7")));
+        }
+
+        assert!(durations[0] <= Duration::from_millis(20));
+        assert!(durations[1] > Duration::from_millis(20));
+        assert!(durations[2] > Duration::from_millis(40));
+        assert!(durations[3] <= Duration::from_millis(70));
+        assert_eq!(bans.bans(), Vec::new());
+    }
+
+    #[test]
+    fn allowlisted_and_disabled_never_ban() {
+        let bans = BanList::new(Metrics::new())
+            .with_max_failures(1)
+            .with_allowlist(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert!(!bans.record_failure(ip("10.1.2.3")));
+        assert!(bans.admit(ip("10.1.2.3")));
+
+        let disabled = BanList::new(Metrics::new()).with_max_failures(0);
+        assert!(!disabled.record_failure(ip("203.0.113.7")));
+        assert!(disabled.admit(ip("203.0.113.7")));
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::net::IpAddr;
+
+use x509_parser::extensions::GeneralName;
+
+/// Identity carried by the TLS certificate a client authenticated with.
+///
+/// The certificate was verified against the CA by the TLS handshake; this
+/// only describes who it was issued to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificate {
+    /// Common name of the subject.
+    pub common_name: Option<String>,
+    /// DNS names, email addresses, URIs and IP addresses of the subject
+    /// alternative name extension.
+    pub subject_alt_names: Vec<String>,
+    /// SHA-256 of the certificate's SubjectPublicKeyInfo, which stays the
+    /// same when a certificate is renewed with the same key.
+    pub spki_fingerprint: [u8; 32],
+}
+
+impl PeerCertificate {
+    /// Parses a DER encoded certificate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate is malformed.
+    pub fn parse(der: &[u8]) -> io::Result<Self> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid certificate: {e}"),
+            )
+        };
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(der).map_err(|e| invalid(e.to_string()))?;
+
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+
+        let mut subject_alt_names = Vec::new();
+        if let Some(extension) = cert
+            .subject_alternative_name()
+            .map_err(|e| invalid(e.to_string()))?
+        {
+            for name in &extension.value.general_names {
+                let name = match name {
+                    GeneralName::DNSName(name)
+                    | GeneralName::RFC822Name(name)
+                    | GeneralName::URI(name) => name.to_string(),
+                    GeneralName::IPAddress(bytes) => match bytes.len() {
+                        4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).unwrap()).to_string(),
+                        16 => IpAddr::from(<[u8; 16]>::try_from(*bytes).unwrap()).to_string(),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                subject_alt_names.push(name);
+            }
+        }
+
+        let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, cert.public_key().raw);
+        let spki_fingerprint = digest
+            .as_ref()
+            .try_into()
+            .expect("SHA-256 digests are 32 bytes");
+
+        Ok(Self {
+            common_name,
+            subject_alt_names,
+            spki_fingerprint,
+        })
+    }
+
+    /// Name of the user holding the certificate: its common name, or else
+    /// its first subject alternative name, or else its SPKI fingerprint.
+    pub fn identity(&self) -> String {
+        self.common_name
+            .clone()
+            .or_else(|| self.subject_alt_names.first().cloned())
+            .unwrap_or_else(|| {
+                self.spki_fingerprint
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate(params: rcgen::CertificateParams) -> (Vec<u8>, rcgen::KeyPair) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        (cert.der().to_vec(), key)
+    }
+
+    #[test]
+    fn subject_and_alt_names_are_parsed() {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["alice.example.com".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "alice");
+        params
+            .subject_alt_names
+            .push(rcgen::SanType::IpAddress("192.0.2.1".parse().unwrap()));
+        let (der, key) = certificate(params);
+
+        let cert = PeerCertificate::parse(&der).unwrap();
+        assert_eq!(cert.common_name.as_deref(), Some("alice"));
+        assert_eq!(
+            cert.subject_alt_names,
+            vec!["alice.example.com".to_string(), "192.0.2.1".to_string()]
+        );
+        assert_eq!(cert.identity(), "alice");
+
+        let spki = aws_lc_rs::digest::digest(
+            &aws_lc_rs::digest::SHA256,
+            &rcgen::PublicKeyData::subject_public_key_info(&key),
+        );
+        assert_eq!(cert.spki_fingerprint.as_slice(), spki.as_ref());
+    }
+
+    #[test]
+    fn identity_falls_back_to_alt_name() {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["bob.example.com".to_string()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let (der, _) = certificate(params);
+
+        let cert = PeerCertificate::parse(&der).unwrap();
+        assert_eq!(cert.identity(), "bob.example.com");
+        assert!(PeerCertificate::parse(b"not a certificate").is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use ombrac::key::PublicKey;
+use ombrac::protocol::{self, Secret};
+use ombrac::token::{self, Claims, TokenError};
+use ombrac_macros::info;
+
+use super::hook::{AuthHook, HookDecision};
+use super::{
+    Authenticator, ClientLimits, ConnectionAuthError, ConnectionHandle, Peer, PeerCertificate,
+};
+
+/// Authenticates clients with the shared secret, with one of the keys of
+/// an authorized keys file or with an access token.
+///
+/// With an [`AuthHook`], clients sending a secret are checked by the hook
+/// instead of against the shared secret, and challenge-response
+/// authentication is refused.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    secret: Option<Secret>,
+    authorized_keys: HashMap<PublicKey, String>,
+    token_key: Option<PublicKey>,
+    revoked_tokens: HashSet<String>,
+    hook: Option<Arc<AuthHook>>,
+}
+
+/// Who a client authenticated as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    /// The client proved knowledge of the shared secret.
+    Secret,
+    /// The client signed with the authorized key of this label.
+    Key(String),
+    /// The client presented a token with these claims.
+    Token(Claims),
+    /// The auth hook allowed the client.
+    Hook(HookDecision),
+    /// The client presented this certificate.
+    Certificate(PeerCertificate),
+}
+
+impl Credentials {
+    /// Creates credentials accepting only the shared secret.
+    pub fn new(secret: Secret) -> Self {
+        Self {
+            secret: Some(secret),
+            ..Self::without_secret()
+        }
+    }
+
+    /// Creates credentials without a shared secret, refusing every client
+    /// sending a secret or answering a challenge with one. Servers
+    /// authenticating clients by key, token or certificate alone use them.
+    pub fn without_secret() -> Self {
+        Self {
+            secret: None,
+            authorized_keys: HashMap::new(),
+            token_key: None,
+            revoked_tokens: HashSet::new(),
+            hook: None,
+        }
+    }
+
+    /// Also accepts clients holding one of `keys`, mapped to their labels.
+    pub fn with_authorized_keys(mut self, keys: HashMap<PublicKey, String>) -> Self {
+        self.authorized_keys = keys;
+        self
+    }
+
+    /// Also accepts clients presenting a token signed by `key`.
+    pub fn with_token_key(mut self, key: PublicKey) -> Self {
+        self.token_key = Some(key);
+        self
+    }
+
+    /// Refuses tokens with one of these IDs.
+    pub fn with_revoked_tokens(mut self, ids: HashSet<String>) -> Self {
+        self.revoked_tokens = ids;
+        self
+    }
+
+    /// Lets `hook` decide on clients sending a secret.
+    pub fn with_hook(mut self, hook: AuthHook) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+}
+
+impl<T: Send + Sync> Authenticator<T> for Credentials {
+    type AuthContext = Identity;
+
+    async fn verify(&self, hello: &protocol::ClientHello) -> Result<Identity, ConnectionAuthError> {
+        if self.secret == Some(hello.secret) {
+            Ok(Identity::Secret)
+        } else {
+            Err(ConnectionAuthError::InvalidSecret)
+        }
+    }
+
+    async fn verify_peer(
+        &self,
+        hello: &protocol::ClientHello,
+        peer: &Peer,
+    ) -> Result<Identity, ConnectionAuthError> {
+        match &self.hook {
+            Some(hook) => hook.check(hello, peer).await.map(Identity::Hook),
+            None => Authenticator::<T>::verify(self, hello).await,
+        }
+    }
+
+    async fn verify_certificate(
+        &self,
+        _hello: &protocol::ClientHello,
+        peer: &Peer,
+    ) -> Result<Identity, ConnectionAuthError> {
+        peer.certificate
+            .clone()
+            .map(Identity::Certificate)
+            .ok_or(ConnectionAuthError::CertificateRequired)
+    }
+
+    async fn verify_challenge(
+        &self,
+        _hello: &protocol::ClientHello,
+        response: &protocol::ChallengeResponse,
+    ) -> Result<Identity, ConnectionAuthError> {
+        // The hook decides on secrets it is sent, but a challenge response
+        // only proves knowledge of the shared secret
+        if self.hook.is_some() {
+            return Err(ConnectionAuthError::Other(
+                "challenge-response authentication is not available with an auth hook".to_string(),
+            ));
+        }
+        if self.secret.is_some_and(|secret| response.verify(&secret)) {
+            Ok(Identity::Secret)
+        } else {
+            Err(ConnectionAuthError::InvalidSecret)
+        }
+    }
+
+    async fn verify_signature(
+        &self,
+        _hello: &protocol::ClientHello,
+        signed: &protocol::SignedChallenge,
+    ) -> Result<Identity, ConnectionAuthError> {
+        match self.authorized_keys.get(&signed.public_key) {
+            Some(label) if signed.verify() => Ok(Identity::Key(label.clone())),
+            _ => Err(ConnectionAuthError::UnauthorizedKey),
+        }
+    }
+
+    async fn verify_token(
+        &self,
+        _hello: &protocol::ClientHello,
+        token: &str,
+    ) -> Result<Identity, ConnectionAuthError> {
+        let key = self
+            .token_key
+            .as_ref()
+            .ok_or(ConnectionAuthError::InvalidToken)?;
+        let claims = Claims::verify(token, key, token::now()).map_err(|e| match e {
+            TokenError::Expired => ConnectionAuthError::TokenExpired,
+            TokenError::Malformed | TokenError::InvalidSignature => {
+                ConnectionAuthError::InvalidToken
+            }
+        })?;
+        if self.revoked_tokens.contains(&claims.id) {
+            return Err(ConnectionAuthError::TokenRevoked);
+        }
+        Ok(Identity::Token(claims))
+    }
+
+    /// Names the client by the identity it authenticated as, tagged with
+    /// how it did so, so no credential can take the name of another. The
+    /// options of the hello are chosen by the client and never used, so
+    /// clients holding the shared secret, and those the hook accepts
+    /// without naming them, can't be told apart and share one name.
+    fn user(&self, _hello: &protocol::ClientHello, identity: &Identity) -> Bytes {
+        let tagged = |kind: &str, name: &[u8]| Bytes::from([kind.as_bytes(), b"\0", name].concat());
+        match identity {
+            Identity::Secret => tagged("secret", &[]),
+            Identity::Key(label) => tagged("key", label.as_bytes()),
+            Identity::Token(claims) => tagged("token", claims.identity.as_bytes()),
+            Identity::Hook(decision) => tagged(
+                "hook",
+                decision.identity.as_deref().unwrap_or_default().as_bytes(),
+            ),
+            Identity::Certificate(certificate) => {
+                tagged("certificate", certificate.identity().as_bytes())
+            }
+        }
+    }
+
+    fn limits(&self, identity: &Identity) -> ClientLimits {
+        match identity {
+            Identity::Token(claims) => ClientLimits {
+                bandwidth: claims.bandwidth,
+                destinations: claims.allow.clone(),
+            },
+            Identity::Hook(decision) => ClientLimits {
+                bandwidth: decision.bandwidth,
+                destinations: decision.destinations.clone(),
+            },
+            _ => ClientLimits::default(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn accept(&self, identity: Identity, _connection: ConnectionHandle<T>) {
+        match identity {
+            Identity::Secret => {}
+            Identity::Key(label) => info!(key = %label, "client authenticated with its key"),
+            Identity::Token(claims) => info!(
+                identity = %claims.identity,
+                token = %claims.id,
+                expires_at = claims.expires_at,
+                "client authenticated with a token"
+            ),
+            Identity::Hook(decision) => info!(
+                identity = ?decision.identity,
+                "client authenticated by the auth hook"
+            ),
+            Identity::Certificate(certificate) => info!(
+                identity = %certificate.identity(),
+                "client authenticated with its certificate"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ombrac::key::{PrivateKey, parse_authorized_keys};
+
+    use super::*;
+
+    fn hello() -> protocol::ClientHello {
+        protocol::ClientHello {
+            version: protocol::PROTOCOL_VERSION,
+            secret: [0u8; 32],
+            options: Default::default(),
+        }
+    }
+
+    fn sign(key: &PrivateKey, nonce: [u8; 32]) -> protocol::SignedChallenge {
+        let message = protocol::key_proof_message(&nonce, &[2u8; 32]);
+        protocol::SignedChallenge {
+            nonce,
+            keying_material: [2u8; 32],
+            public_key: key.public_key(),
+            signature: key.sign(&message).into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn authorized_key_is_accepted_with_its_label() {
+        let laptop = PrivateKey::generate().unwrap();
+        let keys = parse_authorized_keys(&format!("{} laptop\n", laptop.public_key())).unwrap();
+        let credentials = Credentials::new([1u8; 32]).with_authorized_keys(keys);
+
+        let identity = Authenticator::<()>::verify_signature(
+            &credentials,
+            &hello(),
+            &sign(&laptop, [3u8; 32]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(identity, Identity::Key("laptop".to_string()));
+
+        let mut forged = sign(&laptop, [3u8; 32]);
+        forged.nonce = [4u8; 32];
+        let err = Authenticator::<()>::verify_signature(&credentials, &hello(), &forged)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::UnauthorizedKey);
+    }
+
+    #[test]
+    fn users_are_named_by_their_credential() {
+        let credentials = Credentials::new([1u8; 32]);
+        let user = |hello: &protocol::ClientHello, identity: Identity| {
+            Authenticator::<()>::user(&credentials, hello, &identity)
+        };
+        let mut impostor = hello();
+        impostor.options = Bytes::from_static(b"key\0laptop");
+
+        let laptop = user(&hello(), Identity::Key("laptop".to_string()));
+        assert_eq!(laptop, user(&impostor, Identity::Key("laptop".to_string())));
+        assert_ne!(laptop, user(&impostor, Identity::Secret));
+        assert_ne!(
+            laptop,
+            user(
+                &hello(),
+                Identity::Hook(HookDecision {
+                    identity: Some("laptop".to_string()),
+                    ..Default::default()
+                })
+            )
+        );
+        // The options are the client's own choice, so they name nobody
+        assert_eq!(
+            user(&hello(), Identity::Secret),
+            user(&impostor, Identity::Secret)
+        );
+        assert_eq!(
+            user(&hello(), Identity::Hook(HookDecision::default())),
+            user(&impostor, Identity::Hook(HookDecision::default()))
+        );
+    }
+
+    #[tokio::test]
+    async fn without_secret_refuses_secret_clients() {
+        let credentials = Credentials::without_secret();
+
+        let err = Authenticator::<()>::verify(&credentials, &hello())
+            .await
+            .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::InvalidSecret);
+
+        let response = protocol::ChallengeResponse {
+            nonce: [3u8; 32],
+            keying_material: [2u8; 32],
+            mac: protocol::challenge_mac(&[0u8; 32], &[3u8; 32], &[2u8; 32]),
+        };
+        let err = Authenticator::<()>::verify_challenge(&credentials, &hello(), &response)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::InvalidSecret);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_checked_against_the_key_and_revocations() {
+        let token_key = PrivateKey::generate().unwrap();
+        let claims = Claims {
+            id: "t1".to_string(),
+            identity: "contractor".to_string(),
+            expires_at: token::now() + 3600,
+            bandwidth: None,
+            allow: None,
+        };
+        let token = claims.sign(&token_key).unwrap();
+
+        let credentials = Credentials::new([1u8; 32]);
+        let err = Authenticator::<()>::verify_token(&credentials, &hello(), &token)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::InvalidToken);
+
+        let credentials = credentials.with_token_key(token_key.public_key());
+        let identity = Authenticator::<()>::verify_token(&credentials, &hello(), &token)
+            .await
+            .unwrap();
+        assert_eq!(identity, Identity::Token(claims.clone()));
+
+        let expired = Claims {
+            expires_at: token::now() - 1,
+            ..claims
+        }
+        .sign(&token_key)
+        .unwrap();
+        let err = Authenticator::<()>::verify_token(&credentials, &hello(), &expired)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::TokenExpired);
+
+        let credentials = credentials.with_revoked_tokens(HashSet::from(["t1".to_string()]));
+        let err = Authenticator::<()>::verify_token(&credentials, &hello(), &token)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::TokenRevoked);
+    }
+
+    #[tokio::test]
+    async fn unknown_key_is_refused() {
+        let credentials = Credentials::new([1u8; 32]);
+        let stranger = PrivateKey::generate().unwrap();
+
+        let err = Authenticator::<()>::verify_signature(
+            &credentials,
+            &hello(),
+            &sign(&stranger, [3u8; 32]),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, ConnectionAuthError::UnauthorizedKey);
+    }
+}
@@ -6,8 +6,11 @@ use std::time::{Duration, Instant};
 
 use bytes::Bytes;
 use moka::future::Cache;
-use tokio::net::UdpSocket;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpStream, UdpSocket};
 use tokio::sync::Semaphore;
+#[cfg(feature = "chain")]
+use tokio::sync::mpsc;
 use tokio::task::AbortHandle;
 use tokio_util::sync::CancellationToken;
 #[cfg(feature = "tracing")]
@@ -19,16 +22,23 @@ use ombrac::reassembly::UdpReassembler;
 use ombrac_macros::{info, warn};
 use ombrac_transport::Connection;
 
-use crate::connection::dns;
+use crate::config::{ConnectionConfig, IpFamily};
+use crate::connection::limits::ClientPolicy;
+#[cfg(feature = "chain")]
+use crate::connection::outbound::ChainUdpSession;
+use crate::connection::outbound::Outbound;
+use crate::connection::proxy::{decode_socks5_udp, encode_socks5_udp};
+use crate::connection::{Dialer, DnsResolver};
 
 // --- Resource Limits ---
 const MAX_SESSIONS: u64 = 8192;
 const MAX_CONCURRENT_HANDLERS: usize = 4096;
 const MAX_UDP_RECV_BUFFER_SIZE: usize = 65535;
+#[cfg(feature = "chain")]
+const CHAIN_SEND_QUEUE_SIZE: usize = 1024;
 
 // --- Timeouts & TTL ---
 const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(65);
-const DNS_CACHE_TTL: Duration = Duration::from_secs(300);
 const DATAGRAM_SEND_TIMEOUT: Duration = Duration::from_secs(5);
 
 // --- Retry Strategy ---
@@ -39,14 +49,17 @@ pub(crate) struct DatagramTunnel<C: Connection> {
     connection: Arc<C>,
     shutdown: CancellationToken,
     sessions: Cache<u64, Arc<DatagramSession>>,
-    dns_cache: Cache<Bytes, SocketAddr>,
+    dns: Arc<DnsResolver>,
+    outbound: Outbound,
+    policy: Arc<ClientPolicy>,
     reassembler: Arc<UdpReassembler>,
     semaphore: Arc<Semaphore>,
+    ip_family: IpFamily,
     metrics: Metrics,
 }
 
 pub(crate) struct DatagramSession {
-    socket: Arc<UdpSocket>,
+    upstream: SessionUpstream,
     upstream_bytes: Arc<AtomicU64>,
     // These three fields are only read from the eviction listener under the
     // `tracing` feature for end-of-session logging. Suppress the warning when
@@ -61,15 +74,40 @@ pub(crate) struct DatagramSession {
     created_at: Instant,
 }
 
+/// Where the datagrams of a session are sent to.
+enum SessionUpstream {
+    /// A local socket sending straight to the destinations.
+    Direct(Arc<UdpSocket>),
+    /// A queue drained by the task relaying the session to the next hop.
+    #[cfg(feature = "chain")]
+    Chain(mpsc::Sender<(Bytes, Address)>),
+    /// A local socket sending to the relay of a SOCKS5 proxy.
+    Socks5 {
+        socket: Arc<UdpSocket>,
+        relay: SocketAddr,
+    },
+}
+
 impl<C: Connection> DatagramTunnel<C> {
-    pub(crate) fn new(connection: Arc<C>, shutdown: CancellationToken, metrics: Metrics) -> Self {
+    pub(crate) fn new(
+        connection: Arc<C>,
+        shutdown: CancellationToken,
+        config: Arc<ConnectionConfig>,
+        dns: Arc<DnsResolver>,
+        outbound: Outbound,
+        policy: Arc<ClientPolicy>,
+        metrics: Metrics,
+    ) -> Self {
         Self {
             connection,
             shutdown,
             sessions: Self::create_session_cache(metrics.clone()),
-            dns_cache: Self::create_dns_cache(),
+            dns,
+            outbound,
+            policy,
             reassembler: Arc::new(UdpReassembler::default()),
             semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_HANDLERS)),
+            ip_family: config.ip_family(),
             metrics,
         }
     }
@@ -105,10 +143,6 @@ impl<C: Connection> DatagramTunnel<C> {
             .build()
     }
 
-    fn create_dns_cache() -> Cache<Bytes, SocketAddr> {
-        Cache::builder().time_to_idle(DNS_CACHE_TTL).build()
-    }
-
     /// Main loop to accept incoming datagrams from the client connection.
     pub(crate) async fn accept_loop(self) -> io::Result<()> {
         loop {
@@ -153,6 +187,14 @@ impl<C: Connection> DatagramTunnel<C> {
                 .fetch_add(1, Ordering::Relaxed);
         }
         if let Some((session_id, address, data)) = reassembled {
+            if !self.policy.allows(&address) {
+                warn!("Dropping udp packet to {address}, not allowed for this client");
+                return Ok(());
+            }
+            if !self.policy.admit_upload(data.len()) {
+                return Ok(()); // Over the client's bandwidth
+            }
+
             let session = match self.get_or_create_session(session_id, &address).await {
                 Ok(s) => s,
                 Err(e) => {
@@ -170,8 +212,9 @@ impl<C: Connection> DatagramTunnel<C> {
                 }
             };
 
-            // This is a cheap, reference-counted clone, not a deep copy of the cache data.
-            let dns_cache = self.dns_cache.clone();
+            // Cheap reference-counted clone; the resolver cache itself is shared.
+            let dns = Arc::clone(&self.dns);
+            let ip_family = self.ip_family;
 
             let future = async move {
                 // Permit is automatically released when dropped
@@ -181,9 +224,30 @@ impl<C: Connection> DatagramTunnel<C> {
                     .upstream_bytes
                     .fetch_add(data.len() as u64, Ordering::Relaxed);
 
-                match lookup_host(&dns_cache, &address).await {
+                let socket = match &session.upstream {
+                    SessionUpstream::Direct(socket) => socket,
+                    #[cfg(feature = "chain")]
+                    SessionUpstream::Chain(sender) => {
+                        // The next hop resolves domains itself. Like any UDP
+                        // path, drop the packet rather than wait when it lags.
+                        if sender.try_send((data, address)).is_err() {
+                            warn!("Failed to relay udp packet, next hop queue is full or closed");
+                        }
+                        return;
+                    }
+                    SessionUpstream::Socks5 { socket, relay } => {
+                        // The proxy resolves domains itself
+                        let packet = encode_socks5_udp(&address, &data);
+                        if let Err(err) = socket.send_to(&packet, relay).await {
+                            warn!("Failed to relay udp packet to {address} through proxy: {err}");
+                        }
+                        return;
+                    }
+                };
+
+                match lookup_host(&dns, &address, ip_family).await {
                     Ok(dest_addr) => {
-                        if let Err(err) = session.socket.send_to(&data, dest_addr).await {
+                        if let Err(err) = socket.send_to(&data, dest_addr).await {
                             warn!("Failed to send udp packet to {address}: {err}");
                         }
                     }
@@ -203,7 +267,9 @@ impl<C: Connection> DatagramTunnel<C> {
     }
 
     /// Retrieves an existing session or creates a new one.
-    /// A new session involves creating a UDP socket and spawning a downstream loop.
+    /// A new session involves creating a UDP socket, a UDP session with the
+    /// next hop of a server chain or a SOCKS5 association, and spawning a
+    /// downstream loop.
     async fn get_or_create_session(
         &self,
         session_id: u64,
@@ -211,21 +277,56 @@ impl<C: Connection> DatagramTunnel<C> {
     ) -> io::Result<Arc<DatagramSession>> {
         self.sessions
             .try_get_with(session_id, async {
-                let bind_addr = match lookup_host(&self.dns_cache, dest_addr).await? {
-                    SocketAddr::V4(_) => "0.0.0.0:0",
-                    SocketAddr::V6(_) => "[::]:0",
-                };
-
-                // Retry UDP socket binding with exponential backoff
-                let new_socket = Arc::new(Self::bind_udp_socket_with_retry(bind_addr).await?);
                 let upstream_bytes = Arc::new(AtomicU64::new(0));
                 let downstream_bytes = Arc::new(AtomicU64::new(0));
 
-                let abort_handle = self.spawn_downstream_loop(
-                    session_id,
-                    new_socket.clone(),
-                    downstream_bytes.clone(),
-                );
+                let (upstream, abort_handle) = match &self.outbound {
+                    Outbound::Direct(dialer) => {
+                        let ipv6 = lookup_host(&self.dns, dest_addr, self.ip_family)
+                            .await?
+                            .is_ipv6();
+
+                        // Retry UDP socket binding with exponential backoff
+                        let new_socket =
+                            Arc::new(Self::bind_udp_socket_with_retry(dialer, ipv6).await?);
+                        let abort_handle = self.spawn_downstream_loop(
+                            session_id,
+                            new_socket.clone(),
+                            downstream_bytes.clone(),
+                        );
+                        (SessionUpstream::Direct(new_socket), abort_handle)
+                    }
+                    #[cfg(feature = "chain")]
+                    Outbound::Chain(client) => {
+                        let (sender, receiver) = mpsc::channel(CHAIN_SEND_QUEUE_SIZE);
+                        let abort_handle = self.spawn_chain_loop(
+                            session_id,
+                            client.open_associate(),
+                            receiver,
+                            downstream_bytes.clone(),
+                        );
+                        (SessionUpstream::Chain(sender), abort_handle)
+                    }
+                    Outbound::Socks5(proxy) => {
+                        let association = proxy.socks5_udp_associate().await?;
+                        let socket = Arc::new(association.socket);
+                        let relay = association.relay;
+                        let abort_handle = self.spawn_socks5_loop(
+                            session_id,
+                            association.control,
+                            socket.clone(),
+                            relay,
+                            downstream_bytes.clone(),
+                        );
+                        (SessionUpstream::Socks5 { socket, relay }, abort_handle)
+                    }
+                    Outbound::HttpConnect(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            "http-connect outbound cannot relay udp",
+                        ));
+                    }
+                };
 
                 self.metrics
                     .counters()
@@ -233,7 +334,7 @@ impl<C: Connection> DatagramTunnel<C> {
                     .fetch_add(1, Ordering::Relaxed);
 
                 let session = DatagramSession {
-                    socket: new_socket,
+                    upstream,
                     abort_handle,
                     upstream_bytes,
                     downstream_bytes,
@@ -248,10 +349,10 @@ impl<C: Connection> DatagramTunnel<C> {
     }
 
     /// Binds a UDP socket with retry logic to handle transient resource exhaustion.
-    async fn bind_udp_socket_with_retry(bind_addr: &str) -> io::Result<UdpSocket> {
+    async fn bind_udp_socket_with_retry(dialer: &Dialer, ipv6: bool) -> io::Result<UdpSocket> {
         let mut last_error = None;
         for attempt in 0..SOCKET_BIND_RETRY_MAX {
-            match UdpSocket::bind(bind_addr).await {
+            match dialer.bind_udp(ipv6) {
                 Ok(socket) => return Ok(socket),
                 Err(e) => {
                     last_error = Some(e);
@@ -279,14 +380,64 @@ impl<C: Connection> DatagramTunnel<C> {
             connection: Arc::clone(&self.connection),
             shutdown: self.shutdown.child_token(),
             session_id,
-            socket,
             downstream_bytes,
+            policy: Arc::clone(&self.policy),
+        };
+
+        #[cfg(not(feature = "tracing"))]
+        let abort = tokio::spawn(handler.accept_loop(socket)).abort_handle();
+        #[cfg(feature = "tracing")]
+        let abort = tokio::spawn(handler.accept_loop(socket)).abort_handle();
+
+        abort
+    }
+
+    /// Spawns a new asynchronous task relaying a session through the next hop.
+    #[cfg(feature = "chain")]
+    fn spawn_chain_loop(
+        &self,
+        session_id: u64,
+        session: ChainUdpSession,
+        upstream: mpsc::Receiver<(Bytes, Address)>,
+        downstream_bytes: Arc<AtomicU64>,
+    ) -> AbortHandle {
+        let handler = DownstreamHandler {
+            connection: Arc::clone(&self.connection),
+            shutdown: self.shutdown.child_token(),
+            session_id,
+            downstream_bytes,
+            policy: Arc::clone(&self.policy),
+        };
+
+        #[cfg(not(feature = "tracing"))]
+        let abort = tokio::spawn(handler.chain_loop(session, upstream)).abort_handle();
+        #[cfg(feature = "tracing")]
+        let abort = tokio::spawn(handler.chain_loop(session, upstream)).abort_handle();
+
+        abort
+    }
+
+    /// Spawns a new asynchronous task receiving the replies of a SOCKS5 relay.
+    fn spawn_socks5_loop(
+        &self,
+        session_id: u64,
+        control: TcpStream,
+        socket: Arc<UdpSocket>,
+        relay: SocketAddr,
+        downstream_bytes: Arc<AtomicU64>,
+    ) -> AbortHandle {
+        let handler = DownstreamHandler {
+            connection: Arc::clone(&self.connection),
+            shutdown: self.shutdown.child_token(),
+            session_id,
+            downstream_bytes,
+            policy: Arc::clone(&self.policy),
         };
 
         #[cfg(not(feature = "tracing"))]
-        let abort = tokio::spawn(handler.accept_loop()).abort_handle();
+        let abort = tokio::spawn(handler.socks5_loop(control, socket, relay)).abort_handle();
         #[cfg(feature = "tracing")]
-        let abort = tokio::spawn(handler.accept_loop()).abort_handle();
+        let abort = tokio::spawn(handler.socks5_loop(control, socket, relay)).abort_handle();
 
         abort
     }
@@ -295,20 +446,20 @@ impl<C: Connection> DatagramTunnel<C> {
 struct DownstreamHandler<C: Connection> {
     connection: Arc<C>,
     session_id: u64,
-    socket: Arc<UdpSocket>,
     shutdown: CancellationToken,
     downstream_bytes: Arc<AtomicU64>,
+    policy: Arc<ClientPolicy>,
 }
 
 impl<C: Connection> DownstreamHandler<C> {
     /// Runs the downstream loop, receiving packets from the destination and sending them to the client connection.
-    async fn accept_loop(self) {
+    async fn accept_loop(self, socket: Arc<UdpSocket>) {
         let mut buf = vec![0u8; MAX_UDP_RECV_BUFFER_SIZE];
 
         loop {
             tokio::select! {
                 _ = self.shutdown.cancelled() => break,
-                result = self.socket.recv_from(&mut buf) => {
+                result = socket.recv_from(&mut buf) => {
                     match result {
                         Ok((len, from_addr)) => {
                             let address = Address::from(from_addr);
@@ -328,12 +479,86 @@ impl<C: Connection> DownstreamHandler<C> {
         }
     }
 
+    /// Relays a session through the next hop, forwarding queued packets from
+    /// the client and sending the replies back to the client connection.
+    #[cfg(feature = "chain")]
+    async fn chain_loop(
+        self,
+        mut session: ChainUdpSession,
+        mut upstream: mpsc::Receiver<(Bytes, Address)>,
+    ) {
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                packet = upstream.recv() => {
+                    let Some((data, address)) = packet else { break };
+                    if let Err(_err) = session.send_to(data, address).await {
+                        warn!("failed to relay packet to next hop, {_err}");
+                    }
+                }
+                packet = session.recv_from() => {
+                    let Some((data, address)) = packet else { break };
+                    self.downstream_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+
+                    if let Err(_err) = self.process_and_send_datagram(address, data).await {
+                        warn!("failed to send packet to client, {_err}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Receives the replies of a SOCKS5 relay and sends them to the client
+    /// connection, until the proxy closes the control connection.
+    async fn socks5_loop(self, mut control: TcpStream, socket: Arc<UdpSocket>, relay: SocketAddr) {
+        let mut buf = vec![0u8; MAX_UDP_RECV_BUFFER_SIZE];
+        let mut control_buf = [0u8; 1];
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                // The proxy ends the association by closing the control connection
+                _ = control.read(&mut control_buf) => break,
+                result = socket.recv_from(&mut buf) => {
+                    let (len, from_addr) = match result {
+                        Ok(received) => received,
+                        Err(_err) => {
+                            warn!("failed to receive from proxy relay {_err}");
+                            continue;
+                        }
+                    };
+                    // Anyone may send to the socket; only the relay is trusted
+                    if from_addr != relay {
+                        continue;
+                    }
+                    let (address, data) = match decode_socks5_udp(&buf[..len]) {
+                        Ok((address, data)) => (address, Bytes::copy_from_slice(data)),
+                        Err(_err) => {
+                            warn!("dropping packet from proxy relay, {_err}");
+                            continue;
+                        }
+                    };
+                    self.downstream_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+
+                    if let Err(_err) = self.process_and_send_datagram(address, data).await {
+                        warn!("failed to send packet to client, {_err}");
+                    }
+                }
+            }
+        }
+    }
+
     /// Processes a packet and sends it to the client connection without fragmentation.
     ///
     /// The packet is sent as-is, allowing the application layer (e.g., QUIC)
     /// to handle MTU discovery and packet sizing. This ensures proper PMTUD
     /// behavior and optimal performance.
     async fn process_and_send_datagram(&self, address: Address, data: Bytes) -> io::Result<()> {
+        // Like any UDP path, drop rather than delay packets over the
+        // client's bandwidth
+        if !self.policy.admit_download(data.len()) {
+            return Ok(());
+        }
         let packet = UdpPacket::Unfragmented {
             session_id: self.session_id,
             address,
@@ -358,29 +583,14 @@ impl<C: Connection> DownstreamHandler<C> {
 }
 
 async fn lookup_host(
-    dns_cache: &Cache<Bytes, SocketAddr>,
+    dns: &DnsResolver,
     address: &Address,
+    ip_family: IpFamily,
 ) -> io::Result<SocketAddr> {
     match address {
         Address::SocketV4(addr) => Ok(SocketAddr::V4(*addr)),
         Address::SocketV6(addr) => Ok(SocketAddr::V6(*addr)),
-        Address::Domain(domain, port) => {
-            let port = *port;
-
-            // Try to get from cache first (fast path)
-            if let Some(addr) = dns_cache.get(domain).await {
-                return Ok(addr);
-            }
-
-            // Use shared DNS resolver for DNS resolution
-            let addr = dns::resolve_domain(domain, port).await?;
-
-            // Cache successful resolution
-            // Note: There's a race condition here where multiple tasks might resolve
-            // the same domain concurrently, but that's acceptable - we'll just cache
-            // the first successful result, and subsequent lookups will use the cache.
-            dns_cache.insert(domain.clone(), addr).await;
-            Ok(addr)
-        }
+        // The shared resolver caches answers for their TTL, including negative ones
+        Address::Domain(domain, port) => dns.resolve(domain, *port, ip_family).await,
     }
 }
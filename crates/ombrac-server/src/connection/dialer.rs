@@ -0,0 +1,263 @@
+//! Socket options of connections dialed directly from this server.
+//!
+//! Multi-homed exits pin their egress here: a fixed source address per
+//! family, an interface, a firewall mark for policy routing, or an IPv6
+//! source picked from a prefix for each client.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+
+use crate::config::Ipv6PrefixScope;
+
+/// An IPv6 prefix, e.g. `2001:db8:1::/48`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ipv6Prefix {
+    network: Ipv6Addr,
+    len: u8,
+}
+
+impl Ipv6Prefix {
+    /// Returns the address of the prefix whose host part is taken from `bits`.
+    fn address(&self, bits: u128) -> Ipv6Addr {
+        let host_mask = u128::MAX.checked_shr(u32::from(self.len)).unwrap_or(0);
+        Ipv6Addr::from((u128::from(self.network) & !host_mask) | (bits & host_mask))
+    }
+}
+
+impl FromStr for Ipv6Prefix {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (network, len) = value
+            .split_once('/')
+            .ok_or_else(|| format!("missing prefix length in '{value}'"))?;
+        let network = network
+            .parse::<Ipv6Addr>()
+            .map_err(|e| format!("invalid prefix '{value}': {e}"))?;
+        let len = len
+            .parse::<u8>()
+            .ok()
+            .filter(|len| *len <= 128)
+            .ok_or_else(|| format!("invalid prefix length in '{value}'"))?;
+        Ok(Self { network, len })
+    }
+}
+
+/// Socket options applied when dialing destinations directly.
+#[derive(Clone, Debug, Default)]
+pub struct DialerOptions {
+    /// Source address of IPv4 sockets.
+    pub bind_ipv4: Option<Ipv4Addr>,
+    /// Source address of IPv6 sockets, unless `ipv6_prefix` is set.
+    pub bind_ipv6: Option<Ipv6Addr>,
+    /// Interface sockets are bound to with `SO_BINDTODEVICE` (Linux only).
+    pub interface: Option<String>,
+    /// Firewall mark set with `SO_MARK` (Linux only).
+    pub mark: Option<u32>,
+    /// Prefix the IPv6 source of each client is picked from.
+    pub ipv6_prefix: Option<Ipv6Prefix>,
+    /// Whether a prefix source is picked per connection or per user.
+    pub ipv6_prefix_scope: Ipv6PrefixScope,
+}
+
+/// Dials destinations directly with the configured socket options.
+///
+/// Cloning is cheap; the acceptor holds one dialer and derives the dialer of
+/// each client connection from it with [`Dialer::for_client`].
+#[derive(Clone, Debug, Default)]
+pub struct Dialer {
+    options: Arc<DialerOptions>,
+    ipv6_source: Option<Ipv6Addr>,
+}
+
+impl Dialer {
+    pub fn new(options: DialerOptions) -> Self {
+        Self {
+            ipv6_source: options.bind_ipv6,
+            options: Arc::new(options),
+        }
+    }
+
+    /// Returns the dialer of one client connection.
+    ///
+    /// With an IPv6 prefix, its source is picked at random, or derived from
+    /// `user` (see [`Authenticator::user`](super::Authenticator::user)) so
+    /// that the same user always leaves from the same address.
+    pub(crate) fn for_client(&self, user: &[u8]) -> Self {
+        let Some(prefix) = self.options.ipv6_prefix else {
+            return self.clone();
+        };
+        let bits = match self.options.ipv6_prefix_scope {
+            Ipv6PrefixScope::Connection => rand::random::<u128>(),
+            Ipv6PrefixScope::User => {
+                let hash = blake3::hash(user);
+                let bits = hash
+                    .as_bytes()
+                    .first_chunk()
+                    .expect("blake3 hashes are 32 bytes");
+                u128::from_be_bytes(*bits)
+            }
+        };
+        Self {
+            options: Arc::clone(&self.options),
+            ipv6_source: Some(prefix.address(bits)),
+        }
+    }
+
+    /// Opens a TCP connection to `addr`.
+    pub(crate) async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(source) = self.prepare(SockRef::from(&socket), addr.is_ipv6())? {
+            socket.bind(source)?;
+        }
+        socket.connect(addr).await
+    }
+
+    /// Binds a UDP socket sending to destinations of one family.
+    #[cfg_attr(not(feature = "datagram"), allow(dead_code))]
+    pub(crate) fn bind_udp(&self, ipv6: bool) -> io::Result<UdpSocket> {
+        let (domain, unspecified) = if ipv6 {
+            (Domain::IPV6, SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+        } else {
+            (Domain::IPV4, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+        };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        let source = self.prepare(SockRef::from(&socket), ipv6)?;
+        socket.bind(&source.unwrap_or(unspecified).into())?;
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket.into())
+    }
+
+    /// Applies the socket options and returns the source address to bind.
+    fn prepare(&self, socket: SockRef<'_>, ipv6: bool) -> io::Result<Option<SocketAddr>> {
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        {
+            if let Some(interface) = &self.options.interface {
+                socket.bind_device(Some(interface.as_bytes()))?;
+            }
+            if let Some(mark) = self.options.mark {
+                socket.set_mark(mark)?;
+            }
+        }
+        #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+        if self.options.interface.is_some() || self.options.mark.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "outbound interface and mark are only supported on Linux",
+            ));
+        }
+
+        if !ipv6 {
+            return Ok(self.options.bind_ipv4.map(|ip| SocketAddr::from((ip, 0))));
+        }
+        let Some(source) = self.ipv6_source else {
+            return Ok(None);
+        };
+        // Prefixes are usually routed to the host rather than assigned to
+        // an interface, so their addresses aren't local yet.
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if self.options.ipv6_prefix.is_some() {
+            socket.set_freebind_v6(true)?;
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        let _ = socket;
+        Ok(Some(SocketAddr::from((source, 0))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix_dialer(prefix: &str, scope: Ipv6PrefixScope) -> Dialer {
+        Dialer::new(DialerOptions {
+            ipv6_prefix: Some(prefix.parse().unwrap()),
+            ipv6_prefix_scope: scope,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn prefix_parsing() {
+        let prefix: Ipv6Prefix = "2001:db8:1::/48".parse().unwrap();
+        assert_eq!(prefix.len, 48);
+        assert!("2001:db8::".parse::<Ipv6Prefix>().is_err());
+        assert!("2001:db8::/129".parse::<Ipv6Prefix>().is_err());
+        assert!("10.0.0.0/8".parse::<Ipv6Prefix>().is_err());
+    }
+
+    #[test]
+    fn prefix_address_keeps_network_bits() {
+        let prefix: Ipv6Prefix = "2001:db8:1:ffff::/48".parse().unwrap();
+        assert_eq!(
+            prefix.address(u128::MAX),
+            "2001:db8:1:ffff:ffff:ffff:ffff:ffff"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+        assert_eq!(
+            prefix.address(0),
+            "2001:db8:1::".parse::<Ipv6Addr>().unwrap()
+        );
+
+        let host: Ipv6Prefix = "2001:db8::1/128".parse().unwrap();
+        assert_eq!(host.address(u128::MAX), host.network);
+        let all: Ipv6Prefix = "::/0".parse().unwrap();
+        assert_eq!(all.address(1), Ipv6Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn user_scope_is_stable_per_user() {
+        let dialer = prefix_dialer("2001:db8:1::/64", Ipv6PrefixScope::User);
+        let alice = dialer.for_client(b"alice").ipv6_source.unwrap();
+        assert_eq!(dialer.for_client(b"alice").ipv6_source, Some(alice));
+        assert_ne!(dialer.for_client(b"bob").ipv6_source, Some(alice));
+        assert_eq!(alice.segments()[..4], [0x2001, 0xdb8, 1, 0]);
+    }
+
+    #[test]
+    fn connection_scope_is_random() {
+        let dialer = prefix_dialer("2001:db8:1::/64", Ipv6PrefixScope::Connection);
+        let first = dialer.for_client(b"alice").ipv6_source.unwrap();
+        assert_ne!(dialer.for_client(b"alice").ipv6_source, Some(first));
+    }
+
+    #[test]
+    fn without_prefix_bind_ipv6_is_kept() {
+        let source = "2001:db8::7".parse().unwrap();
+        let dialer = Dialer::new(DialerOptions {
+            bind_ipv6: Some(source),
+            ..Default::default()
+        });
+        assert_eq!(dialer.for_client(b"alice").ipv6_source, Some(source));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connect_and_udp_use_bind_ipv4() {
+        // The whole 127.0.0.0/8 is local on Linux.
+        let source = Ipv4Addr::new(127, 0, 0, 2);
+        let dialer = Dialer::new(DialerOptions {
+            bind_ipv4: Some(source),
+            ..Default::default()
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = dialer
+            .connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), source);
+
+        let socket = dialer.bind_udp(false).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), source);
+    }
+}
@@ -1,113 +1,460 @@
+use std::collections::HashMap;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 
-use hickory_resolver::proto::rr::{RData, RecordType};
 use hickory_resolver::TokioResolver;
+use hickory_resolver::config::{ConnectionConfig, NameServerConfig, ResolverConfig, ResolverOpts};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::net::{DnsError, NetError, NoRecords};
+use hickory_resolver::proto::op::{Message, Metadata, OpCode, ResponseCode};
+use hickory_resolver::proto::rr::rdata::{A, AAAA};
+use hickory_resolver::proto::rr::{DNSClass, RData, Record, RecordType};
 use ombrac_macros::debug;
 use tokio::sync::OnceCell;
 
-// Global DNS resolver instance using hickory-resolver
-static DNS_RESOLVER: OnceCell<TokioResolver> = OnceCell::const_new();
+use crate::config::{DnsConfig, IpFamily};
 
-/// Gets or initializes the global DNS resolver instance.
+/// TTL in seconds for answers synthesized from static `hosts` entries.
+const HOSTS_TTL: u32 = 60;
+
+/// Largest DNS response sent back to a client.
 ///
-/// The resolver is created from system configuration on first use.
+/// Leaves headroom below the control frame limit for the message envelope;
+/// larger responses are truncated so the client can retry over TCP.
+const MAX_RESPONSE_LENGTH: usize = ombrac::codec::MAX_CONTROL_FRAME_LENGTH - 1024;
+
+/// Resolver for upstream destinations.
 ///
-/// # Errors
+/// One instance is shared by the stream and datagram tunnels of every
+/// connection on an acceptor, so its response cache (which honours record
+/// TTLs and caches negative answers) is shared as well. Static `hosts`
+/// entries are answered before any upstream lookup.
 ///
-/// Returns an error if the system DNS configuration cannot be read.
-pub(crate) async fn get_dns_resolver() -> io::Result<&'static TokioResolver> {
-    DNS_RESOLVER
-        .get_or_try_init(|| async {
-            let builder = TokioResolver::builder_tokio()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to create dns resolver from system config: {e}")))?;
-            builder.build()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to build dns resolver: {e}")))
-        })
-        .await
+/// The underlying hickory resolver is built lazily on first use so that
+/// constructing a `DnsResolver` never touches the system configuration.
+pub struct DnsResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    name_servers: Vec<NameServerConfig>,
+    options: ResolverOpts,
+    resolver: OnceCell<TokioResolver>,
 }
 
-/// Resolves a domain name to a socket address using hickory-resolver.
-///
-/// This function performs DNS resolution for domain names. For IP addresses,
-/// it returns them directly without DNS lookup.
-///
-/// # Arguments
-///
-/// * `domain` - The domain name as bytes
-/// * `port` - The port number
-///
-/// # Errors
-///
-/// Returns an error if DNS resolution fails or no IP addresses are found.
-pub(crate) async fn resolve_domain(domain: &[u8], port: u16) -> io::Result<SocketAddr> {
-    let domain_str = std::str::from_utf8(domain).map_err(|_| {
-        io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "domain name contains invalid utf-8 characters",
-        )
-    })?;
+impl DnsResolver {
+    /// Creates a resolver from the `dns` configuration section.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error if a nameserver entry cannot be parsed.
+    pub fn new(config: &DnsConfig) -> io::Result<Self> {
+        let name_servers = config
+            .nameservers()
+            .iter()
+            .map(|s| parse_nameserver(s))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let hosts = config
+            .hosts
+            .iter()
+            .flatten()
+            .map(|(name, ips)| (normalize_name(name), ips.clone()))
+            .collect();
+
+        let mut options = ResolverOpts::default();
+        options.cache_size = config.cache_size();
+        options.timeout = Duration::from_millis(config.timeout_ms());
+        options.attempts = config.attempts();
+        options.positive_min_ttl = config.min_ttl_secs.map(Duration::from_secs);
+        options.positive_max_ttl = config.max_ttl_secs.map(Duration::from_secs);
+        options.negative_max_ttl = config.negative_ttl_secs.map(Duration::from_secs);
+
+        Ok(Self {
+            hosts,
+            name_servers,
+            options,
+            resolver: OnceCell::new(),
+        })
+    }
 
-    // If the input is already an IP literal, return it directly without DNS lookup
-    if let Ok(ip) = domain_str.parse::<std::net::IpAddr>() {
-        return Ok(SocketAddr::new(ip, port));
+    /// Gets or initializes the hickory resolver.
+    ///
+    /// Without explicit nameservers the system configuration is read on first
+    /// use; the configured cache, TTL and timeout options are applied on top.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the system DNS configuration cannot be read.
+    async fn resolver(&self) -> io::Result<&TokioResolver> {
+        self.resolver
+            .get_or_try_init(|| async {
+                let builder = if self.name_servers.is_empty() {
+                    let mut builder = TokioResolver::builder_tokio().map_err(|e| {
+                        io::Error::other(format!(
+                            "failed to create dns resolver from system config: {e}"
+                        ))
+                    })?;
+                    let system = builder.options_mut();
+                    system.cache_size = self.options.cache_size;
+                    system.timeout = self.options.timeout;
+                    system.attempts = self.options.attempts;
+                    system.positive_min_ttl = self.options.positive_min_ttl;
+                    system.positive_max_ttl = self.options.positive_max_ttl;
+                    system.negative_max_ttl = self.options.negative_max_ttl;
+                    builder
+                } else {
+                    let config =
+                        ResolverConfig::from_parts(None, Vec::new(), self.name_servers.clone());
+                    TokioResolver::builder_with_config(config, TokioRuntimeProvider::default())
+                        .with_options(self.options.clone())
+                };
+                builder
+                    .build()
+                    .map_err(|e| io::Error::other(format!("failed to build dns resolver: {e}")))
+            })
+            .await
     }
 
-    let resolver = get_dns_resolver().await?;
+    /// Resolves a domain name to a single socket address.
+    ///
+    /// This is the first entry of [`DnsResolver::resolve_all`], i.e. the
+    /// address a single-shot dialer should try given the family preference.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if DNS resolution fails or no IP addresses are found.
+    pub(crate) async fn resolve(
+        &self,
+        domain: &[u8],
+        port: u16,
+        family: IpFamily,
+    ) -> io::Result<SocketAddr> {
+        let addrs = self.resolve_all(domain, port, family).await?;
+        // resolve_all never returns an empty list on success
+        Ok(addrs[0])
+    }
 
-    // Try IPv6 lookup first (RFC 6724 default preference).
-    // If IPv6 fails (common for domains without AAAA records), fall back to IPv4.
-    match resolver.lookup(domain_str, RecordType::AAAA).await {
-        Ok(lookup) => {
-            for record in lookup.answers() {
-                if let RData::AAAA(ipv6) = record.data {
-                    return Ok(SocketAddr::new(std::net::IpAddr::V6(ipv6.0), port));
+    /// Resolves a domain name to every usable socket address.
+    ///
+    /// A and AAAA lookups run in parallel (only the allowed family is queried
+    /// for the `*-only` preferences). The result is ordered as described in
+    /// RFC 8305 section 4: families are interleaved, starting with the
+    /// preferred one, so a Happy Eyeballs dialer alternates between them. IP
+    /// literals are returned directly without a DNS lookup and are not
+    /// filtered by family.
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - The domain name as bytes
+    /// * `port` - The port number
+    /// * `family` - Which address families to keep and which to try first
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if DNS resolution fails or no IP addresses are found.
+    pub(crate) async fn resolve_all(
+        &self,
+        domain: &[u8],
+        port: u16,
+        family: IpFamily,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let domain_str = std::str::from_utf8(domain).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "domain name contains invalid utf-8 characters",
+            )
+        })?;
+
+        // If the input is already an IP literal, return it directly without DNS lookup
+        if let Ok(ip) = domain_str.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+
+        let want_v4 = family != IpFamily::Ipv6Only;
+        let want_v6 = family != IpFamily::Ipv4Only;
+
+        let (v4, v6) = match self.hosts.get(&normalize_name(domain_str)) {
+            Some(ips) => (
+                ips.iter()
+                    .copied()
+                    .filter(|ip| want_v4 && ip.is_ipv4())
+                    .collect(),
+                ips.iter()
+                    .copied()
+                    .filter(|ip| want_v6 && ip.is_ipv6())
+                    .collect(),
+            ),
+            None => {
+                let resolver = self.resolver().await?;
+                tokio::join!(
+                    async {
+                        if want_v4 {
+                            lookup_family(resolver, domain_str, RecordType::A).await
+                        } else {
+                            Vec::new()
+                        }
+                    },
+                    async {
+                        if want_v6 {
+                            lookup_family(resolver, domain_str, RecordType::AAAA).await
+                        } else {
+                            Vec::new()
+                        }
+                    }
+                )
+            }
+        };
+
+        let (preferred, fallback) = match family {
+            IpFamily::Ipv4Only | IpFamily::PreferV4 => (v4, v6),
+            IpFamily::Ipv6Only | IpFamily::PreferV6 => (v6, v4),
+        };
+
+        let addrs: Vec<SocketAddr> = interleave(preferred, fallback)
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect();
+
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("dns resolution failed for {}: no records found", domain_str),
+            ));
+        }
+
+        Ok(addrs)
+    }
+
+    /// Answers a DNS query message in wire format on behalf of a client.
+    ///
+    /// The single question is looked up through the shared resolver (or the
+    /// static `hosts` table for A/AAAA) and the answer is returned as a
+    /// response message with the query's ID. Lookup failures, including a
+    /// resolver that can't be built, are reported in the response code rather
+    /// than as an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the query cannot be parsed, or an
+    /// error if the response cannot be serialized.
+    pub(crate) async fn answer(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let request = Message::from_vec(query).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed dns query: {e}"),
+            )
+        })?;
+
+        let mut response = Message::response(request.metadata.id, request.metadata.op_code);
+        response.metadata = Metadata::response_from_request(&request.metadata);
+        response.metadata.recursion_available = true;
+        response.add_queries(request.queries.iter().cloned());
+
+        match request.queries.as_slice() {
+            [question] if request.metadata.op_code == OpCode::Query => {
+                let name = question.name();
+                let record_type = question.query_type();
+                let hosts = match record_type {
+                    RecordType::A | RecordType::AAAA if question.query_class() == DNSClass::IN => {
+                        self.hosts.get(&normalize_name(&name.to_ascii()))
+                    }
+                    _ => None,
+                };
+
+                if let Some(ips) = hosts {
+                    response.add_answers(ips.iter().filter_map(|ip| {
+                        let data = match (ip, record_type) {
+                            (IpAddr::V4(ip), RecordType::A) => RData::A(A(*ip)),
+                            (IpAddr::V6(ip), RecordType::AAAA) => RData::AAAA(AAAA(*ip)),
+                            _ => return None,
+                        };
+                        Some(Record::from_rdata(name.clone(), HOSTS_TTL, data))
+                    }));
+                } else {
+                    let lookup = match self.resolver().await {
+                        Ok(resolver) => resolver.lookup(name.clone(), record_type).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    match lookup {
+                        Ok(lookup) => {
+                            response.add_answers(lookup.answers().iter().cloned());
+                        }
+                        Err(NetError::Dns(DnsError::NoRecordsFound(NoRecords {
+                            response_code,
+                            soa,
+                            ..
+                        }))) => {
+                            response.metadata.response_code = response_code;
+                            if let Some(soa) = soa {
+                                response.add_authority(soa.into_record_of_rdata());
+                            }
+                        }
+                        Err(NetError::Dns(DnsError::ResponseCode(code))) => {
+                            response.metadata.response_code = code;
+                        }
+                        Err(_e) => {
+                            debug!("{} lookup for {} failed: {}", record_type, name, _e);
+                            response.metadata.response_code = ResponseCode::ServFail;
+                        }
+                    }
                 }
             }
-            debug!("ipv6 lookup for {} returned no AAAA records, trying ipv4", domain_str);
+            [_] => response.metadata.response_code = ResponseCode::NotImp,
+            _ => response.metadata.response_code = ResponseCode::FormErr,
         }
-        Err(e) => {
-            debug!("ipv6 lookup for {} failed: {}, trying ipv4", domain_str, e);
+
+        let bytes = response.to_vec().map_err(io::Error::other)?;
+        if bytes.len() <= MAX_RESPONSE_LENGTH {
+            return Ok(bytes);
         }
+        response.truncate().to_vec().map_err(io::Error::other)
     }
+}
 
-    // Fall back to IPv4
-    match resolver.lookup(domain_str, RecordType::A).await {
+impl Default for DnsResolver {
+    /// A resolver using the system configuration and default cache settings.
+    fn default() -> Self {
+        Self::new(&DnsConfig::default()).expect("default dns config has no nameservers to parse")
+    }
+}
+
+/// Looks up one record type, treating lookup errors as an empty answer so the
+/// other family can still be used.
+async fn lookup_family(
+    resolver: &TokioResolver,
+    domain: &str,
+    record_type: RecordType,
+) -> Vec<IpAddr> {
+    match resolver.lookup(domain, record_type).await {
         Ok(lookup) => {
-            for record in lookup.answers() {
-                if let RData::A(ipv4) = record.data {
-                    return Ok(SocketAddr::new(std::net::IpAddr::V4(ipv4.0), port));
-                }
+            let ips: Vec<IpAddr> = lookup
+                .answers()
+                .iter()
+                .filter_map(|record| match &record.data {
+                    RData::A(ipv4) => Some(IpAddr::V4(ipv4.0)),
+                    RData::AAAA(ipv6) => Some(IpAddr::V6(ipv6.0)),
+                    _ => None,
+                })
+                .collect();
+            if ips.is_empty() {
+                debug!("{} lookup for {} returned no records", record_type, domain);
             }
-            debug!("ipv4 lookup for {} returned no A records", domain_str);
+            ips
         }
-        Err(e) => {
-            debug!("ipv4 lookup for {} failed: {}", domain_str, e);
+        Err(_e) => {
+            debug!("{} lookup for {} failed: {}", record_type, domain, _e);
+            Vec::new()
         }
     }
+}
+
+/// Lowercases a host name and strips a trailing root label for map lookups.
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Parses a nameserver entry from the `dns.nameservers` configuration.
+///
+/// Accepted forms are `IP[:PORT]` (UDP and TCP), `udp://IP[:PORT]`,
+/// `tcp://IP[:PORT]`, `tls://IP[:PORT][#NAME]` and
+/// `https://IP[:PORT][/PATH][#NAME]`. `NAME` is the TLS server name used to
+/// verify the upstream certificate and defaults to the IP address.
+fn parse_nameserver(entry: &str) -> io::Result<NameServerConfig> {
+    let invalid = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid nameserver '{entry}': {reason}"),
+        )
+    };
+
+    let (scheme, rest) = entry.split_once("://").unwrap_or(("", entry));
+    let (rest, server_name) = match rest.split_once('#') {
+        Some((rest, name)) => (rest, Some(name)),
+        None => (rest, None),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], Some(&rest[idx..])),
+        None => (rest, None),
+    };
+
+    let (ip, port) = if let Ok(addr) = host.parse::<SocketAddr>() {
+        (addr.ip(), Some(addr.port()))
+    } else if let Ok(ip) = host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+    {
+        (ip, None)
+    } else {
+        return Err(invalid("expected an IP address with an optional port"));
+    };
+
+    if path.is_some() && scheme != "https" {
+        return Err(invalid("a path is only supported for https://"));
+    }
+    if server_name.is_some() && !matches!(scheme, "tls" | "https") {
+        return Err(invalid(
+            "a server name is only supported for tls:// and https://",
+        ));
+    }
+
+    let server_name: Arc<str> = match server_name {
+        Some(name) if !name.is_empty() => Arc::from(name),
+        Some(_) => return Err(invalid("empty server name")),
+        None => Arc::from(ip.to_string()),
+    };
+
+    let mut connections = match scheme {
+        "" => vec![ConnectionConfig::udp(), ConnectionConfig::tcp()],
+        "udp" => vec![ConnectionConfig::udp()],
+        "tcp" => vec![ConnectionConfig::tcp()],
+        "tls" => vec![ConnectionConfig::tls(server_name)],
+        "https" => vec![ConnectionConfig::https(server_name, path.map(Arc::from))],
+        _ => return Err(invalid("unsupported scheme")),
+    };
+
+    if let Some(port) = port {
+        for connection in &mut connections {
+            connection.port = port;
+        }
+    }
+
+    Ok(NameServerConfig::new(ip, true, connections))
+}
 
-    Err(io::Error::new(
-        io::ErrorKind::NotFound,
-        format!("dns resolution failed for {}: no records found", domain_str),
-    ))
+/// Alternates between two address lists, starting with `first`.
+fn interleave(first: Vec<IpAddr>, second: Vec<IpAddr>) -> Vec<IpAddr> {
+    let mut out = Vec::with_capacity(first.len() + second.len());
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // ── Group I: resolve_domain() ────────────────────────────────────────────
+    // ── Group I: DnsResolver::default().resolve() ────────────────────────────────────────────
 
     #[tokio::test]
     async fn test_resolve_domain_valid_localhost() {
-        let result = resolve_domain(b"localhost", 80).await;
+        let result = DnsResolver::default()
+            .resolve(b"localhost", 80, IpFamily::PreferV6)
+            .await;
         let addr = result.expect("localhost should resolve");
         assert_eq!(addr.port(), 80);
     }
 
     #[tokio::test]
     async fn test_resolve_domain_ipv4_literal() {
-        let result = resolve_domain(b"127.0.0.1", 9000).await;
+        let result = DnsResolver::default()
+            .resolve(b"127.0.0.1", 9000, IpFamily::PreferV6)
+            .await;
         let addr = result.expect("IP literal should resolve");
         assert_eq!(addr.port(), 9000);
         assert_eq!(addr.ip().to_string(), "127.0.0.1");
@@ -115,7 +462,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_resolve_domain_invalid_utf8() {
-        let err = resolve_domain(b"\xff\xfe", 80)
+        let err = DnsResolver::default()
+            .resolve(b"\xff\xfe", 80, IpFamily::PreferV6)
             .await
             .expect_err("invalid utf-8 should fail");
         assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
@@ -124,20 +472,216 @@ mod tests {
 
     #[tokio::test]
     async fn test_resolve_domain_empty_bytes() {
-        let result = resolve_domain(b"", 80).await;
+        let result = DnsResolver::default()
+            .resolve(b"", 80, IpFamily::PreferV6)
+            .await;
         assert!(result.is_err(), "empty domain should fail");
     }
 
     #[tokio::test]
     async fn test_resolve_domain_nonexistent() {
         // .invalid TLD is guaranteed non-resolvable per RFC 2606
-        let err = resolve_domain(
-            b"this-absolutely-does-not-exist.ombrac-test-invalid",
-            80,
-        )
-        .await
-        .expect_err("non-existent domain should fail");
+        let err = DnsResolver::default()
+            .resolve(
+                b"this-absolutely-does-not-exist.ombrac-test-invalid",
+                80,
+                IpFamily::PreferV6,
+            )
+            .await
+            .expect_err("non-existent domain should fail");
         assert_eq!(err.kind(), io::ErrorKind::NotFound);
     }
 
+    #[tokio::test]
+    async fn test_resolve_all_ipv4_only_filters_family() {
+        let addrs = DnsResolver::default()
+            .resolve_all(b"localhost", 443, IpFamily::Ipv4Only)
+            .await
+            .expect("localhost should resolve over ipv4");
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| a.is_ipv4() && a.port() == 443));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_all_literal_ignores_family() {
+        let addrs = DnsResolver::default()
+            .resolve_all(b"::1", 53, IpFamily::Ipv4Only)
+            .await
+            .expect("IP literal should resolve");
+        assert_eq!(addrs, vec!["[::1]:53".parse::<SocketAddr>().unwrap()]);
+    }
+
+    // ── Group II: interleave() ───────────────────────────────────────────────
+
+    #[test]
+    fn test_interleave_alternates_starting_with_first() {
+        let v6: Vec<IpAddr> = vec!["::1".parse().unwrap(), "::2".parse().unwrap()];
+        let v4: Vec<IpAddr> = vec![
+            "10.0.0.1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            "10.0.0.3".parse().unwrap(),
+        ];
+        let out = interleave(v6.clone(), v4.clone());
+        assert_eq!(out, vec![v6[0], v4[0], v6[1], v4[1], v4[2]]);
+    }
+
+    #[test]
+    fn test_interleave_empty_side() {
+        let v4: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap()];
+        assert_eq!(interleave(Vec::new(), v4.clone()), v4);
+        assert!(interleave(Vec::new(), Vec::new()).is_empty());
+    }
+
+    // ── Group III: static hosts ──────────────────────────────────────────────
+
+    fn resolver_with_hosts() -> DnsResolver {
+        let config = DnsConfig {
+            hosts: Some(HashMap::from([(
+                "Internal.Example.".to_string(),
+                vec!["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+            )])),
+            ..Default::default()
+        };
+        DnsResolver::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hosts_entry_answers_without_lookup() {
+        let addrs = resolver_with_hosts()
+            .resolve_all(b"internal.example", 80, IpFamily::PreferV4)
+            .await
+            .unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "10.0.0.1:80".parse::<SocketAddr>().unwrap(),
+                "[fd00::1]:80".parse::<SocketAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hosts_entry_respects_family() {
+        let addr = resolver_with_hosts()
+            .resolve(b"INTERNAL.example.", 443, IpFamily::Ipv6Only)
+            .await
+            .unwrap();
+        assert_eq!(addr, "[fd00::1]:443".parse::<SocketAddr>().unwrap());
+    }
+
+    // ── Group IV: parse_nameserver() ─────────────────────────────────────────
+
+    #[test]
+    fn test_parse_nameserver_plain_ip_uses_udp_and_tcp() {
+        let ns = parse_nameserver("8.8.8.8").unwrap();
+        assert_eq!(ns.ip.to_string(), "8.8.8.8");
+        assert_eq!(ns.connections.len(), 2);
+        assert!(ns.connections.iter().all(|c| c.port == 53));
+    }
+
+    #[test]
+    fn test_parse_nameserver_custom_port() {
+        let ns = parse_nameserver("tcp://[2001:4860:4860::8888]:5353").unwrap();
+        assert!(ns.ip.is_ipv6());
+        assert_eq!(ns.connections.len(), 1);
+        assert_eq!(ns.connections[0].port, 5353);
+    }
+
+    #[test]
+    fn test_parse_nameserver_tls_and_https() {
+        let tls = parse_nameserver("tls://1.1.1.1#cloudflare-dns.com").unwrap();
+        assert_eq!(tls.connections[0].port, 853);
+        assert_eq!(
+            tls.connections[0].protocol,
+            hickory_resolver::config::ProtocolConfig::Tls {
+                server_name: Arc::from("cloudflare-dns.com")
+            }
+        );
+
+        let https = parse_nameserver("https://1.1.1.1/custom-query#cloudflare-dns.com").unwrap();
+        assert_eq!(https.connections[0].port, 443);
+        assert_eq!(
+            https.connections[0].protocol,
+            hickory_resolver::config::ProtocolConfig::Https {
+                server_name: Arc::from("cloudflare-dns.com"),
+                path: Arc::from("/custom-query"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_nameserver_rejects_invalid_entries() {
+        for entry in [
+            "dns.google",
+            "ftp://1.1.1.1",
+            "udp://1.1.1.1#name",
+            "tcp://1.1.1.1/path",
+            "tls://1.1.1.1#",
+        ] {
+            let err = parse_nameserver(entry).expect_err(entry);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{entry}");
+        }
+    }
+
+    #[test]
+    fn test_new_reports_invalid_nameserver() {
+        let config = DnsConfig {
+            nameservers: Some(vec!["not-an-ip".to_string()]),
+            ..Default::default()
+        };
+        assert!(DnsResolver::new(&config).is_err());
+    }
+
+    // ── Group V: DnsResolver::answer() ───────────────────────────────────────
+
+    fn query_bytes(name: &str, record_type: RecordType) -> Vec<u8> {
+        use hickory_resolver::proto::op::Query;
+        use hickory_resolver::proto::rr::Name;
+
+        let mut message = Message::query();
+        message.metadata.id = 0x4242;
+        message.metadata.recursion_desired = true;
+        message.add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        message.to_vec().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_answer_from_hosts_keeps_query_id() {
+        let bytes = resolver_with_hosts()
+            .answer(&query_bytes("internal.example.", RecordType::AAAA))
+            .await
+            .unwrap();
+        let response = Message::from_vec(&bytes).unwrap();
+        assert_eq!(response.metadata.id, 0x4242);
+        assert_eq!(response.metadata.response_code, ResponseCode::NoError);
+        assert!(response.metadata.recursion_desired);
+        assert_eq!(response.queries.len(), 1);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.answers[0].data,
+            RData::AAAA(AAAA("fd00::1".parse().unwrap()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answer_rejects_malformed_query() {
+        let err = DnsResolver::default()
+            .answer(&[0x00, 0x01])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_answer_without_question_is_formerr() {
+        let mut message = Message::query();
+        message.metadata.id = 7;
+        let bytes = DnsResolver::default()
+            .answer(&message.to_vec().unwrap())
+            .await
+            .unwrap();
+        let response = Message::from_vec(&bytes).unwrap();
+        assert_eq!(response.metadata.id, 7);
+        assert_eq!(response.metadata.response_code, ResponseCode::FormErr);
+    }
 }
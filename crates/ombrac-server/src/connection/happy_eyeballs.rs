@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use tokio::net::TcpStream;
+
+use ombrac_macros::debug;
+
+use crate::connection::Dialer;
+
+/// Delay between starting consecutive connection attempts.
+///
+/// RFC 8305 section 5 recommends 250ms as the default "Connection Attempt Delay".
+pub(crate) const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to the first reachable address using the Happy Eyeballs algorithm.
+///
+/// Addresses are attempted in the given order (callers are expected to pass
+/// them already interleaved by family), each dialed with `dialer`. A new
+/// attempt is started whenever the previous one fails or `attempt_delay`
+/// elapses without a winner; attempts already in flight keep running. The
+/// first successful connection wins and all other attempts are dropped.
+///
+/// # Errors
+///
+/// Returns the error of the last failed attempt if every address fails, or
+/// `NotFound` if `addrs` is empty.
+pub(crate) async fn connect(
+    dialer: &Dialer,
+    addrs: &[SocketAddr],
+    attempt_delay: Duration,
+) -> io::Result<TcpStream> {
+    let mut pending: VecDeque<SocketAddr> = addrs.iter().copied().collect();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    let attempt = |addr: SocketAddr| async move { (addr, dialer.connect(addr).await) };
+
+    loop {
+        if attempts.is_empty() {
+            match pending.pop_front() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => break,
+            }
+        }
+
+        let next_attempt = tokio::time::sleep(attempt_delay);
+
+        tokio::select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(stream) => {
+                    debug!("connected to {}", addr);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    debug!("connection attempt to {} failed: {}", addr, e);
+                    last_error = Some(e);
+                    if let Some(next) = pending.pop_front() {
+                        attempts.push(attempt(next));
+                    }
+                }
+            },
+            _ = next_attempt, if !pending.is_empty() => {
+                if let Some(next) = pending.pop_front() {
+                    attempts.push(attempt(next));
+                }
+            }
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    /// An address in TEST-NET-1 (RFC 5737); connections to it never complete.
+    fn blackhole() -> SocketAddr {
+        "192.0.2.1:9".parse().unwrap()
+    }
+
+    /// A loopback port with nothing listening, so connecting fails fast.
+    async fn refused() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_connect_empty_is_not_found() {
+        let err = connect(&Dialer::default(), &[], CONNECTION_ATTEMPT_DELAY)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_connect_falls_through_refused_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good = listener.local_addr().unwrap();
+
+        let stream = connect(
+            &Dialer::default(),
+            &[refused().await, good],
+            Duration::from_secs(10),
+        )
+        .await
+        .expect("second address should be tried after the first fails");
+        assert_eq!(stream.peer_addr().unwrap(), good);
+    }
+
+    #[tokio::test]
+    async fn test_connect_races_past_unresponsive_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good = listener.local_addr().unwrap();
+
+        let stream = tokio::time::timeout(
+            Duration::from_secs(5),
+            connect(
+                &Dialer::default(),
+                &[blackhole(), good],
+                Duration::from_millis(50),
+            ),
+        )
+        .await
+        .expect("staggered attempt should not wait for the first to time out")
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), good);
+    }
+
+    #[tokio::test]
+    async fn test_connect_all_fail_returns_last_error() {
+        let err = connect(
+            &Dialer::default(),
+            &[refused().await, refused().await],
+            CONNECTION_ATTEMPT_DELAY,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,501 @@
+//! Delegates the verification of clients sending a secret to an external
+//! service, e.g. one backed by an existing user database.
+//!
+//! The hook is sent an HTTP `POST` with a JSON object describing the
+//! client, over TCP or a Unix socket:
+//!
+//! ```json
+//! {"secret": "<hex>", "options": "user-1", "remote_address": "203.0.113.7:51234", "certificate_fingerprint": null}
+//! ```
+//!
+//! and answers with a JSON object such as `{"allow": true, "identity":
+//! "alice", "bandwidth": 1000000, "destinations": ["example.com"]}`, where
+//! only `allow` is required. Any status other than 2xx counts as the hook
+//! being unreachable.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use ombrac::protocol;
+use ombrac_macros::warn;
+
+use super::{ConnectionAuthError, Peer};
+
+/// Upper bound for a response of the hook, headers included.
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+/// Upper bound for the number of cached decisions, so that clients trying
+/// many secrets can't grow the cache without bound.
+const MAX_CACHED_DECISIONS: usize = 64 * 1024;
+
+/// How often expired decisions are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Where the hook listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookEndpoint {
+    /// `http://HOST[:PORT][/PATH]`.
+    Http {
+        /// Address connected to, as `HOST:PORT`.
+        address: String,
+        /// Value of the `Host` header.
+        host: String,
+        path: String,
+    },
+    /// `unix:PATH`; requests are sent to `/`.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for HookEndpoint {
+    type Err = io::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = value.strip_prefix("http://") {
+            let (authority, path) = match rest.find('/') {
+                Some(index) => rest.split_at(index),
+                None => (rest, "/"),
+            };
+            if authority.is_empty() {
+                return Err(invalid_data(format!("missing host in '{value}'")));
+            }
+            let has_port = match authority.strip_prefix('[') {
+                Some(bracketed) => bracketed.contains("]:"),
+                None => authority.contains(':'),
+            };
+            let address = if has_port {
+                authority.to_string()
+            } else {
+                format!("{authority}:80")
+            };
+            return Ok(Self::Http {
+                address,
+                host: authority.to_string(),
+                path: path.to_string(),
+            });
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        Err(invalid_data(format!(
+            "unsupported auth hook '{value}', expected http://HOST[:PORT][/PATH] or unix:PATH"
+        )))
+    }
+}
+
+/// What the hook decided about a client it allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct HookDecision {
+    /// Who the client is, as named by the hook.
+    pub identity: Option<String>,
+    /// Bandwidth granted to the client, in bytes per second, see
+    /// [`ClientLimits::bandwidth`](super::ClientLimits::bandwidth).
+    pub bandwidth: Option<u64>,
+    /// Destinations the client may reach, see
+    /// [`ClientLimits::destinations`](super::ClientLimits::destinations).
+    pub destinations: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct HookRequest {
+    secret: String,
+    options: String,
+    remote_address: Option<SocketAddr>,
+    certificate_fingerprint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HookResponse {
+    allow: bool,
+    #[serde(flatten)]
+    decision: HookDecision,
+}
+
+/// A denial is cached as `None`.
+type CachedDecision = (Instant, Option<HookDecision>);
+
+#[derive(Debug)]
+struct DecisionCache {
+    decisions: HashMap<[u8; 32], CachedDecision>,
+    last_prune: Instant,
+}
+
+/// Asks an external service whether clients sending a secret are allowed.
+#[derive(Debug)]
+pub struct AuthHook {
+    endpoint: HookEndpoint,
+    timeout: Duration,
+    cache_ttl: Duration,
+    fail_open: bool,
+    cache: Mutex<DecisionCache>,
+}
+
+impl AuthHook {
+    /// Creates a hook failing closed, with a 3 s timeout and decisions
+    /// cached for 60 s.
+    pub fn new(endpoint: HookEndpoint) -> Self {
+        Self {
+            endpoint,
+            timeout: Duration::from_secs(3),
+            cache_ttl: Duration::from_secs(60),
+            fail_open: false,
+            cache: Mutex::new(DecisionCache {
+                decisions: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    /// Sets how long the hook may take to answer.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how long decisions are reused for the same secret, options,
+    /// source IP and certificate. Zero disables the cache.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Accepts clients, with an empty decision, while the hook can't be
+    /// reached or answers with an error. Otherwise they are refused.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Asks the hook about a client, unless it was recently asked about the
+    /// same one.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) async fn check(
+        &self,
+        hello: &protocol::ClientHello,
+        peer: &Peer,
+    ) -> Result<HookDecision, ConnectionAuthError> {
+        let options = hello.auth_options();
+        let key = cache_key(hello, &options, peer);
+        if let Some(decision) = self.cached(&key) {
+            return decision.ok_or(ConnectionAuthError::InvalidSecret);
+        }
+
+        let request = HookRequest {
+            secret: hex(&hello.secret),
+            options: String::from_utf8_lossy(&options).into_owned(),
+            remote_address: peer.remote_address,
+            certificate_fingerprint: peer.certificate_fingerprint.map(|f| hex(&f)),
+        };
+        let answer = tokio::time::timeout(self.timeout, self.ask(&request))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")));
+
+        match answer {
+            Ok(decision) => {
+                self.store(key, decision.clone());
+                decision.ok_or(ConnectionAuthError::InvalidSecret)
+            }
+            Err(error) if self.fail_open => {
+                warn!(error = %error, "auth hook failed, accepting the client");
+                Ok(HookDecision::default())
+            }
+            Err(error) => {
+                warn!(error = %error, "auth hook failed, refusing the client");
+                Err(ConnectionAuthError::ServerError)
+            }
+        }
+    }
+
+    fn cached(&self, key: &[u8; 32]) -> Option<Option<HookDecision>> {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .decisions
+            .get(key)
+            .filter(|(at, _)| at.elapsed() < self.cache_ttl)
+            .map(|(_, decision)| decision.clone())
+    }
+
+    fn store(&self, key: [u8; 32], decision: Option<HookDecision>) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(cache.last_prune) >= PRUNE_INTERVAL {
+            cache.last_prune = now;
+            cache
+                .decisions
+                .retain(|_, (at, _)| now.duration_since(*at) < self.cache_ttl);
+        }
+        // Once full, clients are asked about again rather than cached
+        if cache.decisions.len() >= MAX_CACHED_DECISIONS && !cache.decisions.contains_key(&key) {
+            return;
+        }
+        cache.decisions.insert(key, (now, decision));
+    }
+
+    async fn ask(&self, request: &HookRequest) -> io::Result<Option<HookDecision>> {
+        let body = serde_json::to_vec(request).map_err(io::Error::other)?;
+        let response = match &self.endpoint {
+            HookEndpoint::Http {
+                address,
+                host,
+                path,
+            } => post(TcpStream::connect(address).await?, host, path, &body).await?,
+            #[cfg(unix)]
+            HookEndpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                post(stream, "localhost", "/", &body).await?
+            }
+        };
+        let response: HookResponse = serde_json::from_slice(&response)
+            .map_err(|e| invalid_data(format!("invalid auth hook response: {e}")))?;
+        Ok(response.allow.then_some(response.decision))
+    }
+}
+
+/// Identifies what the hook is told about a client, except the source port,
+/// which changes with every connection.
+fn cache_key(hello: &protocol::ClientHello, options: &[u8], peer: &Peer) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&hello.secret);
+    hasher.update(&(options.len() as u64).to_be_bytes());
+    hasher.update(options);
+    match peer.remote_address.map(|addr| addr.ip()) {
+        Some(IpAddr::V4(ip)) => hasher.update(&[4]).update(&ip.octets()),
+        Some(IpAddr::V6(ip)) => hasher.update(&[6]).update(&ip.octets()),
+        None => hasher.update(&[0]),
+    };
+    if let Some(fingerprint) = &peer.certificate_fingerprint {
+        hasher.update(fingerprint);
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// Sends an HTTP/1.0 `POST`, so the response is neither chunked nor kept
+/// alive, and returns the body of a 2xx response.
+async fn post<S>(mut stream: S, host: &str, path: &str, body: &[u8]) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = format!(
+        "POST {path} HTTP/1.0\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(body);
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE_SIZE + 1)
+        .read_to_end(&mut response)
+        .await?;
+    if response.len() as u64 > MAX_RESPONSE_SIZE {
+        return Err(invalid_data("auth hook response is too large"));
+    }
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid_data("incomplete auth hook response"))?;
+    let status = std::str::from_utf8(&response[..header_end])
+        .ok()
+        .and_then(|head| head.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid_data("malformed auth hook response"))?;
+    if !(200..300).contains(&status) {
+        return Err(io::Error::other(format!(
+            "auth hook answered with status {status}"
+        )));
+    }
+    Ok(response.split_off(header_end + 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn hello(secret: u8) -> protocol::ClientHello {
+        protocol::ClientHello {
+            version: protocol::PROTOCOL_VERSION,
+            secret: [secret; 32],
+            options: bytes::Bytes::from_static(b"user-1"),
+        }
+    }
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            remote_address: Some(SocketAddr::from(([203, 0, 113, 7], port))),
+            ..Default::default()
+        }
+    }
+
+    /// Reads a request of `post`, whose JSON body ends it.
+    async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !request.ends_with(b"}") {
+            let len = stream.read(&mut buf).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..len]);
+        }
+        String::from_utf8(request).unwrap()
+    }
+
+    /// Serves a hook allowing the secret `[1; 32]` only, counting requests.
+    async fn spawn_hook() -> (HookEndpoint, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let request = read_request(&mut stream).await;
+                let body = if request.contains(&hex(&[1u8; 32])) {
+                    r#"{"allow": true, "identity": "alice", "bandwidth": 1000}"#
+                } else {
+                    r#"{"allow": false}"#
+                };
+                let response = format!("HTTP/1.0 200 OK\r\n\r\n{body}");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let endpoint = format!("http://{address}/auth").parse().unwrap();
+        (endpoint, requests)
+    }
+
+    #[test]
+    fn endpoint_parsing() {
+        assert_eq!(
+            "http://auth.internal/check"
+                .parse::<HookEndpoint>()
+                .unwrap(),
+            HookEndpoint::Http {
+                address: "auth.internal:80".to_string(),
+                host: "auth.internal".to_string(),
+                path: "/check".to_string(),
+            }
+        );
+        assert_eq!(
+            "http://[::1]:8080".parse::<HookEndpoint>().unwrap(),
+            HookEndpoint::Http {
+                address: "[::1]:8080".to_string(),
+                host: "[::1]:8080".to_string(),
+                path: "/".to_string(),
+            }
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            "unix:/run/auth.sock".parse::<HookEndpoint>().unwrap(),
+            HookEndpoint::Unix(PathBuf::from("/run/auth.sock"))
+        );
+        assert!("https://auth.internal".parse::<HookEndpoint>().is_err());
+        assert!("http:///path".parse::<HookEndpoint>().is_err());
+    }
+
+    #[tokio::test]
+    async fn decisions_are_cached() {
+        let (endpoint, requests) = spawn_hook().await;
+        let hook = AuthHook::new(endpoint);
+
+        let decision = hook.check(&hello(1), &peer(1000)).await.unwrap();
+        assert_eq!(decision.identity.as_deref(), Some("alice"));
+        assert_eq!(decision.bandwidth, Some(1000));
+        // Another connection from the same client
+        hook.check(&hello(1), &peer(1001)).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let err = hook.check(&hello(2), &peer(1000)).await.unwrap_err();
+        assert_eq!(err, ConnectionAuthError::InvalidSecret);
+        hook.check(&hello(2), &peer(1000)).await.unwrap_err();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let uncached = AuthHook::new(hook.endpoint.clone()).with_cache_ttl(Duration::ZERO);
+        uncached.check(&hello(1), &peer(1000)).await.unwrap();
+        uncached.check(&hello(1), &peer(1000)).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        let hook = AuthHook::new("unix:/nonexistent".parse().unwrap());
+        for index in 0..MAX_CACHED_DECISIONS as u64 {
+            let mut key = [0u8; 32];
+            key[..8].copy_from_slice(&index.to_be_bytes());
+            hook.store(key, None);
+        }
+
+        hook.store([0xff; 32], None);
+        assert!(hook.cached(&[0xff; 32]).is_none());
+        // Decisions already cached are still refreshed
+        hook.store([0u8; 32], Some(HookDecision::default()));
+        assert_eq!(hook.cached(&[0u8; 32]), Some(Some(HookDecision::default())));
+    }
+
+    #[tokio::test]
+    async fn unreachable_hook_follows_the_failure_policy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint: HookEndpoint = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        drop(listener);
+
+        let closed = AuthHook::new(endpoint.clone());
+        let err = closed.check(&hello(1), &peer(1000)).await.unwrap_err();
+        assert_eq!(err, ConnectionAuthError::ServerError);
+
+        let open = AuthHook::new(endpoint).with_fail_open(true);
+        let decision = open.check(&hello(1), &peer(1000)).await.unwrap();
+        assert_eq!(decision, HookDecision::default());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_hook() {
+        let path = std::env::temp_dir().join(format!("ombrac-hook-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await;
+            let response = "HTTP/1.0 200 OK\r\n\r\n{\"allow\": true, \"identity\": \"bob\"}";
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let hook = AuthHook::new(HookEndpoint::Unix(path.clone()));
+        let decision = hook.check(&hello(1), &peer(1000)).await.unwrap();
+        assert_eq!(decision.identity.as_deref(), Some("bob"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,115 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+/// How long to wait for an echo reply.
+pub(crate) const ECHO_TIMEOUT: Duration = Duration::from_secs(4);
+
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// Sends an ICMP echo request to `addr` and waits for the reply.
+///
+/// Uses an unprivileged ICMP datagram socket, so no raw socket capability is
+/// needed. On Linux the server's group must be within
+/// `net.ipv4.ping_group_range`; the kernel then fills in the identifier and
+/// only delivers replies to this socket.
+///
+/// # Errors
+///
+/// Returns `PermissionDenied` if ICMP sockets are not allowed, `TimedOut` if
+/// no reply arrives within `timeout`, or the error of the send.
+pub(crate) async fn echo(addr: IpAddr, data: &[u8], timeout: Duration) -> io::Result<()> {
+    let (domain, protocol, request, reply) = match addr {
+        IpAddr::V4(_) => (
+            Domain::IPV4,
+            Protocol::ICMPV4,
+            ICMPV4_ECHO_REQUEST,
+            ICMPV4_ECHO_REPLY,
+        ),
+        IpAddr::V6(_) => (
+            Domain::IPV6,
+            Protocol::ICMPV6,
+            ICMPV6_ECHO_REQUEST,
+            ICMPV6_ECHO_REPLY,
+        ),
+    };
+
+    let socket = Socket::new(domain, Type::DGRAM, Some(protocol))?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.connect(SocketAddr::new(addr, 0)).await?;
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let mut packet = Vec::with_capacity(8 + data.len());
+    packet.extend_from_slice(&[request, 0, 0, 0, 0, 0]);
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(data);
+    if addr.is_ipv4() {
+        // Linux computes it, other platforms expect it filled in.
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    socket.send(&packet).await?;
+
+    let wait_reply = async {
+        let mut buf = vec![0u8; 8 + data.len() + 60];
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            let mut message = &buf[..n];
+            // Some platforms prepend the IPv4 header.
+            if addr.is_ipv4() && message.first().is_some_and(|b| b >> 4 == 4) {
+                let header_len = ((message[0] & 0x0f) as usize) * 4;
+                message = message.get(header_len..).unwrap_or_default();
+            }
+            if message.len() >= 8 && message[0] == reply && message[6..8] == sequence.to_be_bytes()
+            {
+                return Ok(());
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, wait_reply)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "echo request timed out"))?
+}
+
+/// Internet checksum (RFC 1071).
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]) as u32)
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_matches_rfc_1071_example() {
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data), !0xddf2);
+    }
+
+    #[tokio::test]
+    async fn echo_to_loopback() {
+        match echo(IpAddr::from([127, 0, 0, 1]), b"ombrac", ECHO_TIMEOUT).await {
+            // Unprivileged ICMP sockets are disabled on this host.
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+            result => result.unwrap(),
+        }
+    }
+}
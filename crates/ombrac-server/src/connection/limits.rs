@@ -0,0 +1,384 @@
+//! Limits a client is held to once authenticated.
+//!
+//! Tokens and auth hooks may grant a client a bandwidth and the destinations
+//! it may reach. The tunnels of the connection check every destination
+//! before reaching it and share one budget per direction between all its
+//! streams and UDP sessions.
+
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+use ombrac::protocol::Address;
+
+/// Limits granted to a client when it authenticated, e.g. by the claims of
+/// its token or the decision of an auth hook.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientLimits {
+    /// Bytes per second the client may send, and receive, over all its
+    /// streams and UDP sessions. `None` or zero is unlimited.
+    pub bandwidth: Option<u64>,
+    /// Destinations the client may reach, `None` allowing every destination.
+    ///
+    /// Each entry is an IP address, a network such as `10.0.0.0/8`, or a
+    /// domain, which also allows its subdomains. Addresses are only matched
+    /// by addresses and networks, and domains by domains: a domain is
+    /// allowed to resolve to any address.
+    pub destinations: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DestinationRule {
+    Network(IpNet),
+    Domain(String),
+}
+
+impl DestinationRule {
+    fn parse(value: &str) -> Self {
+        let value = value.trim();
+        if let Ok(network) = value.parse::<IpNet>() {
+            return Self::Network(network);
+        }
+        if let Ok(ip) = value.parse::<IpAddr>() {
+            return Self::Network(IpNet::from(ip.to_canonical()));
+        }
+        let domain = value.trim_start_matches("*.").trim_end_matches('.');
+        Self::Domain(domain.to_ascii_lowercase())
+    }
+
+    fn allows_ip(&self, ip: IpAddr) -> bool {
+        match self {
+            Self::Network(network) => network.contains(&ip.to_canonical()),
+            Self::Domain(_) => false,
+        }
+    }
+
+    fn allows_domain(&self, domain: &str) -> bool {
+        match self {
+            Self::Network(_) => false,
+            Self::Domain(rule) => {
+                domain == rule
+                    || domain
+                        .strip_suffix(rule.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            }
+        }
+    }
+}
+
+/// A token bucket holding one second worth of bytes.
+///
+/// Reads and writes can't know their size in advance, so they take what
+/// they moved afterwards and leave the bucket in debt. The next one waits
+/// until the debt is paid off.
+#[derive(Debug)]
+struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn refilled(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.updated = now;
+        bucket
+    }
+
+    /// Returns how long to wait until the bucket is out of debt.
+    fn delay(&self) -> Option<Duration> {
+        let bucket = self.refilled();
+        (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / self.rate))
+    }
+
+    fn consume(&self, len: usize) {
+        self.refilled().tokens -= len as f64;
+    }
+
+    /// Takes `len` bytes unless the bucket is in debt.
+    #[cfg(feature = "datagram")]
+    fn try_consume(&self, len: usize) -> bool {
+        let mut bucket = self.refilled();
+        if bucket.tokens < 0.0 {
+            return false;
+        }
+        bucket.tokens -= len as f64;
+        true
+    }
+}
+
+/// The limits of one client connection, shared by all its tunnels.
+#[derive(Debug, Default)]
+pub(crate) struct ClientPolicy {
+    destinations: Option<Vec<DestinationRule>>,
+    upload: Option<RateLimiter>,
+    download: Option<RateLimiter>,
+}
+
+impl ClientPolicy {
+    pub(crate) fn new(limits: &ClientLimits) -> Self {
+        let bandwidth = limits.bandwidth.filter(|&rate| rate > 0);
+        Self {
+            destinations: limits.destinations.as_ref().map(|destinations| {
+                destinations
+                    .iter()
+                    .map(|value| DestinationRule::parse(value))
+                    .collect()
+            }),
+            upload: bandwidth.map(RateLimiter::new),
+            download: bandwidth.map(RateLimiter::new),
+        }
+    }
+
+    /// Returns whether the client may reach `destination`.
+    pub(crate) fn allows(&self, destination: &Address) -> bool {
+        let Some(rules) = &self.destinations else {
+            return true;
+        };
+        match destination {
+            Address::SocketV4(addr) => rules.iter().any(|rule| rule.allows_ip((*addr.ip()).into())),
+            Address::SocketV6(addr) => rules.iter().any(|rule| rule.allows_ip((*addr.ip()).into())),
+            Address::Domain(domain, _) => {
+                let Ok(domain) = std::str::from_utf8(domain) else {
+                    return false;
+                };
+                match domain.parse::<IpAddr>() {
+                    Ok(ip) => rules.iter().any(|rule| rule.allows_ip(ip)),
+                    Err(_) => {
+                        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+                        rules.iter().any(|rule| rule.allows_domain(&domain))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fails with `PermissionDenied` unless the client may reach
+    /// `destination`.
+    pub(crate) fn check(&self, destination: &Address) -> io::Result<()> {
+        if self.allows(destination) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("destination {destination} is not allowed for this client"),
+            ))
+        }
+    }
+
+    /// Returns whether a datagram of `len` bytes from the client may be
+    /// sent upstream. Like any UDP path, datagrams over the budget are
+    /// dropped rather than delayed.
+    #[cfg(feature = "datagram")]
+    pub(crate) fn admit_upload(&self, len: usize) -> bool {
+        self.upload
+            .as_ref()
+            .is_none_or(|limiter| limiter.try_consume(len))
+    }
+
+    /// Returns whether a datagram of `len` bytes may be sent to the client.
+    #[cfg(feature = "datagram")]
+    pub(crate) fn admit_download(&self, len: usize) -> bool {
+        self.download
+            .as_ref()
+            .is_none_or(|limiter| limiter.try_consume(len))
+    }
+}
+
+/// An upstream connection whose writes count against the client's upload
+/// budget, and reads against its download budget.
+pub(crate) struct Throttled<S> {
+    inner: S,
+    policy: Arc<ClientPolicy>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    pub(crate) fn new(inner: S, policy: Arc<ClientPolicy>) -> Self {
+        Self {
+            inner,
+            policy,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+/// Waits until `limiter` is out of debt, if there is one.
+fn poll_budget(
+    limiter: Option<&RateLimiter>,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    let Some(limiter) = limiter else {
+        return Poll::Ready(());
+    };
+    loop {
+        if let Some(sleep) = delay {
+            ready!(sleep.as_mut().poll(cx));
+            *delay = None;
+        }
+        match limiter.delay() {
+            Some(duration) => *delay = Some(Box::pin(tokio::time::sleep(duration))),
+            None => return Poll::Ready(()),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let limiter = this.policy.download.as_ref();
+        ready!(poll_budget(limiter, &mut this.read_delay, cx));
+
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(limiter) = limiter {
+            limiter.consume(buf.filled().len() - before);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let limiter = this.policy.upload.as_ref();
+        ready!(poll_budget(limiter, &mut this.write_delay, cx));
+
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(limiter) = limiter {
+            limiter.consume(written);
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(destinations: &[&str]) -> ClientPolicy {
+        ClientPolicy::new(&ClientLimits {
+            bandwidth: None,
+            destinations: Some(destinations.iter().map(|d| d.to_string()).collect()),
+        })
+    }
+
+    fn address(value: &str) -> Address {
+        Address::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn destinations_match_networks_and_domains() {
+        let policy = policy(&["10.0.0.0/8", "192.0.2.1", "Example.com", "*.example.net"]);
+
+        assert!(policy.allows(&address("10.1.2.3:80")));
+        assert!(policy.allows(&address("192.0.2.1:443")));
+        assert!(policy.allows(&address("[::ffff:10.0.0.1]:80")));
+        assert!(!policy.allows(&address("192.0.2.2:443")));
+
+        assert!(policy.allows(&address("example.com:443")));
+        assert!(policy.allows(&address("www.EXAMPLE.com.:443")));
+        assert!(policy.allows(&address("api.example.net:443")));
+        assert!(!policy.allows(&address("badexample.com:443")));
+        assert!(!policy.allows(&address("example.org:443")));
+
+        // Addresses sent as domains are still matched as addresses
+        assert!(!policy.allows(&Address::from(("192.0.2.2", 80))));
+        assert!(policy.allows(&Address::from(("10.0.0.1", 80))));
+
+        let err = policy.check(&address("example.org:443")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn no_limits_allow_everything() {
+        let policy = ClientPolicy::new(&ClientLimits::default());
+        assert!(policy.allows(&address("203.0.113.1:22")));
+        assert!(policy.allows(&address("example.org:443")));
+        assert!(!self::policy(&[]).allows(&address("example.org:443")));
+    }
+
+    #[cfg(feature = "datagram")]
+    #[test]
+    fn datagrams_over_the_budget_are_dropped() {
+        let policy = ClientPolicy::new(&ClientLimits {
+            bandwidth: Some(1000),
+            destinations: None,
+        });
+        assert!(policy.admit_upload(1500));
+        assert!(!policy.admit_upload(10));
+        assert!(policy.admit_download(10));
+    }
+
+    #[tokio::test]
+    async fn streams_are_throttled_to_the_bandwidth() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let policy = Arc::new(ClientPolicy::new(&ClientLimits {
+            bandwidth: Some(10_000),
+            destinations: None,
+        }));
+        let (near, mut far) = tokio::io::duplex(64 * 1024);
+        let mut throttled = Throttled::new(near, policy);
+
+        let started = Instant::now();
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            far.read_to_end(&mut received).await.unwrap();
+            received.len()
+        });
+        // Twice the one second worth of burst leaves a second of debt
+        throttled.write_all(&[0u8; 20_000]).await.unwrap();
+        throttled.write_all(&[0u8; 1]).await.unwrap();
+        throttled.shutdown().await.unwrap();
+        drop(throttled);
+
+        assert_eq!(reader.await.unwrap(), 20_001);
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+}
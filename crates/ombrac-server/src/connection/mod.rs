@@ -1,8 +1,31 @@
+mod ban;
+mod certificate;
+mod credentials;
 #[cfg(feature = "datagram")]
 mod datagram;
+mod dialer;
 mod dns;
+mod happy_eyeballs;
+mod hook;
+mod icmp;
+mod limits;
+mod outbound;
+mod proxy;
 mod stream;
 
+pub use ban::{Ban, BanList};
+pub use certificate::PeerCertificate;
+pub use credentials::{Credentials, Identity};
+pub use dialer::{Dialer, DialerOptions, Ipv6Prefix};
+pub use dns::DnsResolver;
+pub use hook::{AuthHook, HookDecision, HookEndpoint};
+pub use limits::ClientLimits;
+#[cfg(feature = "chain")]
+pub use outbound::ChainClient;
+pub use outbound::Outbound;
+pub use proxy::ProxyServer;
+
+use std::fmt;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
@@ -11,6 +34,7 @@ use std::sync::Weak;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
+use bytes::Bytes;
 use futures::{SinkExt, StreamExt};
 use tokio::sync::OwnedSemaphorePermit;
 use tokio::sync::{Semaphore, broadcast};
@@ -21,12 +45,33 @@ use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
 
 use ombrac::codec;
+use ombrac::key::PublicKey;
 use ombrac::metrics::Metrics;
 use ombrac::protocol;
 use ombrac_macros::{debug, error, warn};
 use ombrac_transport::{Acceptor, Connection};
 
-use crate::config::ConnectionConfig;
+use crate::config::{AuthMode, ConnectionConfig};
+use crate::connection::limits::ClientPolicy;
+
+/// Optional features this server offers to clients advertising capabilities.
+#[cfg(feature = "datagram")]
+const SERVER_FEATURES: protocol::Features = protocol::Features::from_bits(
+    protocol::Features::DATAGRAM.bits()
+        | protocol::Features::DNS_QUERY.bits()
+        | protocol::Features::ECHO.bits()
+        | protocol::Features::CHALLENGE_AUTH.bits()
+        | protocol::Features::PUBLIC_KEY_AUTH.bits()
+        | protocol::Features::TOKEN_AUTH.bits(),
+);
+#[cfg(not(feature = "datagram"))]
+const SERVER_FEATURES: protocol::Features = protocol::Features::from_bits(
+    protocol::Features::DNS_QUERY.bits()
+        | protocol::Features::ECHO.bits()
+        | protocol::Features::CHALLENGE_AUTH.bits()
+        | protocol::Features::PUBLIC_KEY_AUTH.bits()
+        | protocol::Features::TOKEN_AUTH.bits(),
+);
 
 /// Processes a single client connection, handling authentication and tunnel management.
 ///
@@ -36,6 +81,10 @@ use crate::config::ConnectionConfig;
 pub struct ClientConnectionProcessor<C: Connection> {
     transport_connection: Arc<C>,
     shutdown_token: CancellationToken,
+    config: Arc<ConnectionConfig>,
+    dns: Arc<DnsResolver>,
+    outbound: Outbound,
+    policy: Arc<ClientPolicy>,
     metrics: Metrics,
 }
 
@@ -50,13 +99,17 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         connection: C,
         authenticator: &A,
         config: Arc<ConnectionConfig>,
+        dns: Arc<DnsResolver>,
+        outbound: Outbound,
         metrics: &Metrics,
     ) -> io::Result<()>
     where
         A: Authenticator<C>,
     {
-        let (auth_context, connection) =
+        let (auth_context, connection, user) =
             Self::perform_authentication(connection, authenticator, &config).await?;
+        let outbound = outbound.for_client(&user);
+        let policy = Arc::new(ClientPolicy::new(&authenticator.limits(&auth_context)));
 
         let transport_connection = Arc::new(connection);
 
@@ -72,6 +125,10 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         let processor = Self {
             transport_connection,
             shutdown_token: CancellationToken::new(),
+            config,
+            dns,
+            outbound,
+            policy,
             metrics: metrics.clone(),
         };
 
@@ -84,7 +141,7 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         connection: C,
         authenticator: &A,
         config: &ConnectionConfig,
-    ) -> io::Result<(A::AuthContext, C)> {
+    ) -> io::Result<(A::AuthContext, C, Bytes)> {
         let auth_timeout = Duration::from_secs(config.auth_timeout_secs());
 
         // Accept control stream
@@ -100,11 +157,21 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         Self::trace_auth(&hello);
 
         // Verify authentication
-        let auth_context =
-            Self::verify_authentication(&hello, authenticator, auth_timeout, &mut control_frame)
-                .await?;
+        let peer = Peer::from_connection(&connection);
+        let auth_context = Self::verify_authentication(
+            &connection,
+            &hello,
+            &peer,
+            authenticator,
+            config.auth_mode(),
+            auth_timeout,
+            &mut control_frame,
+        )
+        .await?;
 
-        Ok((auth_context, connection))
+        let user = authenticator.user(&hello, &auth_context);
+
+        Ok((auth_context, connection, user))
     }
 
     /// Reads and parses the hello message from the client.
@@ -119,13 +186,10 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         let payload = tokio::time::timeout(timeout, control_frame.next())
             .await
             .map_err(|_| {
-                io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    format!(
-                        "authentication timeout: failed to receive hello message within {:?}",
-                        timeout
-                    ),
-                )
+                client_timeout(format!(
+                    "authentication timeout: failed to receive hello message within {:?}",
+                    timeout
+                ))
             })?
             .ok_or_else(|| {
                 io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed before hello")
@@ -155,15 +219,209 @@ impl<C: Connection> ClientConnectionProcessor<C> {
     }
 
     /// Verifies authentication and sends response.
+    ///
+    /// Clients advertising capabilities get the negotiated ones, or the
+    /// precise reason they are refused. Older clients only understand `Ok`,
+    /// so they are still disconnected silently on failure.
     async fn verify_authentication<A: Authenticator<C>>(
+        connection: &C,
+        hello: &protocol::ClientHello,
+        peer: &Peer,
+        authenticator: &A,
+        auth_mode: AuthMode,
+        timeout: Duration,
+        control_frame: &mut Framed<&mut <C as Connection>::Stream, codec::LengthDelimitedCodec>,
+    ) -> io::Result<A::AuthContext>
+    where
+        C: Connection,
+    {
+        let capabilities = match hello.capabilities() {
+            Ok(capabilities) => capabilities,
+            Err(err) => {
+                Self::reject(control_frame, protocol::AuthRejection::Malformed, timeout).await;
+                return Err(err);
+            }
+        };
+        let Some(capabilities) = capabilities else {
+            return Self::verify_legacy_authentication(
+                hello,
+                peer,
+                authenticator,
+                auth_mode,
+                timeout,
+                control_frame,
+            )
+            .await;
+        };
+
+        let Some(version) = capabilities
+            .negotiate_version(protocol::MIN_PROTOCOL_VERSION, protocol::PROTOCOL_VERSION)
+        else {
+            let rejection = protocol::AuthRejection::IncompatibleVersion {
+                min_version: protocol::MIN_PROTOCOL_VERSION,
+                max_version: protocol::PROTOCOL_VERSION,
+            };
+            Self::reject(control_frame, rejection, timeout).await;
+            return Err(ConnectionAuthError::IncompatibleVersion.into());
+        };
+
+        let auth_context = if capabilities
+            .features
+            .contains(protocol::Features::CHALLENGE_AUTH)
+            || capabilities
+                .features
+                .contains(protocol::Features::PUBLIC_KEY_AUTH)
+            || capabilities
+                .features
+                .contains(protocol::Features::TOKEN_AUTH)
+        {
+            Self::verify_challenge(
+                connection,
+                hello,
+                authenticator,
+                auth_mode,
+                timeout,
+                control_frame,
+            )
+            .await?
+        } else if auth_mode == AuthMode::Certificate {
+            match authenticate_within(timeout, authenticator.verify_certificate(hello, peer))
+                .await?
+            {
+                Ok(auth_context) => auth_context,
+                Err(err) => {
+                    Self::reject(control_frame, err.clone().into(), timeout).await;
+                    return Err(err.into());
+                }
+            }
+        } else if auth_mode != AuthMode::Secret {
+            let (rejection, err) = mode_required(auth_mode);
+            Self::reject(control_frame, rejection, timeout).await;
+            return Err(err);
+        } else {
+            match authenticate_within(timeout, authenticator.verify_peer(hello, peer)).await? {
+                Ok(auth_context) => auth_context,
+                Err(err) => {
+                    Self::reject(control_frame, err.clone().into(), timeout).await;
+                    return Err(err.into());
+                }
+            }
+        };
+
+        let response = protocol::ServerAuthResponse::Accepted(protocol::ServerCapabilities {
+            version,
+            features: capabilities.features.intersection(SERVER_FEATURES),
+        });
+        Self::send_auth_response(control_frame, &response, timeout).await?;
+
+        Ok(auth_context)
+    }
+
+    /// Challenges a client using challenge-response, public-key or token
+    /// authentication and verifies its answer.
+    async fn verify_challenge<A: Authenticator<C>>(
+        connection: &C,
+        hello: &protocol::ClientHello,
+        authenticator: &A,
+        auth_mode: AuthMode,
+        timeout: Duration,
+        control_frame: &mut Framed<&mut <C as Connection>::Stream, codec::LengthDelimitedCodec>,
+    ) -> io::Result<A::AuthContext>
+    where
+        C: Connection,
+    {
+        let mut keying_material = [0u8; 32];
+        if let Err(err) = connection.export_keying_material(
+            &mut keying_material,
+            protocol::CHALLENGE_EXPORTER_LABEL,
+            &[],
+        ) {
+            let rejection = protocol::AuthRejection::Other(
+                "challenge authentication is not supported by this transport".to_string(),
+            );
+            Self::reject(control_frame, rejection, timeout).await;
+            return Err(err);
+        }
+
+        let nonce: [u8; 32] = rand::random();
+        let challenge = protocol::ServerAuthResponse::Challenge(protocol::AuthChallenge { nonce });
+        Self::send_auth_response(control_frame, &challenge, timeout).await?;
+
+        let payload = tokio::time::timeout(timeout, control_frame.next())
+            .await
+            .map_err(|_| {
+                client_timeout(format!(
+                    "authentication timeout: failed to receive proof within {:?}",
+                    timeout
+                ))
+            })?
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed before proof")
+            })??;
+
+        let verification = match protocol::decode(&payload) {
+            Ok(message) if !mode_accepts(auth_mode, &message) => {
+                let (rejection, err) = mode_required(auth_mode);
+                Self::reject(control_frame, rejection, timeout).await;
+                return Err(err);
+            }
+            Ok(codec::ClientMessage::KeyProof(proof)) => {
+                let signed = protocol::SignedChallenge {
+                    nonce,
+                    keying_material,
+                    public_key: PublicKey::from_bytes(proof.public_key),
+                    signature: proof.signature,
+                };
+                authenticate_within(timeout, authenticator.verify_signature(hello, &signed)).await?
+            }
+            Ok(codec::ClientMessage::Token(token)) => {
+                let token = String::from_utf8_lossy(&token.token);
+                authenticate_within(timeout, authenticator.verify_token(hello, &token)).await?
+            }
+            Ok(codec::ClientMessage::AuthProof(proof)) => {
+                let response = protocol::ChallengeResponse {
+                    nonce,
+                    keying_material,
+                    mac: proof.mac,
+                };
+                authenticate_within(timeout, authenticator.verify_challenge(hello, &response))
+                    .await?
+            }
+            _ => {
+                Self::reject(control_frame, protocol::AuthRejection::Malformed, timeout).await;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "authentication failed: invalid message type (expected an answer to the challenge)",
+                ));
+            }
+        };
+
+        match verification {
+            Ok(auth_context) => Ok(auth_context),
+            Err(err) => {
+                Self::reject(control_frame, err.clone().into(), timeout).await;
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Verifies a client predating capability negotiation.
+    async fn verify_legacy_authentication<A: Authenticator<C>>(
         hello: &protocol::ClientHello,
+        peer: &Peer,
         authenticator: &A,
+        auth_mode: AuthMode,
         timeout: Duration,
         control_frame: &mut Framed<&mut <C as Connection>::Stream, codec::LengthDelimitedCodec>,
     ) -> io::Result<A::AuthContext>
     where
         C: Connection,
     {
+        if !matches!(auth_mode, AuthMode::Secret | AuthMode::Certificate) {
+            Self::handle_auth_failure(control_frame).await;
+            return Err(mode_required(auth_mode).1);
+        }
+
         // Check protocol version
         if hello.version != protocol::PROTOCOL_VERSION {
             Self::handle_auth_failure(control_frame).await;
@@ -174,35 +432,38 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         }
 
         // Perform authentication with timeout
-        let auth_context = tokio::time::timeout(timeout, authenticator.verify(hello)).await??;
+        let verification = if auth_mode == AuthMode::Certificate {
+            authenticate_within(timeout, authenticator.verify_certificate(hello, peer)).await?
+        } else {
+            authenticate_within(timeout, authenticator.verify_peer(hello, peer)).await?
+        };
+        let auth_context = verification?;
 
-        Self::send_auth_ok_response(control_frame, timeout).await?;
+        Self::send_auth_response(control_frame, &protocol::ServerAuthResponse::Ok, timeout).await?;
 
         Ok(auth_context)
     }
 
     /// Sends authentication response with timeout.
-    async fn send_auth_ok_response(
+    async fn send_auth_response(
         control_frame: &mut Framed<&mut <C as Connection>::Stream, codec::LengthDelimitedCodec>,
+        response: &protocol::ServerAuthResponse,
         timeout: Duration,
     ) -> io::Result<()>
     where
         C: Connection,
     {
-        tokio::time::timeout(
-            timeout,
-            control_frame.send(protocol::encode(&protocol::ServerAuthResponse::Ok)?),
-        )
-        .await
-        .map_err(|_| {
-            io::Error::new(
-                io::ErrorKind::TimedOut,
-                format!(
-                    "authentication timeout: failed to send response within {:?}",
-                    timeout
-                ),
-            )
-        })??;
+        tokio::time::timeout(timeout, control_frame.send(protocol::encode(response)?))
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "authentication timeout: failed to send response within {:?}",
+                        timeout
+                    ),
+                )
+            })??;
         Ok(())
     }
 
@@ -217,12 +478,36 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         Self::disconnect_with_random_delay(*stream).await;
     }
 
+    /// Tells a client advertising capabilities why it is refused, then
+    /// disconnects it.
+    ///
+    /// The reply is sent after the same random delay as a silent disconnect,
+    /// so timing still doesn't tell the failure modes apart.
+    async fn reject(
+        control_frame: &mut Framed<&mut <C as Connection>::Stream, codec::LengthDelimitedCodec>,
+        rejection: protocol::AuthRejection,
+        timeout: Duration,
+    ) where
+        C: Connection,
+    {
+        Self::random_delay().await;
+        let response = protocol::ServerAuthResponse::Rejected(rejection);
+        let _ = Self::send_auth_response(control_frame, &response, timeout).await;
+        let stream = control_frame.get_mut();
+        let _ = tokio::io::AsyncWriteExt::shutdown(*stream).await;
+    }
+
     /// Disconnects the stream with a random delay to prevent timing attacks.
     ///
     /// This function introduces a random delay (100-500ms) before closing the stream,
     /// making it harder for attackers to distinguish between different failure modes
     /// based on response timing.
     async fn disconnect_with_random_delay(stream: &mut C::Stream) {
+        Self::random_delay().await;
+        let _ = tokio::io::AsyncWriteExt::shutdown(stream).await;
+    }
+
+    async fn random_delay() {
         use rand::RngExt;
 
         let delay_ms = {
@@ -231,7 +516,6 @@ impl<C: Connection> ClientConnectionProcessor<C> {
         };
 
         tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
-        let _ = tokio::io::AsyncWriteExt::shutdown(stream).await;
     }
 
     /// Runs the tunnel loops for streams and datagrams until the connection closes.
@@ -264,7 +548,15 @@ impl<C: Connection> ClientConnectionProcessor<C> {
 
         let connection = Arc::clone(&self.transport_connection);
         let shutdown = self.shutdown_token.child_token();
-        let tunnel = StreamTunnel::new(connection, shutdown, self.metrics.clone());
+        let tunnel = StreamTunnel::new(
+            connection,
+            shutdown,
+            Arc::clone(&self.config),
+            Arc::clone(&self.dns),
+            self.outbound.clone(),
+            Arc::clone(&self.policy),
+            self.metrics.clone(),
+        );
 
         #[cfg(not(feature = "tracing"))]
         let handle = tokio::spawn(tunnel.accept_loop());
@@ -280,7 +572,15 @@ impl<C: Connection> ClientConnectionProcessor<C> {
 
         let connection = Arc::clone(&self.transport_connection);
         let shutdown = self.shutdown_token.child_token();
-        let tunnel = DatagramTunnel::new(connection, shutdown, self.metrics.clone());
+        let tunnel = DatagramTunnel::new(
+            connection,
+            shutdown,
+            Arc::clone(&self.config),
+            Arc::clone(&self.dns),
+            self.outbound.clone(),
+            Arc::clone(&self.policy),
+            self.metrics.clone(),
+        );
 
         #[cfg(not(feature = "tracing"))]
         let handle = tokio::spawn(tunnel.accept_loop());
@@ -320,7 +620,10 @@ pub struct ConnectionAcceptor<T, A> {
     authenticator: Arc<A>,
     connection_semaphore: Arc<Semaphore>,
     config: Arc<ConnectionConfig>,
+    dns: Arc<DnsResolver>,
+    outbound: Outbound,
     metrics: Metrics,
+    bans: Option<Arc<BanList>>,
 }
 
 impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<T, A> {
@@ -343,10 +646,47 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
             authenticator: Arc::new(authenticator),
             connection_semaphore: Arc::new(Semaphore::new(max_connections)),
             config,
+            dns: Arc::new(DnsResolver::default()),
+            outbound: Outbound::default(),
             metrics: Metrics::new(),
+            bans: None,
         }
     }
 
+    /// Replaces the upstream DNS resolver.
+    ///
+    /// By default the system resolver configuration is used. The resolver and
+    /// its cache are shared by every connection accepted by this acceptor.
+    pub fn with_dns_resolver(mut self, dns: DnsResolver) -> Self {
+        self.dns = Arc::new(dns);
+        self
+    }
+
+    /// Replaces how upstream destinations are reached.
+    ///
+    /// By default destinations are dialed directly. Clients are still
+    /// authenticated by this acceptor whatever the outbound is.
+    pub fn with_outbound(mut self, outbound: Outbound) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// Replaces the metrics counters are incremented in, e.g. to share them
+    /// with a [`BanList`].
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Refuses connections from banned addresses and records the
+    /// authentication failures of the others in `bans`.
+    ///
+    /// By default nobody is banned.
+    pub fn with_ban_list(mut self, bans: Arc<BanList>) -> Self {
+        self.bans = Some(bans);
+        self
+    }
+
     /// Returns a clone-able handle to runtime metrics.
     ///
     /// Counters are incremented as connections/streams flow through this acceptor;
@@ -355,6 +695,11 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
         self.metrics.clone()
     }
 
+    /// Returns the list of banned addresses, if banning is enabled.
+    pub fn ban_list(&self) -> Option<Arc<BanList>> {
+        self.bans.clone()
+    }
+
     /// Main accept loop that accepts incoming connections and manages them with resource limits.
     ///
     /// This method will:
@@ -369,13 +714,7 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
                     break;
                 },
                 accepted = self.acceptor.accept() => {
-                    Self::handle_incoming_connection(
-                        accepted,
-                        Arc::clone(&self.authenticator),
-                        Arc::clone(&self.connection_semaphore),
-                        Arc::clone(&self.config),
-                        self.metrics.clone(),
-                    );
+                    self.handle_incoming_connection(accepted);
                 },
             }
         }
@@ -384,55 +723,62 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
     }
 
     /// Handles an incoming connection, either spawning a processor or rejecting it.
-    fn handle_incoming_connection(
-        result: io::Result<<T as Acceptor>::Connection>,
-        authenticator: Arc<A>,
-        semaphore: Arc<Semaphore>,
-        config: Arc<ConnectionConfig>,
-        metrics: Metrics,
-    ) {
-        match result {
-            Ok(connection) => match semaphore.try_acquire_owned() {
-                Ok(permit) => {
-                    metrics
-                        .counters()
-                        .connections_accepted
-                        .fetch_add(1, Ordering::Relaxed);
-                    #[cfg(not(feature = "tracing"))]
-                    tokio::spawn(Self::process_connection_with_permit(
-                        connection,
-                        authenticator,
-                        permit,
-                        config,
-                        metrics,
-                    ));
-                    #[cfg(feature = "tracing")]
-                    tokio::spawn(
-                        Self::process_connection_with_permit(
-                            connection,
-                            authenticator,
-                            permit,
-                            config,
-                            metrics,
-                        )
-                        .in_current_span(),
-                    );
-                }
-                Err(_) => {
-                    metrics
-                        .counters()
-                        .connections_rejected
-                        .fetch_add(1, Ordering::Relaxed);
-                    warn!(
-                        "connection rejected: maximum concurrent connections ({}) reached",
-                        config.max_connections()
-                    );
-                }
-            },
+    fn handle_incoming_connection(&self, result: io::Result<<T as Acceptor>::Connection>) {
+        let connection = match result {
+            Ok(connection) => connection,
             Err(err) => {
                 error!("failed to accept connection: {}", err);
+                return;
             }
+        };
+
+        let remote_ip = connection.remote_address().ok().map(|addr| addr.ip());
+        if let (Some(bans), Some(ip)) = (&self.bans, remote_ip)
+            && !bans.admit(ip)
+        {
+            debug!("connection refused: {} is banned", ip);
+            connection.close(0, b"");
+            return;
         }
+
+        let Ok(permit) = Arc::clone(&self.connection_semaphore).try_acquire_owned() else {
+            self.metrics
+                .counters()
+                .connections_rejected
+                .fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "connection rejected: maximum concurrent connections ({}) reached",
+                self.config.max_connections()
+            );
+            return;
+        };
+        self.metrics
+            .counters()
+            .connections_accepted
+            .fetch_add(1, Ordering::Relaxed);
+
+        let process = Self::process_connection_with_permit(
+            connection,
+            Arc::clone(&self.authenticator),
+            permit,
+            Arc::clone(&self.config),
+            Arc::clone(&self.dns),
+            self.outbound.clone(),
+            self.metrics.clone(),
+        );
+        let bans = self.bans.clone();
+        let task = async move {
+            let result = process.await;
+            if let (Err(err), Some(bans), Some(ip)) = (result, bans, remote_ip)
+                && is_client_failure(&err)
+            {
+                bans.record_failure(ip);
+            }
+        };
+        #[cfg(not(feature = "tracing"))]
+        tokio::spawn(task);
+        #[cfg(feature = "tracing")]
+        tokio::spawn(task.in_current_span());
     }
 
     /// Processes a connection with a semaphore permit.
@@ -443,10 +789,12 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
         authenticator: Arc<A>,
         _permit: OwnedSemaphorePermit,
         config: Arc<ConnectionConfig>,
+        dns: Arc<DnsResolver>,
+        outbound: Outbound,
         metrics: Metrics,
-    ) {
+    ) -> io::Result<()> {
         // Permit is held for the lifetime of this function
-        Self::process_connection(connection, authenticator, config, metrics).await;
+        Self::process_connection(connection, authenticator, config, dns, outbound, metrics).await
         // Permit is automatically released when dropped
     }
 
@@ -466,18 +814,26 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
         connection: <T as Acceptor>::Connection,
         authenticator: Arc<A>,
         config: Arc<ConnectionConfig>,
+        dns: Arc<DnsResolver>,
+        outbound: Outbound,
         metrics: Metrics,
-    ) {
+    ) -> io::Result<()> {
         #[cfg(feature = "tracing")]
         if let Ok(addr) = connection.remote_address() {
             tracing::Span::current().record("from", tracing::field::display(addr));
         }
 
-        let _result =
-            ClientConnectionProcessor::handle(connection, authenticator.as_ref(), config, &metrics)
-                .await;
+        let result = ClientConnectionProcessor::handle(
+            connection,
+            authenticator.as_ref(),
+            config,
+            dns,
+            outbound,
+            &metrics,
+        )
+        .await;
 
-        if _result.is_err() {
+        if result.is_err() {
             metrics
                 .counters()
                 .connections_auth_failed
@@ -485,16 +841,18 @@ impl<T: Acceptor, A: Authenticator<T::Connection> + 'static> ConnectionAcceptor<
         }
 
         #[cfg(feature = "tracing")]
-        match _result {
+        match &result {
             Ok(_) => {
                 tracing::Span::current().record("reason", "ok");
                 tracing::info!("connection closed");
             }
             Err(e) => {
-                tracing::Span::current().record("reason", tracing::field::display(&e));
+                tracing::Span::current().record("reason", tracing::field::display(e));
                 tracing::error!(error = %e, "connection closed with error");
             }
         }
+
+        result
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
@@ -516,6 +874,47 @@ impl<C: Connection> ConnectionHandle<C> {
     }
 }
 
+/// What the server knows about a client before it authenticates.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Peer {
+    /// Address the client connects from.
+    pub remote_address: Option<SocketAddr>,
+    /// DER certificate chain the client presented in mTLS mode, leaf first.
+    pub certificate_chain: Vec<Bytes>,
+    /// Identity of the client's certificate, in mTLS mode.
+    pub certificate: Option<PeerCertificate>,
+    /// SHA-256 fingerprint of the client's certificate, in mTLS mode.
+    pub certificate_fingerprint: Option<[u8; 32]>,
+}
+
+impl Peer {
+    /// Describes the client at the other end of `connection`.
+    pub fn from_connection<C: Connection>(connection: &C) -> Self {
+        let certificate_chain = connection.peer_certificates();
+        let leaf = certificate_chain.first();
+        let certificate = leaf.and_then(|leaf| match PeerCertificate::parse(leaf) {
+            Ok(certificate) => Some(certificate),
+            Err(_err) => {
+                warn!("failed to parse the client certificate: {}", _err);
+                None
+            }
+        });
+        let certificate_fingerprint = leaf.map(|leaf| {
+            let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, leaf);
+            digest
+                .as_ref()
+                .try_into()
+                .expect("SHA-256 digests are 32 bytes")
+        });
+        Self {
+            remote_address: connection.remote_address().ok(),
+            certificate,
+            certificate_fingerprint,
+            certificate_chain,
+        }
+    }
+}
+
 /// Authentication error types returned by the server during authentication.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConnectionAuthError {
@@ -523,12 +922,45 @@ pub enum ConnectionAuthError {
     IncompatibleVersion,
     /// Authentication secret is invalid or incorrect.
     InvalidSecret,
+    /// The client's key isn't authorized or its signature is invalid.
+    UnauthorizedKey,
+    /// The client's token wasn't issued by this server or is malformed.
+    InvalidToken,
+    /// The client's token is past its expiry.
+    TokenExpired,
+    /// The client's token was revoked.
+    TokenRevoked,
+    /// The client presented no certificate.
+    CertificateRequired,
     /// Internal server error during authentication processing.
     ServerError,
     /// Other error
     Other(String),
 }
 
+impl From<ConnectionAuthError> for protocol::AuthRejection {
+    fn from(value: ConnectionAuthError) -> Self {
+        match value {
+            ConnectionAuthError::IncompatibleVersion => {
+                protocol::AuthRejection::IncompatibleVersion {
+                    min_version: protocol::MIN_PROTOCOL_VERSION,
+                    max_version: protocol::PROTOCOL_VERSION,
+                }
+            }
+            ConnectionAuthError::InvalidSecret => protocol::AuthRejection::InvalidSecret,
+            ConnectionAuthError::UnauthorizedKey => protocol::AuthRejection::UnauthorizedKey,
+            ConnectionAuthError::InvalidToken => protocol::AuthRejection::InvalidToken,
+            ConnectionAuthError::TokenExpired => protocol::AuthRejection::TokenExpired,
+            ConnectionAuthError::TokenRevoked => protocol::AuthRejection::TokenRevoked,
+            ConnectionAuthError::CertificateRequired => {
+                protocol::AuthRejection::CertificateRequired
+            }
+            ConnectionAuthError::ServerError => protocol::AuthRejection::ServerError,
+            ConnectionAuthError::Other(msg) => protocol::AuthRejection::Other(msg),
+        }
+    }
+}
+
 impl From<ConnectionAuthError> for io::Error {
     fn from(value: ConnectionAuthError) -> Self {
         match value {
@@ -539,6 +971,24 @@ impl From<ConnectionAuthError> for io::Error {
                 io::ErrorKind::PermissionDenied,
                 "invalid authentication secret",
             ),
+            ConnectionAuthError::UnauthorizedKey => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "public key is not authorized",
+            ),
+            ConnectionAuthError::InvalidToken => {
+                io::Error::new(io::ErrorKind::PermissionDenied, "invalid access token")
+            }
+            ConnectionAuthError::TokenExpired => {
+                io::Error::new(io::ErrorKind::PermissionDenied, "access token has expired")
+            }
+            ConnectionAuthError::TokenRevoked => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "access token has been revoked",
+            ),
+            ConnectionAuthError::CertificateRequired => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "client certificate required",
+            ),
             ConnectionAuthError::ServerError => io::Error::new(
                 io::ErrorKind::ConnectionAborted,
                 "internal server error during auth",
@@ -548,6 +998,96 @@ impl From<ConnectionAuthError> for io::Error {
     }
 }
 
+/// Whether `auth_mode` lets a client answer the challenge with `message`.
+///
+/// The strict modes only take their own credential: a token is a bearer
+/// credential that could be replayed, and every client of a certificate
+/// mode server must hold a certificate. Other messages are left to be
+/// refused as malformed.
+fn mode_accepts(auth_mode: AuthMode, message: &codec::ClientMessage) -> bool {
+    let open = matches!(auth_mode, AuthMode::Secret | AuthMode::Challenge);
+    match message {
+        codec::ClientMessage::AuthProof(_) => open,
+        codec::ClientMessage::KeyProof(_) => open || auth_mode == AuthMode::PublicKey,
+        codec::ClientMessage::Token(_) => open || auth_mode == AuthMode::Token,
+        _ => true,
+    }
+}
+
+/// Why a client is refused for not using the way of authenticating
+/// `auth_mode` requires.
+fn mode_required(auth_mode: AuthMode) -> (protocol::AuthRejection, io::Error) {
+    match auth_mode {
+        AuthMode::PublicKey => (
+            protocol::AuthRejection::PublicKeyRequired,
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "public-key authentication required",
+            ),
+        ),
+        AuthMode::Token => (
+            protocol::AuthRejection::TokenRequired,
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "token authentication required",
+            ),
+        ),
+        AuthMode::Certificate => (
+            protocol::AuthRejection::CertificateRequired,
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "certificate authentication required",
+            ),
+        ),
+        _ => (
+            protocol::AuthRejection::ChallengeRequired,
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "challenge-response authentication required",
+            ),
+        ),
+    }
+}
+
+/// A client failing to send its hello or its proof in time.
+#[derive(Debug)]
+struct ClientTimeout(String);
+
+impl fmt::Display for ClientTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ClientTimeout {}
+
+fn client_timeout(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, ClientTimeout(message))
+}
+
+/// Runs a check of the authenticator, which is the server's own fault if it
+/// takes too long, e.g. because of a slow auth hook.
+async fn authenticate_within<F: Future>(timeout: Duration, check: F) -> io::Result<F::Output> {
+    tokio::time::timeout(timeout, check).await.map_err(|_| {
+        io::Error::other(format!(
+            "authentication timeout: authenticator did not decide within {:?}",
+            timeout
+        ))
+    })
+}
+
+/// Whether a connection failed because of what the client sent, rather than
+/// because of the server or the network. Only those failures lead to bans.
+fn is_client_failure(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied | io::ErrorKind::InvalidData => true,
+        io::ErrorKind::TimedOut => err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ClientTimeout>()),
+        _ => false,
+    }
+}
+
 /// Authenticator trait for verifying and accepting client connections.
 ///
 /// This trait provides authentication logic for incoming connections.
@@ -567,6 +1107,108 @@ pub trait Authenticator<T>: Send + Sync {
         hello: &protocol::ClientHello,
     ) -> impl Future<Output = Result<Self::AuthContext, ConnectionAuthError>> + Send;
 
+    /// Verifies the client's hello knowing who is connecting.
+    ///
+    /// The server calls this rather than `verify`, so implementations
+    /// deciding on the client's address or certificate override it. The
+    /// default ignores `peer`.
+    fn verify_peer(
+        &self,
+        hello: &protocol::ClientHello,
+        _peer: &Peer,
+    ) -> impl Future<Output = Result<Self::AuthContext, ConnectionAuthError>> + Send {
+        self.verify(hello)
+    }
+
+    /// Verifies a client using challenge-response authentication.
+    ///
+    /// Such clients don't send their secret in the hello; `response` holds
+    /// the nonce they were challenged with, the keying material exported
+    /// from this connection and their MAC over both, which can be checked
+    /// with [`protocol::ChallengeResponse::verify`]. The default refuses
+    /// every such client.
+    fn verify_challenge(
+        &self,
+        _hello: &protocol::ClientHello,
+        _response: &protocol::ChallengeResponse,
+    ) -> impl Future<Output = Result<Self::AuthContext, ConnectionAuthError>> + Send {
+        async {
+            Err(ConnectionAuthError::Other(
+                "challenge-response authentication is not supported".to_string(),
+            ))
+        }
+    }
+
+    /// Verifies a client using public-key authentication.
+    ///
+    /// `signed` holds the nonce the client was challenged with, the keying
+    /// material exported from this connection, the client's public key and
+    /// its signature, which can be checked with
+    /// [`protocol::SignedChallenge::verify`]. The default refuses every such
+    /// client.
+    fn verify_signature(
+        &self,
+        _hello: &protocol::ClientHello,
+        _signed: &protocol::SignedChallenge,
+    ) -> impl Future<Output = Result<Self::AuthContext, ConnectionAuthError>> + Send {
+        async {
+            Err(ConnectionAuthError::Other(
+                "public-key authentication is not supported".to_string(),
+            ))
+        }
+    }
+
+    /// Verifies a client by the certificate it presented, with the
+    /// `certificate` auth mode.
+    ///
+    /// `peer.certificate` was already verified against the CA by the TLS
+    /// handshake, and is `None` if the client presented none. The secret
+    /// in `hello` isn't checked. The default refuses every client.
+    fn verify_certificate(
+        &self,
+        _hello: &protocol::ClientHello,
+        _peer: &Peer,
+    ) -> impl Future<Output = Result<Self::AuthContext, ConnectionAuthError>> + Send {
+        async {
+            Err(ConnectionAuthError::Other(
+                "certificate authentication is not supported".to_string(),
+            ))
+        }
+    }
+
+    /// Verifies a client using token authentication.
+    ///
+    /// `token` is the access token the client answered the challenge with,
+    /// see [`ombrac::token`]. The default refuses every such client.
+    fn verify_token(
+        &self,
+        _hello: &protocol::ClientHello,
+        _token: &str,
+    ) -> impl Future<Output = Result<Self::AuthContext, ConnectionAuthError>> + Send {
+        async {
+            Err(ConnectionAuthError::Other(
+                "token authentication is not supported".to_string(),
+            ))
+        }
+    }
+
+    /// Returns who the client authenticated with `auth_context` is, which
+    /// keys per-client state such as its IPv6 source with the `user` prefix
+    /// scope.
+    ///
+    /// The default can't tell clients apart and names them all alike, as
+    /// the options of the hello are chosen by the client itself, so
+    /// implementations knowing who authenticated should override it.
+    fn user(&self, _hello: &protocol::ClientHello, _auth_context: &Self::AuthContext) -> Bytes {
+        Bytes::new()
+    }
+
+    /// Returns the limits the client authenticated with `auth_context` is
+    /// held to. The default has none.
+    fn limits(&self, _auth_context: &Self::AuthContext) -> ClientLimits {
+        ClientLimits::default()
+    }
+
     /// Called after successful authentication to handle the accepted connection.
     ///
     /// This method is called with the authentication context from `verify` and
@@ -590,5 +1232,17 @@ impl<T: Send + Sync> Authenticator<T> for ombrac::protocol::Secret {
         }
     }
 
+    async fn verify_challenge(
+        &self,
+        _hello: &protocol::ClientHello,
+        response: &protocol::ChallengeResponse,
+    ) -> Result<(), ConnectionAuthError> {
+        if response.verify(self) {
+            Ok(())
+        } else {
+            Err(ConnectionAuthError::InvalidSecret)
+        }
+    }
+
     async fn accept(&self, _auth_context: Self::AuthContext, _connection: ConnectionHandle<T>) {}
 }
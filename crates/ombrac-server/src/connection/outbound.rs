@@ -0,0 +1,207 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use ombrac::protocol::Address;
+#[cfg(feature = "chain")]
+use ombrac_client::client::Client;
+#[cfg(feature = "chain")]
+use ombrac_client::connection::BufferedStream;
+#[cfg(all(feature = "chain", feature = "datagram"))]
+use ombrac_client::connection::UdpSession;
+#[cfg(feature = "chain")]
+use ombrac_transport::Connection;
+#[cfg(feature = "chain")]
+use ombrac_transport::quic::Connection as QuicConnection;
+#[cfg(feature = "chain")]
+use ombrac_transport::quic::client::Client as QuicClient;
+
+use crate::config::IpFamily;
+use crate::connection::proxy::ProxyServer;
+use crate::connection::{Dialer, DnsResolver, happy_eyeballs, icmp};
+
+/// Client connection to the next hop of a server chain.
+#[cfg(feature = "chain")]
+pub type ChainClient = Client<QuicClient, QuicConnection>;
+
+#[cfg(all(feature = "chain", feature = "datagram"))]
+pub(crate) type ChainUdpSession = UdpSession<QuicClient, QuicConnection>;
+
+#[cfg(feature = "chain")]
+type ChainStream = BufferedStream<<QuicConnection as Connection>::Stream>;
+
+/// How the tunnels of accepted connections reach upstream destinations.
+///
+/// Clients are always authenticated by this server; the outbound only
+/// decides where their streams, UDP sessions, DNS queries and pings leave
+/// from.
+#[derive(Clone)]
+pub enum Outbound {
+    /// Destinations are dialed from this server.
+    Direct(Dialer),
+    /// Destinations are reached through another ombrac server, which sees
+    /// every relayed connection as coming from this server.
+    #[cfg(feature = "chain")]
+    Chain(Arc<ChainClient>),
+    /// Streams go through a SOCKS5 proxy, and UDP sessions through its
+    /// `UDP ASSOCIATE` relay.
+    Socks5(Arc<ProxyServer>),
+    /// Streams go through an HTTP proxy with `CONNECT`. UDP can't be
+    /// relayed this way, so UDP sessions are refused.
+    HttpConnect(Arc<ProxyServer>),
+}
+
+impl Default for Outbound {
+    fn default() -> Self {
+        Outbound::Direct(Dialer::default())
+    }
+}
+
+impl Outbound {
+    /// Returns the outbound of one client connection, identified by the
+    /// user it authenticated as.
+    pub(crate) fn for_client(&self, user: &[u8]) -> Self {
+        match self {
+            Outbound::Direct(dialer) => Outbound::Direct(dialer.for_client(user)),
+            outbound => outbound.clone(),
+        }
+    }
+
+    /// Opens a TCP connection to `destination`.
+    ///
+    /// Domains are resolved to every address allowed by `ip_family` and
+    /// dialed with Happy Eyeballs (RFC 8305) when connecting directly. A next
+    /// hop or proxy receives them unresolved and resolves them itself.
+    pub(crate) async fn connect(
+        &self,
+        destination: &Address,
+        dns: &DnsResolver,
+        ip_family: IpFamily,
+    ) -> io::Result<UpstreamStream> {
+        match self {
+            Outbound::Direct(dialer) => {
+                let addrs = match destination {
+                    Address::SocketV4(addr) => vec![SocketAddr::V4(*addr)],
+                    Address::SocketV6(addr) => vec![SocketAddr::V6(*addr)],
+                    Address::Domain(domain, port) => {
+                        dns.resolve_all(domain, *port, ip_family).await?
+                    }
+                };
+                happy_eyeballs::connect(dialer, &addrs, happy_eyeballs::CONNECTION_ATTEMPT_DELAY)
+                    .await
+                    .map(UpstreamStream::Direct)
+            }
+            #[cfg(feature = "chain")]
+            Outbound::Chain(client) => client
+                .open_bidirectional(destination.clone())
+                .await
+                .map(UpstreamStream::Chain),
+            Outbound::Socks5(proxy) => proxy
+                .socks5_connect(destination)
+                .await
+                .map(UpstreamStream::Direct),
+            Outbound::HttpConnect(proxy) => proxy
+                .http_connect(destination)
+                .await
+                .map(UpstreamStream::Direct),
+        }
+    }
+
+    /// Answers a wire-format DNS query with the local resolver, or forwards
+    /// it to the next hop.
+    ///
+    /// Proxies can't carry these, so the local resolver answers them; its
+    /// nameservers must then be reachable, e.g. as `tcp://` through the
+    /// proxy's network.
+    pub(crate) async fn dns_query(&self, dns: &DnsResolver, message: Bytes) -> io::Result<Bytes> {
+        match self {
+            Outbound::Direct(_) | Outbound::Socks5(_) | Outbound::HttpConnect(_) => {
+                dns.answer(&message).await.map(Bytes::from)
+            }
+            #[cfg(feature = "chain")]
+            Outbound::Chain(client) => client.dns_query(message).await,
+        }
+    }
+
+    /// Pings `address` from this server, or from the next hop.
+    ///
+    /// Proxies can't carry ICMP, so pings leave from this server when one is
+    /// used.
+    pub(crate) async fn echo(
+        &self,
+        dns: &DnsResolver,
+        ip_family: IpFamily,
+        address: &Address,
+        data: Bytes,
+    ) -> io::Result<()> {
+        match self {
+            Outbound::Direct(_) | Outbound::Socks5(_) | Outbound::HttpConnect(_) => {
+                let addr = match address {
+                    Address::SocketV4(addr) => SocketAddr::V4(*addr),
+                    Address::SocketV6(addr) => SocketAddr::V6(*addr),
+                    Address::Domain(domain, port) => dns.resolve(domain, *port, ip_family).await?,
+                };
+                icmp::echo(addr.ip(), &data, icmp::ECHO_TIMEOUT).await
+            }
+            #[cfg(feature = "chain")]
+            Outbound::Chain(client) => client.echo(address.clone(), data).await,
+        }
+    }
+}
+
+/// An upstream TCP connection opened by [`Outbound::connect`].
+pub(crate) enum UpstreamStream {
+    Direct(TcpStream),
+    #[cfg(feature = "chain")]
+    Chain(ChainStream),
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Direct(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "chain")]
+            UpstreamStream::Chain(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Direct(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "chain")]
+            UpstreamStream::Chain(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Direct(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "chain")]
+            UpstreamStream::Chain(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Direct(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "chain")]
+            UpstreamStream::Chain(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,537 @@
+//! Client side of the SOCKS5 ([RFC 1928]) and HTTP CONNECT ([RFC 9110]
+//! section 9.3.6) protocols, used to reach destinations through an upstream
+//! proxy.
+//!
+//! Destinations are always handed to the proxy unresolved, so domains are
+//! resolved by the proxy.
+//!
+//! [RFC 1928]: https://datatracker.ietf.org/doc/html/rfc1928
+//! [RFC 9110]: https://datatracker.ietf.org/doc/html/rfc9110
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use ombrac::protocol::Address;
+
+/// Upper bound for the SOCKS5 handshake of a UDP association.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Upper bound for the header of an HTTP CONNECT response.
+const MAX_RESPONSE_HEADER_SIZE: usize = 8192;
+
+const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NO_AUTHENTICATION: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NO_ACCEPTABLE: u8 = 0xFF;
+const AUTH_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// An upstream proxy server.
+#[derive(Clone)]
+pub struct ProxyServer {
+    /// Address of the proxy, as `HOST:PORT`.
+    pub address: String,
+    /// Username, enabling authentication when set.
+    pub username: Option<String>,
+    /// Password, empty when unset.
+    pub password: Option<String>,
+}
+
+impl ProxyServer {
+    /// Opens a TCP connection to `destination` with a SOCKS5 `CONNECT`.
+    pub(crate) async fn socks5_connect(&self, destination: &Address) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        self.socks5_handshake(&mut stream).await?;
+        socks5_request(&mut stream, CMD_CONNECT, destination).await?;
+        Ok(stream)
+    }
+
+    /// Sets up a SOCKS5 `UDP ASSOCIATE` relay.
+    #[cfg_attr(not(feature = "datagram"), allow(dead_code))]
+    pub(crate) async fn socks5_udp_associate(&self) -> io::Result<Socks5UdpRelay> {
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            let mut control = TcpStream::connect(&self.address).await?;
+            self.socks5_handshake(&mut control).await?;
+
+            let proxy_ip = control.peer_addr()?.ip();
+            let unspecified = match proxy_ip {
+                std::net::IpAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                std::net::IpAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            };
+            let socket = UdpSocket::bind(unspecified).await?;
+
+            // The client address is unknown before the socket is used, so
+            // the unspecified address is sent as RFC 1928 allows.
+            let mut relay =
+                socks5_request(&mut control, CMD_UDP_ASSOCIATE, &Address::from(unspecified))
+                    .await?;
+            // Proxies commonly answer with an unspecified address meaning
+            // "the address you reached me at".
+            if relay.ip().is_unspecified() {
+                relay.set_ip(proxy_ip);
+            }
+
+            Ok(Socks5UdpRelay {
+                control,
+                socket,
+                relay,
+            })
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "socks5 handshake timeout"))?
+    }
+
+    /// Opens a TCP connection to `destination` with an HTTP `CONNECT`.
+    pub(crate) async fn http_connect(&self, destination: &Address) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+
+        let authority = match destination {
+            Address::SocketV6(addr) => format!("[{}]:{}", addr.ip(), addr.port()),
+            _ => destination.to_string(),
+        };
+        let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+        if let Some(username) = &self.username {
+            let credentials = format!("{}:{}", username, self.password.as_deref().unwrap_or(""));
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                STANDARD.encode(credentials)
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte so nothing the destination sends right after the
+        // response header is consumed here.
+        let mut header = Vec::with_capacity(128);
+        while !header.ends_with(b"\r\n\r\n") {
+            if header.len() >= MAX_RESPONSE_HEADER_SIZE {
+                return Err(invalid_data("http proxy response header too large"));
+            }
+            header.push(stream.read_u8().await?);
+        }
+
+        let status = parse_http_status(&header)?;
+        match status {
+            200..=299 => Ok(stream),
+            407 => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "http proxy authentication required",
+            )),
+            403 => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "http proxy refused the connection",
+            )),
+            502 | 503 => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("http proxy failed to connect to {destination}: {status}"),
+            )),
+            504 => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("http proxy timed out connecting to {destination}"),
+            )),
+            _ => Err(io::Error::other(format!(
+                "http proxy answered with status {status}"
+            ))),
+        }
+    }
+
+    /// Negotiates the authentication method and authenticates if required.
+    async fn socks5_handshake(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let greeting: &[u8] = match self.username {
+            Some(_) => &[
+                SOCKS_VERSION,
+                2,
+                METHOD_NO_AUTHENTICATION,
+                METHOD_USERNAME_PASSWORD,
+            ],
+            None => &[SOCKS_VERSION, 1, METHOD_NO_AUTHENTICATION],
+        };
+        stream.write_all(greeting).await?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS_VERSION {
+            return Err(invalid_data("unexpected socks version from proxy"));
+        }
+
+        match (reply[1], &self.username) {
+            (METHOD_NO_AUTHENTICATION, _) => Ok(()),
+            (METHOD_USERNAME_PASSWORD, Some(username)) => {
+                let password = self.password.as_deref().unwrap_or("");
+                if username.len() > 255 || password.len() > 255 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "socks5 username and password are limited to 255 bytes",
+                    ));
+                }
+
+                let mut request = BytesMut::with_capacity(3 + username.len() + password.len());
+                request.put_u8(AUTH_VERSION);
+                request.put_u8(username.len() as u8);
+                request.put_slice(username.as_bytes());
+                request.put_u8(password.len() as u8);
+                request.put_slice(password.as_bytes());
+                stream.write_all(&request).await?;
+
+                let mut reply = [0u8; 2];
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0x00 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "socks5 proxy rejected the credentials",
+                    ));
+                }
+                Ok(())
+            }
+            (METHOD_NO_ACCEPTABLE, _) | (METHOD_USERNAME_PASSWORD, None) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "socks5 proxy accepts none of the offered authentication methods",
+            )),
+            _ => Err(invalid_data("socks5 proxy selected an unknown method")),
+        }
+    }
+}
+
+/// A SOCKS5 UDP association.
+///
+/// The association lasts as long as the control connection, which must be
+/// kept open while the relay is used.
+#[cfg_attr(not(feature = "datagram"), allow(dead_code))]
+pub(crate) struct Socks5UdpRelay {
+    pub(crate) control: TcpStream,
+    pub(crate) socket: UdpSocket,
+    pub(crate) relay: SocketAddr,
+}
+
+/// Wraps `data` in the SOCKS5 UDP request header.
+#[cfg_attr(not(feature = "datagram"), allow(dead_code))]
+pub(crate) fn encode_socks5_udp(address: &Address, data: &[u8]) -> Bytes {
+    let mut packet = BytesMut::with_capacity(3 + 1 + 255 + 2 + data.len());
+    // RSV and FRAG; fragmentation is never used
+    packet.put_slice(&[0, 0, 0]);
+    encode_address(address, &mut packet);
+    packet.put_slice(data);
+    packet.freeze()
+}
+
+/// Splits a SOCKS5 UDP datagram into its source address and payload.
+///
+/// Fragmented datagrams are rejected; proxies that fragment are rare and
+/// reassembly isn't worth it for them.
+#[cfg_attr(not(feature = "datagram"), allow(dead_code))]
+pub(crate) fn decode_socks5_udp(packet: &[u8]) -> io::Result<(Address, &[u8])> {
+    match packet {
+        [0, 0, 0, rest @ ..] => decode_address(rest),
+        [0, 0, _, ..] => Err(invalid_data("fragmented socks5 udp datagram")),
+        _ => Err(invalid_data("malformed socks5 udp datagram")),
+    }
+}
+
+/// Sends a SOCKS5 request and returns the bound address of the reply.
+async fn socks5_request(
+    stream: &mut TcpStream,
+    command: u8,
+    address: &Address,
+) -> io::Result<SocketAddr> {
+    let mut request = BytesMut::with_capacity(3 + 1 + 255 + 2);
+    request.put_slice(&[SOCKS_VERSION, command, 0x00]);
+    encode_address(address, &mut request);
+    stream.write_all(&request).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[0] != SOCKS_VERSION {
+        return Err(invalid_data("unexpected socks version from proxy"));
+    }
+    if head[1] != 0x00 {
+        return Err(reply_error(head[1], address));
+    }
+
+    let bound = match head[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            let port = stream.read_u16().await?;
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            let port = stream.read_u16().await?;
+            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(ip), port, 0, 0))
+        }
+        ATYP_DOMAIN => {
+            // A bound domain is useless to us, but must still be consumed.
+            let len = stream.read_u8().await? as usize;
+            let mut buf = vec![0u8; len + 2];
+            stream.read_exact(&mut buf).await?;
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        }
+        _ => return Err(invalid_data("unknown address type in socks5 reply")),
+    };
+
+    Ok(bound)
+}
+
+/// Maps a SOCKS5 reply code to the error a direct dial would have returned.
+fn reply_error(code: u8, address: &Address) -> io::Error {
+    let (kind, reason) = match code {
+        0x02 => (io::ErrorKind::PermissionDenied, "not allowed by ruleset"),
+        0x03 => (io::ErrorKind::NetworkUnreachable, "network unreachable"),
+        0x04 => (io::ErrorKind::HostUnreachable, "host unreachable"),
+        0x05 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (io::ErrorKind::TimedOut, "ttl expired"),
+        0x07 => (io::ErrorKind::Unsupported, "command not supported"),
+        0x08 => (io::ErrorKind::Unsupported, "address type not supported"),
+        _ => (io::ErrorKind::Other, "general failure"),
+    };
+    io::Error::new(
+        kind,
+        format!("socks5 proxy failed to reach {address}: {reason}"),
+    )
+}
+
+fn encode_address(address: &Address, dst: &mut BytesMut) {
+    match address {
+        Address::SocketV4(addr) => {
+            dst.put_u8(ATYP_IPV4);
+            dst.put_slice(&addr.ip().octets());
+            dst.put_u16(addr.port());
+        }
+        Address::SocketV6(addr) => {
+            dst.put_u8(ATYP_IPV6);
+            dst.put_slice(&addr.ip().octets());
+            dst.put_u16(addr.port());
+        }
+        Address::Domain(domain, port) => {
+            // The protocol guarantees domains of at most 255 bytes
+            dst.put_u8(ATYP_DOMAIN);
+            dst.put_u8(domain.len() as u8);
+            dst.put_slice(domain);
+            dst.put_u16(*port);
+        }
+    }
+}
+
+fn decode_address(buf: &[u8]) -> io::Result<(Address, &[u8])> {
+    let truncated = || invalid_data("truncated socks5 address");
+    match buf {
+        [ATYP_IPV4, rest @ ..] => {
+            let (ip, rest) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+            let (port, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+            let addr = SocketAddrV4::new(Ipv4Addr::from(*ip), u16::from_be_bytes(*port));
+            Ok((Address::SocketV4(addr), rest))
+        }
+        [ATYP_IPV6, rest @ ..] => {
+            let (ip, rest) = rest.split_first_chunk::<16>().ok_or_else(truncated)?;
+            let (port, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+            let addr = SocketAddrV6::new(Ipv6Addr::from(*ip), u16::from_be_bytes(*port), 0, 0);
+            Ok((Address::SocketV6(addr), rest))
+        }
+        [ATYP_DOMAIN, len, rest @ ..] => {
+            let len = *len as usize;
+            if rest.len() < len + 2 {
+                return Err(truncated());
+            }
+            let domain = Bytes::copy_from_slice(&rest[..len]);
+            let port = u16::from_be_bytes([rest[len], rest[len + 1]]);
+            Ok((Address::Domain(domain, port), &rest[len + 2..]))
+        }
+        _ => Err(invalid_data("unknown address type in socks5 datagram")),
+    }
+}
+
+/// Extracts the status code from an HTTP/1.x response header.
+fn parse_http_status(header: &[u8]) -> io::Result<u16> {
+    let line = header.split(|&b| b == b'\r').next().unwrap_or_default();
+    let line = std::str::from_utf8(line).map_err(|_| invalid_data("invalid http response"))?;
+    let mut parts = line.split_ascii_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status
+            .parse()
+            .map_err(|_| invalid_data("invalid http status code")),
+        _ => Err(invalid_data("invalid http response")),
+    }
+}
+
+#[inline]
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socks5_udp_roundtrip() {
+        for address in [
+            Address::from("1.2.3.4:53".parse::<SocketAddr>().unwrap()),
+            Address::from("[2001:db8::1]:443".parse::<SocketAddr>().unwrap()),
+            Address::from(("example.com", 8080)),
+        ] {
+            let packet = encode_socks5_udp(&address, b"payload");
+            let (decoded, data) = decode_socks5_udp(&packet).unwrap();
+            assert_eq!(decoded, address);
+            assert_eq!(data, b"payload");
+        }
+    }
+
+    #[test]
+    fn socks5_udp_rejects_fragments_and_truncation() {
+        assert!(decode_socks5_udp(&[0, 0, 1, ATYP_IPV4, 1, 2, 3, 4, 0, 53]).is_err());
+        assert!(decode_socks5_udp(&[0, 0, 0, ATYP_IPV4, 1, 2, 3]).is_err());
+        assert!(decode_socks5_udp(&[0, 0, 0, ATYP_DOMAIN, 5, b'a']).is_err());
+    }
+
+    #[test]
+    fn http_status_is_parsed() {
+        assert_eq!(
+            parse_http_status(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap(),
+            200
+        );
+        assert_eq!(
+            parse_http_status(b"HTTP/1.0 407 Auth\r\n\r\n").unwrap(),
+            407
+        );
+        assert!(parse_http_status(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_authenticates_and_sends_domain() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyServer {
+            address: listener.local_addr().unwrap().to_string(),
+            username: Some("user".into()),
+            password: Some("pass".into()),
+        };
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 4];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            stream.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = [0u8; 11];
+            stream.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 4 + 1 + 11 + 2];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..5], &[5, CMD_CONNECT, 0, ATYP_DOMAIN, 11]);
+            assert_eq!(&request[5..16], b"example.com");
+            stream
+                .write_all(&[5, 0, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1F, 0x90])
+                .await
+                .unwrap();
+            stream.write_all(b"hello").await.unwrap();
+        });
+
+        let mut stream = proxy
+            .socks5_connect(&Address::from(("example.com", 443)))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_reply_code_maps_to_error_kind() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyServer {
+            address: listener.local_addr().unwrap().to_string(),
+            username: None,
+            password: None,
+        };
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            stream
+                .write_all(&[5, 0x05, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        let err = proxy
+            .socks5_connect(&Address::from("10.0.0.1:80".parse::<SocketAddr>().unwrap()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn http_connect_keeps_early_data_and_sends_credentials() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyServer {
+            address: listener.local_addr().unwrap().to_string(),
+            username: Some("user".into()),
+            password: Some("pass".into()),
+        };
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.starts_with("CONNECT [2001:db8::1]:443 HTTP/1.1\r\n"));
+            assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nbanner")
+                .await
+                .unwrap();
+        });
+
+        let destination = Address::from("[2001:db8::1]:443".parse::<SocketAddr>().unwrap());
+        let mut stream = proxy.http_connect(&destination).await.unwrap();
+        let mut buf = [0u8; 6];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"banner");
+    }
+
+    #[tokio::test]
+    async fn http_connect_maps_refusal() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyServer {
+            address: listener.local_addr().unwrap().to_string(),
+            username: None,
+            password: None,
+        };
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 16];
+            let _ = stream.read(&mut buf).await;
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let err = proxy
+            .http_connect(&Address::from(("example.com", 80)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
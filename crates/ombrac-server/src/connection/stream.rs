@@ -1,12 +1,10 @@
 use std::io;
-use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
 
 use futures::{SinkExt, StreamExt};
 use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
 use tokio::sync::Semaphore;
 use tokio_util::codec::Framed;
 use tokio_util::sync::CancellationToken;
@@ -19,7 +17,10 @@ use ombrac_macros::{debug, info};
 use ombrac_transport::Connection;
 use ombrac_transport::io::{CopyBidirectionalStats, copy_bidirectional, is_clean_stream_close};
 
-use crate::connection::dns;
+use crate::config::{ConnectionConfig, IpFamily};
+use crate::connection::DnsResolver;
+use crate::connection::limits::{ClientPolicy, Throttled};
+use crate::connection::outbound::{Outbound, UpstreamStream};
 
 const MAX_CONCURRENT_CONNECTIONS: usize = 4096;
 const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
@@ -29,15 +30,31 @@ pub(crate) struct StreamTunnel<C: Connection> {
     connection: Arc<C>,
     shutdown: CancellationToken,
     semaphore: Arc<Semaphore>,
+    config: Arc<ConnectionConfig>,
+    dns: Arc<DnsResolver>,
+    outbound: Outbound,
+    policy: Arc<ClientPolicy>,
     metrics: Metrics,
 }
 
 impl<C: Connection> StreamTunnel<C> {
-    pub(crate) fn new(connection: Arc<C>, shutdown: CancellationToken, metrics: Metrics) -> Self {
+    pub(crate) fn new(
+        connection: Arc<C>,
+        shutdown: CancellationToken,
+        config: Arc<ConnectionConfig>,
+        dns: Arc<DnsResolver>,
+        outbound: Outbound,
+        policy: Arc<ClientPolicy>,
+        metrics: Metrics,
+    ) -> Self {
         Self {
             connection,
             shutdown,
             semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_CONNECTIONS)),
+            config,
+            dns,
+            outbound,
+            policy,
             metrics,
         }
     }
@@ -52,6 +69,10 @@ impl<C: Connection> StreamTunnel<C> {
                     let semaphore = Arc::clone(&self.semaphore);
                     let shutdown = self.shutdown.child_token();
                     let metrics = self.metrics.clone();
+                    let ip_family = self.config.ip_family();
+                    let dns = Arc::clone(&self.dns);
+                    let outbound = self.outbound.clone();
+                    let policy = Arc::clone(&self.policy);
 
                     let future = async move {
                         // Acquire semaphore permit to limit concurrent connections
@@ -69,7 +90,7 @@ impl<C: Connection> StreamTunnel<C> {
                             .fetch_add(1, Ordering::Relaxed);
 
                         let mut guard = StreamGuard::default();
-                        let result = Self::handle_connect(stream, &mut guard, shutdown).await;
+                        let result = Self::handle_connect(stream, &mut guard, &dns, &outbound, &policy, ip_family, shutdown).await;
 
                         if let Err(e) = result {
                             metrics
@@ -110,37 +131,60 @@ impl<C: Connection> StreamTunnel<C> {
     pub(crate) async fn handle_connect(
         mut stream: C::Stream,
         guard: &mut StreamGuard,
+        dns: &DnsResolver,
+        outbound: &Outbound,
+        policy: &Arc<ClientPolicy>,
+        ip_family: IpFamily,
         shutdown: CancellationToken,
     ) -> io::Result<()> {
         let mut framed = Framed::new(&mut stream, codec::length_codec());
 
         // Step 1: Read the connection request from the client (with timeout)
-        let destination = Self::read_connect_message(&mut framed).await?;
+        let destination = match Self::read_request(&mut framed).await? {
+            codec::ClientMessage::Connect(connect) => connect.address,
+            codec::ClientMessage::DnsQuery(query) => {
+                return Self::answer_dns_query(&mut framed, dns, outbound, query).await;
+            }
+            codec::ClientMessage::Echo(echo) => {
+                return Self::answer_echo(&mut framed, dns, outbound, policy, ip_family, echo)
+                    .await;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected connect, dns query or echo message",
+                ));
+            }
+        };
         guard.destination = Some(destination.clone());
 
-        // Step 2: Attempt to connect to the destination (with timeout)
-        let connect_result = Self::connect_to_destination(&destination).await;
+        // Step 2: Attempt to connect to the destination (with timeout), if
+        // the client may reach it
+        let connect_result = match policy.check(&destination) {
+            Ok(()) => Self::connect_to_destination(&destination, dns, outbound, ip_family).await,
+            Err(e) => Err(e),
+        };
 
         // Step 3: Send connection response to client
         // This must happen before we proceed, so the client knows the connection status
         Self::send_connect_response(&mut framed, &connect_result).await?;
 
         // Step 4: If connection failed, return error (client has already been notified)
-        let mut tcp_stream = connect_result?;
+        let mut upstream = Throttled::new(connect_result?, Arc::clone(policy));
 
         // Step 5: Exchange data between client and destination
         // Note: This phase has no timeout as it's the normal data transfer phase
         // that can last for the entire lifetime of the TCP connection.
         // Graceful shutdown is supported via tokio::select!
-        Self::exchange_data(framed, &mut tcp_stream, guard, shutdown).await
+        Self::exchange_data(framed, &mut upstream, guard, shutdown).await
     }
 
-    /// Reads the connect message from the client.
+    /// Reads the request message that opens a stream from the client.
     ///
     /// This function includes a timeout to prevent hanging on unresponsive clients.
-    async fn read_connect_message(
+    async fn read_request(
         framed: &mut Framed<&mut C::Stream, codec::LengthDelimitedCodec>,
-    ) -> io::Result<protocol::Address> {
+    ) -> io::Result<codec::ClientMessage> {
         let payload = tokio::time::timeout(HANDSHAKE_TIMEOUT, framed.next())
             .await
             .map_err(|_| {
@@ -150,33 +194,89 @@ impl<C: Connection> StreamTunnel<C> {
                 io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed unexpectedly")
             })??;
 
-        match protocol::decode(&payload)? {
-            codec::ClientMessage::Connect(connect) => Ok(connect.address),
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "expected connect message",
-            )),
-        }
+        protocol::decode(&payload)
     }
 
-    /// Attempts to connect to the destination address with a timeout.
+    /// Answers a DNS query with the shared resolver, or the next hop of a
+    /// server chain, and finishes the stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query is malformed or the response cannot be
+    /// sent; the client sees the stream close without a response.
+    async fn answer_dns_query(
+        framed: &mut Framed<&mut C::Stream, codec::LengthDelimitedCodec>,
+        dns: &DnsResolver,
+        outbound: &Outbound,
+        query: protocol::ClientDnsQuery,
+    ) -> io::Result<()> {
+        let message = outbound.dns_query(dns, query.message).await?;
+        let response = codec::ServerMessage::DnsResponse(protocol::ServerDnsResponse { message });
+        framed.send(protocol::encode(&response)?).await?;
+        framed.get_mut().shutdown().await
+    }
+
+    /// Pings the requested destination and reports the outcome.
     ///
     /// # Errors
     ///
-    /// Returns an error if DNS resolution fails or the connection times out.
-    async fn connect_to_destination(destination: &protocol::Address) -> io::Result<TcpStream> {
-        let addr = match destination {
-            protocol::Address::SocketV4(addr) => SocketAddr::V4(*addr),
-            protocol::Address::SocketV6(addr) => SocketAddr::V6(*addr),
-            protocol::Address::Domain(domain, port) => {
-                // Use shared DNS resolver for DNS resolution
-                dns::resolve_domain(domain, *port).await?
+    /// Returns an error if the response cannot be sent; a failed ping is
+    /// reported to the client instead.
+    async fn answer_echo(
+        framed: &mut Framed<&mut C::Stream, codec::LengthDelimitedCodec>,
+        dns: &DnsResolver,
+        outbound: &Outbound,
+        policy: &ClientPolicy,
+        ip_family: IpFamily,
+        echo: protocol::ClientEcho,
+    ) -> io::Result<()> {
+        let result = match policy.check(&echo.address) {
+            Ok(()) => {
+                outbound
+                    .echo(dns, ip_family, &echo.address, echo.data)
+                    .await
             }
+            Err(e) => Err(e),
         };
 
-        tokio::time::timeout(UPSTREAM_CONNECT_TIMEOUT, TcpStream::connect(addr))
-            .await
-            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timeout"))?
+        let response = match result {
+            Ok(()) => protocol::ServerEchoResponse::Ok,
+            Err(e) => {
+                debug!("echo to {} failed: {}", echo.address, e);
+                protocol::ServerEchoResponse::Err {
+                    kind: protocol::ConnectErrorKind::from_io_error(&e),
+                    message: e.to_string(),
+                }
+            }
+        };
+        let response = codec::ServerMessage::EchoResponse(response);
+        framed.send(protocol::encode(&response)?).await?;
+        framed.get_mut().shutdown().await
+    }
+
+    /// Attempts to connect to the destination address with a timeout.
+    ///
+    /// When dialing directly, domains are resolved to every address allowed
+    /// by `ip_family` and dialed with Happy Eyeballs (RFC 8305), so a broken
+    /// route for one family falls back to the other instead of running into
+    /// the timeout. A next hop receives the destination unresolved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if DNS resolution fails, every connection attempt
+    /// fails, the next hop refuses the connection, or the connection times out.
+    async fn connect_to_destination(
+        destination: &protocol::Address,
+        dns: &DnsResolver,
+        outbound: &Outbound,
+        ip_family: IpFamily,
+    ) -> io::Result<UpstreamStream> {
+        tokio::time::timeout(
+            UPSTREAM_CONNECT_TIMEOUT,
+            outbound.connect(destination, dns, ip_family),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timeout"))?
     }
 
     /// Sends the connection response to the client.
@@ -184,7 +284,7 @@ impl<C: Connection> StreamTunnel<C> {
     /// This function sends either a success or error response based on the connection result.
     async fn send_connect_response(
         framed: &mut Framed<&mut C::Stream, codec::LengthDelimitedCodec>,
-        connect_result: &io::Result<TcpStream>,
+        connect_result: &io::Result<UpstreamStream>,
     ) -> io::Result<()> {
         let response = match connect_result {
             Ok(_) => {
@@ -210,13 +310,13 @@ impl<C: Connection> StreamTunnel<C> {
         Ok(())
     }
 
-    /// Exchanges data between the client stream and the destination TCP stream.
+    /// Exchanges data between the client stream and the upstream connection.
     ///
     /// This function uses tokio::select! to listen for shutdown signals during
     /// data exchange, allowing graceful shutdown of active connections.
     async fn exchange_data(
         framed: Framed<&mut C::Stream, codec::LengthDelimitedCodec>,
-        tcp_stream: &mut TcpStream,
+        upstream: &mut Throttled<UpstreamStream>,
         guard: &mut StreamGuard,
         shutdown: CancellationToken,
     ) -> io::Result<()> {
@@ -226,7 +326,7 @@ impl<C: Connection> StreamTunnel<C> {
 
         // Write any buffered upstream data to the destination
         if !parts.read_buf.is_empty() {
-            tcp_stream.write_all(&parts.read_buf).await?;
+            upstream.write_all(&parts.read_buf).await?;
         }
 
         // Perform bidirectional data copying with graceful shutdown support
@@ -243,7 +343,7 @@ impl<C: Connection> StreamTunnel<C> {
                     "connection closed due to active closure"
                 ))
             }
-            result = copy_bidirectional(&mut stream_inner, tcp_stream) => {
+            result = copy_bidirectional(&mut stream_inner, upstream) => {
                 match result {
                     Ok(stats) => {
                         guard.stats = Some(stats);
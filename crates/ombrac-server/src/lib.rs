@@ -7,5 +7,5 @@ pub mod logging;
 pub mod service;
 
 // Re-export commonly used types for convenience
-pub use config::{ConnectionConfig, ServiceConfig, TransportConfig};
+pub use config::{ConnectionConfig, DnsConfig, OutboundConfig, ServiceConfig, TransportConfig};
 pub use service::{Error as ServiceError, OmbracServer, Result as ServiceResult};
@@ -1,23 +1,37 @@
 use std::io;
-use std::net::UdpSocket;
+use std::net::{IpAddr, UdpSocket};
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
+use ipnet::IpNet;
 use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
 
+use ombrac::key::{PrivateKey, parse_authorized_keys};
 use ombrac::metrics::Metrics;
-use ombrac::protocol::Secret;
+use ombrac::token::parse_revocation_list;
+#[cfg(feature = "chain")]
+use ombrac_client::client::Client;
 use ombrac_macros::{error, info, warn};
 use ombrac_transport::quic::TransportConfig as QuicTransportConfig;
+#[cfg(feature = "chain")]
+use ombrac_transport::quic::client::Client as QuicClient;
+#[cfg(feature = "chain")]
+use ombrac_transport::quic::client::Config as QuicClientConfig;
 use ombrac_transport::quic::error::Error as QuicError;
 use ombrac_transport::quic::server::Config as QuicConfig;
 use ombrac_transport::quic::server::Server as QuicServer;
+use ombrac_transport::quic::server::{HandshakeRejection, IncomingFilter, RejectionListener};
 
-use crate::config::{ServiceConfig, TlsMode};
-use crate::connection::ConnectionAcceptor;
+use crate::config::{AuthMode, OutboundMode, ServiceConfig, TlsMode, TransportConfig};
+use crate::connection::{
+    AuthHook, BanList, ConnectionAcceptor, Credentials, Dialer, DialerOptions, DnsResolver,
+    HookEndpoint, Ipv6Prefix, Outbound, ProxyServer,
+};
 
-type BuiltAcceptor = ConnectionAcceptor<QuicServer, Secret>;
+type BuiltAcceptor = ConnectionAcceptor<QuicServer, Credentials>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -62,6 +76,8 @@ macro_rules! require_config {
 ///     listen: "0.0.0.0:8080".parse()?,
 ///     transport: Default::default(),
 ///     connection: Default::default(),
+///     dns: Default::default(),
+///     outbound: Default::default(),
 ///     logging: Default::default(),
 /// });
 ///
@@ -75,6 +91,7 @@ pub struct OmbracServer {
     handle: JoinHandle<Result<()>>,
     shutdown_tx: broadcast::Sender<()>,
     metrics: Metrics,
+    bans: Option<Arc<BanList>>,
     // Held to keep the QUIC endpoint alive after the accept loop exits, so
     // `shutdown_with_drain` can wait for in-flight streams without the
     // underlying transport being torn down.
@@ -86,9 +103,10 @@ impl OmbracServer {
     ///
     /// This method:
     /// 1. Creates a QUIC server from the transport configuration
-    /// 2. Sets up connection validation using the secret
-    /// 3. Spawns the accept loop in a background task
-    /// 4. Returns an OmbracServer handle for lifecycle management
+    /// 2. Sets up connection validation using the secret and authorized keys
+    /// 3. Connects to the next hop if the outbound chains servers
+    /// 4. Spawns the accept loop in a background task
+    /// 5. Returns an OmbracServer handle for lifecycle management
     ///
     /// # Arguments
     ///
@@ -99,20 +117,35 @@ impl OmbracServer {
     /// A configured `OmbracServer` instance ready to accept connections, or an error
     /// if configuration is invalid or server setup fails.
     pub async fn build(config: Arc<ServiceConfig>) -> Result<Self> {
+        // Banned addresses are refused by the QUIC server before their
+        // handshake, and their failures are recorded by the acceptor
+        let metrics = Metrics::new();
+        let bans = ban_list_from_config(&config, metrics.clone())?;
+
         // Build QUIC server from config
-        let acceptor = quic_server_from_config(&config).await?;
+        let acceptor = quic_server_from_config(&config, bans.clone(), metrics.clone()).await?;
+
+        // Create authenticator from the secret and the authorized keys
+        let credentials = credentials_from_config(&config)?;
 
-        // Create secret authenticator from config
-        let secret = *blake3::hash(config.secret.as_bytes()).as_bytes();
+        // Create upstream DNS resolver from the dns section
+        let dns = DnsResolver::new(&config.dns).map_err(|e| Error::Config(e.to_string()))?;
+
+        // Connect to the next hop before accepting clients, so a chained
+        // server never falls back to dialing destinations directly
+        let outbound = outbound_from_config(&config).await?;
 
         // Create connection acceptor with connection config
         let connection_config = Arc::new(config.connection.clone());
-        let acceptor = Arc::new(ConnectionAcceptor::with_config(
-            acceptor,
-            secret,
-            connection_config,
-        ));
-        let metrics = acceptor.metrics();
+        let mut acceptor =
+            ConnectionAcceptor::with_config(acceptor, credentials, connection_config)
+                .with_dns_resolver(dns)
+                .with_outbound(outbound)
+                .with_metrics(metrics.clone());
+        if let Some(bans) = &bans {
+            acceptor = acceptor.with_ban_list(Arc::clone(bans));
+        }
+        let acceptor = Arc::new(acceptor);
 
         // Set up shutdown channel
         let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
@@ -133,6 +166,7 @@ impl OmbracServer {
             handle,
             shutdown_tx,
             metrics,
+            bans,
             _acceptor_keepalive: acceptor,
         })
     }
@@ -145,6 +179,15 @@ impl OmbracServer {
         self.metrics.clone()
     }
 
+    /// Returns the addresses banned for failing to authenticate, or `None`
+    /// if banning is disabled.
+    ///
+    /// Bans can be listed with `BanList::bans` and lifted early with
+    /// `BanList::unban`.
+    pub fn ban_list(&self) -> Option<Arc<BanList>> {
+        self.bans.clone()
+    }
+
     /// Gracefully shuts down the server.
     ///
     /// This method will:
@@ -163,6 +206,8 @@ impl OmbracServer {
     /// #     listen: "0.0.0.0:0".parse()?,
     /// #     transport: Default::default(),
     /// #     connection: Default::default(),
+    /// #     dns: Default::default(),
+    /// #     outbound: Default::default(),
     /// #     logging: Default::default(),
     /// # });
     /// # let server = OmbracServer::build(config).await?;
@@ -228,13 +273,40 @@ impl OmbracServer {
     }
 }
 
-async fn quic_server_from_config(config: &ServiceConfig) -> Result<QuicServer> {
+async fn quic_server_from_config(
+    config: &ServiceConfig,
+    bans: Option<Arc<BanList>>,
+    metrics: Metrics,
+) -> Result<QuicServer> {
     let transport_cfg = &config.transport;
     let mut quic_config = QuicConfig::new();
 
+    quic_config.incoming_filter =
+        bans.map(|bans| IncomingFilter::new(move |address| bans.admit(address.ip())));
+    quic_config.retry_threshold =
+        Some(transport_cfg.handshake_retry_threshold()).filter(|&n| n > 0);
+    quic_config.max_handshakes = Some(transport_cfg.max_handshakes()).filter(|&n| n > 0);
+    quic_config.max_handshakes_per_ip =
+        Some(transport_cfg.max_handshakes_per_ip()).filter(|&n| n > 0);
+    quic_config.rejection_listener = Some(RejectionListener::new(move |rejection| {
+        let counters = metrics.counters();
+        let counter = match rejection {
+            HandshakeRejection::Retried => &counters.handshakes_retried,
+            HandshakeRejection::TooManyHandshakes => &counters.handshakes_refused,
+            HandshakeRejection::TooManyHandshakesFromAddress => &counters.handshakes_refused_per_ip,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }));
+
     quic_config.enable_zero_rtt = transport_cfg.zero_rtt();
     quic_config.alpn_protocols = transport_cfg.alpn_protocols();
 
+    if transport_cfg.crl.is_some() && transport_cfg.tls_mode() != TlsMode::MTls {
+        return Err(Error::Config(
+            "'transport.crl' requires the m-tls mode".to_string(),
+        ));
+    }
+
     match transport_cfg.tls_mode() {
         TlsMode::Tls => {
             let cert_path = require_config!(transport_cfg.tls_cert.clone(), "transport.tls_cert")?;
@@ -253,6 +325,10 @@ async fn quic_server_from_config(config: &ServiceConfig) -> Result<QuicServer> {
                 transport_cfg.ca_cert.clone(),
                 "transport.ca_cert for mTLS"
             )?);
+            quic_config.crl_paths = transport_cfg.crl();
+            quic_config.allow_unknown_revocation_status = transport_cfg.crl_allow_unknown();
+            quic_config.crl_reload_interval =
+                Duration::from_secs(transport_cfg.crl_reload_secs().max(1));
         }
         TlsMode::Insecure => {
             warn!(
@@ -277,6 +353,247 @@ async fn quic_server_from_config(config: &ServiceConfig) -> Result<QuicServer> {
         }
     }
 
+    quic_config.transport_config(quic_transport_config(transport_cfg)?);
+
+    info!("binding udp socket to {}", config.listen);
+    let socket = UdpSocket::bind(config.listen)?;
+
+    QuicServer::new(socket, quic_config)
+        .await
+        .map_err(Error::Quic)
+}
+
+fn ban_list_from_config(config: &ServiceConfig, metrics: Metrics) -> Result<Option<Arc<BanList>>> {
+    let connection_cfg = &config.connection;
+    if connection_cfg.auth_ban_failures() == 0 {
+        return Ok(None);
+    }
+
+    let mut allowlist = Vec::new();
+    for value in connection_cfg.auth_ban_allowlist.iter().flatten() {
+        let network = value
+            .parse::<IpNet>()
+            .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+            .map_err(|_| {
+                Error::Config(format!(
+                    "invalid address in 'connection.auth_ban_allowlist': {value}"
+                ))
+            })?;
+        allowlist.push(network);
+    }
+
+    let bans = BanList::new(metrics)
+        .with_max_failures(connection_cfg.auth_ban_failures())
+        .with_window(Duration::from_secs(connection_cfg.auth_ban_window_secs()))
+        .with_ban_duration(
+            Duration::from_secs(connection_cfg.auth_ban_secs()),
+            Duration::from_secs(connection_cfg.auth_ban_max_secs()),
+        )
+        .with_allowlist(allowlist);
+    Ok(Some(Arc::new(bans)))
+}
+
+fn credentials_from_config(config: &ServiceConfig) -> Result<Credentials> {
+    let connection_cfg = &config.connection;
+    let mut credentials = if !config.secret.is_empty() {
+        Credentials::new(*blake3::hash(config.secret.as_bytes()).as_bytes())
+    } else if connection_cfg.requires_secret() {
+        return Err(Error::Config(
+            "'secret' is required with the challenge auth mode, or the secret auth mode without an auth hook"
+                .to_string(),
+        ));
+    } else {
+        Credentials::without_secret()
+    };
+
+    if connection_cfg.auth_mode() == AuthMode::PublicKey && connection_cfg.authorized_keys.is_none()
+    {
+        return Err(Error::Config(
+            "'connection.authorized_keys' is required with the public-key auth mode".to_string(),
+        ));
+    }
+
+    if connection_cfg.auth_mode() == AuthMode::Token && connection_cfg.token_key.is_none() {
+        return Err(Error::Config(
+            "'connection.token_key' is required with the token auth mode".to_string(),
+        ));
+    }
+
+    if connection_cfg.auth_mode() == AuthMode::Certificate
+        && config.transport.tls_mode() != TlsMode::MTls
+    {
+        return Err(Error::Config(
+            "'transport.tls_mode' must be m-tls with the certificate auth mode".to_string(),
+        ));
+    }
+
+    if let Some(path) = &connection_cfg.authorized_keys {
+        let keys =
+            parse_authorized_keys(&read_config_file(path, "authorized keys")?).map_err(|e| {
+                Error::Config(format!("invalid authorized keys {}: {e}", path.display()))
+            })?;
+        info!("loaded {} authorized keys", keys.len());
+        credentials = credentials.with_authorized_keys(keys);
+    }
+
+    if let Some(path) = &connection_cfg.token_key {
+        let key = PrivateKey::load(path)
+            .map_err(|e| Error::Config(format!("invalid token key {}: {e}", path.display())))?;
+        credentials = credentials.with_token_key(key.public_key());
+    }
+
+    if let Some(path) = &connection_cfg.revoked_tokens {
+        let revoked = parse_revocation_list(&read_config_file(path, "revoked tokens")?);
+        info!("loaded {} revoked tokens", revoked.len());
+        credentials = credentials.with_revoked_tokens(revoked);
+    }
+
+    if let Some(url) = &connection_cfg.auth_hook {
+        if connection_cfg.auth_mode() == AuthMode::Challenge {
+            return Err(Error::Config(
+                "'connection.auth_hook' can't check clients in the challenge auth mode".to_string(),
+            ));
+        }
+        let endpoint: HookEndpoint = url
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid 'connection.auth_hook': {e}")))?;
+        let hook = AuthHook::new(endpoint)
+            .with_timeout(Duration::from_millis(connection_cfg.auth_hook_timeout()))
+            .with_cache_ttl(Duration::from_secs(connection_cfg.auth_hook_cache_secs()))
+            .with_fail_open(connection_cfg.auth_hook_fail_open());
+        info!("clients sending a secret are checked by the auth hook {url}");
+        credentials = credentials.with_hook(hook);
+    }
+
+    Ok(credentials)
+}
+
+fn read_config_file(path: &Path, what: &str) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("failed to read {what} {}: {e}", path.display())))
+}
+
+async fn outbound_from_config(config: &ServiceConfig) -> Result<Outbound> {
+    let outbound_cfg = &config.outbound;
+
+    match outbound_cfg.mode() {
+        OutboundMode::Direct => Ok(Outbound::Direct(dialer_from_config(config)?)),
+        #[cfg(feature = "chain")]
+        OutboundMode::Ombrac => {
+            let server = require_config!(outbound_cfg.server.as_deref(), "outbound.server")?;
+            let secret = require_config!(outbound_cfg.secret.as_ref(), "outbound.secret")?;
+            let secret = *blake3::hash(secret.as_bytes()).as_bytes();
+            let transport = quic_client_from_config(config, server).await?;
+
+            info!("connecting to next hop {}", server);
+            let client = Client::new(transport, secret, None).await?;
+            Ok(Outbound::Chain(Arc::new(client)))
+        }
+        #[cfg(not(feature = "chain"))]
+        OutboundMode::Ombrac => Err(Error::Config(
+            "outbound mode 'ombrac' requires the 'chain' feature".to_string(),
+        )),
+        OutboundMode::Socks5 => Ok(Outbound::Socks5(proxy_from_config(config)?)),
+        OutboundMode::HttpConnect => Ok(Outbound::HttpConnect(proxy_from_config(config)?)),
+    }
+}
+
+fn dialer_from_config(config: &ServiceConfig) -> Result<Dialer> {
+    let outbound_cfg = &config.outbound;
+
+    let ipv6_prefix = match &outbound_cfg.ipv6_prefix {
+        Some(prefix) => Some(
+            prefix
+                .parse::<Ipv6Prefix>()
+                .map_err(|e| Error::Config(format!("invalid outbound.ipv6_prefix: {e}")))?,
+        ),
+        None => None,
+    };
+
+    Ok(Dialer::new(DialerOptions {
+        bind_ipv4: outbound_cfg.bind_ipv4,
+        bind_ipv6: outbound_cfg.bind_ipv6,
+        interface: outbound_cfg.interface.clone(),
+        mark: outbound_cfg.mark,
+        ipv6_prefix,
+        ipv6_prefix_scope: outbound_cfg.ipv6_prefix_scope(),
+    }))
+}
+
+fn proxy_from_config(config: &ServiceConfig) -> Result<Arc<ProxyServer>> {
+    let outbound_cfg = &config.outbound;
+    let server = require_config!(outbound_cfg.server.as_deref(), "outbound.server")?;
+
+    info!("dialing destinations through proxy {}", server);
+    Ok(Arc::new(ProxyServer {
+        address: server.to_string(),
+        username: outbound_cfg.username.clone(),
+        password: outbound_cfg.password.clone(),
+    }))
+}
+
+/// Builds the QUIC client towards the next hop of a server chain.
+///
+/// The ALPN, idle timeout, keep-alive and congestion settings of the
+/// `transport` section apply to this connection as well.
+#[cfg(feature = "chain")]
+async fn quic_client_from_config(config: &ServiceConfig, server: &str) -> Result<QuicClient> {
+    let outbound_cfg = &config.outbound;
+
+    let server_name = match &outbound_cfg.server_name {
+        Some(value) => value.clone(),
+        None => {
+            let pos = server.rfind(':').ok_or_else(|| {
+                Error::Config(format!("invalid outbound server address: {}", server))
+            })?;
+            server[..pos].to_string()
+        }
+    };
+
+    let server_addr = tokio::net::lookup_host(server)
+        .await?
+        .next()
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "failed to resolve outbound server address: '{}'",
+                server
+            ))
+        })?;
+
+    let mut quic_config = QuicClientConfig::new(server_addr, server_name);
+    quic_config.alpn_protocols = config.transport.alpn_protocols();
+
+    match outbound_cfg.tls_mode() {
+        TlsMode::Tls => {
+            quic_config.root_ca_path = outbound_cfg.ca_cert.clone();
+        }
+        TlsMode::MTls => {
+            quic_config.root_ca_path = Some(require_config!(
+                outbound_cfg.ca_cert.clone(),
+                "outbound.ca_cert for mTLS"
+            )?);
+            let cert_path = require_config!(
+                outbound_cfg.client_cert.clone(),
+                "outbound.client_cert for mTLS"
+            )?;
+            let key_path = require_config!(
+                outbound_cfg.client_key.clone(),
+                "outbound.client_key for mTLS"
+            )?;
+            quic_config.client_cert_key_paths = Some((cert_path, key_path));
+        }
+        TlsMode::Insecure => {
+            warn!("outbound tls is in insecure mode, the next hop identity is NOT validated");
+            quic_config.skip_server_verification = true;
+        }
+    }
+
+    quic_config.transport_config(quic_transport_config(&config.transport)?);
+
+    QuicClient::new(quic_config).map_err(Error::Quic)
+}
+
+fn quic_transport_config(transport_cfg: &TransportConfig) -> Result<QuicTransportConfig> {
     let mut transport_config = QuicTransportConfig::default();
     let map_transport_err = |e: QuicError| Error::Quic(e);
     transport_config
@@ -291,12 +608,5 @@ async fn quic_server_from_config(config: &ServiceConfig) -> Result<QuicServer> {
     transport_config
         .congestion(transport_cfg.congestion(), transport_cfg.cwnd_init)
         .map_err(map_transport_err)?;
-    quic_config.transport_config(transport_config);
-
-    info!("binding udp socket to {}", config.listen);
-    let socket = UdpSocket::bind(config.listen)?;
-
-    QuicServer::new(socket, quic_config)
-        .await
-        .map_err(Error::Quic)
+    Ok(transport_config)
 }
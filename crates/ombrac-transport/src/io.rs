@@ -121,10 +121,10 @@ where
 /// rather than string-matching, so it is robust across quinn message format changes.
 pub fn is_clean_stream_close(error: &io::Error) -> bool {
     #[cfg(feature = "quic")]
-    if let Some(inner) = error.get_ref() {
-        if let Some(write_err) = inner.downcast_ref::<quinn::WriteError>() {
-            return matches!(write_err, quinn::WriteError::Stopped(code) if *code == quinn::VarInt::from(0u32));
-        }
+    if let Some(inner) = error.get_ref()
+        && let Some(write_err) = inner.downcast_ref::<quinn::WriteError>()
+    {
+        return matches!(write_err, quinn::WriteError::Stopped(code) if *code == quinn::VarInt::from(0u32));
     }
     false
 }
@@ -166,11 +166,11 @@ mod tests {
         ) -> Poll<io::Result<()>> {
             self.read_count += 1;
 
-            if let Some((n, e)) = &self.error_on_nth_read {
-                if self.read_count == *n {
-                    // Create a new error instance to avoid borrowing issues
-                    return Poll::Ready(Err(io::Error::new(e.kind(), e.to_string())));
-                }
+            if let Some((n, e)) = &self.error_on_nth_read
+                && self.read_count == *n
+            {
+                // Create a new error instance to avoid borrowing issues
+                return Poll::Ready(Err(io::Error::new(e.kind(), e.to_string())));
             }
 
             let remaining = &self.data[self.position..];
@@ -205,10 +205,10 @@ mod tests {
         ) -> Poll<io::Result<usize>> {
             self.write_count += 1;
 
-            if let Some((n, e)) = &self.error_on_nth_write {
-                if self.write_count == *n {
-                    return Poll::Ready(Err(io::Error::new(e.kind(), e.to_string())));
-                }
+            if let Some((n, e)) = &self.error_on_nth_write
+                && self.write_count == *n
+            {
+                return Poll::Ready(Err(io::Error::new(e.kind(), e.to_string())));
             }
 
             self.written.extend_from_slice(buf);
@@ -337,7 +337,7 @@ mod tests {
     async fn test_copy_with_cancel_read_error_with_stats() {
         let data = vec![0u8; DEFAULT_BUF_SIZE * 2];
         let mut reader = MockReader::new(data);
-        reader.error_on_nth_read = Some((2, io::Error::new(io::ErrorKind::Other, "read error")));
+        reader.error_on_nth_read = Some((2, io::Error::other("read error")));
         let mut writer = MockWriter::new();
         let token = CancellationToken::new();
 
@@ -511,7 +511,7 @@ mod tests {
         let (mut a, mut a_peer) = duplex(DEFAULT_BUF_SIZE * 2);
 
         let mut b_reader = MockReader::new(vec![0; DEFAULT_BUF_SIZE * 2]);
-        b_reader.error_on_nth_read = Some((2, io::Error::new(io::ErrorKind::Other, "read error")));
+        b_reader.error_on_nth_read = Some((2, io::Error::other("read error")));
         let mut b = MockStream {
             reader: b_reader,
             writer: MockWriter::new(),
@@ -33,6 +33,29 @@ pub trait Connection: Send + Sync + 'static {
     fn close(&self, error_code: u32, reason: &[u8]);
     fn remote_address(&self) -> Result<SocketAddr>;
 
+    /// Fills `output` with keying material exported from the TLS session
+    /// (RFC 5705), unique to this connection.
+    ///
+    /// Fails with [`std::io::ErrorKind::Unsupported`] by default, in which
+    /// case challenge-based authentication is refused on the connection.
+    fn export_keying_material(
+        &self,
+        _output: &mut [u8],
+        _label: &[u8],
+        _context: &[u8],
+    ) -> Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "keying material export is not supported by this transport",
+        ))
+    }
+
+    /// Returns the DER certificate chain the peer authenticated with, leaf
+    /// first. Empty if the peer presented none.
+    fn peer_certificates(&self) -> Vec<bytes::Bytes> {
+        Vec::new()
+    }
+
     fn open_bidirectional(&self) -> impl Future<Output = Result<Self::Stream>> + Send;
     fn accept_bidirectional(&self) -> impl Future<Output = Result<Self::Stream>> + Send;
 
@@ -1,13 +1,70 @@
+use std::fmt;
 use std::io;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use ombrac_macros::{debug, info, warn};
+use rustls::pki_types::CertificateDer;
 
 use super::Result;
 use crate::quic::TransportConfig;
 
+/// SHA-256 of a certificate's SubjectPublicKeyInfo. It stays the same when
+/// the certificate is renewed with the same key.
+///
+/// Written as `sha256/<base64>`, the `sha256/` prefix being optional when
+/// parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pin(pub [u8; 32]);
+
+impl Pin {
+    /// Returns the pin of a DER encoded certificate.
+    pub fn of_certificate(der: &CertificateDer<'_>) -> Result<Self> {
+        let cert = rustls::server::ParsedCertificate::try_from(der)?;
+        Ok(Self::of_spki(&cert.subject_public_key_info()))
+    }
+
+    fn of_spki(spki: &[u8]) -> Self {
+        let digest = aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, spki);
+        Self(
+            digest
+                .as_ref()
+                .try_into()
+                .expect("SHA-256 digests are 32 bytes"),
+        )
+    }
+}
+
+impl FromStr for Pin {
+    type Err = io::Error;
+
+    fn from_str(value: &str) -> io::Result<Self> {
+        let encoded = value.trim();
+        let encoded = encoded.strip_prefix("sha256/").unwrap_or(encoded);
+        STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(Self)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid pin '{value}', expected sha256/<base64>"),
+                )
+            })
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256/{}", STANDARD.encode(self.0))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub bind_addr: SocketAddr,
@@ -19,6 +76,16 @@ pub struct Config {
     pub skip_server_verification: bool,
     pub root_ca_path: Option<PathBuf>,
     pub client_cert_key_paths: Option<(PathBuf, PathBuf)>,
+    /// Revocation lists the server certificate is checked against.
+    pub crl_paths: Vec<PathBuf>,
+    /// Accept server certificates none of the revocation lists covers.
+    pub allow_unknown_revocation_status: bool,
+    /// Keys the server chain must contain, in place of verifying it against
+    /// the root certificates. Can't be combined with `crl_paths`.
+    pub pinned_keys: Vec<Pin>,
+    /// Check that the server certificate is valid for `server_name` when
+    /// keys are pinned.
+    pub verify_pinned_hostname: bool,
 
     transport_config: Arc<quinn::TransportConfig>,
 }
@@ -36,6 +103,10 @@ impl Config {
             bind_addr: default_bind_addr,
             root_ca_path: None,
             client_cert_key_paths: None,
+            crl_paths: Vec::new(),
+            allow_unknown_revocation_status: false,
+            pinned_keys: Vec::new(),
+            verify_pinned_hostname: true,
             skip_server_verification: false,
             enable_zero_rtt: false,
             alpn_protocols: Vec::new(),
@@ -71,7 +142,17 @@ impl Config {
             roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
         }
 
-        let config_builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+        let config_builder = if self.crl_paths.is_empty() {
+            rustls::ClientConfig::builder().with_root_certificates(roots)
+        } else {
+            let mut verifier_builder = rustls::client::WebPkiServerVerifier::builder(roots.into())
+                .with_crls(super::load_crls(&self.crl_paths)?);
+            if self.allow_unknown_revocation_status {
+                verifier_builder = verifier_builder.allow_unknown_revocation_status();
+            }
+            let verifier = verifier_builder.build().map_err(io::Error::other)?;
+            rustls::ClientConfig::builder().with_webpki_verifier(verifier)
+        };
 
         let mut tls_config = if let Some((cert_path, key_path)) = &self.client_cert_key_paths {
             let client_certs = super::load_certificates(cert_path)?;
@@ -83,6 +164,25 @@ impl Config {
 
         tls_config.alpn_protocols = self.alpn_protocols.clone();
 
+        if !self.pinned_keys.is_empty() {
+            // A pinned leaf has no issuer the revocation lists could be
+            // checked against
+            if !self.crl_paths.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "pinned keys can't be combined with revocation lists",
+                )
+                .into());
+            }
+            let verifier = cert_verifier::PinnedVerifier::new(
+                self.pinned_keys.clone(),
+                self.verify_pinned_hostname,
+            );
+            tls_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(verifier));
+        }
+
         if self.skip_server_verification {
             warn!("tls certificate verification is DISABLED - this is not secure!");
             tls_config
@@ -167,6 +267,25 @@ impl Client {
     }
 }
 
+/// Connects to the server without verifying it and returns the certificate
+/// chain it presents, leaf first.
+pub async fn fetch_certificate_chain(mut config: Config) -> Result<Vec<CertificateDer<'static>>> {
+    config.pinned_keys.clear();
+    config.skip_server_verification = true;
+    config.enable_zero_rtt = false;
+
+    let client = Client::new(config)?;
+    let connection = client.connect().await?;
+    let chain = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .map(|chain| *chain)
+        .unwrap_or_default();
+    connection.close(0u32.into(), b"");
+
+    Ok(chain)
+}
+
 impl crate::Initiator for Client {
     type Connection = quinn::Connection;
 
@@ -185,8 +304,118 @@ impl crate::Initiator for Client {
 
 mod cert_verifier {
     use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::WebPkiSupportedAlgorithms;
     use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
-    use rustls::{DigitallySignedStruct, SignatureScheme};
+    use rustls::server::ParsedCertificate;
+    use rustls::{CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme};
+    use x509_parser::time::ASN1Time;
+
+    use super::Pin;
+
+    /// Accepts a server chain if it contains a pinned key, with or without
+    /// checking the server name.
+    ///
+    /// A pinned leaf is trusted as is, as long as it is valid at the time.
+    /// A pinned certificate further up the chain is the trust anchor the
+    /// leaf must chain to, so it can't be replayed in front of any other key.
+    #[derive(Debug)]
+    pub struct PinnedVerifier {
+        pins: Vec<Pin>,
+        verify_hostname: bool,
+        algorithms: WebPkiSupportedAlgorithms,
+    }
+
+    impl PinnedVerifier {
+        pub fn new(pins: Vec<Pin>, verify_hostname: bool) -> Self {
+            Self {
+                pins,
+                verify_hostname,
+                algorithms: rustls::crypto::aws_lc_rs::default_provider()
+                    .signature_verification_algorithms,
+            }
+        }
+    }
+
+    impl ServerCertVerifier for PinnedVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            _: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let cert = ParsedCertificate::try_from(end_entity)?;
+            if self.verify_hostname {
+                rustls::client::verify_server_name(&cert, server_name)?;
+            }
+
+            if self
+                .pins
+                .contains(&Pin::of_spki(&cert.subject_public_key_info()))
+            {
+                check_validity(end_entity, now)?;
+                return Ok(ServerCertVerified::assertion());
+            }
+
+            for (index, anchor) in intermediates.iter().enumerate() {
+                let spki = ParsedCertificate::try_from(anchor)?.subject_public_key_info();
+                if !self.pins.contains(&Pin::of_spki(&spki)) {
+                    continue;
+                }
+                let mut roots = RootCertStore::empty();
+                roots.add(anchor.clone().into_owned())?;
+                rustls::client::verify_server_cert_signed_by_trust_anchor(
+                    &cert,
+                    &roots,
+                    &intermediates[..index],
+                    now,
+                    self.algorithms.all,
+                )?;
+                return Ok(ServerCertVerified::assertion());
+            }
+
+            Err(CertificateError::ApplicationVerificationFailure.into())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.algorithms.supported_schemes()
+        }
+    }
+
+    /// Checks that `now` falls within the validity period of `cert`.
+    fn check_validity(cert: &CertificateDer<'_>, now: UnixTime) -> Result<(), rustls::Error> {
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(cert).map_err(|_| CertificateError::BadEncoding)?;
+        let now = ASN1Time::from_timestamp(now.as_secs() as i64)
+            .map_err(|_| CertificateError::Expired)?;
+        let validity = cert.validity();
+        if now < validity.not_before {
+            Err(CertificateError::NotValidYet.into())
+        } else if now > validity.not_after {
+            Err(CertificateError::Expired.into())
+        } else {
+            Ok(())
+        }
+    }
 
     #[derive(Debug)]
     pub struct NullVerifier;
@@ -226,3 +455,66 @@ mod cert_verifier {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin_roundtrip() {
+        let pin = Pin([7; 32]);
+        let text = pin.to_string();
+        assert!(text.starts_with("sha256/"));
+        assert_eq!(text.parse::<Pin>().unwrap(), pin);
+        assert_eq!(
+            text.trim_start_matches("sha256/").parse::<Pin>().unwrap(),
+            pin
+        );
+
+        assert!("sha256/AAAA".parse::<Pin>().is_err());
+        assert!("not base64".parse::<Pin>().is_err());
+    }
+
+    #[test]
+    fn pinned_leaf_must_be_valid() {
+        use rustls::CertificateError;
+        use rustls::client::danger::ServerCertVerifier;
+        use rustls::pki_types::{ServerName, UnixTime};
+
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".into()]).unwrap();
+        params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        let der = cert.der();
+
+        let verifier =
+            cert_verifier::PinnedVerifier::new(vec![Pin::of_certificate(der).unwrap()], true);
+        let name = ServerName::try_from("localhost").unwrap();
+        let at = |secs| UnixTime::since_unix_epoch(std::time::Duration::from_secs(secs));
+
+        // 2000-07-01
+        assert!(
+            verifier
+                .verify_server_cert(der, &[], &name, &[], at(962_409_600))
+                .is_ok()
+        );
+        // 1999-07-01 and 2001-07-01
+        let err = verifier
+            .verify_server_cert(der, &[], &name, &[], at(930_787_200))
+            .unwrap_err();
+        assert_eq!(err, CertificateError::NotValidYet.into());
+        let err = verifier
+            .verify_server_cert(der, &[], &name, &[], at(994_032_000))
+            .unwrap_err();
+        assert_eq!(err, CertificateError::Expired.into());
+    }
+
+    #[test]
+    fn pins_cannot_be_combined_with_crls() {
+        let mut config = Config::new("127.0.0.1:443".parse().unwrap(), "localhost".into());
+        config.pinned_keys = vec![Pin([7; 32])];
+        config.crl_paths = vec![PathBuf::from("ca.crl")];
+        assert!(config.build_tls_config().is_err());
+    }
+}
@@ -4,14 +4,14 @@ pub mod client;
 pub mod error;
 pub mod server;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fs, io};
 
 use quinn::{IdleTimeout, VarInt};
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer};
 use serde::{Deserialize, Serialize};
 
 type Result<T> = std::result::Result<T, error::Error>;
@@ -115,6 +115,22 @@ fn load_private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
     Ok(key)
 }
 
+/// Loads certificate revocation lists, PEM files holding any number of CRLs
+/// or DER files holding one.
+fn load_crls(paths: &[PathBuf]) -> io::Result<Vec<CertificateRevocationListDer<'static>>> {
+    let mut crls = Vec::new();
+    for path in paths {
+        let content = fs::read(path)?;
+        let pem = rustls_pemfile::crls(&mut &*content).collect::<io::Result<Vec<_>>>()?;
+        if pem.is_empty() {
+            crls.push(CertificateRevocationListDer::from(content));
+        } else {
+            crls.extend(pem);
+        }
+    }
+    Ok(crls)
+}
+
 #[derive(Debug)]
 pub enum ConnectionError {
     QuinnConnection(quinn::ConnectionError),
@@ -194,6 +210,32 @@ impl crate::Connection for quinn::Connection {
         Ok(quinn::Connection::remote_address(self))
     }
 
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: &[u8],
+    ) -> io::Result<()> {
+        quinn::Connection::export_keying_material(self, output, label, context).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "failed to export keying material",
+            )
+        })
+    }
+
+    fn peer_certificates(&self) -> Vec<bytes::Bytes> {
+        quinn::Connection::peer_identity(self)
+            .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+            .map(|chain| {
+                chain
+                    .iter()
+                    .map(|cert| bytes::Bytes::copy_from_slice(cert))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     #[cfg(feature = "datagram")]
     fn max_datagram_size(&self) -> Option<usize> {
         quinn::Connection::max_datagram_size(self)
@@ -1,16 +1,76 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::io;
-use std::net::SocketAddr;
 use std::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_channel::{Receiver, Sender};
-use ombrac_macros::{debug, error, warn};
+use ombrac_macros::{debug, error, info, warn};
 use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
 use tokio::sync::watch;
 
 use super::error::{Error, Result};
 
+/// Decides from its source address whether an incoming connection may
+/// start a handshake.
+#[derive(Clone)]
+pub struct IncomingFilter(Arc<dyn Fn(SocketAddr) -> bool + Send + Sync>);
+
+impl IncomingFilter {
+    /// Admits the connections for which `admit` returns `true`.
+    pub fn new(admit: impl Fn(SocketAddr) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(admit))
+    }
+
+    pub fn admits(&self, address: SocketAddr) -> bool {
+        (self.0)(address)
+    }
+}
+
+impl fmt::Debug for IncomingFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IncomingFilter")
+    }
+}
+
+/// Why an incoming connection was turned away by the handshake limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeRejection {
+    /// Sent a stateless Retry, as handshakes arrive faster than the retry
+    /// threshold. The client comes back with a token proving it owns its
+    /// address.
+    Retried,
+    /// Refused, as the maximum number of handshakes are in progress.
+    TooManyHandshakes,
+    /// Refused, as the maximum number of handshakes from its IP address are
+    /// in progress.
+    TooManyHandshakesFromAddress,
+}
+
+/// Told about every incoming connection turned away by the handshake
+/// limits, e.g. to count them.
+#[derive(Clone)]
+pub struct RejectionListener(Arc<dyn Fn(HandshakeRejection) + Send + Sync>);
+
+impl RejectionListener {
+    pub fn new(listener: impl Fn(HandshakeRejection) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(listener))
+    }
+
+    fn notify(&self, rejection: HandshakeRejection) {
+        (self.0)(rejection)
+    }
+}
+
+impl fmt::Debug for RejectionListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RejectionListener")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub enable_zero_rtt: bool,
@@ -18,6 +78,25 @@ pub struct Config {
     pub alpn_protocols: Vec<Vec<u8>>,
     pub root_ca_path: Option<PathBuf>,
     pub tls_cert_key_paths: Option<(PathBuf, PathBuf)>,
+    /// Revocation lists client certificates are checked against in mTLS
+    /// mode. They are reloaded when their files change.
+    pub crl_paths: Vec<PathBuf>,
+    /// Accept client certificates none of the revocation lists covers.
+    pub allow_unknown_revocation_status: bool,
+    /// How often the revocation lists are checked for changes.
+    pub crl_reload_interval: Duration,
+    /// Incoming connections it doesn't admit are refused before their
+    /// handshake, without spending any TLS work on them.
+    pub incoming_filter: Option<IncomingFilter>,
+    /// Handshakes per second above which clients must prove their address
+    /// with a stateless Retry before any connection state is allocated.
+    pub retry_threshold: Option<u32>,
+    /// Maximum number of handshakes in progress. Further incoming
+    /// connections are refused until some complete.
+    pub max_handshakes: Option<usize>,
+    /// Maximum number of handshakes in progress from a single IP address.
+    pub max_handshakes_per_ip: Option<usize>,
+    pub rejection_listener: Option<RejectionListener>,
 
     transport_config: Arc<quinn::TransportConfig>,
 }
@@ -27,6 +106,14 @@ impl Config {
         Self {
             tls_cert_key_paths: None,
             root_ca_path: None,
+            crl_paths: Vec::new(),
+            allow_unknown_revocation_status: false,
+            crl_reload_interval: Duration::from_secs(30),
+            incoming_filter: None,
+            retry_threshold: None,
+            max_handshakes: None,
+            max_handshakes_per_ip: None,
+            rejection_listener: None,
             enable_zero_rtt: false,
             enable_self_signed: false,
             alpn_protocols: Vec::new(),
@@ -78,9 +165,15 @@ impl Config {
             let ca_certs = super::load_certificates(ca_path)?;
             ca_store.add_parsable_certificates(ca_certs);
 
-            let verifier = rustls::server::WebPkiClientVerifier::builder(ca_store.into())
-                .build()
-                .map_err(io::Error::other)?;
+            let mut verifier_builder =
+                rustls::server::WebPkiClientVerifier::builder(ca_store.into());
+            if !self.crl_paths.is_empty() {
+                verifier_builder = verifier_builder.with_crls(super::load_crls(&self.crl_paths)?);
+                if self.allow_unknown_revocation_status {
+                    verifier_builder = verifier_builder.allow_unknown_revocation_status();
+                }
+            }
+            let verifier = verifier_builder.build().map_err(io::Error::other)?;
 
             config_builder
                 .with_client_cert_verifier(verifier)
@@ -130,8 +223,21 @@ impl Server {
 
         let (sender, receiver) = async_channel::bounded(128);
         let (shutdown_sender, shutdown_receiver) = watch::channel(());
-
-        tokio::spawn(accept_loop(endpoint.clone(), sender, shutdown_receiver));
+        let policy = IncomingPolicy::new(&config);
+
+        if config.root_ca_path.is_some() && !config.crl_paths.is_empty() {
+            tokio::spawn(crl_reload_loop(
+                endpoint.clone(),
+                config,
+                shutdown_receiver.clone(),
+            ));
+        }
+        tokio::spawn(accept_loop(
+            endpoint.clone(),
+            sender,
+            policy,
+            shutdown_receiver,
+        ));
 
         Ok(Self {
             endpoint,
@@ -141,9 +247,135 @@ impl Server {
     }
 }
 
+/// Handshakes in progress, overall and per source IP address.
+#[derive(Debug, Default)]
+struct InFlight {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Counts a handshake as in progress until dropped.
+struct HandshakeSlot {
+    in_flight: Arc<Mutex<InFlight>>,
+    ip: IpAddr,
+}
+
+impl Drop for HandshakeSlot {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        in_flight.total -= 1;
+        if let Some(count) = in_flight.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+enum Admission {
+    Accept(HandshakeSlot),
+    Retry,
+    Refuse,
+}
+
+/// Decides what becomes of incoming connections before their handshake
+/// starts, so that turning them away costs no connection state.
+struct IncomingPolicy {
+    filter: Option<IncomingFilter>,
+    retry_threshold: Option<u32>,
+    max_handshakes: Option<usize>,
+    max_handshakes_per_ip: Option<usize>,
+    listener: Option<RejectionListener>,
+    window_start: Instant,
+    window_handshakes: u32,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl IncomingPolicy {
+    fn new(config: &Config) -> Self {
+        Self {
+            filter: config.incoming_filter.clone(),
+            retry_threshold: config.retry_threshold,
+            max_handshakes: config.max_handshakes,
+            max_handshakes_per_ip: config.max_handshakes_per_ip,
+            listener: config.rejection_listener.clone(),
+            window_start: Instant::now(),
+            window_handshakes: 0,
+            in_flight: Arc::default(),
+        }
+    }
+
+    fn reject(&self, rejection: HandshakeRejection) {
+        if let Some(listener) = &self.listener {
+            listener.notify(rejection);
+        }
+    }
+
+    /// Counts a new handshake and returns whether there were more than the
+    /// retry threshold within the last second.
+    fn rate_exceeded(&mut self, now: Instant) -> bool {
+        let Some(threshold) = self.retry_threshold else {
+            return false;
+        };
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.window_handshakes = 0;
+        }
+        self.window_handshakes = self.window_handshakes.saturating_add(1);
+        self.window_handshakes > threshold
+    }
+
+    /// `validated` tells whether the client already proved its address,
+    /// i.e. it comes back after a Retry.
+    fn admit(&mut self, remote: SocketAddr, validated: bool) -> Admission {
+        if let Some(filter) = &self.filter
+            && !filter.admits(remote)
+        {
+            debug!("Refuse connection from {}", remote);
+            return Admission::Refuse;
+        }
+
+        if self.rate_exceeded(Instant::now()) && !validated {
+            self.reject(HandshakeRejection::Retried);
+            return Admission::Retry;
+        }
+
+        let ip = remote.ip().to_canonical();
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if self
+            .max_handshakes
+            .is_some_and(|max| in_flight.total >= max)
+        {
+            drop(in_flight);
+            debug!("Refuse connection from {}: too many handshakes", remote);
+            self.reject(HandshakeRejection::TooManyHandshakes);
+            return Admission::Refuse;
+        }
+        let from_ip = in_flight.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.max_handshakes_per_ip.is_some_and(|max| from_ip >= max) {
+            drop(in_flight);
+            debug!(
+                "Refuse connection from {}: too many handshakes from it",
+                remote
+            );
+            self.reject(HandshakeRejection::TooManyHandshakesFromAddress);
+            return Admission::Refuse;
+        }
+        in_flight.total += 1;
+        *in_flight.per_ip.entry(ip).or_insert(0) += 1;
+
+        Admission::Accept(HandshakeSlot {
+            in_flight: self.in_flight.clone(),
+            ip,
+        })
+    }
+}
+
 async fn accept_loop(
     endpoint: Arc<quinn::Endpoint>,
     sender: Sender<quinn::Connection>,
+    mut policy: IncomingPolicy,
     mut shutdown_receiver: watch::Receiver<()>,
 ) {
     loop {
@@ -152,13 +384,28 @@ async fn accept_loop(
 
         tokio::select! {
             Some(accept) = endpoint.accept() => {
+                let slot = match policy.admit(accept.remote_address(), accept.remote_address_validated()) {
+                    Admission::Accept(slot) => slot,
+                    Admission::Retry => {
+                        if let Err(err) = accept.retry() {
+                            err.into_incoming().refuse();
+                        }
+                        continue;
+                    }
+                    Admission::Refuse => {
+                        accept.refuse();
+                        continue;
+                    }
+                };
                 tokio::spawn(async move {
-                    match accept.await {
+                    // The handshake is in progress until the connection is established
+                    let result = accept.await;
+                    drop(slot);
+                    match result {
                         Ok(connection) => {
                             debug!("Accept connection from {}", connection.remote_address());
                             if sender_clone.send(connection).await.is_err() {
                                 warn!("Connection receiver is closed, stopping accept loop");
-                                return;
                             }
                         }
                         Err(_err) => {
@@ -175,6 +422,51 @@ async fn accept_loop(
     }
 }
 
+fn crl_modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Rebuilds the TLS config whenever a revocation list changes. New
+/// handshakes use the new lists; established connections are kept.
+async fn crl_reload_loop(
+    endpoint: Arc<quinn::Endpoint>,
+    config: Config,
+    mut shutdown_receiver: watch::Receiver<()>,
+) {
+    let mut modified = crl_modified_times(&config.crl_paths);
+    let mut interval = tokio::time::interval(config.crl_reload_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown_receiver.changed() => break,
+        }
+
+        let current = crl_modified_times(&config.crl_paths);
+        if current == modified {
+            continue;
+        }
+        modified = current;
+
+        match config.build_server_config() {
+            Ok(server_config) => {
+                endpoint.set_server_config(Some(server_config));
+                info!("reloaded certificate revocation lists");
+            }
+            Err(_err) => {
+                warn!(
+                    "failed to reload certificate revocation lists, keeping the old ones: {_err}"
+                );
+            }
+        }
+    }
+}
+
 impl crate::Acceptor for Server {
     type Connection = quinn::Connection;
 
@@ -195,3 +487,104 @@ impl Drop for Server {
         let _ = self.shutdown_sender.send(());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(ip: [u8; 4]) -> SocketAddr {
+        SocketAddr::from((ip, 443))
+    }
+
+    #[test]
+    fn handshakes_over_the_threshold_must_retry() {
+        let rejections = Arc::new(Mutex::new(Vec::new()));
+        let recorded = rejections.clone();
+        let mut config = Config::new();
+        config.retry_threshold = Some(2);
+        config.rejection_listener = Some(RejectionListener::new(move |rejection| {
+            recorded.lock().unwrap().push(rejection)
+        }));
+        let mut policy = IncomingPolicy::new(&config);
+
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 1]), false),
+            Admission::Accept(_)
+        ));
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 2]), false),
+            Admission::Accept(_)
+        ));
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 3]), false),
+            Admission::Retry
+        ));
+        // Coming back with a Retry token
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 3]), true),
+            Admission::Accept(_)
+        ));
+        assert_eq!(
+            *rejections.lock().unwrap(),
+            vec![HandshakeRejection::Retried]
+        );
+    }
+
+    #[test]
+    fn handshakes_in_flight_are_capped() {
+        let rejections = Arc::new(Mutex::new(Vec::new()));
+        let recorded = rejections.clone();
+        let mut config = Config::new();
+        config.max_handshakes = Some(3);
+        config.max_handshakes_per_ip = Some(2);
+        config.rejection_listener = Some(RejectionListener::new(move |rejection| {
+            recorded.lock().unwrap().push(rejection)
+        }));
+        let mut policy = IncomingPolicy::new(&config);
+
+        let first = policy.admit(address([192, 0, 2, 1]), false);
+        let _second = policy.admit(address([192, 0, 2, 1]), false);
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 1]), false),
+            Admission::Refuse
+        ));
+        let _third = policy.admit(address([192, 0, 2, 2]), false);
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 3]), false),
+            Admission::Refuse
+        ));
+
+        // A completed handshake frees its slot
+        drop(first);
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 1]), false),
+            Admission::Accept(_)
+        ));
+        assert_eq!(
+            *rejections.lock().unwrap(),
+            vec![
+                HandshakeRejection::TooManyHandshakesFromAddress,
+                HandshakeRejection::TooManyHandshakes
+            ]
+        );
+    }
+
+    #[test]
+    fn filtered_addresses_are_refused() {
+        let mut config = Config::new();
+        config.incoming_filter = Some(IncomingFilter::new(|address| {
+            address.ip() != IpAddr::from([192, 0, 2, 1])
+        }));
+        let mut policy = IncomingPolicy::new(&config);
+
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 1]), true),
+            Admission::Refuse
+        ));
+        assert!(matches!(
+            policy.admit(address([192, 0, 2, 2]), false),
+            Admission::Accept(_)
+        ));
+        assert_eq!(policy.in_flight.lock().unwrap().total, 0);
+    }
+}
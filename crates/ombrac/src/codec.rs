@@ -1,14 +1,18 @@
 use serde::{Deserialize, Serialize};
 pub use tokio_util::codec::LengthDelimitedCodec;
 
-use crate::protocol::{ClientConnect, ClientHello, ServerConnectResponse};
+use crate::protocol::{
+    ClientAuthProof, ClientConnect, ClientDnsQuery, ClientEcho, ClientHello, ClientKeyProof,
+    ClientToken, ServerConnectResponse, ServerDnsResponse, ServerEchoResponse,
+};
 
 /// Maximum frame length for the control plane codec.
 ///
 /// Control messages (`ClientHello`, `ClientConnect`, `ServerConnectResponse`,
 /// `ServerAuthResponse`) are small by construction — typically <1 KiB.
 /// 64 KiB is generous for opaque `options` payloads while keeping the
-/// memory amplification factor bounded against malicious senders.
+/// memory amplification factor bounded against malicious senders. DNS
+/// messages carried in `DnsQuery`/`DnsResponse` are bounded by it as well.
 pub const MAX_CONTROL_FRAME_LENGTH: usize = 64 * 1024;
 
 /// Maximum frame length for any future data-plane codec [8 MB].
@@ -28,6 +32,17 @@ pub enum ClientMessage {
     Hello(ClientHello),
     /// Connection request to establish a tunnel to a destination address.
     Connect(ClientConnect),
+    /// DNS query to be answered by the server's resolver.
+    DnsQuery(ClientDnsQuery),
+    /// ICMP echo to be sent by the server.
+    Echo(ClientEcho),
+    /// Answer to the server's authentication challenge.
+    AuthProof(ClientAuthProof),
+    /// Answer to the server's authentication challenge, signed with the
+    /// client's key.
+    KeyProof(ClientKeyProof),
+    /// Answer to the server's authentication challenge with an access token.
+    Token(ClientToken),
 }
 
 /// Messages sent from server to client.
@@ -38,6 +53,10 @@ pub enum ClientMessage {
 pub enum ServerMessage {
     /// Response to a connection request, indicating success or failure.
     ConnectResponse(ServerConnectResponse),
+    /// Answer to a DNS query.
+    DnsResponse(ServerDnsResponse),
+    /// Outcome of an ICMP echo.
+    EchoResponse(ServerEchoResponse),
 }
 
 /// Creates a length-delimited codec for control-plane messages.
@@ -48,7 +67,7 @@ pub enum ServerMessage {
 /// before the control message is rejected.
 ///
 /// All current uses of this codec are control flow (auth handshake,
-/// connect request/response). Bulk data is forwarded raw, not through this
+/// connect request/response, DNS query/response, echo). Bulk data is forwarded raw, not through this
 /// codec.
 pub fn length_codec() -> LengthDelimitedCodec {
     LengthDelimitedCodec::builder()
@@ -70,7 +89,8 @@ mod tests {
 
     use super::*;
     use crate::protocol::{
-        ClientHello, ConnectErrorKind, ServerConnectResponse, PROTOCOL_VERSION, decode, encode,
+        ClientDnsQuery, ClientEcho, ClientHello, ConnectErrorKind, PROTOCOL_VERSION,
+        ServerConnectResponse, ServerDnsResponse, ServerEchoResponse, decode, encode,
     };
 
     // ── Group G: length_codec() encoder / decoder ────────────────────────────
@@ -175,4 +195,54 @@ mod tests {
         let decoded: ServerMessage = decode(&bytes).unwrap();
         assert_eq!(decoded, err_msg);
     }
+
+    #[test]
+    fn test_dns_messages_roundtrip() {
+        let query = ClientMessage::DnsQuery(ClientDnsQuery {
+            message: Bytes::from_static(&[0x12, 0x34, 0x01, 0x00, 0x00, 0x01]),
+        });
+        let bytes = encode(&query).unwrap();
+        let decoded: ClientMessage = decode(&bytes).unwrap();
+        assert_eq!(decoded, query);
+
+        let response = ServerMessage::DnsResponse(ServerDnsResponse {
+            message: Bytes::from_static(&[0x12, 0x34, 0x81, 0x80]),
+        });
+        let bytes = encode(&response).unwrap();
+        let decoded: ServerMessage = decode(&bytes).unwrap();
+        assert_eq!(decoded, response);
+    }
+
+    #[test]
+    fn test_echo_messages_roundtrip() {
+        let echo = ClientMessage::Echo(ClientEcho {
+            address: crate::protocol::Address::try_from("example.com:0").unwrap(),
+            data: Bytes::from_static(b"ping"),
+        });
+        let bytes = encode(&echo).unwrap();
+        let decoded: ClientMessage = decode(&bytes).unwrap();
+        assert_eq!(decoded, echo);
+
+        let response = ServerMessage::EchoResponse(ServerEchoResponse::Err {
+            kind: ConnectErrorKind::TimedOut,
+            message: "no reply".to_string(),
+        });
+        let bytes = encode(&response).unwrap();
+        let decoded: ServerMessage = decode(&bytes).unwrap();
+        assert_eq!(decoded, response);
+    }
+
+    #[test]
+    fn test_existing_message_tags_are_stable() {
+        // New variants are appended so peers that predate them still decode
+        // hello/connect messages with the same discriminant.
+        let connect = ClientMessage::Connect(ClientConnect {
+            address: crate::protocol::Address::try_from("1.2.3.4:80").unwrap(),
+        });
+        assert_eq!(encode(&connect).unwrap()[0], 1);
+        let query = ClientMessage::DnsQuery(ClientDnsQuery {
+            message: Bytes::new(),
+        });
+        assert_eq!(encode(&query).unwrap()[0], 2);
+    }
 }
@@ -2,10 +2,14 @@
 //!
 //! This crate provides the core protocol components:
 //! - **codec**: Message encoding/decoding with length-delimited framing
+//! - **key**: Ed25519 keys for public-key authentication
 //! - **protocol**: Protocol message definitions and serialization
 //! - **reassembly**: UDP packet fragmentation and reassembly
+//! - **token**: Signed, expiring access tokens
 
 pub mod codec;
+pub mod key;
 pub mod metrics;
 pub mod protocol;
 pub mod reassembly;
+pub mod token;
@@ -22,6 +22,16 @@ pub struct Counters {
     pub connections_rejected: AtomicU64,
     /// Auth/handshake failures.
     pub connections_auth_failed: AtomicU64,
+    /// Addresses banned after repeated authentication failures.
+    pub auth_bans: AtomicU64,
+    /// Incoming connections refused because their address is banned.
+    pub connections_banned: AtomicU64,
+    /// QUIC handshakes sent a stateless Retry, as they arrived too fast.
+    pub handshakes_retried: AtomicU64,
+    /// QUIC handshakes refused, as too many were in progress.
+    pub handshakes_refused: AtomicU64,
+    /// QUIC handshakes refused, as too many from the same IP were in progress.
+    pub handshakes_refused_per_ip: AtomicU64,
 
     /// Bidirectional streams successfully opened on a tunnel.
     pub streams_opened: AtomicU64,
@@ -82,6 +92,11 @@ impl Metrics {
             connections_accepted: c.connections_accepted.load(Ordering::Relaxed),
             connections_rejected: c.connections_rejected.load(Ordering::Relaxed),
             connections_auth_failed: c.connections_auth_failed.load(Ordering::Relaxed),
+            auth_bans: c.auth_bans.load(Ordering::Relaxed),
+            connections_banned: c.connections_banned.load(Ordering::Relaxed),
+            handshakes_retried: c.handshakes_retried.load(Ordering::Relaxed),
+            handshakes_refused: c.handshakes_refused.load(Ordering::Relaxed),
+            handshakes_refused_per_ip: c.handshakes_refused_per_ip.load(Ordering::Relaxed),
             streams_opened: c.streams_opened.load(Ordering::Relaxed),
             streams_closed: c.streams_closed.load(Ordering::Relaxed),
             streams_failed: c.streams_failed.load(Ordering::Relaxed),
@@ -103,6 +118,11 @@ pub struct MetricsSnapshot {
     pub connections_accepted: u64,
     pub connections_rejected: u64,
     pub connections_auth_failed: u64,
+    pub auth_bans: u64,
+    pub connections_banned: u64,
+    pub handshakes_retried: u64,
+    pub handshakes_refused: u64,
+    pub handshakes_refused_per_ip: u64,
     pub streams_opened: u64,
     pub streams_closed: u64,
     pub streams_failed: u64,
@@ -153,32 +173,42 @@ mod tests {
         c.connections_accepted.fetch_add(1, Ordering::Relaxed);
         c.connections_rejected.fetch_add(2, Ordering::Relaxed);
         c.connections_auth_failed.fetch_add(3, Ordering::Relaxed);
-        c.streams_opened.fetch_add(4, Ordering::Relaxed);
-        c.streams_closed.fetch_add(5, Ordering::Relaxed);
-        c.streams_failed.fetch_add(6, Ordering::Relaxed);
-        c.udp_sessions_opened.fetch_add(7, Ordering::Relaxed);
-        c.udp_sessions_closed.fetch_add(8, Ordering::Relaxed);
-        c.bytes_rx.fetch_add(9, Ordering::Relaxed);
-        c.bytes_tx.fetch_add(10, Ordering::Relaxed);
-        c.reassemblies_completed.fetch_add(11, Ordering::Relaxed);
-        c.reassembly_drops.fetch_add(12, Ordering::Relaxed);
-        c.reconnect_attempts.fetch_add(13, Ordering::Relaxed);
-        c.reconnect_succeeded.fetch_add(14, Ordering::Relaxed);
+        c.auth_bans.fetch_add(4, Ordering::Relaxed);
+        c.connections_banned.fetch_add(5, Ordering::Relaxed);
+        c.handshakes_retried.fetch_add(6, Ordering::Relaxed);
+        c.handshakes_refused.fetch_add(7, Ordering::Relaxed);
+        c.handshakes_refused_per_ip.fetch_add(8, Ordering::Relaxed);
+        c.streams_opened.fetch_add(9, Ordering::Relaxed);
+        c.streams_closed.fetch_add(10, Ordering::Relaxed);
+        c.streams_failed.fetch_add(11, Ordering::Relaxed);
+        c.udp_sessions_opened.fetch_add(12, Ordering::Relaxed);
+        c.udp_sessions_closed.fetch_add(13, Ordering::Relaxed);
+        c.bytes_rx.fetch_add(14, Ordering::Relaxed);
+        c.bytes_tx.fetch_add(15, Ordering::Relaxed);
+        c.reassemblies_completed.fetch_add(16, Ordering::Relaxed);
+        c.reassembly_drops.fetch_add(17, Ordering::Relaxed);
+        c.reconnect_attempts.fetch_add(18, Ordering::Relaxed);
+        c.reconnect_succeeded.fetch_add(19, Ordering::Relaxed);
 
         let s = m.snapshot();
         assert_eq!(s.connections_accepted, 1);
         assert_eq!(s.connections_rejected, 2);
         assert_eq!(s.connections_auth_failed, 3);
-        assert_eq!(s.streams_opened, 4);
-        assert_eq!(s.streams_closed, 5);
-        assert_eq!(s.streams_failed, 6);
-        assert_eq!(s.udp_sessions_opened, 7);
-        assert_eq!(s.udp_sessions_closed, 8);
-        assert_eq!(s.bytes_rx, 9);
-        assert_eq!(s.bytes_tx, 10);
-        assert_eq!(s.reassemblies_completed, 11);
-        assert_eq!(s.reassembly_drops, 12);
-        assert_eq!(s.reconnect_attempts, 13);
-        assert_eq!(s.reconnect_succeeded, 14);
+        assert_eq!(s.auth_bans, 4);
+        assert_eq!(s.connections_banned, 5);
+        assert_eq!(s.handshakes_retried, 6);
+        assert_eq!(s.handshakes_refused, 7);
+        assert_eq!(s.handshakes_refused_per_ip, 8);
+        assert_eq!(s.streams_opened, 9);
+        assert_eq!(s.streams_closed, 10);
+        assert_eq!(s.streams_failed, 11);
+        assert_eq!(s.udp_sessions_opened, 12);
+        assert_eq!(s.udp_sessions_closed, 13);
+        assert_eq!(s.bytes_rx, 14);
+        assert_eq!(s.bytes_tx, 15);
+        assert_eq!(s.reassemblies_completed, 16);
+        assert_eq!(s.reassembly_drops, 17);
+        assert_eq!(s.reconnect_attempts, 18);
+        assert_eq!(s.reconnect_succeeded, 19);
     }
 }
@@ -5,12 +5,30 @@ use std::sync::LazyLock;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
+use crate::key::PublicKey;
+
 /// Secret key type for authentication (32 bytes, 256 bits).
 pub type Secret = [u8; 32];
 
-/// Current protocol version.
+/// Current protocol version, the highest one this build speaks.
 pub const PROTOCOL_VERSION: u8 = 0x01;
 
+/// Oldest protocol version this build still speaks.
+pub const MIN_PROTOCOL_VERSION: u8 = 0x01;
+
+/// Marks `ClientHello::options` that carry [`ClientCapabilities`].
+///
+/// Clients predating capability negotiation send their authentication
+/// options there as-is; the magic tells the two apart.
+pub const CAPABILITIES_MAGIC: [u8; 4] = *b"OMBC";
+
+/// Label of the TLS keying material a challenge-response proof is bound to.
+pub const CHALLENGE_EXPORTER_LABEL: &[u8] = b"EXPORTER-ombrac-challenge";
+
+/// Prefix of the message signed by [`ClientKeyProof`], so the signature
+/// can't be reused in any other context.
+pub const KEY_PROOF_CONTEXT: &[u8] = b"ombrac key proof v1";
+
 /// Maximum domain name length in bytes (RFC 1035).
 pub const MAX_DOMAIN_LENGTH: usize = 255;
 
@@ -44,14 +62,353 @@ pub fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> io::Result<T> {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ClientHello {
     /// Protocol version the client supports.
+    ///
+    /// Clients advertising [`ClientCapabilities`] set it to their lowest
+    /// version, so servers predating negotiation still accept them.
     pub version: u8,
     /// Authentication secret (32-byte hash of the configured secret).
+    ///
+    /// Zeroed by clients using [`Features::CHALLENGE_AUTH`], which prove
+    /// knowledge of the secret with a [`ClientAuthProof`] instead.
     pub secret: Secret,
-    /// Optional protocol extensions and configuration (opaque to the protocol).
+    /// Encoded [`ClientCapabilities`], or the authentication options of a
+    /// client predating capability negotiation.
     #[serde(with = "serde_bytes")]
     pub options: Bytes,
 }
 
+impl ClientHello {
+    /// Returns the capabilities advertised by the client, or `None` for a
+    /// client predating capability negotiation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the options are marked as capabilities but
+    /// malformed.
+    pub fn capabilities(&self) -> io::Result<Option<ClientCapabilities>> {
+        match self.options.strip_prefix(&CAPABILITIES_MAGIC) {
+            Some(encoded) => decode(encoded).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the application-defined authentication options of the client.
+    pub fn auth_options(&self) -> Bytes {
+        match self.capabilities() {
+            Ok(Some(capabilities)) => capabilities.auth,
+            _ => self.options.clone(),
+        }
+    }
+}
+
+/// Optional protocol features, as a set of bits.
+///
+/// Unknown bits are kept and ignored, so new features can be added without
+/// a new protocol version; peers only use what both of them advertise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Features(u64);
+
+impl Features {
+    /// UDP sessions relayed over QUIC datagrams.
+    pub const DATAGRAM: Features = Features(1 << 0);
+    /// DNS queries answered by the server's resolver.
+    pub const DNS_QUERY: Features = Features(1 << 1);
+    /// ICMP echo sent by the server.
+    pub const ECHO: Features = Features(1 << 2);
+    /// Compressed streams. Reserved: not implemented by this release.
+    pub const COMPRESSION: Features = Features(1 << 3);
+    /// UDP sessions relayed over streams. Reserved: not implemented by this release.
+    pub const UDP_OVER_STREAM: Features = Features(1 << 4);
+    /// Challenge-response authentication: the hello carries no secret and
+    /// the client answers an [`AuthChallenge`] with a [`ClientAuthProof`].
+    pub const CHALLENGE_AUTH: Features = Features(1 << 5);
+    /// Public-key authentication: the hello carries no secret and the client
+    /// answers an [`AuthChallenge`] with a [`ClientKeyProof`].
+    pub const PUBLIC_KEY_AUTH: Features = Features(1 << 6);
+    /// Token authentication: the hello carries no secret and the client
+    /// answers an [`AuthChallenge`] with a [`ClientToken`].
+    pub const TOKEN_AUTH: Features = Features(1 << 7);
+
+    /// The features every server spoke before capability negotiation. DNS
+    /// queries and ICMP echo came later, so such servers aren't sent them.
+    pub const LEGACY: Features = Self::DATAGRAM;
+
+    pub const fn empty() -> Self {
+        Features(0)
+    }
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Features(bits)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether every feature of `other` is in `self`.
+    pub const fn contains(&self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features in both sets.
+    pub const fn intersection(&self, other: Features) -> Self {
+        Features(self.0 & other.0)
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+/// Capabilities a client advertises in its hello.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientCapabilities {
+    /// Lowest protocol version the client speaks.
+    pub min_version: u8,
+    /// Highest protocol version the client speaks.
+    pub max_version: u8,
+    /// Features the client can use.
+    pub features: Features,
+    /// Application-defined authentication options (opaque to the protocol).
+    #[serde(with = "serde_bytes")]
+    pub auth: Bytes,
+}
+
+impl ClientCapabilities {
+    /// Encodes the capabilities as `ClientHello::options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization fails.
+    pub fn to_options(&self) -> io::Result<Bytes> {
+        let encoded = encode(self)?;
+        let mut options = Vec::with_capacity(CAPABILITIES_MAGIC.len() + encoded.len());
+        options.extend_from_slice(&CAPABILITIES_MAGIC);
+        options.extend_from_slice(&encoded);
+        Ok(options.into())
+    }
+
+    /// Picks the highest version both this client and a server speaking
+    /// `min_version..=max_version` support.
+    pub fn negotiate_version(&self, min_version: u8, max_version: u8) -> Option<u8> {
+        let version = self.max_version.min(max_version);
+        (version >= self.min_version.max(min_version)).then_some(version)
+    }
+}
+
+/// What the server agreed to use with a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    /// Negotiated protocol version.
+    pub version: u8,
+    /// Features both sides support.
+    pub features: Features,
+}
+
+impl ServerCapabilities {
+    /// What a server predating capability negotiation speaks.
+    pub const LEGACY: ServerCapabilities = ServerCapabilities {
+        version: 0x01,
+        features: Features::LEGACY,
+    };
+}
+
+/// Why the server refused a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthRejection {
+    /// No protocol version is spoken by both sides.
+    IncompatibleVersion {
+        /// Lowest protocol version the server speaks.
+        min_version: u8,
+        /// Highest protocol version the server speaks.
+        max_version: u8,
+    },
+    /// The secret doesn't match.
+    InvalidSecret,
+    /// The hello couldn't be understood.
+    Malformed,
+    /// The server failed while authenticating.
+    ServerError,
+    /// Any other reason, described by the message.
+    Other(String),
+    /// The server only accepts challenge-response authentication.
+    ChallengeRequired,
+    /// The server only accepts clients holding an authorized key.
+    PublicKeyRequired,
+    /// The key isn't authorized or the signature doesn't match it.
+    UnauthorizedKey,
+    /// The token wasn't issued by the server or is malformed.
+    InvalidToken,
+    /// The token is past its expiry.
+    TokenExpired,
+    /// The token was revoked.
+    TokenRevoked,
+    /// The server only accepts clients presenting a certificate.
+    CertificateRequired,
+    /// The server only accepts clients presenting an access token.
+    TokenRequired,
+}
+
+impl From<AuthRejection> for io::Error {
+    fn from(value: AuthRejection) -> Self {
+        match value {
+            AuthRejection::IncompatibleVersion {
+                min_version,
+                max_version,
+            } => io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "incompatible protocol version: server speaks {min_version}..={max_version}"
+                ),
+            ),
+            AuthRejection::InvalidSecret => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "invalid authentication secret",
+            ),
+            AuthRejection::Malformed => {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed hello")
+            }
+            AuthRejection::ServerError => io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "internal server error during auth",
+            ),
+            AuthRejection::Other(message) => io::Error::other(message),
+            AuthRejection::ChallengeRequired => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "server requires challenge-response authentication",
+            ),
+            AuthRejection::PublicKeyRequired => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "server requires public-key authentication",
+            ),
+            AuthRejection::UnauthorizedKey => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "public key is not authorized",
+            ),
+            AuthRejection::InvalidToken => {
+                io::Error::new(io::ErrorKind::PermissionDenied, "invalid access token")
+            }
+            AuthRejection::TokenExpired => {
+                io::Error::new(io::ErrorKind::PermissionDenied, "access token has expired")
+            }
+            AuthRejection::TokenRevoked => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "access token has been revoked",
+            ),
+            AuthRejection::CertificateRequired => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "server requires a client certificate",
+            ),
+            AuthRejection::TokenRequired => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "server requires an access token",
+            ),
+        }
+    }
+}
+
+/// Nonce the server asks a client using [`Features::CHALLENGE_AUTH`] to
+/// prove knowledge of the secret with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthChallenge {
+    pub nonce: [u8; 32],
+}
+
+/// The client's answer to an [`AuthChallenge`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientAuthProof {
+    /// See [`challenge_mac`].
+    pub mac: [u8; 32],
+}
+
+/// Computes the proof of knowledge of `secret` for a challenge.
+///
+/// The MAC covers the server's nonce and the keying material exported
+/// with [`CHALLENGE_EXPORTER_LABEL`] from the TLS session the challenge was
+/// sent on, so it is useless on any other connection.
+pub fn challenge_mac(secret: &Secret, nonce: &[u8; 32], keying_material: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_keyed(secret);
+    hasher.update(nonce);
+    hasher.update(keying_material);
+    *hasher.finalize().as_bytes()
+}
+
+/// A challenge as answered by a client, checked by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChallengeResponse {
+    /// The nonce sent to the client.
+    pub nonce: [u8; 32],
+    /// Keying material exported from the connection on the server side.
+    pub keying_material: [u8; 32],
+    /// The MAC the client answered with.
+    pub mac: [u8; 32],
+}
+
+impl ChallengeResponse {
+    /// Returns whether the client's MAC was computed with `secret`.
+    ///
+    /// The comparison takes constant time.
+    pub fn verify(&self, secret: &Secret) -> bool {
+        let expected = challenge_mac(secret, &self.nonce, &self.keying_material);
+        blake3::Hash::from_bytes(expected) == blake3::Hash::from_bytes(self.mac)
+    }
+}
+
+/// The client's answer to an [`AuthChallenge`] when authenticating with
+/// an Ed25519 key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientKeyProof {
+    /// The client's public key.
+    pub public_key: [u8; 32],
+    /// Signature of [`key_proof_message`].
+    #[serde(with = "serde_bytes")]
+    pub signature: Bytes,
+}
+
+/// Builds the message a client signs to answer a challenge with its key.
+///
+/// Like [`challenge_mac`], it covers the nonce and the keying material of
+/// the connection.
+pub fn key_proof_message(nonce: &[u8; 32], keying_material: &[u8; 32]) -> Vec<u8> {
+    [KEY_PROOF_CONTEXT, nonce, keying_material].concat()
+}
+
+/// A challenge as answered by a client with its key, checked by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedChallenge {
+    /// The nonce sent to the client.
+    pub nonce: [u8; 32],
+    /// Keying material exported from the connection on the server side.
+    pub keying_material: [u8; 32],
+    /// The key the client claims to hold.
+    pub public_key: PublicKey,
+    /// The signature the client answered with.
+    pub signature: Bytes,
+}
+
+impl SignedChallenge {
+    /// Returns whether the signature was made by the client's key.
+    pub fn verify(&self) -> bool {
+        let message = key_proof_message(&self.nonce, &self.keying_material);
+        self.public_key.verify(&message, &self.signature)
+    }
+}
+
+/// The client's answer to an [`AuthChallenge`] when authenticating with
+/// an access token.
+///
+/// See [`crate::token`]; the token is sent as is, protected by TLS.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientToken {
+    #[serde(with = "serde_bytes")]
+    pub token: Bytes,
+}
+
 /// Client connection request to establish a tunnel to a destination.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ClientConnect {
@@ -59,10 +416,46 @@ pub struct ClientConnect {
     pub address: Address,
 }
 
+/// Client request to resolve a DNS query using the server's resolver.
+///
+/// The query is forwarded as-is, so any record type can be looked up from
+/// the server's network view.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientDnsQuery {
+    /// DNS query message in wire format (RFC 1035 section 4).
+    #[serde(with = "serde_bytes")]
+    pub message: Bytes,
+}
+
+/// Client request to send an ICMP echo to a destination from the server.
+///
+/// Domains (e.g. recovered from a fake DNS address) are resolved by the
+/// server; the port of `address` is ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientEcho {
+    /// Destination to ping.
+    pub address: Address,
+    /// Echo payload, sent unchanged.
+    #[serde(with = "serde_bytes")]
+    pub data: Bytes,
+}
+
+/// The server's answer to a client hello.
+///
+/// `Ok` and `Err` are only sent to clients predating capability
+/// negotiation, which can't decode the other variants.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ServerAuthResponse {
     Ok,
     Err,
+    /// The client is authenticated with the negotiated capabilities.
+    Accepted(ServerCapabilities),
+    /// The client is refused for the given reason.
+    Rejected(AuthRejection),
+    /// The client must answer with a [`ClientAuthProof`], a
+    /// [`ClientKeyProof`] or a [`ClientToken`] before the server accepts or
+    /// rejects it.
+    Challenge(AuthChallenge),
 }
 
 /// UDP packet representation with support for fragmentation.
@@ -188,6 +581,32 @@ pub enum ServerConnectResponse {
     },
 }
 
+/// Response to a client's DNS query.
+///
+/// Resolution failures are reported inside the DNS message itself (e.g.
+/// `NXDOMAIN` or `SERVFAIL`), so the client can hand it back to the
+/// application unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerDnsResponse {
+    /// DNS response message in wire format, carrying the ID of the query.
+    #[serde(with = "serde_bytes")]
+    pub message: Bytes,
+}
+
+/// Outcome of a client's echo request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerEchoResponse {
+    /// The destination answered the echo request.
+    Ok,
+    /// The destination did not answer or could not be pinged.
+    Err {
+        /// Error kind that categorizes the failure
+        kind: ConnectErrorKind,
+        /// Human-readable error message
+        message: String,
+    },
+}
+
 /// Categorizes connection errors to help clients handle them appropriately.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ConnectErrorKind {
@@ -221,6 +640,17 @@ impl ConnectErrorKind {
             _ => ConnectErrorKind::Other,
         }
     }
+
+    /// Converts the error kind back to the closest `io::ErrorKind`.
+    pub fn to_io_error_kind(&self) -> io::ErrorKind {
+        match self {
+            ConnectErrorKind::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+            ConnectErrorKind::NetworkUnreachable => io::ErrorKind::NetworkUnreachable,
+            ConnectErrorKind::HostUnreachable => io::ErrorKind::HostUnreachable,
+            ConnectErrorKind::TimedOut => io::ErrorKind::TimedOut,
+            ConnectErrorKind::Other => io::ErrorKind::Other,
+        }
+    }
 }
 
 /// Network address representation supporting IPv4, IPv6, and domain names.
@@ -533,6 +963,122 @@ mod tests {
         assert_eq!(decoded, original);
     }
 
+    fn capabilities() -> ClientCapabilities {
+        ClientCapabilities {
+            min_version: 1,
+            max_version: 3,
+            features: Features::DATAGRAM | Features::ECHO,
+            auth: Bytes::from_static(b"user-1"),
+        }
+    }
+
+    #[test]
+    fn test_client_hello_capabilities_roundtrip() {
+        let hello = ClientHello {
+            version: 1,
+            secret: [0u8; 32],
+            options: capabilities().to_options().unwrap(),
+        };
+        let decoded: ClientHello = decode(&encode(&hello).unwrap()).unwrap();
+        assert_eq!(decoded.capabilities().unwrap(), Some(capabilities()));
+        assert_eq!(decoded.auth_options(), Bytes::from_static(b"user-1"));
+    }
+
+    #[test]
+    fn test_client_hello_legacy_options() {
+        let hello = ClientHello {
+            version: 1,
+            secret: [0u8; 32],
+            options: Bytes::from_static(b"user-1"),
+        };
+        assert_eq!(hello.capabilities().unwrap(), None);
+        assert_eq!(hello.auth_options(), Bytes::from_static(b"user-1"));
+
+        let malformed = ClientHello {
+            options: Bytes::from_static(b"OMBC\xff"),
+            ..hello
+        };
+        assert!(malformed.capabilities().is_err());
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let client = capabilities();
+        assert_eq!(client.negotiate_version(1, 1), Some(1));
+        assert_eq!(client.negotiate_version(2, 5), Some(3));
+        assert_eq!(client.negotiate_version(4, 5), None);
+    }
+
+    #[test]
+    fn test_features_ignore_unknown_bits() {
+        let client = Features::from_bits(Features::DATAGRAM.bits() | 1 << 63);
+        let negotiated = client.intersection(Features::LEGACY);
+        assert_eq!(negotiated, Features::DATAGRAM);
+        assert!(negotiated.contains(Features::DATAGRAM));
+        assert!(!negotiated.contains(Features::DATAGRAM | Features::ECHO));
+    }
+
+    #[test]
+    fn test_legacy_auth_response_encoding_is_stable() {
+        // Clients predating negotiation only decode these two.
+        assert_eq!(encode(&ServerAuthResponse::Ok).unwrap().as_ref(), [0]);
+        assert_eq!(encode(&ServerAuthResponse::Err).unwrap().as_ref(), [1]);
+
+        let rejected = ServerAuthResponse::Rejected(AuthRejection::IncompatibleVersion {
+            min_version: 2,
+            max_version: 3,
+        });
+        let decoded: ServerAuthResponse = decode(&encode(&rejected).unwrap()).unwrap();
+        assert_eq!(decoded, rejected);
+    }
+
+    #[test]
+    fn test_challenge_response_verify() {
+        let secret = [7u8; 32];
+        let response = ChallengeResponse {
+            nonce: [1u8; 32],
+            keying_material: [2u8; 32],
+            mac: challenge_mac(&secret, &[1u8; 32], &[2u8; 32]),
+        };
+        assert!(response.verify(&secret));
+        assert!(!response.verify(&[8u8; 32]));
+
+        // Replayed on another connection or against another nonce
+        let other_connection = ChallengeResponse {
+            keying_material: [3u8; 32],
+            ..response.clone()
+        };
+        assert!(!other_connection.verify(&secret));
+        let other_nonce = ChallengeResponse {
+            nonce: [4u8; 32],
+            ..response
+        };
+        assert!(!other_nonce.verify(&secret));
+    }
+
+    #[test]
+    fn test_signed_challenge_verify() {
+        let key = crate::key::PrivateKey::generate().unwrap();
+        let signed = SignedChallenge {
+            nonce: [1u8; 32],
+            keying_material: [2u8; 32],
+            public_key: key.public_key(),
+            signature: key.sign(&key_proof_message(&[1u8; 32], &[2u8; 32])).into(),
+        };
+        assert!(signed.verify());
+
+        let other_connection = SignedChallenge {
+            keying_material: [3u8; 32],
+            ..signed.clone()
+        };
+        assert!(!other_connection.verify());
+        let other_key = SignedChallenge {
+            public_key: crate::key::PrivateKey::generate().unwrap().public_key(),
+            ..signed
+        };
+        assert!(!other_key.verify());
+    }
+
     #[test]
     fn test_encode_decode_client_connect_ipv4() {
         let original = ClientConnect {
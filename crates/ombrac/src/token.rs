@@ -0,0 +1,197 @@
+//! Signed, expiring access tokens.
+//!
+//! A token is `<claims>.<signature>`, both base64url without padding. The
+//! claims are signed with the server's Ed25519 token key, so a server
+//! holding the public half checks tokens offline, without any per-user
+//! state.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+
+use crate::key::{PrivateKey, PublicKey};
+use crate::protocol;
+
+/// Prefix of the signed message, so a token signature can't be reused in
+/// any other context.
+const TOKEN_CONTEXT: &[u8] = b"ombrac token v1";
+
+/// What a token grants its holder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// Unique ID of the token, as listed in revocation lists.
+    pub id: String,
+    /// Who the token was issued to.
+    pub identity: String,
+    /// Expiry, in seconds since the Unix epoch.
+    pub expires_at: u64,
+    /// Bandwidth granted to the holder, in bytes per second in each
+    /// direction.
+    pub bandwidth: Option<u64>,
+    /// Destinations the holder may reach: IP addresses, networks and
+    /// domains, which include their subdomains.
+    pub allow: Option<Vec<String>>,
+}
+
+/// Why a token was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// The token couldn't be decoded.
+    Malformed,
+    /// The token wasn't signed by the server's key.
+    InvalidSignature,
+    /// The token is past its expiry.
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Malformed => f.write_str("malformed token"),
+            TokenError::InvalidSignature => f.write_str("invalid token signature"),
+            TokenError::Expired => f.write_str("token has expired"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+fn signed_message(claims: &[u8]) -> Vec<u8> {
+    [TOKEN_CONTEXT, claims].concat()
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Claims {
+    /// Signs the claims with `key` and returns the token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the claims can't be encoded.
+    pub fn sign(&self, key: &PrivateKey) -> std::io::Result<String> {
+        let claims = protocol::encode(self)?;
+        let signature = key.sign(&signed_message(&claims));
+        Ok(format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(claims),
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    /// Checks that `token` was signed by `key` and hasn't expired at `now`,
+    /// and returns its claims.
+    pub fn verify(token: &str, key: &PublicKey, now: u64) -> Result<Self, TokenError> {
+        let (claims, signature) = token.trim().split_once('.').ok_or(TokenError::Malformed)?;
+        let claims = URL_SAFE_NO_PAD
+            .decode(claims)
+            .map_err(|_| TokenError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| TokenError::Malformed)?;
+
+        if !key.verify(&signed_message(&claims), &signature) {
+            return Err(TokenError::InvalidSignature);
+        }
+        let claims: Claims = protocol::decode(&claims).map_err(|_| TokenError::Malformed)?;
+        if claims.expires_at <= now {
+            return Err(TokenError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+/// Parses a revocation list: one token ID per line. Empty lines and lines
+/// starting with `#` are skipped.
+pub fn parse_revocation_list(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> Claims {
+        Claims {
+            id: "c0ffee".to_string(),
+            identity: "contractor".to_string(),
+            expires_at: 2_000,
+            bandwidth: Some(1_000_000),
+            allow: Some(vec!["example.com".to_string()]),
+        }
+    }
+
+    #[test]
+    fn signed_token_roundtrip() {
+        let key = PrivateKey::generate().unwrap();
+        let token = claims().sign(&key).unwrap();
+
+        let verified = Claims::verify(&token, &key.public_key(), 1_000).unwrap();
+        assert_eq!(verified, claims());
+    }
+
+    #[test]
+    fn expired_token_is_refused() {
+        let key = PrivateKey::generate().unwrap();
+        let token = claims().sign(&key).unwrap();
+
+        assert_eq!(
+            Claims::verify(&token, &key.public_key(), 2_000),
+            Err(TokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn forged_token_is_refused() {
+        let key = PrivateKey::generate().unwrap();
+        let other = PrivateKey::generate().unwrap();
+        let token = claims().sign(&other).unwrap();
+        assert_eq!(
+            Claims::verify(&token, &key.public_key(), 1_000),
+            Err(TokenError::InvalidSignature)
+        );
+
+        // Claims swapped under a valid signature
+        let token = claims().sign(&key).unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+        let longer = Claims {
+            expires_at: u64::MAX,
+            ..claims()
+        };
+        let forged = format!(
+            "{}.{signature}",
+            URL_SAFE_NO_PAD.encode(protocol::encode(&longer).unwrap())
+        );
+        assert_eq!(
+            Claims::verify(&forged, &key.public_key(), 1_000),
+            Err(TokenError::InvalidSignature)
+        );
+
+        assert_eq!(
+            Claims::verify("garbage", &key.public_key(), 1_000),
+            Err(TokenError::Malformed)
+        );
+    }
+
+    #[test]
+    fn revocation_list_parsing() {
+        let revoked = parse_revocation_list("# leaked\nc0ffee\n\n  beef  \n");
+        assert_eq!(
+            revoked,
+            HashSet::from(["c0ffee".to_string(), "beef".to_string()])
+        );
+    }
+}
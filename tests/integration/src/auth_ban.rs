@@ -0,0 +1,198 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tests_support::mock_transport::MockInitiator;
+    use tests_support::net::find_available_local_udp_addr;
+    use tests_support::server::spawn_mock_server_with;
+    use tokio::sync::broadcast;
+
+    use ombrac::metrics::Metrics;
+    use ombrac::protocol::{ClientHello, Secret};
+    use ombrac_client::client::Client;
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::{ConnectionConfig, TlsMode as ServerTlsMode};
+    use ombrac_server::connection::{
+        Authenticator, BanList, ConnectionAuthError, ConnectionHandle, Credentials,
+    };
+    use ombrac_server::{
+        OmbracServer, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+
+    const SECRET: Secret = [7u8; 32];
+    const WRONG_SECRET: Secret = [8u8; 32];
+
+    /// Starts a mock server sharing `bans` and returns the initiator
+    /// connecting to it. Mock clients all connect from 127.0.0.1.
+    fn spawn_server(
+        bans: Arc<BanList>,
+        metrics: Metrics,
+    ) -> (MockInitiator, broadcast::Sender<()>) {
+        spawn_mock_server_with(
+            Credentials::new(SECRET),
+            ConnectionConfig::default(),
+            |acceptor| acceptor.with_metrics(metrics).with_ban_list(bans),
+        )
+    }
+
+    /// Takes longer to decide on a client than the server waits for it.
+    struct SlowAuthenticator;
+
+    impl<T: Send + Sync> Authenticator<T> for SlowAuthenticator {
+        type AuthContext = ();
+
+        async fn verify(&self, _hello: &ClientHello) -> Result<(), ConnectionAuthError> {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }
+
+        async fn accept(&self, _auth_context: (), _connection: ConnectionHandle<T>) {}
+    }
+
+    /// Waits for the server to record the failure of a refused client.
+    async fn wait_until_banned(bans: &BanList) {
+        while bans.bans().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_repeated_failures_get_the_address_banned() {
+        let metrics = Metrics::new();
+        let bans = Arc::new(BanList::new(metrics.clone()).with_max_failures(2));
+
+        for _ in 0..2 {
+            let (initiator, _shutdown_tx) = spawn_server(bans.clone(), metrics.clone());
+            let err = Client::new(initiator, WRONG_SECRET, None)
+                .await
+                .err()
+                .expect("the wrong secret should be refused");
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        }
+        wait_until_banned(&bans).await;
+        let listed = bans.bans();
+        assert_eq!(listed[0].network, "127.0.0.1/32".parse().unwrap());
+        assert_eq!(metrics.snapshot().auth_bans, 1);
+
+        // Even the right secret is refused, before authentication
+        let (initiator, _shutdown_tx) = spawn_server(bans.clone(), metrics.clone());
+        assert!(Client::new(initiator, SECRET, None).await.is_err());
+        assert_eq!(metrics.snapshot().connections_banned, 1);
+        assert_eq!(metrics.snapshot().connections_auth_failed, 2);
+
+        assert!(bans.unban("127.0.0.1".parse().unwrap()));
+        let (initiator, _shutdown_tx) = spawn_server(bans.clone(), metrics.clone());
+        Client::new(initiator, SECRET, None)
+            .await
+            .expect("the address should be admitted once unbanned");
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_allowlisted_address_is_never_banned() {
+        let metrics = Metrics::new();
+        let bans = Arc::new(
+            BanList::new(metrics.clone())
+                .with_max_failures(1)
+                .with_allowlist(vec!["127.0.0.0/8".parse().unwrap()]),
+        );
+
+        for _ in 0..3 {
+            let (initiator, _shutdown_tx) = spawn_server(bans.clone(), metrics.clone());
+            assert!(Client::new(initiator, WRONG_SECRET, None).await.is_err());
+        }
+        let (initiator, _shutdown_tx) = spawn_server(bans.clone(), metrics.clone());
+        Client::new(initiator, SECRET, None)
+            .await
+            .expect("an allowlisted address should never be banned");
+        assert!(bans.bans().is_empty());
+        assert_eq!(metrics.snapshot().auth_bans, 0);
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_slow_authenticator_does_not_ban_the_client() {
+        let metrics = Metrics::new();
+        let bans = Arc::new(BanList::new(metrics.clone()).with_max_failures(1));
+        let config = ConnectionConfig {
+            auth_timeout_secs: Some(1),
+            ..Default::default()
+        };
+
+        let server_bans = bans.clone();
+        let (initiator, _shutdown_tx) =
+            spawn_mock_server_with(SlowAuthenticator, config, |acceptor| {
+                acceptor.with_metrics(metrics).with_ban_list(server_bans)
+            });
+
+        assert!(Client::new(initiator, SECRET, None).await.is_err());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(bans.bans().is_empty());
+    }
+
+    /// Handshakes from a banned address are refused by the QUIC server.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_banned_address_is_refused_over_quic() {
+        let listen = find_available_local_udp_addr();
+        let server = OmbracServer::build(Arc::new(ServerServiceConfig {
+            secret: "server-secret".to_string(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: ConnectionConfig {
+                auth_ban_failures: Some(1),
+                ..Default::default()
+            },
+            dns: Default::default(),
+            outbound: Default::default(),
+            logging: Default::default(),
+        }))
+        .await
+        .unwrap();
+
+        let client_config = |secret: &str| {
+            Arc::new(ClientServiceConfig {
+                secret: secret.to_string(),
+                server: listen.to_string(),
+                auth_option: None,
+                endpoint: ombrac_client::config::EndpointConfig {
+                    socks: Some("127.0.0.1:0".parse().unwrap()),
+                    ..Default::default()
+                },
+                transport: ClientTransportConfig {
+                    tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                    ..Default::default()
+                },
+                logging: Default::default(),
+            })
+        };
+
+        assert!(
+            OmbracClient::build(client_config("wrong-secret"))
+                .await
+                .is_err()
+        );
+        let bans = server.ban_list().expect("banning is enabled by default");
+        wait_until_banned(&bans).await;
+
+        assert!(
+            OmbracClient::build(client_config("server-secret"))
+                .await
+                .is_err(),
+            "a banned address should be refused"
+        );
+        assert!(server.metrics().snapshot().connections_banned >= 1);
+        assert_eq!(server.metrics().snapshot().connections_accepted, 1);
+
+        server.shutdown().await;
+    }
+}
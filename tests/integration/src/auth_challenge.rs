@@ -0,0 +1,246 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use tests_support::frame::{read_frame, write_frame};
+    use tests_support::mock_transport::MockInitiator;
+    use tests_support::net::find_available_local_udp_addr;
+    use tests_support::server::spawn_mock_server;
+    use tokio::sync::broadcast;
+
+    use ombrac::codec::ClientMessage;
+    use ombrac::protocol::{
+        AuthRejection, CHALLENGE_EXPORTER_LABEL, ClientAuthProof, ClientCapabilities, ClientHello,
+        Features, PROTOCOL_VERSION, Secret, ServerAuthResponse, challenge_mac, decode, encode,
+    };
+    use ombrac_client::client::Client;
+    use ombrac_client::config::AuthMode as ClientAuthMode;
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::{AuthMode, ConnectionConfig, TlsMode as ServerTlsMode};
+    use ombrac_server::{
+        OmbracServer, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+    use ombrac_transport::{Connection, Initiator};
+
+    fn random_secret() -> Secret {
+        use rand::Rng;
+        let mut secret = [0u8; 32];
+        rand::rng().fill_bytes(&mut secret);
+        secret
+    }
+
+    /// Starts a mock server checking `secret` with the given auth mode and
+    /// returns the initiator connecting to it.
+    fn spawn_server(secret: Secret, auth_mode: AuthMode) -> (MockInitiator, broadcast::Sender<()>) {
+        let config = ConnectionConfig {
+            auth_mode: Some(auth_mode),
+            ..Default::default()
+        };
+        spawn_mock_server(secret, config)
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_challenge_client_is_accepted() {
+        let secret = random_secret();
+        let (initiator, _shutdown_tx) = spawn_server(secret, AuthMode::Secret);
+
+        let client = Client::with_auth_mode(initiator, secret, None, ClientAuthMode::Challenge)
+            .await
+            .expect("challenge-response authentication should succeed");
+        assert!(
+            client
+                .capabilities()
+                .features
+                .contains(Features::CHALLENGE_AUTH)
+        );
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_challenge_client_with_wrong_secret_is_rejected() {
+        let (initiator, _shutdown_tx) = spawn_server(random_secret(), AuthMode::Secret);
+
+        let err =
+            Client::with_auth_mode(initiator, random_secret(), None, ClientAuthMode::Challenge)
+                .await
+                .err()
+                .expect("client should be rejected with wrong secret");
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_challenge_mode_refuses_secret_clients() {
+        let secret = random_secret();
+        let (initiator, _shutdown_tx) = spawn_server(secret, AuthMode::Challenge);
+
+        let err = Client::new(initiator, secret, None)
+            .await
+            .err()
+            .expect("a client sending its secret should be refused");
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("challenge-response"));
+
+        let (initiator, _shutdown_tx) = spawn_server(secret, AuthMode::Challenge);
+        Client::with_auth_mode(initiator, secret, None, ClientAuthMode::Challenge)
+            .await
+            .expect("a challenge-response client should be accepted");
+    }
+
+    /// A proof computed for another connection is refused, even with the
+    /// right secret and nonce.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_proof_is_bound_to_the_connection() {
+        let secret = random_secret();
+        let (initiator, _shutdown_tx) = spawn_server(secret, AuthMode::Secret);
+
+        let observed = initiator.connect().await.unwrap();
+        let conn = initiator.connect().await.unwrap();
+        let mut stream = Connection::open_bidirectional(&conn).await.unwrap();
+
+        let capabilities = ClientCapabilities {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            features: Features::CHALLENGE_AUTH,
+            auth: Bytes::new(),
+        };
+        let hello = ClientMessage::Hello(ClientHello {
+            version: PROTOCOL_VERSION,
+            secret: [0u8; 32],
+            options: capabilities.to_options().unwrap(),
+        });
+        write_frame(&mut stream, &encode(&hello).unwrap()).await;
+
+        let challenge = match decode(&read_frame(&mut stream).await).unwrap() {
+            ServerAuthResponse::Challenge(challenge) => challenge,
+            other => panic!("expected a challenge, got {other:?}"),
+        };
+
+        let mut keying_material = [0u8; 32];
+        observed
+            .export_keying_material(&mut keying_material, CHALLENGE_EXPORTER_LABEL, &[])
+            .unwrap();
+        let proof = ClientMessage::AuthProof(ClientAuthProof {
+            mac: challenge_mac(&secret, &challenge.nonce, &keying_material),
+        });
+        write_frame(&mut stream, &encode(&proof).unwrap()).await;
+
+        let resp: ServerAuthResponse = decode(&read_frame(&mut stream).await).unwrap();
+        assert_eq!(
+            resp,
+            ServerAuthResponse::Rejected(AuthRejection::InvalidSecret)
+        );
+    }
+
+    /// Over a transport that can't export keying material, the client gives
+    /// up before sending anything rather than falling back to its secret.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_challenge_client_refuses_transport_without_keying_material() {
+        let secret = random_secret();
+        let (initiator, _shutdown_tx) = spawn_server(secret, AuthMode::Secret);
+
+        let err = Client::with_auth_mode(
+            initiator.without_keying_material(),
+            secret,
+            None,
+            ClientAuthMode::Challenge,
+        )
+        .await
+        .err()
+        .expect("challenge-response needs keying material");
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("challenge-response"));
+    }
+
+    /// A server that can't export keying material rejects challenge clients
+    /// instead of sending them a challenge it couldn't verify.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_server_without_keying_material_rejects_challenge_clients() {
+        let (initiator, _shutdown_tx) = spawn_server(random_secret(), AuthMode::Secret);
+
+        let conn = initiator.without_keying_material().connect().await.unwrap();
+        let mut stream = Connection::open_bidirectional(&conn).await.unwrap();
+
+        let capabilities = ClientCapabilities {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            features: Features::CHALLENGE_AUTH,
+            auth: Bytes::new(),
+        };
+        let hello = ClientMessage::Hello(ClientHello {
+            version: PROTOCOL_VERSION,
+            secret: [0u8; 32],
+            options: capabilities.to_options().unwrap(),
+        });
+        write_frame(&mut stream, &encode(&hello).unwrap()).await;
+
+        let resp: ServerAuthResponse = decode(&read_frame(&mut stream).await).unwrap();
+        assert!(matches!(
+            resp,
+            ServerAuthResponse::Rejected(AuthRejection::Other(_))
+        ));
+    }
+
+    /// The proof is bound to keying material exported from a real QUIC
+    /// connection on both ends.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_challenge_over_quic() {
+        let listen = find_available_local_udp_addr();
+        let server = OmbracServer::build(Arc::new(ServerServiceConfig {
+            secret: "challenge-secret".to_string(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: ConnectionConfig {
+                auth_mode: Some(AuthMode::Challenge),
+                ..Default::default()
+            },
+            dns: Default::default(),
+            outbound: Default::default(),
+            logging: Default::default(),
+        }))
+        .await
+        .unwrap();
+
+        let client = OmbracClient::build(Arc::new(ClientServiceConfig {
+            secret: "challenge-secret".to_string(),
+            server: listen.to_string(),
+            auth_option: None,
+            endpoint: ombrac_client::config::EndpointConfig {
+                socks: Some("127.0.0.1:0".parse().unwrap()),
+                ..Default::default()
+            },
+            transport: ClientTransportConfig {
+                tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                auth_mode: Some(ClientAuthMode::Challenge),
+                ..Default::default()
+            },
+            logging: Default::default(),
+        }))
+        .await
+        .expect("challenge-response authentication over QUIC should succeed");
+
+        assert!(
+            client
+                .client()
+                .capabilities()
+                .features
+                .contains(Features::CHALLENGE_AUTH)
+        );
+
+        client.shutdown().await;
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tests_support::mock_transport::MockInitiator;
+    use tests_support::server::spawn_mock_server;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::{broadcast, mpsc};
+
+    use ombrac::protocol::{Address, Secret};
+    use ombrac_client::client::Client;
+    use ombrac_client::config::AuthMode as ClientAuthMode;
+    use ombrac_server::config::ConnectionConfig;
+    use ombrac_server::connection::{AuthHook, Credentials};
+
+    const SECRET: Secret = [7u8; 32];
+    const USER_SECRET: Secret = [9u8; 32];
+    const LIMITED_SECRET: Secret = [10u8; 32];
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Serves a hook allowing `USER_SECRET`, and `LIMITED_SECRET` to reach
+    /// 127.0.0.1 only, and forwards the body of every request it gets.
+    async fn spawn_hook() -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/auth", listener.local_addr().unwrap());
+        let (body_tx, body_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while !request.ends_with(b"}") {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..len]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let (_, body) = request.split_once("\r\n\r\n").unwrap();
+                let answer = if body.contains(&hex(&USER_SECRET)) {
+                    r#"{"allow": true, "identity": "alice"}"#
+                } else if body.contains(&hex(&LIMITED_SECRET)) {
+                    r#"{"allow": true, "identity": "bob", "destinations": ["127.0.0.1"]}"#
+                } else {
+                    r#"{"allow": false}"#
+                };
+                let _ = body_tx.send(body.to_string());
+                let response = format!("HTTP/1.0 200 OK\r\n\r\n{answer}");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, body_rx)
+    }
+
+    fn spawn_server(hook_url: &str) -> (MockInitiator, broadcast::Sender<()>) {
+        let credentials =
+            Credentials::new(SECRET).with_hook(AuthHook::new(hook_url.parse().unwrap()));
+        spawn_mock_server(credentials, ConnectionConfig::default())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_hook_decides_on_the_secret() {
+        let (url, mut bodies) = spawn_hook().await;
+        let (initiator, _shutdown_tx) = spawn_server(&url);
+
+        Client::new(initiator, USER_SECRET, Some(Bytes::from_static(b"alice")))
+            .await
+            .expect("the hook should allow the user's secret");
+        let body = bodies.recv().await.unwrap();
+        assert!(body.contains(r#""options":"alice""#), "{body}");
+        assert!(body.contains(r#""remote_address":"#), "{body}");
+        assert!(body.contains(r#""certificate_fingerprint":null"#), "{body}");
+    }
+
+    /// The server's own secret is refused too: the hook alone decides.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_hook_denial_is_reported() {
+        let (url, _bodies) = spawn_hook().await;
+        let (initiator, _shutdown_tx) = spawn_server(&url);
+
+        let err = Client::new(initiator, SECRET, None)
+            .await
+            .err()
+            .expect("the hook should refuse the secret");
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    /// Knowing the shared secret doesn't get past the hook with
+    /// challenge-response authentication either.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_challenge_client_is_refused() {
+        let (url, mut bodies) = spawn_hook().await;
+        let (initiator, _shutdown_tx) = spawn_server(&url);
+
+        let err = Client::with_auth_mode(initiator, SECRET, None, ClientAuthMode::Challenge)
+            .await
+            .err()
+            .expect("challenge-response authentication should be refused");
+        assert!(err.to_string().contains("auth hook"), "{err}");
+        assert!(bodies.try_recv().is_err());
+    }
+
+    /// The destinations granted by the hook are the only ones reachable.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_hook_destinations_are_enforced() {
+        let (url, _bodies) = spawn_hook().await;
+        let (initiator, _shutdown_tx) = spawn_server(&url);
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = target.accept().await {
+                let _ = stream.write_all(b"hello").await;
+            }
+        });
+
+        let client = Client::new(initiator, LIMITED_SECRET, None)
+            .await
+            .expect("the hook should allow the limited secret");
+
+        let mut stream = client
+            .open_bidirectional(Address::from(target_addr))
+            .await
+            .expect("127.0.0.1 was granted by the hook");
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hello");
+
+        let err = client
+            .open_bidirectional(Address::from(("localhost", target_addr.port())))
+            .await
+            .err()
+            .expect("destinations the hook didn't grant should be refused");
+        assert!(err.to_string().contains("not allowed"), "{err}");
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_unreachable_hook_fails_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let (initiator, _shutdown_tx) = spawn_server(&url);
+
+        let err = Client::new(initiator, USER_SECRET, None)
+            .await
+            .err()
+            .expect("clients should be refused while the hook is down");
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionAborted);
+    }
+}
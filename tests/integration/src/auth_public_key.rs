@@ -0,0 +1,272 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use tests_support::frame::{read_frame, write_frame};
+    use tests_support::mock_transport::MockInitiator;
+    use tests_support::net::find_available_local_udp_addr;
+    use tests_support::server::spawn_mock_server;
+    use tokio::sync::broadcast;
+
+    use ombrac::codec::ClientMessage;
+    use ombrac::key::{PrivateKey, parse_authorized_keys};
+    use ombrac::protocol::{
+        AuthRejection, ClientCapabilities, ClientHello, ClientKeyProof, Features, PROTOCOL_VERSION,
+        Secret, ServerAuthResponse, decode, encode, key_proof_message,
+    };
+    use ombrac_client::client::Client;
+    use ombrac_client::connection::Credential;
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::{AuthMode, ConnectionConfig, TlsMode as ServerTlsMode};
+    use ombrac_server::connection::Credentials;
+    use ombrac_server::{
+        OmbracServer, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+    use ombrac_transport::{Connection, Initiator};
+
+    const SECRET: Secret = [7u8; 32];
+
+    /// Starts a mock server accepting `SECRET` and the given authorized keys
+    /// and returns the initiator connecting to it.
+    fn spawn_server(
+        authorized_keys: &str,
+        auth_mode: AuthMode,
+    ) -> (MockInitiator, broadcast::Sender<()>) {
+        let credentials = Credentials::new(SECRET)
+            .with_authorized_keys(parse_authorized_keys(authorized_keys).unwrap());
+        let config = ConnectionConfig {
+            auth_mode: Some(auth_mode),
+            ..Default::default()
+        };
+        spawn_mock_server(credentials, config)
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_authorized_key_is_accepted() {
+        let key = Arc::new(PrivateKey::generate().unwrap());
+        let authorized = format!("{} laptop\n", key.public_key());
+        let (initiator, _shutdown_tx) = spawn_server(&authorized, AuthMode::PublicKey);
+
+        let client = Client::with_credential(initiator, Credential::Key(key), None)
+            .await
+            .expect("an authorized key should be accepted");
+        assert!(
+            client
+                .capabilities()
+                .features
+                .contains(Features::PUBLIC_KEY_AUTH)
+        );
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_unknown_key_is_rejected() {
+        let authorized = format!("{} laptop\n", PrivateKey::generate().unwrap().public_key());
+        let (initiator, _shutdown_tx) = spawn_server(&authorized, AuthMode::Secret);
+
+        let stranger = Arc::new(PrivateKey::generate().unwrap());
+        let err = Client::with_credential(initiator, Credential::Key(stranger), None)
+            .await
+            .err()
+            .expect("an unknown key should be rejected");
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("not authorized"));
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_public_key_mode_refuses_secret_clients() {
+        let (initiator, _shutdown_tx) = spawn_server("", AuthMode::PublicKey);
+        let err = Client::new(initiator, SECRET, None)
+            .await
+            .err()
+            .expect("a client sending the secret should be refused");
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("public-key"));
+
+        let (initiator, _shutdown_tx) = spawn_server("", AuthMode::PublicKey);
+        let err = Client::with_credential(
+            initiator,
+            Credential::Secret(SECRET, ombrac_client::config::AuthMode::Challenge),
+            None,
+        )
+        .await
+        .err()
+        .expect("a challenge-response client should be refused");
+        assert!(err.to_string().contains("public-key"));
+    }
+
+    /// Even an authorized key is refused by the modes requiring another
+    /// credential, such as a certificate mode client presenting none.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_key_is_refused_by_token_and_certificate_modes() {
+        let key = Arc::new(PrivateKey::generate().unwrap());
+        let authorized = format!("{} laptop\n", key.public_key());
+
+        for (auth_mode, reason) in [
+            (AuthMode::Token, "access token"),
+            (AuthMode::Certificate, "certificate"),
+        ] {
+            let (initiator, _shutdown_tx) = spawn_server(&authorized, auth_mode);
+            let err = Client::with_credential(initiator, Credential::Key(key.clone()), None)
+                .await
+                .err()
+                .expect("the key should be refused");
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+            assert!(err.to_string().contains(reason), "{err} ({reason})");
+        }
+    }
+
+    /// A signature made for another connection is refused, even by an
+    /// authorized key.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_signature_is_bound_to_the_connection() {
+        let key = PrivateKey::generate().unwrap();
+        let authorized = format!("{}\n", key.public_key());
+        let (initiator, _shutdown_tx) = spawn_server(&authorized, AuthMode::PublicKey);
+
+        let observed = initiator.connect().await.unwrap();
+        let conn = initiator.connect().await.unwrap();
+        let mut stream = Connection::open_bidirectional(&conn).await.unwrap();
+
+        let capabilities = ClientCapabilities {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            features: Features::PUBLIC_KEY_AUTH,
+            auth: Bytes::new(),
+        };
+        let hello = ClientMessage::Hello(ClientHello {
+            version: PROTOCOL_VERSION,
+            secret: [0u8; 32],
+            options: capabilities.to_options().unwrap(),
+        });
+        write_frame(&mut stream, &encode(&hello).unwrap()).await;
+
+        let challenge = match decode(&read_frame(&mut stream).await).unwrap() {
+            ServerAuthResponse::Challenge(challenge) => challenge,
+            other => panic!("expected a challenge, got {other:?}"),
+        };
+
+        let mut keying_material = [0u8; 32];
+        observed
+            .export_keying_material(
+                &mut keying_material,
+                ombrac::protocol::CHALLENGE_EXPORTER_LABEL,
+                &[],
+            )
+            .unwrap();
+        let message = key_proof_message(&challenge.nonce, &keying_material);
+        let proof = ClientMessage::KeyProof(ClientKeyProof {
+            public_key: key.public_key().to_bytes(),
+            signature: key.sign(&message).into(),
+        });
+        write_frame(&mut stream, &encode(&proof).unwrap()).await;
+
+        let resp: ServerAuthResponse = decode(&read_frame(&mut stream).await).unwrap();
+        assert_eq!(
+            resp,
+            ServerAuthResponse::Rejected(AuthRejection::UnauthorizedKey)
+        );
+    }
+
+    /// Keys are loaded from files on both ends and the signature is bound
+    /// to a real QUIC connection.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_public_key_over_quic() {
+        let dir = std::env::temp_dir().join(format!("ombrac-key-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = PrivateKey::generate().unwrap();
+        let key_path = dir.join("client.pem");
+        std::fs::write(&key_path, key.to_pem().unwrap()).unwrap();
+        let authorized_keys_path = dir.join("authorized_keys");
+        std::fs::write(
+            &authorized_keys_path,
+            format!("# clients\n{} phone\n", key.public_key()),
+        )
+        .unwrap();
+
+        let listen = find_available_local_udp_addr();
+        let server = OmbracServer::build(Arc::new(ServerServiceConfig {
+            secret: String::new(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: ConnectionConfig {
+                auth_mode: Some(AuthMode::PublicKey),
+                authorized_keys: Some(authorized_keys_path),
+                ..Default::default()
+            },
+            dns: Default::default(),
+            outbound: Default::default(),
+            logging: Default::default(),
+        }))
+        .await
+        .unwrap();
+
+        let client = OmbracClient::build(Arc::new(ClientServiceConfig {
+            secret: String::new(),
+            server: listen.to_string(),
+            auth_option: None,
+            endpoint: ombrac_client::config::EndpointConfig {
+                socks: Some("127.0.0.1:0".parse().unwrap()),
+                ..Default::default()
+            },
+            transport: ClientTransportConfig {
+                tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                auth_key: Some(key_path),
+                ..Default::default()
+            },
+            logging: Default::default(),
+        }))
+        .await
+        .expect("public-key authentication over QUIC should succeed");
+
+        assert!(
+            client
+                .client()
+                .capabilities()
+                .features
+                .contains(Features::PUBLIC_KEY_AUTH)
+        );
+
+        client.shutdown().await;
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_public_key_mode_requires_authorized_keys() {
+        let result = OmbracServer::build(Arc::new(ServerServiceConfig {
+            secret: "secret".to_string(),
+            listen: find_available_local_udp_addr(),
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: ConnectionConfig {
+                auth_mode: Some(AuthMode::PublicKey),
+                ..Default::default()
+            },
+            dns: Default::default(),
+            outbound: Default::default(),
+            logging: Default::default(),
+        }))
+        .await;
+        let err = result
+            .err()
+            .expect("public-key mode without keys should fail");
+        assert!(err.to_string().contains("authorized_keys"));
+    }
+}
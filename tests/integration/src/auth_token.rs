@@ -0,0 +1,249 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use tests_support::mock_transport::MockInitiator;
+    use tests_support::net::find_available_local_udp_addr;
+    use tests_support::server::spawn_mock_server;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+
+    use ombrac::key::PrivateKey;
+    use ombrac::protocol::{Address, Features};
+    use ombrac::token::{self, Claims};
+    use ombrac_client::client::Client;
+    use ombrac_client::connection::Credential;
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::{AuthMode, ConnectionConfig, TlsMode as ServerTlsMode};
+    use ombrac_server::connection::Credentials;
+    use ombrac_server::{
+        OmbracServer, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+
+    fn claims(id: &str, ttl: i64) -> Claims {
+        Claims {
+            id: id.to_string(),
+            identity: "contractor".to_string(),
+            expires_at: token::now().saturating_add_signed(ttl),
+            bandwidth: Some(1_000_000),
+            allow: None,
+        }
+    }
+
+    /// Starts a mock server accepting tokens signed by `token_key` and
+    /// returns the initiator connecting to it.
+    fn spawn_server(
+        token_key: &PrivateKey,
+        revoked: &[&str],
+    ) -> (MockInitiator, broadcast::Sender<()>) {
+        spawn_server_with_mode(token_key, revoked, AuthMode::Token)
+    }
+
+    fn spawn_server_with_mode(
+        token_key: &PrivateKey,
+        revoked: &[&str],
+        auth_mode: AuthMode,
+    ) -> (MockInitiator, broadcast::Sender<()>) {
+        let credentials = Credentials::new([7u8; 32])
+            .with_token_key(token_key.public_key())
+            .with_revoked_tokens(
+                revoked
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<HashSet<_>>(),
+            );
+        let config = ConnectionConfig {
+            auth_mode: Some(auth_mode),
+            ..Default::default()
+        };
+        spawn_mock_server(credentials, config)
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_valid_token_is_accepted() {
+        let token_key = PrivateKey::generate().unwrap();
+        let (initiator, _shutdown_tx) = spawn_server(&token_key, &[]);
+
+        let token = claims("t1", 3600).sign(&token_key).unwrap();
+        let client = Client::with_credential(initiator, Credential::Token(token), None)
+            .await
+            .expect("a valid token should be accepted");
+        assert!(
+            client
+                .capabilities()
+                .features
+                .contains(Features::TOKEN_AUTH)
+        );
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_expired_revoked_and_forged_tokens_are_rejected() {
+        let token_key = PrivateKey::generate().unwrap();
+        let cases = [
+            (claims("t1", -1).sign(&token_key).unwrap(), "expired"),
+            (claims("t2", 3600).sign(&token_key).unwrap(), "revoked"),
+            (
+                claims("t3", 3600)
+                    .sign(&PrivateKey::generate().unwrap())
+                    .unwrap(),
+                "invalid",
+            ),
+        ];
+
+        for (token, reason) in cases {
+            let (initiator, _shutdown_tx) = spawn_server(&token_key, &["t2"]);
+            let err = Client::with_credential(initiator, Credential::Token(token), None)
+                .await
+                .err()
+                .expect("the token should be rejected");
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+            assert!(err.to_string().contains(reason), "{err} ({reason})");
+        }
+    }
+
+    /// Tokens can be replayed, so the public-key mode refuses them, and the
+    /// certificate mode refuses clients presenting no certificate.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_token_is_refused_by_public_key_and_certificate_modes() {
+        let token_key = PrivateKey::generate().unwrap();
+
+        for (auth_mode, reason) in [
+            (AuthMode::PublicKey, "public-key"),
+            (AuthMode::Certificate, "certificate"),
+        ] {
+            let (initiator, _shutdown_tx) = spawn_server_with_mode(&token_key, &[], auth_mode);
+            let token = claims("t1", 3600).sign(&token_key).unwrap();
+            let err = Client::with_credential(initiator, Credential::Token(token), None)
+                .await
+                .err()
+                .expect("the token should be refused");
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+            assert!(err.to_string().contains(reason), "{err} ({reason})");
+        }
+    }
+
+    /// The holder only reaches the destinations its token allows.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_allowed_destinations_are_enforced() {
+        let token_key = PrivateKey::generate().unwrap();
+        let (initiator, _shutdown_tx) = spawn_server(&token_key, &[]);
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = target.accept().await {
+                let _ = stream.write_all(b"hello").await;
+            }
+        });
+
+        let token = Claims {
+            allow: Some(vec!["127.0.0.0/8".to_string()]),
+            ..claims("t1", 3600)
+        }
+        .sign(&token_key)
+        .unwrap();
+        let client = Client::with_credential(initiator, Credential::Token(token), None)
+            .await
+            .expect("a valid token should be accepted");
+
+        let mut stream = client
+            .open_bidirectional(Address::from(target_addr))
+            .await
+            .expect("127.0.0.0/8 is allowed by the token");
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hello");
+
+        let err = client
+            .open_bidirectional(Address::from(("localhost", target_addr.port())))
+            .await
+            .err()
+            .expect("destinations the token doesn't allow should be refused");
+        assert!(err.to_string().contains("not allowed"), "{err}");
+    }
+
+    /// The server loads its token key and revocation list from files and
+    /// the client only holds a token, no secret.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_token_over_quic() {
+        let dir = std::env::temp_dir().join(format!("ombrac-token-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let token_key = PrivateKey::generate().unwrap();
+        let token_key_path = dir.join("token.pem");
+        let _ = std::fs::remove_file(&token_key_path);
+        token_key.save(&token_key_path).unwrap();
+        let revoked_path = dir.join("revoked_tokens");
+        std::fs::write(&revoked_path, "# leaked\nold-token\n").unwrap();
+
+        let listen = find_available_local_udp_addr();
+        let server = OmbracServer::build(Arc::new(ServerServiceConfig {
+            secret: String::new(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: ConnectionConfig {
+                auth_mode: Some(AuthMode::Token),
+                token_key: Some(token_key_path),
+                revoked_tokens: Some(revoked_path),
+                ..Default::default()
+            },
+            dns: Default::default(),
+            outbound: Default::default(),
+            logging: Default::default(),
+        }))
+        .await
+        .unwrap();
+
+        let client_config = |token: String| {
+            Arc::new(ClientServiceConfig {
+                secret: String::new(),
+                server: listen.to_string(),
+                auth_option: None,
+                endpoint: ombrac_client::config::EndpointConfig {
+                    socks: Some("127.0.0.1:0".parse().unwrap()),
+                    ..Default::default()
+                },
+                transport: ClientTransportConfig {
+                    tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                    auth_token: Some(token),
+                    ..Default::default()
+                },
+                logging: Default::default(),
+            })
+        };
+
+        let token = claims("new-token", 3600).sign(&token_key).unwrap();
+        let client = OmbracClient::build(client_config(token))
+            .await
+            .expect("token authentication over QUIC should succeed");
+        assert!(
+            client
+                .client()
+                .capabilities()
+                .features
+                .contains(Features::TOKEN_AUTH)
+        );
+        client.shutdown().await;
+
+        let revoked = claims("old-token", 3600).sign(&token_key).unwrap();
+        assert!(
+            OmbracClient::build(client_config(revoked)).await.is_err(),
+            "a revoked token should be rejected"
+        );
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,16 +1,22 @@
 #[cfg(test)]
 mod tests {
+    use std::net::SocketAddr;
     use std::time::Duration;
 
     use bytes::Bytes;
+    use tests_support::frame::{read_frame, write_frame};
     use tests_support::mock_transport::mock_transport_pair;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::io::AsyncReadExt;
     use tokio::sync::broadcast;
 
     use ombrac::codec::ClientMessage;
-    use ombrac::protocol::{ClientHello, PROTOCOL_VERSION, Secret, ServerAuthResponse, encode};
+    use ombrac::protocol::{
+        Address, AuthRejection, ClientCapabilities, ClientHello, Features, PROTOCOL_VERSION,
+        Secret, ServerAuthResponse, ServerCapabilities, encode,
+    };
+    use ombrac_client::client::Client;
     use ombrac_server::connection::ConnectionAcceptor;
-    use ombrac_transport::{Connection, Initiator};
+    use ombrac_transport::{Acceptor, Connection, Initiator};
 
     fn random_secret() -> Secret {
         use rand::Rng;
@@ -19,24 +25,6 @@ mod tests {
         secret
     }
 
-    /// Write a single length-delimited frame (4-byte big-endian length + payload).
-    async fn write_frame<W: AsyncWriteExt + Unpin>(w: &mut W, payload: &[u8]) {
-        let len = payload.len() as u32;
-        w.write_all(&len.to_be_bytes()).await.unwrap();
-        w.write_all(payload).await.unwrap();
-        w.flush().await.unwrap();
-    }
-
-    /// Read a single length-delimited frame.
-    async fn read_frame<R: AsyncReadExt + Unpin>(r: &mut R) -> Vec<u8> {
-        let mut len_buf = [0u8; 4];
-        r.read_exact(&mut len_buf).await.unwrap();
-        let len = u32::from_be_bytes(len_buf) as usize;
-        let mut payload = vec![0u8; len];
-        r.read_exact(&mut payload).await.unwrap();
-        payload
-    }
-
     /// A wrong protocol version should result in the server closing the stream
     /// (either an error or EOF — no `Ok` auth response).
     #[tokio::test]
@@ -123,4 +111,152 @@ mod tests {
             "correct version + secret should be accepted"
         );
     }
+
+    /// Sends a hello advertising `capabilities` and returns the server's answer.
+    async fn negotiate(
+        capabilities: ClientCapabilities,
+        server_secret: Secret,
+        client_secret: Secret,
+    ) -> ServerAuthResponse {
+        let (initiator, acceptor) = mock_transport_pair();
+
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            let acceptor = ConnectionAcceptor::new(acceptor, server_secret);
+            let _ = acceptor.accept_loop(shutdown_rx).await;
+        });
+
+        let conn = initiator.connect().await.unwrap();
+        let mut stream = Connection::open_bidirectional(&conn).await.unwrap();
+
+        let hello = ClientMessage::Hello(ClientHello {
+            version: capabilities.min_version,
+            secret: client_secret,
+            options: capabilities.to_options().unwrap(),
+        });
+        write_frame(&mut stream, &encode(&hello).unwrap()).await;
+
+        let response_bytes = read_frame(&mut stream).await;
+        ombrac::protocol::decode(&response_bytes).expect("server should send a valid response")
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_capabilities_are_negotiated() {
+        let secret = random_secret();
+        let capabilities = ClientCapabilities {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION + 1,
+            features: Features::ECHO | Features::COMPRESSION,
+            auth: Bytes::new(),
+        };
+
+        let resp = negotiate(capabilities, secret, secret).await;
+        assert_eq!(
+            resp,
+            ServerAuthResponse::Accepted(ServerCapabilities {
+                version: PROTOCOL_VERSION,
+                features: Features::ECHO,
+            })
+        );
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_capabilities_version_mismatch_is_explained() {
+        let secret = random_secret();
+        let capabilities = ClientCapabilities {
+            min_version: PROTOCOL_VERSION + 1,
+            max_version: PROTOCOL_VERSION + 2,
+            features: Features::empty(),
+            auth: Bytes::new(),
+        };
+
+        let resp = negotiate(capabilities, secret, secret).await;
+        assert!(
+            matches!(
+                resp,
+                ServerAuthResponse::Rejected(AuthRejection::IncompatibleVersion {
+                    max_version: PROTOCOL_VERSION,
+                    ..
+                })
+            ),
+            "unexpected response: {resp:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_capabilities_wrong_secret_is_explained() {
+        let capabilities = ClientCapabilities {
+            min_version: PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            features: Features::empty(),
+            auth: Bytes::new(),
+        };
+
+        let resp = negotiate(capabilities, random_secret(), random_secret()).await;
+        assert_eq!(
+            resp,
+            ServerAuthResponse::Rejected(AuthRejection::InvalidSecret)
+        );
+    }
+
+    /// The client surfaces the rejection reason as the error kind.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_client_reports_invalid_secret() {
+        let (initiator, acceptor) = mock_transport_pair();
+        let secret = random_secret();
+
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            let acceptor = ConnectionAcceptor::new(acceptor, secret);
+            let _ = acceptor.accept_loop(shutdown_rx).await;
+        });
+
+        let err = Client::new(initiator, random_secret(), None)
+            .await
+            .err()
+            .expect("client should be rejected with wrong secret");
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    /// A server predating negotiation answers with a plain `Ok`; the client
+    /// keeps to what such servers spoke and refuses DNS queries and echo.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_legacy_server_gets_no_dns_or_echo() {
+        let (initiator, acceptor) = mock_transport_pair();
+
+        let server = tokio::spawn(async move {
+            let conn = acceptor.accept().await.unwrap();
+            let mut stream = conn.accept_bidirectional().await.unwrap();
+            read_frame(&mut stream).await;
+            write_frame(&mut stream, &encode(&ServerAuthResponse::Ok).unwrap()).await;
+            conn
+        });
+
+        let client = Client::new(initiator, random_secret(), None)
+            .await
+            .expect("a plain Ok should be accepted");
+        let _conn = server.await.unwrap();
+        assert_eq!(client.capabilities(), ServerCapabilities::LEGACY);
+        assert_eq!(client.capabilities().features, Features::DATAGRAM);
+
+        let err = client
+            .dns_query(Bytes::from_static(&[0u8; 12]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+        let err = client
+            .echo(
+                Address::from(SocketAddr::from(([127, 0, 0, 1], 0))),
+                Bytes::new(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
 }
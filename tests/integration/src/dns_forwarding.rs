@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+    use hickory_proto::rr::rdata::A;
+    use hickory_proto::rr::{Name, RData, RecordType};
+    use tests_support::mock_transport::{MockConnection, MockInitiator, mock_transport_pair};
+    use tokio::sync::broadcast;
+
+    use ombrac::protocol::Secret;
+    use ombrac_client::client::Client;
+    use ombrac_client::dns::RemoteResolver;
+    use ombrac_server::DnsConfig;
+    use ombrac_server::connection::{ConnectionAcceptor, DnsResolver};
+
+    fn random_secret() -> Secret {
+        use rand::Rng;
+        let mut secret = [0u8; 32];
+        let mut rng = rand::rng();
+        rng.fill_bytes(&mut secret);
+        secret
+    }
+
+    async fn setup_test_env() -> (
+        Arc<Client<MockInitiator, MockConnection>>,
+        broadcast::Sender<()>,
+    ) {
+        let (initiator, acceptor) = mock_transport_pair();
+        let secret = random_secret();
+
+        let dns = DnsResolver::new(&DnsConfig {
+            hosts: Some(HashMap::from([(
+                "exit.internal".to_string(),
+                vec!["10.1.2.3".parse().unwrap()],
+            )])),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        tokio::spawn(async move {
+            let acceptor = ConnectionAcceptor::new(acceptor, secret).with_dns_resolver(dns);
+            acceptor.accept_loop(shutdown_rx).await.unwrap();
+        });
+
+        let client = Client::new(initiator, secret, None).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        (Arc::new(client), shutdown_tx)
+    }
+
+    fn query(id: u16, name: &str) -> Message {
+        let mut message = Message::new(id, MessageType::Query, OpCode::Query);
+        message.metadata.recursion_desired = true;
+        message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        message
+    }
+
+    /// The server answers forwarded queries with its own resolver view.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_dns_query_is_answered_by_server() {
+        let (client, _shutdown_tx) = setup_test_env().await;
+
+        let request = query(0x1234, "exit.internal.").to_vec().unwrap();
+        let response = client.dns_query(Bytes::from(request)).await.unwrap();
+        let response = Message::from_vec(&response).unwrap();
+
+        assert_eq!(response.metadata.id, 0x1234);
+        assert_eq!(response.metadata.message_type, MessageType::Response);
+        assert_eq!(response.metadata.response_code, ResponseCode::NoError);
+        assert_eq!(
+            response.answers[0].data,
+            RData::A(A(Ipv4Addr::new(10, 1, 2, 3)))
+        );
+    }
+
+    /// A malformed query closes the stream without a response.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_malformed_dns_query_is_rejected() {
+        let (client, _shutdown_tx) = setup_test_env().await;
+
+        let err = client
+            .dns_query(Bytes::from_static(&[0xde, 0xad]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Cached answers are returned with the ID of the new query.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_remote_resolver_serves_from_cache() {
+        let (client, shutdown_tx) = setup_test_env().await;
+        let resolver = RemoteResolver::new(Arc::clone(&client));
+
+        let first = resolver.resolve(&query(1, "exit.internal.")).await.unwrap();
+        assert_eq!(first.answers.len(), 1);
+
+        // With the server gone, only the cache can answer.
+        shutdown_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let second = resolver.resolve(&query(2, "EXIT.internal.")).await.unwrap();
+        assert_eq!(second.metadata.id, 2);
+        assert_eq!(second.answers, first.answers);
+    }
+}
@@ -68,6 +68,8 @@ async fn build_server_and_client() -> (OmbracServer, OmbracClient, String) {
             ..Default::default()
         },
         connection: Default::default(),
+        dns: Default::default(),
+        outbound: Default::default(),
         logging: Default::default(),
     });
     let server = OmbracServer::build(server_config).await.unwrap();
@@ -75,16 +75,11 @@ async fn spawn_tcp_echo() -> SocketAddr {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
     tokio::spawn(async move {
-        loop {
-            match listener.accept().await {
-                Ok((mut stream, _)) => {
-                    tokio::spawn(async move {
-                        let (mut r, mut w) = stream.split();
-                        let _ = tokio::io::copy(&mut r, &mut w).await;
-                    });
-                }
-                Err(_) => break,
-            }
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = stream.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
         }
     });
     addr
@@ -96,13 +91,8 @@ async fn spawn_udp_echo() -> SocketAddr {
     let sock = socket.clone();
     tokio::spawn(async move {
         let mut buf = vec![0u8; 65535];
-        loop {
-            match sock.recv_from(&mut buf).await {
-                Ok((n, peer)) => {
-                    let _ = sock.send_to(&buf[..n], peer).await;
-                }
-                Err(_) => break,
-            }
+        while let Ok((n, peer)) = sock.recv_from(&mut buf).await {
+            let _ = sock.send_to(&buf[..n], peer).await;
         }
     });
     addr
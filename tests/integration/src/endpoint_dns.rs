@@ -0,0 +1,164 @@
+//! Integration tests for the in-tree DNS endpoint.
+//!
+//! Each test sends raw DNS messages over UDP or TCP to the endpoint, which
+//! forwards them through a real QUIC tunnel to the server's resolver.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hickory_proto::op::{Message, MessageType, OpCode, Query};
+use hickory_proto::rr::rdata::A;
+use hickory_proto::rr::{Name, RData, RecordType};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+
+use ombrac::protocol::Secret;
+use ombrac_client::client::Client as TunnelClient;
+use ombrac_client::dns::RemoteResolver;
+use ombrac_client::endpoint::dns::Server as DnsServer;
+use ombrac_server::DnsConfig;
+use ombrac_server::connection::{ConnectionAcceptor, DnsResolver};
+use ombrac_transport::quic::client::{Client as QuicClient, Config as QuicClientCfg};
+use ombrac_transport::quic::server::{Config as QuicServerCfg, Server as QuicServer};
+
+fn random_secret() -> Secret {
+    use rand::Rng;
+    let mut s = [0u8; 32];
+    let mut rng = rand::rng();
+    rng.fill_bytes(&mut s);
+    s
+}
+
+/// Build a running ombrac tunnel + DNS endpoint, returning the endpoint
+/// address (UDP and TCP).
+async fn build_dns_endpoint() -> (SocketAddr, broadcast::Sender<()>) {
+    // 1. QUIC server on loopback (self-signed cert) with a static host entry.
+    let server_udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server_udp.local_addr().unwrap();
+    let secret = random_secret();
+
+    let mut server_cfg = QuicServerCfg::default();
+    server_cfg.enable_self_signed = true;
+    server_cfg.alpn_protocols = vec![b"h3".to_vec()];
+    let quic_server = QuicServer::new(server_udp, server_cfg).await.unwrap();
+
+    let dns = DnsResolver::new(&DnsConfig {
+        hosts: Some(HashMap::from([(
+            "exit.internal".to_string(),
+            vec!["10.1.2.3".parse().unwrap()],
+        )])),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let (shutdown_tx, shutdown_rx) = broadcast::channel::<()>(1);
+    tokio::spawn(async move {
+        let acceptor = ConnectionAcceptor::new(quic_server, secret).with_dns_resolver(dns);
+        let _ = acceptor.accept_loop(shutdown_rx).await;
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // 2. QUIC client / ombrac tunnel client.
+    let mut client_cfg = QuicClientCfg::new(server_addr, "localhost".to_string());
+    client_cfg.skip_server_verification = true;
+    client_cfg.alpn_protocols = vec![b"h3".to_vec()];
+    let quic_client = QuicClient::new(client_cfg).unwrap();
+    let tunnel_client = Arc::new(TunnelClient::new(quic_client, secret, None).await.unwrap());
+
+    // 3. DNS endpoint on a free local port, for both UDP and TCP.
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint_addr = tcp.local_addr().unwrap();
+    let udp = UdpSocket::bind(endpoint_addr).await.unwrap();
+    let mut rx = shutdown_tx.subscribe();
+    tokio::spawn(async move {
+        let server = DnsServer::new(RemoteResolver::new(tunnel_client));
+        let _ = server
+            .run(udp, tcp, async move {
+                let _ = rx.recv().await;
+            })
+            .await;
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    (endpoint_addr, shutdown_tx)
+}
+
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut message = Message::new(id, MessageType::Query, OpCode::Query);
+    message.metadata.recursion_desired = true;
+    message.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+    message.to_vec().unwrap()
+}
+
+fn assert_exit_internal(response: &[u8], id: u16) {
+    let response = Message::from_vec(response).unwrap();
+    assert_eq!(response.metadata.id, id);
+    assert_eq!(response.metadata.message_type, MessageType::Response);
+    assert_eq!(
+        response.answers[0].data,
+        RData::A(A(Ipv4Addr::new(10, 1, 2, 3)))
+    );
+}
+
+#[tokio::test]
+#[ntest::timeout(30000)]
+async fn test_udp_query_is_answered() {
+    let (endpoint, _shutdown_tx) = build_dns_endpoint().await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket
+        .send_to(&query(0x1234, "exit.internal."), endpoint)
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 512];
+    let len = socket.recv(&mut buf).await.unwrap();
+    assert_exit_internal(&buf[..len], 0x1234);
+}
+
+/// Several length-prefixed queries are answered on one TCP connection.
+#[tokio::test]
+#[ntest::timeout(30000)]
+async fn test_tcp_queries_are_answered() {
+    let (endpoint, _shutdown_tx) = build_dns_endpoint().await;
+
+    let mut stream = TcpStream::connect(endpoint).await.unwrap();
+    for id in [1, 2] {
+        let request = query(id, "exit.internal.");
+        stream
+            .write_all(&(request.len() as u16).to_be_bytes())
+            .await
+            .unwrap();
+        stream.write_all(&request).await.unwrap();
+
+        let len = stream.read_u16().await.unwrap();
+        let mut response = vec![0u8; len as usize];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_exit_internal(&response, id);
+    }
+}
+
+/// A peer that announces a query but never finishes sending it is
+/// disconnected instead of holding the connection open.
+#[tokio::test]
+#[ntest::timeout(30000)]
+async fn test_tcp_stalled_query_is_closed() {
+    let (endpoint, _shutdown_tx) = build_dns_endpoint().await;
+
+    let mut stream = TcpStream::connect(endpoint).await.unwrap();
+    let request = query(1, "exit.internal.");
+    stream
+        .write_all(&(request.len() as u16).to_be_bytes())
+        .await
+        .unwrap();
+    stream.write_all(&request[..4]).await.unwrap();
+
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(15), stream.read(&mut buf))
+        .await
+        .expect("the endpoint should close a stalled connection");
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
@@ -0,0 +1,74 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use tests_support::net::find_available_local_udp_addr;
+
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::TlsMode as ServerTlsMode;
+    use ombrac_server::{
+        OmbracServer, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+
+    fn client_config(listen: SocketAddr) -> Arc<ClientServiceConfig> {
+        Arc::new(ClientServiceConfig {
+            secret: "server-secret".to_string(),
+            server: listen.to_string(),
+            auth_option: None,
+            endpoint: ombrac_client::config::EndpointConfig {
+                socks: Some("127.0.0.1:0".parse().unwrap()),
+                ..Default::default()
+            },
+            transport: ClientTransportConfig {
+                tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                ..Default::default()
+            },
+            logging: Default::default(),
+        })
+    }
+
+    /// Past the retry threshold, clients connect after proving their
+    /// address with a stateless Retry.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn test_clients_over_the_threshold_connect_after_a_retry() {
+        let listen = find_available_local_udp_addr();
+        let server = OmbracServer::build(Arc::new(ServerServiceConfig {
+            secret: "server-secret".to_string(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                handshake_retry_threshold: Some(1),
+                ..Default::default()
+            },
+            connection: Default::default(),
+            dns: Default::default(),
+            outbound: Default::default(),
+            logging: Default::default(),
+        }))
+        .await
+        .unwrap();
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let client = OmbracClient::build(client_config(listen))
+                .await
+                .expect("a client asked to retry should still connect");
+            clients.push(client);
+        }
+
+        let snapshot = server.metrics().snapshot();
+        assert!(snapshot.handshakes_retried >= 1, "{snapshot:?}");
+        assert_eq!(snapshot.connections_accepted, 3);
+
+        for client in clients {
+            client.shutdown().await;
+        }
+        server.shutdown().await;
+    }
+}
@@ -39,3 +39,33 @@ mod drain_shutdown;
 
 #[cfg(test)]
 mod endpoint_socks;
+
+#[cfg(test)]
+mod endpoint_dns;
+
+#[cfg(test)]
+mod dns_forwarding;
+
+#[cfg(test)]
+mod server_chain;
+
+#[cfg(test)]
+mod server_proxy;
+
+#[cfg(test)]
+mod auth_challenge;
+
+#[cfg(test)]
+mod auth_public_key;
+
+#[cfg(test)]
+mod auth_token;
+
+#[cfg(test)]
+mod auth_hook;
+
+#[cfg(test)]
+mod auth_ban;
+
+#[cfg(test)]
+mod handshake_limits;
@@ -41,6 +41,8 @@ mod tests {
                 ..Default::default()
             },
             connection: Default::default(),
+            dns: Default::default(),
+            outbound: Default::default(),
             logging: Default::default(),
         });
 
@@ -73,6 +75,8 @@ mod tests {
                 ..Default::default()
             },
             connection: Default::default(),
+            dns: Default::default(),
+            outbound: Default::default(),
             logging: Default::default(),
         });
 
@@ -117,6 +121,8 @@ mod tests {
                 ..Default::default()
             },
             connection: Default::default(),
+            dns: Default::default(),
+            outbound: Default::default(),
             logging: Default::default(),
         });
 
@@ -199,16 +205,11 @@ mod tests {
         let echo_addr = echo_listener.local_addr()?;
 
         tokio::spawn(async move {
-            loop {
-                match echo_listener.accept().await {
-                    Ok((mut stream, _)) => {
-                        tokio::spawn(async move {
-                            let (mut reader, mut writer) = stream.split();
-                            let _ = tokio::io::copy(&mut reader, &mut writer).await;
-                        });
-                    }
-                    Err(_) => break,
-                }
+            while let Ok((mut stream, _)) = echo_listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
             }
         });
 
@@ -253,6 +254,8 @@ mod tests {
                 ..Default::default()
             },
             connection: Default::default(),
+            dns: Default::default(),
+            outbound: Default::default(),
             logging: Default::default(),
         });
 
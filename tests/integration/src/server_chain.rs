@@ -0,0 +1,271 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tests_support::net::find_available_local_udp_addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use ombrac::key::PrivateKey;
+    use ombrac::protocol::Address;
+    use ombrac::token::{self, Claims};
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::{AuthMode, OutboundMode, TlsMode as ServerTlsMode};
+    use ombrac_server::{
+        OmbracServer, OutboundConfig, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+
+    fn server_config(
+        secret: &str,
+        listen: SocketAddr,
+        outbound: OutboundConfig,
+    ) -> ServerServiceConfig {
+        ServerServiceConfig {
+            secret: secret.to_string(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: Default::default(),
+            dns: Default::default(),
+            outbound,
+            logging: Default::default(),
+        }
+    }
+
+    /// Starts an exit server and returns it with the outbound of an entry
+    /// server chained to it.
+    async fn start_exit() -> (OmbracServer, OutboundConfig) {
+        let exit_addr = find_available_local_udp_addr();
+        let exit = OmbracServer::build(Arc::new(server_config(
+            "exit-secret",
+            exit_addr,
+            OutboundConfig::default(),
+        )))
+        .await
+        .unwrap();
+
+        let outbound = OutboundConfig {
+            mode: Some(OutboundMode::Ombrac),
+            server: Some(exit_addr.to_string()),
+            secret: Some("exit-secret".to_string()),
+            server_name: Some("localhost".to_string()),
+            tls_mode: Some(ServerTlsMode::Insecure),
+            ..Default::default()
+        };
+        (exit, outbound)
+    }
+
+    fn client_config(
+        secret: &str,
+        server: SocketAddr,
+        transport: ClientTransportConfig,
+    ) -> Arc<ClientServiceConfig> {
+        Arc::new(ClientServiceConfig {
+            secret: secret.to_string(),
+            server: server.to_string(),
+            auth_option: None,
+            endpoint: ombrac_client::config::EndpointConfig {
+                socks: Some("127.0.0.1:0".parse().unwrap()),
+                ..Default::default()
+            },
+            transport: ClientTransportConfig {
+                tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                ..transport
+            },
+            logging: Default::default(),
+        })
+    }
+
+    /// Starts an exit server, an entry server chained to it and a client
+    /// connected to the entry server.
+    async fn setup_chain() -> (OmbracClient, OmbracServer, OmbracServer) {
+        let (exit, outbound) = start_exit().await;
+
+        let entry_addr = find_available_local_udp_addr();
+        let entry = OmbracServer::build(Arc::new(server_config(
+            "entry-secret",
+            entry_addr,
+            outbound,
+        )))
+        .await
+        .unwrap();
+
+        let client = OmbracClient::build(client_config(
+            "entry-secret",
+            entry_addr,
+            Default::default(),
+        ))
+        .await
+        .unwrap();
+
+        (client, entry, exit)
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn tcp_is_relayed_through_next_hop() -> io::Result<()> {
+        let (client, entry, exit) = setup_chain().await;
+
+        let echo_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let echo_addr = echo_listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = echo_listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+
+        let dest_addr: Address = echo_addr.to_string().try_into().unwrap();
+        let mut stream = client.client().open_bidirectional(dest_addr).await?;
+        stream.write_all(b"through the chain").await?;
+
+        let mut buf = [0u8; 17];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"through the chain");
+
+        // Both hops saw the stream
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(entry.metrics().snapshot().streams_opened >= 1);
+        assert!(exit.metrics().snapshot().streams_opened >= 1);
+
+        client.shutdown().await;
+        entry.shutdown().await;
+        exit.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn udp_is_relayed_through_next_hop() -> io::Result<()> {
+        let (client, entry, exit) = setup_chain().await;
+
+        let echo_server = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let echo_addr = echo_server.local_addr()?;
+
+        let mut session = client.client().open_associate();
+        let dest_addr: Address = echo_addr.to_string().try_into().unwrap();
+        session
+            .send_to(bytes::Bytes::from_static(b"ping"), dest_addr)
+            .await?;
+
+        let mut buf = [0u8; 64];
+        let (len, from) = echo_server.recv_from(&mut buf).await?;
+        assert_eq!(&buf[..len], b"ping");
+        echo_server.send_to(b"pong", from).await?;
+
+        let (response, from_addr) = session.recv_from().await.unwrap();
+        assert_eq!(response.as_ref(), b"pong");
+        assert_eq!(from_addr.to_string(), echo_addr.to_string());
+
+        client.shutdown().await;
+        entry.shutdown().await;
+        exit.shutdown().await;
+        Ok(())
+    }
+
+    /// The destinations a client's token allows are checked by the entry
+    /// server, which authenticated it, before anything reaches the exit.
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn client_destinations_are_checked_at_the_entry() -> io::Result<()> {
+        let (exit, outbound) = start_exit().await;
+
+        let dir = std::env::temp_dir().join(format!("ombrac-chain-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let token_key = PrivateKey::generate().unwrap();
+        let token_key_path = dir.join("token.pem");
+        let _ = std::fs::remove_file(&token_key_path);
+        token_key.save(&token_key_path).unwrap();
+
+        let entry_addr = find_available_local_udp_addr();
+        let mut config = server_config("", entry_addr, outbound);
+        config.connection.auth_mode = Some(AuthMode::Token);
+        config.connection.token_key = Some(token_key_path);
+        let entry = OmbracServer::build(Arc::new(config)).await.unwrap();
+
+        let token = Claims {
+            id: "t1".to_string(),
+            identity: "contractor".to_string(),
+            expires_at: token::now() + 3600,
+            bandwidth: None,
+            allow: Some(vec!["127.0.0.0/8".to_string()]),
+        }
+        .sign(&token_key)
+        .unwrap();
+        let client = OmbracClient::build(client_config(
+            "",
+            entry_addr,
+            ClientTransportConfig {
+                auth_token: Some(token),
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let target_addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"hello").await;
+            }
+        });
+
+        let mut stream = client
+            .client()
+            .open_bidirectional(Address::from(target_addr))
+            .await?;
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await?;
+        assert_eq!(&greeting, b"hello");
+
+        let err = client
+            .client()
+            .open_bidirectional(Address::from(("localhost", target_addr.port())))
+            .await
+            .err()
+            .expect("destinations the token doesn't allow should be refused");
+        assert!(err.to_string().contains("not allowed"), "{err}");
+
+        // Only the allowed stream was relayed to the exit
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(exit.metrics().snapshot().streams_opened, 1);
+
+        client.shutdown().await;
+        entry.shutdown().await;
+        exit.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn entry_server_requires_next_hop_secret() {
+        let entry_addr = find_available_local_udp_addr();
+        let result = OmbracServer::build(Arc::new(server_config(
+            "entry-secret",
+            entry_addr,
+            OutboundConfig {
+                mode: Some(OutboundMode::Ombrac),
+                server: Some("127.0.0.1:1".to_string()),
+                ..Default::default()
+            },
+        )))
+        .await;
+
+        let err = result
+            .err()
+            .expect("build should fail without outbound.secret");
+        assert!(err.to_string().contains("outbound.secret"));
+    }
+}
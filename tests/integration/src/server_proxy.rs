@@ -0,0 +1,230 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tests_support::net::{find_available_local_tcp_addr, find_available_local_udp_addr};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use ombrac::protocol::Address;
+    use ombrac_client::{
+        OmbracClient, ServiceConfig as ClientServiceConfig,
+        TransportConfig as ClientTransportConfig,
+    };
+    use ombrac_server::config::{OutboundMode, TlsMode as ServerTlsMode};
+    use ombrac_server::{
+        OmbracServer, OutboundConfig, ServiceConfig as ServerServiceConfig,
+        TransportConfig as ServerTransportConfig,
+    };
+
+    fn server_config(
+        secret: &str,
+        listen: SocketAddr,
+        outbound: OutboundConfig,
+    ) -> ServerServiceConfig {
+        ServerServiceConfig {
+            secret: secret.to_string(),
+            listen,
+            transport: ServerTransportConfig {
+                tls_mode: Some(ServerTlsMode::Insecure),
+                ..Default::default()
+            },
+            connection: Default::default(),
+            dns: Default::default(),
+            outbound,
+            logging: Default::default(),
+        }
+    }
+
+    async fn client(
+        secret: &str,
+        server: SocketAddr,
+        endpoint: ombrac_client::config::EndpointConfig,
+    ) -> OmbracClient {
+        OmbracClient::build(Arc::new(ClientServiceConfig {
+            secret: secret.to_string(),
+            server: server.to_string(),
+            auth_option: None,
+            endpoint,
+            transport: ClientTransportConfig {
+                tls_mode: Some(ombrac_client::config::TlsMode::Insecure),
+                ..Default::default()
+            },
+            logging: Default::default(),
+        }))
+        .await
+        .unwrap()
+    }
+
+    struct ProxyChain {
+        client: OmbracClient,
+        entry: OmbracServer,
+        proxy: OmbracClient,
+        exit: OmbracServer,
+    }
+
+    impl ProxyChain {
+        async fn shutdown(self) {
+            self.client.shutdown().await;
+            self.entry.shutdown().await;
+            self.proxy.shutdown().await;
+            self.exit.shutdown().await;
+        }
+    }
+
+    /// Starts an entry server whose outbound goes through an upstream proxy
+    /// and a client connected to it. The proxy is the SOCKS5 or HTTP endpoint
+    /// of another ombrac client, so its traffic can be observed on its server.
+    async fn setup_proxy_chain(mode: OutboundMode) -> ProxyChain {
+        let exit_addr = find_available_local_udp_addr();
+        let exit = OmbracServer::build(Arc::new(server_config(
+            "exit-secret",
+            exit_addr,
+            OutboundConfig::default(),
+        )))
+        .await
+        .unwrap();
+
+        let proxy_addr = find_available_local_tcp_addr();
+        let endpoint = match mode {
+            OutboundMode::HttpConnect => ombrac_client::config::EndpointConfig {
+                http: Some(proxy_addr),
+                ..Default::default()
+            },
+            _ => ombrac_client::config::EndpointConfig {
+                socks: Some(proxy_addr),
+                ..Default::default()
+            },
+        };
+        let proxy = client("exit-secret", exit_addr, endpoint).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let entry_addr = find_available_local_udp_addr();
+        let entry = OmbracServer::build(Arc::new(server_config(
+            "entry-secret",
+            entry_addr,
+            OutboundConfig {
+                mode: Some(mode),
+                server: Some(proxy_addr.to_string()),
+                ..Default::default()
+            },
+        )))
+        .await
+        .unwrap();
+
+        let client = client(
+            "entry-secret",
+            entry_addr,
+            ombrac_client::config::EndpointConfig {
+                socks: Some("127.0.0.1:0".parse().unwrap()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        ProxyChain {
+            client,
+            entry,
+            proxy,
+            exit,
+        }
+    }
+
+    async fn spawn_tcp_echo() -> io::Result<SocketAddr> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn assert_tcp_relayed(chain: &ProxyChain) -> io::Result<()> {
+        let echo_addr = spawn_tcp_echo().await?;
+
+        let dest_addr: Address = echo_addr.to_string().try_into().unwrap();
+        let mut stream = chain.client.client().open_bidirectional(dest_addr).await?;
+        stream.write_all(b"through the proxy").await?;
+
+        let mut buf = [0u8; 17];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"through the proxy");
+
+        // The stream left through the proxy, not from the entry server
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(chain.exit.metrics().snapshot().streams_opened >= 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn tcp_is_relayed_through_socks5_proxy() -> io::Result<()> {
+        let chain = setup_proxy_chain(OutboundMode::Socks5).await;
+        assert_tcp_relayed(&chain).await?;
+        chain.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn tcp_is_relayed_through_http_connect_proxy() -> io::Result<()> {
+        let chain = setup_proxy_chain(OutboundMode::HttpConnect).await;
+        assert_tcp_relayed(&chain).await?;
+        chain.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn udp_is_relayed_through_socks5_proxy() -> io::Result<()> {
+        let chain = setup_proxy_chain(OutboundMode::Socks5).await;
+
+        let echo_server = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let echo_addr = echo_server.local_addr()?;
+
+        let mut session = chain.client.client().open_associate();
+        let dest_addr: Address = echo_addr.to_string().try_into().unwrap();
+        session
+            .send_to(bytes::Bytes::from_static(b"ping"), dest_addr)
+            .await?;
+
+        let mut buf = [0u8; 64];
+        let (len, from) = echo_server.recv_from(&mut buf).await?;
+        assert_eq!(&buf[..len], b"ping");
+        echo_server.send_to(b"pong", from).await?;
+
+        let (response, from_addr) = session.recv_from().await.unwrap();
+        assert_eq!(response.as_ref(), b"pong");
+        assert_eq!(from_addr.to_string(), echo_addr.to_string());
+
+        chain.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ntest::timeout(30000)]
+    async fn proxy_outbound_requires_server() {
+        let entry_addr = find_available_local_udp_addr();
+        let result = OmbracServer::build(Arc::new(server_config(
+            "entry-secret",
+            entry_addr,
+            OutboundConfig {
+                mode: Some(OutboundMode::Socks5),
+                ..Default::default()
+            },
+        )))
+        .await;
+
+        let err = result
+            .err()
+            .expect("build should fail without outbound.server");
+        assert!(err.to_string().contains("outbound.server"));
+    }
+}
@@ -192,7 +192,7 @@ mod tests {
         // Should fail with DNS resolution error
         // Note: Some DNS resolvers may return different error types or may timeout
         // So we check for various possible error kinds
-        if let Ok(_) = result {
+        if result.is_ok() {
             // In some network configurations, DNS might resolve to a placeholder
             // or the test might succeed for other reasons - this is acceptable
             return;
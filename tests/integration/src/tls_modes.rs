@@ -8,22 +8,36 @@
 //!   signed by a CA it trusts.
 //!
 //! Bonus: a negative test that verifies untrusted certs are rejected.
+//!
+//! The certificate auth mode tests check that the client certificate reaches
+//! the authenticator and can stand in for the secret.
+//!
+//! The CRL tests check that revoked certificates are rejected on both sides,
+//! that the server picks up a changed CRL and the unknown status policy.
+//!
+//! The pinning tests check that a pinned key stands in for the CA, and that a
+//! pinned CA can't be replayed in front of another key.
 
 use std::io;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 
-use ombrac::protocol::{Address, Secret};
+use ombrac::protocol::{Address, ClientHello, Secret};
 use ombrac_client::client::Client as TunnelClient;
-use ombrac_server::connection::ConnectionAcceptor;
+use ombrac_server::config::{AuthMode, ConnectionConfig};
+use ombrac_server::connection::{
+    Authenticator, ConnectionAcceptor, ConnectionAuthError, ConnectionHandle, Credentials, Peer,
+    PeerCertificate,
+};
 use ombrac_transport::quic::Connection as QuicConnection;
-use ombrac_transport::quic::client::{Client as QuicClient, Config as QuicClientCfg};
+use ombrac_transport::quic::client::{Client as QuicClient, Config as QuicClientCfg, Pin};
 use ombrac_transport::quic::server::{Config as QuicServerCfg, Server as QuicServer};
 
 fn random_secret() -> Secret {
@@ -42,6 +56,10 @@ struct PkiPaths {
     server_key: PathBuf,
     client_cert: PathBuf,
     client_key: PathBuf,
+    /// CRL of the CA revoking nothing, in DER.
+    empty_crl: PathBuf,
+    /// CRL of the CA revoking the client and server certificates, in PEM.
+    revoked_crl: PathBuf,
 }
 
 impl Drop for PkiPaths {
@@ -57,9 +75,16 @@ static PKI_COUNTER: AtomicU64 = AtomicU64::new(0);
 /// all written to a temporary directory. Returns absolute paths suitable for
 /// passing into `QuicClientCfg::root_ca_path` / `QuicServerCfg::tls_cert_key_paths`.
 fn generate_pki() -> PkiPaths {
+    generate_pki_with_ca("ombrac-test-ca")
+}
+
+/// Like `generate_pki`, with `ca_name` as the common name of the CA.
+fn generate_pki_with_ca(ca_name: &str) -> PkiPaths {
     use rcgen::string::Ia5String;
     use rcgen::{
-        BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair, KeyUsagePurpose, SanType,
+        BasicConstraints, CertificateParams, CertificateRevocationListParams, IsCa, Issuer,
+        KeyIdMethod, KeyPair, KeyUsagePurpose, RevocationReason, RevokedCertParams, SanType,
+        SerialNumber, date_time_ymd,
     };
     use std::str::FromStr;
 
@@ -77,7 +102,7 @@ fn generate_pki() -> PkiPaths {
     ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
     ca_params
         .distinguished_name
-        .push(rcgen::DnType::CommonName, "ombrac-test-ca");
+        .push(rcgen::DnType::CommonName, ca_name);
     let ca_key = KeyPair::generate().unwrap();
     let ca_cert = ca_params.self_signed(&ca_key).unwrap();
     let ca_cert_path = dir.join("ca.pem");
@@ -87,6 +112,7 @@ fn generate_pki() -> PkiPaths {
 
     // 2. Server cert (SAN: localhost + 127.0.0.1), signed by CA.
     let mut server_params = CertificateParams::default();
+    server_params.serial_number = Some(SerialNumber::from(2u64));
     server_params.subject_alt_names = vec![
         SanType::DnsName(Ia5String::from_str("localhost").unwrap()),
         SanType::IpAddress(std::net::IpAddr::from([127, 0, 0, 1])),
@@ -104,6 +130,7 @@ fn generate_pki() -> PkiPaths {
 
     // 3. Client cert, signed by the same CA.
     let mut client_params = CertificateParams::default();
+    client_params.serial_number = Some(SerialNumber::from(3u64));
     client_params
         .distinguished_name
         .push(rcgen::DnType::CommonName, "ombrac-test-client");
@@ -115,6 +142,33 @@ fn generate_pki() -> PkiPaths {
     std::fs::write(&client_cert_path, client_cert.pem()).unwrap();
     std::fs::write(&client_key_path, client_key.serialize_pem()).unwrap();
 
+    // 4. CRLs of the CA: one revoking nothing, one revoking both certs.
+    let crl = |revoked: &[u64]| {
+        CertificateRevocationListParams {
+            this_update: date_time_ymd(2024, 1, 1),
+            next_update: date_time_ymd(2124, 1, 1),
+            crl_number: SerialNumber::from(1u64),
+            issuing_distribution_point: None,
+            revoked_certs: revoked
+                .iter()
+                .map(|&serial| RevokedCertParams {
+                    serial_number: SerialNumber::from(serial),
+                    revocation_time: date_time_ymd(2024, 1, 1),
+                    reason_code: Some(RevocationReason::KeyCompromise),
+                    invalidity_date: None,
+                })
+                .collect(),
+            key_identifier_method: KeyIdMethod::Sha256,
+        }
+        .signed_by(&ca_issuer)
+        .unwrap()
+    };
+
+    let empty_crl_path = dir.join("empty.crl");
+    let revoked_crl_path = dir.join("revoked.crl.pem");
+    std::fs::write(&empty_crl_path, crl(&[]).der()).unwrap();
+    std::fs::write(&revoked_crl_path, crl(&[2, 3]).pem().unwrap()).unwrap();
+
     PkiPaths {
         dir,
         ca_cert: ca_cert_path,
@@ -122,6 +176,8 @@ fn generate_pki() -> PkiPaths {
         server_key: server_key_path,
         client_cert: client_cert_path,
         client_key: client_key_path,
+        empty_crl: empty_crl_path,
+        revoked_crl: revoked_crl_path,
     }
 }
 
@@ -148,6 +204,15 @@ async fn spawn_tcp_echo() -> SocketAddr {
 async fn spawn_server(
     pki: &PkiPaths,
     verify_client_ca: Option<&PathBuf>,
+) -> (SocketAddr, Secret, broadcast::Sender<()>) {
+    spawn_server_with(pki, verify_client_ca, |_| {}).await
+}
+
+/// Like `spawn_server`, with `configure` applied to the server config.
+async fn spawn_server_with(
+    pki: &PkiPaths,
+    verify_client_ca: Option<&PathBuf>,
+    configure: impl FnOnce(&mut QuicServerCfg),
 ) -> (SocketAddr, Secret, broadcast::Sender<()>) {
     let server_udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     let server_addr = server_udp.local_addr().unwrap();
@@ -159,6 +224,7 @@ async fn spawn_server(
     if let Some(ca) = verify_client_ca {
         server_cfg.root_ca_path = Some(ca.clone());
     }
+    configure(&mut server_cfg);
 
     let quic_server = QuicServer::new(server_udp, server_cfg).await.unwrap();
     let (shutdown_tx, shutdown_rx) = broadcast::channel::<()>(1);
@@ -171,6 +237,64 @@ async fn spawn_server(
     (server_addr, secret, shutdown_tx)
 }
 
+/// Accepts clients by their certificate alone and forwards it.
+struct CertificateRecorder(mpsc::UnboundedSender<Option<PeerCertificate>>);
+
+impl<T: Send + Sync> Authenticator<T> for CertificateRecorder {
+    type AuthContext = ();
+
+    async fn verify(&self, _hello: &ClientHello) -> Result<(), ConnectionAuthError> {
+        Err(ConnectionAuthError::InvalidSecret)
+    }
+
+    async fn verify_certificate(
+        &self,
+        _hello: &ClientHello,
+        peer: &Peer,
+    ) -> Result<(), ConnectionAuthError> {
+        let _ = self.0.send(peer.certificate.clone());
+        match peer.certificate {
+            Some(_) => Ok(()),
+            None => Err(ConnectionAuthError::CertificateRequired),
+        }
+    }
+
+    async fn accept(&self, _auth_context: (), _connection: ConnectionHandle<T>) {}
+}
+
+/// Spawns a server in the certificate auth mode, with mTLS if
+/// `verify_client_ca` is `Some`.
+async fn spawn_certificate_server<A>(
+    pki: &PkiPaths,
+    verify_client_ca: Option<&PathBuf>,
+    authenticator: A,
+) -> (SocketAddr, broadcast::Sender<()>)
+where
+    A: Authenticator<QuicConnection> + 'static,
+{
+    let server_udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server_udp.local_addr().unwrap();
+
+    let mut server_cfg = QuicServerCfg::default();
+    server_cfg.tls_cert_key_paths = Some((pki.server_cert.clone(), pki.server_key.clone()));
+    server_cfg.alpn_protocols = vec![b"h3".to_vec()];
+    server_cfg.root_ca_path = verify_client_ca.cloned();
+
+    let quic_server = QuicServer::new(server_udp, server_cfg).await.unwrap();
+    let config = Arc::new(ConnectionConfig {
+        auth_mode: Some(AuthMode::Certificate),
+        ..Default::default()
+    });
+    let (shutdown_tx, shutdown_rx) = broadcast::channel::<()>(1);
+    tokio::spawn(async move {
+        let acceptor = ConnectionAcceptor::with_config(quic_server, authenticator, config);
+        let _ = acceptor.accept_loop(shutdown_rx).await;
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    (server_addr, shutdown_tx)
+}
+
 fn build_quic_client_cfg(
     server_addr: SocketAddr,
     root_ca: Option<PathBuf>,
@@ -302,3 +426,249 @@ async fn mtls_with_wrong_client_cert_rejected() {
 
     let _ = shutdown.send(());
 }
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn certificate_mode_identifies_client_by_its_certificate() -> io::Result<()> {
+    let pki = generate_pki();
+    let (certificates_tx, mut certificates) = mpsc::unbounded_channel();
+    let (server_addr, shutdown) = spawn_certificate_server(
+        &pki,
+        Some(&pki.ca_cert),
+        CertificateRecorder(certificates_tx),
+    )
+    .await;
+
+    let client_cfg = build_quic_client_cfg(
+        server_addr,
+        Some(pki.ca_cert.clone()),
+        Some((pki.client_cert.clone(), pki.client_key.clone())),
+    );
+    let quic_client = QuicClient::new(client_cfg).unwrap();
+
+    // Any secret will do: the certificate is the credential
+    let _tunnel: TunnelClient<QuicClient, QuicConnection> =
+        TunnelClient::new(quic_client, random_secret(), None)
+            .await
+            .unwrap();
+
+    let certificate = certificates.recv().await.unwrap().unwrap();
+    assert_eq!(
+        certificate.common_name.as_deref(),
+        Some("ombrac-test-client")
+    );
+    assert_eq!(certificate.identity(), "ombrac-test-client");
+
+    let _ = shutdown.send(());
+    Ok(())
+}
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn certificate_mode_refuses_client_without_certificate() {
+    let pki = generate_pki();
+    let credentials = Credentials::new(random_secret());
+    let (server_addr, shutdown) = spawn_certificate_server(&pki, None, credentials).await;
+
+    let client_cfg = build_quic_client_cfg(server_addr, Some(pki.ca_cert.clone()), None);
+    let quic_client = QuicClient::new(client_cfg).unwrap();
+
+    let result = TunnelClient::new(quic_client, random_secret(), None).await;
+    assert!(
+        result.is_err(),
+        "a client without a certificate should be refused"
+    );
+
+    let _ = shutdown.send(());
+}
+
+async fn mtls_client(
+    pki: &PkiPaths,
+    server_addr: SocketAddr,
+    secret: Secret,
+) -> io::Result<TunnelClient<QuicClient, QuicConnection>> {
+    let client_cfg = build_quic_client_cfg(
+        server_addr,
+        Some(pki.ca_cert.clone()),
+        Some((pki.client_cert.clone(), pki.client_key.clone())),
+    );
+    TunnelClient::new(QuicClient::new(client_cfg).unwrap(), secret, None).await
+}
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn mtls_revoked_client_cert_rejected_after_crl_reload() {
+    let pki = generate_pki();
+    let crl = pki.dir.join("server.crl");
+    std::fs::copy(&pki.empty_crl, &crl).unwrap();
+    let crl_paths = vec![crl.clone()];
+    let (server_addr, secret, shutdown) = spawn_server_with(&pki, Some(&pki.ca_cert), |cfg| {
+        cfg.crl_paths = crl_paths;
+        cfg.crl_reload_interval = Duration::from_millis(50);
+    })
+    .await;
+
+    mtls_client(&pki, server_addr, secret)
+        .await
+        .expect("the client certificate isn't revoked yet");
+
+    std::fs::copy(&pki.revoked_crl, &crl).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert!(
+        mtls_client(&pki, server_addr, secret).await.is_err(),
+        "mTLS server should reject a revoked client cert once the CRL is reloaded"
+    );
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn mtls_unknown_revocation_status_follows_policy() {
+    let pki = generate_pki();
+    let other_pki = generate_pki_with_ca("ombrac-other-ca");
+
+    // The only CRL is of another CA, so it says nothing about the client
+    for allow_unknown in [false, true] {
+        let crl_paths = vec![other_pki.revoked_crl.clone()];
+        let (server_addr, secret, shutdown) = spawn_server_with(&pki, Some(&pki.ca_cert), |cfg| {
+            cfg.crl_paths = crl_paths;
+            cfg.allow_unknown_revocation_status = allow_unknown;
+        })
+        .await;
+
+        let result = mtls_client(&pki, server_addr, secret).await;
+        assert_eq!(
+            result.is_ok(),
+            allow_unknown,
+            "allow_unknown: {allow_unknown}"
+        );
+
+        let _ = shutdown.send(());
+    }
+}
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn tls_client_rejects_revoked_server_cert() {
+    let pki = generate_pki();
+    let (server_addr, secret, shutdown) = spawn_server(&pki, None).await;
+
+    let mut client_cfg = build_quic_client_cfg(server_addr, Some(pki.ca_cert.clone()), None);
+    client_cfg.crl_paths = vec![pki.empty_crl.clone()];
+    TunnelClient::new(QuicClient::new(client_cfg.clone()).unwrap(), secret, None)
+        .await
+        .expect("the server certificate isn't revoked");
+
+    client_cfg.crl_paths = vec![pki.revoked_crl.clone()];
+    let result = TunnelClient::new(QuicClient::new(client_cfg).unwrap(), secret, None).await;
+    assert!(
+        result.is_err(),
+        "client should reject a server cert listed in its CRL"
+    );
+
+    let _ = shutdown.send(());
+}
+
+/// Writes the server certificate of `leaf_pki` followed by the CA of
+/// `ca_pki` to a chain file and returns its path.
+fn write_chain(leaf_pki: &PkiPaths, ca_pki: &PkiPaths) -> PathBuf {
+    let chain = leaf_pki.dir.join("server-chain.pem");
+    let mut pem = std::fs::read(&leaf_pki.server_cert).unwrap();
+    pem.extend(std::fs::read(&ca_pki.ca_cert).unwrap());
+    std::fs::write(&chain, pem).unwrap();
+    chain
+}
+
+async fn pinned_client(
+    server_addr: SocketAddr,
+    server_name: &str,
+    secret: Secret,
+    pins: Vec<Pin>,
+    verify_hostname: bool,
+) -> io::Result<TunnelClient<QuicClient, QuicConnection>> {
+    let mut client_cfg = build_quic_client_cfg(server_addr, None, None);
+    client_cfg.server_name = server_name.to_string();
+    client_cfg.pinned_keys = pins;
+    client_cfg.verify_pinned_hostname = verify_hostname;
+    TunnelClient::new(QuicClient::new(client_cfg).unwrap(), secret, None).await
+}
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn pinned_key_replaces_ca_verification() {
+    let pki = generate_pki();
+    let chain = write_chain(&pki, &pki);
+    let server_key = pki.server_key.clone();
+    let (server_addr, secret, shutdown) = spawn_server_with(&pki, None, |cfg| {
+        cfg.tls_cert_key_paths = Some((chain, server_key));
+    })
+    .await;
+
+    let pins = ombrac_client::service::server_pins(
+        &server_addr.to_string(),
+        Some(&"localhost".to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(pins.len(), 2, "the leaf and the CA should be pinnable");
+
+    // The CA isn't trusted: the pins alone verify the server
+    for pin in &pins {
+        pinned_client(server_addr, "localhost", secret, vec![*pin], true)
+            .await
+            .expect("a pinned server should be accepted");
+    }
+
+    assert!(
+        pinned_client(server_addr, "localhost", secret, vec![Pin([0; 32])], true)
+            .await
+            .is_err(),
+        "a server without a pinned key should be rejected"
+    );
+
+    assert!(
+        pinned_client(server_addr, "example.com", secret, vec![pins[0]], true)
+            .await
+            .is_err(),
+        "the server name should be checked"
+    );
+    pinned_client(server_addr, "example.com", secret, vec![pins[0]], false)
+        .await
+        .expect("the server name check should be optional");
+
+    let _ = shutdown.send(());
+}
+
+#[tokio::test]
+#[ntest::timeout(60000)]
+async fn pinned_ca_in_front_of_another_key_rejected() {
+    let pki = generate_pki();
+    let attacker_pki = generate_pki();
+
+    // The attacker serves its own key followed by the pinned CA, whose name
+    // is even the one of its own CA
+    let chain = write_chain(&attacker_pki, &pki);
+    let attacker_key = attacker_pki.server_key.clone();
+    let (attacker_addr, secret, shutdown) = spawn_server_with(&attacker_pki, None, |cfg| {
+        cfg.tls_cert_key_paths = Some((chain, attacker_key));
+    })
+    .await;
+    let pins = ombrac_client::service::server_pins(
+        &attacker_addr.to_string(),
+        Some(&"localhost".to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(pins.len(), 2);
+
+    assert!(
+        pinned_client(attacker_addr, "localhost", secret, vec![pins[1]], true)
+            .await
+            .is_err(),
+        "a leaf not signed by the pinned CA should be rejected"
+    );
+
+    let _ = shutdown.send(());
+}
@@ -112,13 +112,11 @@ async fn test_udp_multiple_sessions() -> io::Result<()> {
 
     let mut found1 = false;
     let mut found2 = false;
-    for response in responses {
-        if let Some((data, _)) = response {
-            if data == message1 {
-                found1 = true;
-            } else if data == message2 {
-                found2 = true;
-            }
+    for (data, _) in responses.into_iter().flatten() {
+        if data == message1 {
+            found1 = true;
+        } else if data == message2 {
+            found2 = true;
         }
     }
 
@@ -53,13 +53,8 @@ mod tests {
         // Spawn an echo server that handles all incoming packets.
         tokio::spawn(async move {
             let mut buf = [0u8; 4096];
-            loop {
-                match echo_server.recv_from(&mut buf).await {
-                    Ok((n, from)) => {
-                        let _ = echo_server.send_to(&buf[..n], from).await;
-                    }
-                    Err(_) => break,
-                }
+            while let Ok((n, from)) = echo_server.recv_from(&mut buf).await {
+                let _ = echo_server.send_to(&buf[..n], from).await;
             }
         });
 
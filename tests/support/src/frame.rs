@@ -0,0 +1,19 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Write a single length-delimited frame (4-byte big-endian length + payload).
+pub async fn write_frame<W: AsyncWriteExt + Unpin>(w: &mut W, payload: &[u8]) {
+    let len = payload.len() as u32;
+    w.write_all(&len.to_be_bytes()).await.unwrap();
+    w.write_all(payload).await.unwrap();
+    w.flush().await.unwrap();
+}
+
+/// Read a single length-delimited frame.
+pub async fn read_frame<R: AsyncReadExt + Unpin>(r: &mut R) -> Vec<u8> {
+    let mut len_buf = [0u8; 4];
+    r.read_exact(&mut len_buf).await.unwrap();
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload).await.unwrap();
+    payload
+}
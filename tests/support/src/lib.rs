@@ -1,6 +1,8 @@
 pub mod binary;
 pub mod cert;
+pub mod frame;
 pub mod mock_transport;
 pub mod net;
 pub mod path;
 pub mod process;
+pub mod server;
@@ -3,6 +3,7 @@ use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::{Context, Poll};
 
 use bytes::Bytes;
@@ -46,6 +47,8 @@ impl AsyncWrite for MockStream {
 pub struct MockConnection {
     id: usize,
     remote_addr: SocketAddr,
+    // Stands in for the TLS session both ends of a pair share, if any.
+    session: Option<u64>,
     bidi_tx: mpsc::Sender<MockStream>,
     bidi_rx: Arc<Mutex<mpsc::Receiver<MockStream>>>,
     datagram_tx: mpsc::Sender<Bytes>,
@@ -63,6 +66,26 @@ impl Connection for MockConnection {
         Ok(self.remote_addr)
     }
 
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: &[u8],
+    ) -> io::Result<()> {
+        let Some(session) = self.session else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "keying material export is not supported by this transport",
+            ));
+        };
+        let session = session.to_be_bytes();
+        let input = session.iter().chain(label).chain(context).cycle();
+        for (byte, value) in output.iter_mut().zip(input) {
+            *byte = *value;
+        }
+        Ok(())
+    }
+
     fn max_datagram_size(&self) -> Option<usize> {
         // Use a reasonable MTU size that allows for fragmentation testing
         // This should be larger than the fragmented_overhead() (~277 bytes)
@@ -118,18 +141,23 @@ impl Connection for MockConnection {
     fn close(&self, _error_code: u32, _reason: &[u8]) {}
 }
 
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
 fn create_connection_pair(
     client_addr: SocketAddr,
     server_addr: SocketAddr,
+    exports_keying_material: bool,
 ) -> (MockConnection, MockConnection) {
     let (c2s_bidi_tx, c2s_bidi_rx) = mpsc::channel(16);
     let (s2c_bidi_tx, s2c_bidi_rx) = mpsc::channel(16);
     let (c2s_dgram_tx, c2s_dgram_rx) = mpsc::channel(128);
     let (s2c_dgram_tx, s2c_dgram_rx) = mpsc::channel(128);
+    let session = exports_keying_material.then(|| NEXT_SESSION.fetch_add(1, Ordering::Relaxed));
 
     let client_conn = MockConnection {
         id: 1,
         remote_addr: server_addr,
+        session,
         bidi_tx: c2s_bidi_tx,
         bidi_rx: Arc::new(Mutex::new(s2c_bidi_rx)),
         datagram_tx: c2s_dgram_tx,
@@ -139,6 +167,7 @@ fn create_connection_pair(
     let server_conn = MockConnection {
         id: 1,
         remote_addr: client_addr,
+        session,
         bidi_tx: s2c_bidi_tx,
         bidi_rx: Arc::new(Mutex::new(c2s_bidi_rx)),
         datagram_tx: s2c_dgram_tx,
@@ -152,6 +181,16 @@ pub struct MockInitiator {
     local_addr: SocketAddr,
     server_addr: SocketAddr,
     connection_tx: mpsc::Sender<MockConnection>,
+    exports_keying_material: bool,
+}
+
+impl MockInitiator {
+    /// Makes connections behave like a transport without TLS, failing to
+    /// export keying material.
+    pub fn without_keying_material(mut self) -> Self {
+        self.exports_keying_material = false;
+        self
+    }
 }
 
 impl Initiator for MockInitiator {
@@ -166,7 +205,11 @@ impl Initiator for MockInitiator {
     }
 
     fn connect(&self) -> impl Future<Output = io::Result<Self::Connection>> + Send {
-        let (client_conn, server_conn) = create_connection_pair(self.local_addr, self.server_addr);
+        let (client_conn, server_conn) = create_connection_pair(
+            self.local_addr,
+            self.server_addr,
+            self.exports_keying_material,
+        );
         let tx = self.connection_tx.clone();
         async move {
             tx.send(server_conn)
@@ -207,6 +250,7 @@ pub fn mock_transport_pair() -> (MockInitiator, MockAcceptor) {
         local_addr: client_addr,
         server_addr,
         connection_tx: tx,
+        exports_keying_material: true,
     };
 
     let acceptor = MockAcceptor {
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use ombrac_server::config::ConnectionConfig;
+use ombrac_server::connection::{Authenticator, ConnectionAcceptor};
+use tokio::sync::broadcast;
+
+use crate::mock_transport::{MockAcceptor, MockConnection, MockInitiator, mock_transport_pair};
+
+/// Starts a mock server authenticating clients with `authenticator` and
+/// returns the initiator connecting to it.
+pub fn spawn_mock_server<A>(
+    authenticator: A,
+    config: ConnectionConfig,
+) -> (MockInitiator, broadcast::Sender<()>)
+where
+    A: Authenticator<MockConnection> + 'static,
+{
+    spawn_mock_server_with(authenticator, config, |acceptor| acceptor)
+}
+
+/// Like [`spawn_mock_server`], letting `setup` configure the acceptor
+/// further, e.g. with a ban list.
+pub fn spawn_mock_server_with<A, F>(
+    authenticator: A,
+    config: ConnectionConfig,
+    setup: F,
+) -> (MockInitiator, broadcast::Sender<()>)
+where
+    A: Authenticator<MockConnection> + 'static,
+    F: FnOnce(ConnectionAcceptor<MockAcceptor, A>) -> ConnectionAcceptor<MockAcceptor, A>
+        + Send
+        + 'static,
+{
+    let (initiator, acceptor) = mock_transport_pair();
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    tokio::spawn(async move {
+        let acceptor = setup(ConnectionAcceptor::with_config(
+            acceptor,
+            authenticator,
+            Arc::new(config),
+        ));
+        let _ = acceptor.accept_loop(shutdown_rx).await;
+    });
+    (initiator, shutdown_tx)
+}